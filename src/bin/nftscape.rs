@@ -0,0 +1,890 @@
+//! Command line interface to the OpenSea and Rarible APIs.
+//!
+//! Every subcommand maps onto one of the [`ApiClient`] calls, the query
+//! options are exposed as flags. Results are printed as a table of the
+//! flattened [`Record`] columns, or as the raw API models in JSON or NDJSON.
+
+use std::io::{self, BufWriter, Write};
+
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use serde::Serialize;
+
+use nftscape::export::{Cell, Record, Schema};
+use nftscape::opensea::models::{OrderSide, SaleKind};
+use nftscape::opensea::query::{
+    OpenSeaAssetBundleQuery, OpenSeaAssetQuery, OpenSeaAssetsQuery, OpenSeaFungibleTokenQuery,
+    OrderQuery,
+};
+use nftscape::rarible::models::{
+    ActivityTypes, NftActivityFilter, OrderActivityFilter, OrderActivityTypes,
+};
+use nftscape::{opensea, rarible, ApiClient};
+
+/// Table cells are cut off after this many characters, unless `--wide`
+const MAX_CELL_WIDTH: usize = 44;
+
+#[derive(Parser)]
+#[command(
+    name = "nftscape",
+    version,
+    about = "Query the OpenSea and Rarible APIs"
+)]
+struct Cli {
+    #[command(flatten)]
+    output: OutputArgs,
+
+    /// Base url of the OpenSea API
+    #[arg(long, env = "OPENSEA_API_URL", default_value = opensea::API_BASE_MAINNET)]
+    opensea_url: String,
+
+    /// OpenSea API key, sent as `X-API-KEY`
+    #[arg(long, env = "OPENSEA_API_KEY", hide_env_values = true)]
+    opensea_api_key: Option<String>,
+
+    /// Base url of the Rarible API
+    #[arg(long, env = "RARIBLE_API_URL", default_value = rarible::API_BASE_MAINNET)]
+    rarible_url: String,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Args)]
+struct OutputArgs {
+    /// How the results are printed
+    #[arg(long, short, value_enum, default_value_t = Output::Table, global = true)]
+    output: Output,
+
+    /// Only print these columns in the table, comma separated
+    #[arg(long, value_delimiter = ',', global = true)]
+    columns: Vec<String>,
+
+    /// Add a column for every trait of the results to the table
+    #[arg(long, global = true)]
+    traits: bool,
+
+    /// Don't cut off long table cells
+    #[arg(long, global = true)]
+    wide: bool,
+}
+
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+enum Output {
+    Table,
+    Json,
+    Ndjson,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Query the OpenSea API
+    #[command(subcommand)]
+    Opensea(OpenSeaCommand),
+    /// Query the Rarible API
+    #[command(subcommand)]
+    Rarible(RaribleCommand),
+}
+
+#[derive(Subcommand)]
+enum OpenSeaCommand {
+    /// List assets
+    Assets(AssetsArgs),
+    /// Show a single asset
+    Asset {
+        /// Address of the asset's contract
+        contract: String,
+        token_id: Option<String>,
+    },
+    /// List orders of the orderbook
+    Orders(OrdersArgs),
+    /// List bundles
+    Bundles(BundlesArgs),
+    /// List the payment tokens
+    Tokens {
+        #[arg(long)]
+        symbol: Option<String>,
+        #[command(flatten)]
+        page: Page,
+    },
+}
+
+#[derive(Args)]
+struct Page {
+    /// Number of results to return
+    #[arg(long)]
+    limit: Option<u32>,
+    /// Number of results to skip
+    #[arg(long)]
+    offset: Option<u32>,
+}
+
+#[derive(Args)]
+struct AssetsArgs {
+    /// Address of the owner
+    #[arg(long)]
+    owner: Option<String>,
+    /// Address of the contract
+    #[arg(long)]
+    contract: Option<String>,
+    /// Addresses of several contracts, comma separated
+    #[arg(long, value_delimiter = ',', conflicts_with = "contract")]
+    contracts: Vec<String>,
+    /// Token ids to return, comma separated
+    #[arg(long, value_delimiter = ',')]
+    token_ids: Vec<String>,
+    #[arg(long)]
+    search: Option<String>,
+    /// Field to order by, like `sale_date` or `sale_price`
+    #[arg(long)]
+    order_by: Option<String>,
+    /// `asc` or `desc`
+    #[arg(long)]
+    order_direction: Option<String>,
+    #[command(flatten)]
+    page: Page,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Side {
+    Buy,
+    Sell,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Sale {
+    Fixed,
+    Dutch,
+}
+
+#[derive(Args)]
+struct OrdersArgs {
+    /// Address of the maker
+    #[arg(long)]
+    owner: Option<String>,
+    #[arg(long, value_enum)]
+    side: Option<Side>,
+    #[arg(long, value_enum)]
+    sale_kind: Option<Sale>,
+    /// Address of the asset's contract
+    #[arg(long)]
+    contract: Option<String>,
+    /// Address of the token the order is paid in
+    #[arg(long)]
+    payment_token: Option<String>,
+    #[arg(long)]
+    token_id: Option<String>,
+    /// Token ids, comma separated
+    #[arg(long, value_delimiter = ',', conflicts_with = "token_id")]
+    token_ids: Vec<String>,
+    /// Only orders listed after this timestamp
+    #[arg(long)]
+    listed_after: Option<String>,
+    /// Only orders listed before this timestamp
+    #[arg(long)]
+    listed_before: Option<String>,
+    #[arg(long)]
+    is_english: Option<bool>,
+    #[arg(long)]
+    is_expired: Option<bool>,
+    #[arg(long)]
+    bundled: Option<bool>,
+    /// Include orders that are no longer valid
+    #[arg(long)]
+    include_invalid: bool,
+    #[command(flatten)]
+    page: Page,
+}
+
+#[derive(Args)]
+struct BundlesArgs {
+    /// Address of the contract
+    #[arg(long)]
+    contract: Option<String>,
+    /// Token ids, comma separated
+    #[arg(long, value_delimiter = ',')]
+    token_ids: Vec<String>,
+    #[arg(long)]
+    on_sale: Option<bool>,
+    /// Address of the owner
+    #[arg(long)]
+    owner: Option<String>,
+    #[arg(long)]
+    search: Option<String>,
+    #[command(flatten)]
+    page: Page,
+}
+
+#[derive(Subcommand)]
+enum RaribleCommand {
+    /// List items, of all collections unless filtered
+    Items(ItemsArgs),
+    /// List collections
+    Collections(CollectionsArgs),
+    /// List orders, all orders unless filtered
+    Orders(RaribleOrdersArgs),
+    /// List bids of an item or a maker
+    Bids(BidsArgs),
+    /// Search activities, order activities unless `--nft`
+    Activities(ActivitiesArgs),
+}
+
+#[derive(Args)]
+struct Continuation {
+    /// Continuation of the previous page
+    #[arg(long)]
+    continuation: Option<String>,
+    /// Number of results to return
+    #[arg(long)]
+    size: Option<i32>,
+}
+
+#[derive(Args)]
+#[group(multiple = false)]
+struct ItemsBy {
+    /// An item id, `<contract>:<token id>`
+    #[arg(long)]
+    id: Option<String>,
+    /// Items of a collection
+    #[arg(long)]
+    collection: Option<String>,
+    /// Items of an owner
+    #[arg(long)]
+    owner: Option<String>,
+    /// Items of a creator
+    #[arg(long)]
+    creator: Option<String>,
+}
+
+#[derive(Args)]
+struct ItemsArgs {
+    #[command(flatten)]
+    by: ItemsBy,
+    /// Include the items' meta
+    #[arg(long)]
+    meta: bool,
+    /// Include deleted items, only for all items
+    #[arg(long)]
+    show_deleted: bool,
+    /// Only items updated after this unix timestamp in millis, only for all items
+    #[arg(long)]
+    last_updated_from: Option<i64>,
+    /// Only items updated before this unix timestamp in millis, only for all items
+    #[arg(long)]
+    last_updated_to: Option<i64>,
+    #[command(flatten)]
+    continuation: Continuation,
+}
+
+#[derive(Args)]
+#[group(multiple = false)]
+struct CollectionsBy {
+    /// A collection's address
+    #[arg(long)]
+    id: Option<String>,
+    /// Collections of an owner
+    #[arg(long)]
+    owner: Option<String>,
+}
+
+#[derive(Args)]
+struct CollectionsArgs {
+    #[command(flatten)]
+    by: CollectionsBy,
+    #[command(flatten)]
+    continuation: Continuation,
+}
+
+#[derive(Args)]
+#[group(multiple = false)]
+struct OrdersBy {
+    /// An order's hash
+    #[arg(long)]
+    hash: Option<String>,
+    /// Sell orders of a collection
+    #[arg(long)]
+    collection: Option<String>,
+    /// Sell orders of a maker
+    #[arg(long)]
+    maker: Option<String>,
+    /// Sell orders of an item, `<contract>:<token id>`
+    #[arg(long)]
+    item: Option<String>,
+    /// All sell orders
+    #[arg(long)]
+    sell: bool,
+}
+
+#[derive(Args)]
+struct RaribleOrdersArgs {
+    #[command(flatten)]
+    by: OrdersBy,
+    /// Only orders of this origin
+    #[arg(long)]
+    origin: Option<String>,
+    #[command(flatten)]
+    continuation: Continuation,
+}
+
+#[derive(Args)]
+#[group(required = true, multiple = false)]
+struct BidsBy {
+    /// Bids for an item, `<contract>:<token id>`
+    #[arg(long)]
+    item: Option<String>,
+    /// Bids of a maker
+    #[arg(long)]
+    maker: Option<String>,
+}
+
+#[derive(Args)]
+struct BidsArgs {
+    #[command(flatten)]
+    by: BidsBy,
+    /// Only bids of this origin
+    #[arg(long)]
+    origin: Option<String>,
+    #[command(flatten)]
+    continuation: Continuation,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum ActivityType {
+    Transfer,
+    Mint,
+    Burn,
+    Bid,
+    List,
+    Match,
+}
+
+#[derive(Args)]
+#[group(multiple = false)]
+struct ActivitiesBy {
+    /// Activities of a collection
+    #[arg(long)]
+    collection: Option<String>,
+    /// Activities of an item, `<contract>:<token id>`
+    #[arg(long)]
+    item: Option<String>,
+    /// Activities of users, comma separated
+    #[arg(long, value_delimiter = ',')]
+    user: Vec<String>,
+}
+
+#[derive(Args)]
+struct ActivitiesArgs {
+    /// Search transfers, mints and burns instead of order activities
+    #[arg(long)]
+    nft: bool,
+    /// The activities to return, comma separated
+    #[arg(long = "type", value_enum, value_delimiter = ',')]
+    types: Vec<ActivityType>,
+    #[command(flatten)]
+    by: ActivitiesBy,
+    #[command(flatten)]
+    continuation: Continuation,
+}
+
+/// Splits an item id into contract and token id
+fn split_item(item: &str) -> anyhow::Result<(&str, &str)> {
+    let mut parts = item.splitn(2, ':');
+    match (parts.next(), parts.next()) {
+        (Some(contract), Some(token_id)) if !contract.is_empty() && !token_id.is_empty() => {
+            Ok((contract, token_id))
+        }
+        _ => anyhow::bail!("`{}` is not an item id like `<contract>:<token id>`", item),
+    }
+}
+
+fn print_continuation(continuation: Option<&str>) {
+    if let Some(continuation) = continuation {
+        eprintln!("continuation: {}", continuation);
+    }
+}
+
+/// Prints the records in the selected output format
+fn print<R: Record + Serialize>(args: &OutputArgs, records: &[R]) -> anyhow::Result<()> {
+    let stdout = io::stdout();
+    let mut out = BufWriter::new(stdout.lock());
+    match args.output {
+        Output::Json => {
+            writeln!(out, "{}", serde_json::to_string_pretty(records)?)?;
+        }
+        Output::Ndjson => {
+            for record in records {
+                writeln!(out, "{}", serde_json::to_string(record)?)?;
+            }
+        }
+        Output::Table => {
+            let schema = if args.traits {
+                Schema::of_records(records)
+            } else {
+                Schema::of::<R>()
+            };
+            let rows = records.iter().map(|r| schema.row(r)).collect::<Vec<_>>();
+            print_table(&mut out, args, &schema, &rows)?;
+        }
+    }
+    out.flush()?;
+    Ok(())
+}
+
+fn print_table<W: Write>(
+    out: &mut W,
+    args: &OutputArgs,
+    schema: &Schema,
+    rows: &[Vec<Cell>],
+) -> anyhow::Result<()> {
+    let columns = if args.columns.is_empty() {
+        (0..schema.columns().len()).collect::<Vec<_>>()
+    } else {
+        args.columns
+            .iter()
+            .map(|name| {
+                schema
+                    .columns()
+                    .iter()
+                    .position(|c| &c.name == name)
+                    .ok_or_else(|| {
+                        let available = schema
+                            .columns()
+                            .iter()
+                            .map(|c| c.name.as_str())
+                            .collect::<Vec<_>>();
+                        anyhow::anyhow!(
+                            "unknown column `{}`, available: {}",
+                            name,
+                            available.join(", ")
+                        )
+                    })
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?
+    };
+    let cell = |cell: &Cell| {
+        let text = cell.to_string();
+        if args.wide || text.chars().count() <= MAX_CELL_WIDTH {
+            text
+        } else {
+            let mut text = text.chars().take(MAX_CELL_WIDTH - 1).collect::<String>();
+            text.push('…');
+            text
+        }
+    };
+    let header = columns
+        .iter()
+        .map(|idx| schema.columns()[*idx].name.clone())
+        .collect::<Vec<_>>();
+    let body = rows
+        .iter()
+        .map(|row| columns.iter().map(|idx| cell(&row[*idx])).collect())
+        .collect::<Vec<Vec<String>>>();
+    let widths = header
+        .iter()
+        .enumerate()
+        .map(|(idx, name)| {
+            body.iter()
+                .map(|row| row[idx].chars().count())
+                .chain(std::iter::once(name.chars().count()))
+                .max()
+                .unwrap_or_default()
+        })
+        .collect::<Vec<_>>();
+    for row in std::iter::once(&header).chain(&body) {
+        let line = row
+            .iter()
+            .zip(&widths)
+            .map(|(text, width)| {
+                let pad = width - text.chars().count();
+                format!("{}{}", text, " ".repeat(pad))
+            })
+            .collect::<Vec<_>>()
+            .join("  ");
+        writeln!(out, "{}", line.trim_end())?;
+    }
+    Ok(())
+}
+
+async fn run_opensea(
+    client: &ApiClient,
+    output: &OutputArgs,
+    command: OpenSeaCommand,
+) -> anyhow::Result<()> {
+    match command {
+        OpenSeaCommand::Assets(args) => {
+            let mut query = OpenSeaAssetsQuery::default();
+            if let Some(owner) = args.owner {
+                query = query.owner(owner);
+            }
+            if let Some(contract) = args.contract {
+                query = query.asset_contract_address(contract);
+            }
+            if !args.contracts.is_empty() {
+                query = query.asset_contract_addresses(args.contracts);
+            }
+            if !args.token_ids.is_empty() {
+                query = query.token_ids(args.token_ids);
+            }
+            if let Some(search) = args.search {
+                query = query.search(search);
+            }
+            if let Some(order_by) = args.order_by {
+                query = query.order_by(order_by);
+            }
+            if let Some(order_direction) = args.order_direction {
+                query = query.order_direction(order_direction);
+            }
+            if let Some(limit) = args.page.limit {
+                query = query.limit(limit);
+            }
+            if let Some(offset) = args.page.offset {
+                query = query.offset(offset);
+            }
+            print(output, &client.get_assets(&query).await?.assets)
+        }
+        OpenSeaCommand::Asset { contract, token_id } => {
+            let query = match token_id {
+                Some(token_id) => OpenSeaAssetQuery::with_token_id(contract, token_id),
+                None => OpenSeaAssetQuery::new(contract),
+            };
+            let asset = client
+                .get_asset(query)
+                .await?
+                .ok_or_else(|| anyhow::anyhow!("asset not found"))?;
+            print(output, &[asset])
+        }
+        OpenSeaCommand::Orders(args) => {
+            let mut query = OrderQuery::default();
+            if let Some(owner) = args.owner {
+                query = query.owner(owner);
+            }
+            if let Some(side) = args.side {
+                query = query.side(match side {
+                    Side::Buy => OrderSide::Buy,
+                    Side::Sell => OrderSide::Sell,
+                });
+            }
+            if let Some(sale_kind) = args.sale_kind {
+                query = query.sale_kind(match sale_kind {
+                    Sale::Fixed => SaleKind::FixedPrice,
+                    Sale::Dutch => SaleKind::DutchAuction,
+                });
+            }
+            if let Some(contract) = args.contract {
+                query = query.asset_contract_address(contract);
+            }
+            if let Some(payment_token) = args.payment_token {
+                query = query.payment_token_address(payment_token);
+            }
+            if let Some(token_id) = args.token_id {
+                query = query.token_id(token_id);
+            }
+            if !args.token_ids.is_empty() {
+                query = query.token_ids(args.token_ids);
+            }
+            if let Some(listed_after) = args.listed_after {
+                query = query.listed_after(listed_after);
+            }
+            if let Some(listed_before) = args.listed_before {
+                query = query.listed_before(listed_before);
+            }
+            if let Some(is_english) = args.is_english {
+                query = query.is_english(is_english);
+            }
+            if let Some(is_expired) = args.is_expired {
+                query = query.is_expired(is_expired);
+            }
+            if let Some(bundled) = args.bundled {
+                query = query.bundled(bundled);
+            }
+            if args.include_invalid {
+                query = query.include_invalid(true);
+            }
+            if let Some(limit) = args.page.limit {
+                query = query.limit(limit);
+            }
+            if let Some(offset) = args.page.offset {
+                query = query.offset(offset);
+            }
+            print(output, &client.get_orders(&query).await?.orders)
+        }
+        OpenSeaCommand::Bundles(args) => {
+            let mut query = OpenSeaAssetBundleQuery::default();
+            if let Some(contract) = args.contract {
+                query = query.asset_contract_address(contract);
+            }
+            if !args.token_ids.is_empty() {
+                query = query.token_ids(args.token_ids);
+            }
+            if let Some(on_sale) = args.on_sale {
+                query = query.on_sale(on_sale);
+            }
+            if let Some(owner) = args.owner {
+                query = query.owner(owner);
+            }
+            if let Some(search) = args.search {
+                query = query.search(search);
+            }
+            if let Some(limit) = args.page.limit {
+                query = query.limit(limit);
+            }
+            if let Some(offset) = args.page.offset {
+                query = query.offset(offset);
+            }
+            print(output, &client.get_bundles(&query).await?.bundles)
+        }
+        OpenSeaCommand::Tokens { symbol, page } => {
+            let mut query = OpenSeaFungibleTokenQuery::default();
+            if let Some(symbol) = symbol {
+                query = query.symbol(symbol);
+            }
+            if let Some(limit) = page.limit {
+                query = query.limit(limit);
+            }
+            if let Some(offset) = page.offset {
+                query = query.offset(offset);
+            }
+            print(output, &client.get_payment_tokens(&query).await?)
+        }
+    }
+}
+
+async fn run_rarible(
+    client: &ApiClient,
+    output: &OutputArgs,
+    command: RaribleCommand,
+) -> anyhow::Result<()> {
+    match command {
+        RaribleCommand::Items(args) => {
+            let Continuation { continuation, size } = args.continuation;
+            let continuation = continuation.as_deref();
+            let meta = Some(args.meta);
+            let by = args.by;
+            if let Some(id) = by.id {
+                let item = client.get_nft_item_by_id(&id, meta).await?;
+                return print(output, &[item]);
+            }
+            let items = if let Some(collection) = by.collection {
+                client
+                    .get_nft_items_by_collection(&collection, continuation, size, meta)
+                    .await?
+            } else if let Some(owner) = by.owner {
+                client
+                    .get_nft_items_by_owner(&owner, continuation, size, meta)
+                    .await?
+            } else if let Some(creator) = by.creator {
+                client
+                    .get_nft_items_by_creator(&creator, continuation, size, meta)
+                    .await?
+            } else {
+                client
+                    .get_nft_all_items(
+                        continuation,
+                        size,
+                        Some(args.show_deleted),
+                        args.last_updated_from,
+                        args.last_updated_to,
+                        meta,
+                    )
+                    .await?
+            };
+            print(output, &items.items)?;
+            print_continuation(items.continuation.as_deref());
+            Ok(())
+        }
+        RaribleCommand::Collections(args) => {
+            let Continuation { continuation, size } = args.continuation;
+            let continuation = continuation.as_deref();
+            if let Some(id) = args.by.id {
+                let collection = client.get_nft_collection_by_id(&id).await?;
+                return print(output, &[collection]);
+            }
+            let collections = match args.by.owner {
+                Some(owner) => {
+                    client
+                        .search_nft_collections_by_owner(&owner, continuation, size)
+                        .await?
+                }
+                None => {
+                    client
+                        .search_nft_all_collections(continuation, size)
+                        .await?
+                }
+            };
+            print(output, &collections.collections)?;
+            print_continuation(collections.continuation.as_deref());
+            Ok(())
+        }
+        RaribleCommand::Orders(args) => {
+            let Continuation { continuation, size } = args.continuation;
+            let continuation = continuation.as_deref();
+            let origin = args.origin.as_deref();
+            let by = args.by;
+            if let Some(hash) = by.hash {
+                let order = client.get_order_by_hash(&hash).await?;
+                return print(output, &[order]);
+            }
+            let orders = if let Some(collection) = by.collection {
+                client
+                    .get_sell_orders_by_collection(&collection, origin, continuation, size)
+                    .await?
+            } else if let Some(maker) = by.maker {
+                client
+                    .get_sell_orders_by_maker(&maker, origin, continuation, size)
+                    .await?
+            } else if let Some(item) = by.item {
+                let (contract, token_id) = split_item(&item)?;
+                client
+                    .get_sell_orders_by_item(contract, token_id, None, origin, continuation, size)
+                    .await?
+            } else if by.sell {
+                client.get_sell_orders(origin, continuation, size).await?
+            } else {
+                client.get_orders_all(origin, continuation, size).await?
+            };
+            print(output, &orders.orders)?;
+            print_continuation(orders.continuation.as_deref());
+            Ok(())
+        }
+        RaribleCommand::Bids(args) => {
+            let Continuation { continuation, size } = args.continuation;
+            let continuation = continuation.as_deref();
+            let origin = args.origin.as_deref();
+            let bids = match (args.by.item, args.by.maker) {
+                (Some(item), _) => {
+                    let (contract, token_id) = split_item(&item)?;
+                    client
+                        .get_order_bids_by_item(
+                            contract,
+                            token_id,
+                            None,
+                            origin,
+                            continuation,
+                            size,
+                        )
+                        .await?
+                }
+                (None, Some(maker)) => {
+                    client
+                        .get_order_bids_by_maker(&maker, origin, continuation, size)
+                        .await?
+                }
+                (None, None) => anyhow::bail!("either `--item` or `--maker` is required"),
+            };
+            print(output, &bids.orders)?;
+            print_continuation(bids.continuation.as_deref());
+            Ok(())
+        }
+        RaribleCommand::Activities(args) => {
+            let Continuation { continuation, size } = args.continuation;
+            let continuation = continuation.as_deref();
+            let by = args.by;
+            let (_type, contract, token_id) = if let Some(collection) = by.collection {
+                ("by_collection", collection, String::new())
+            } else if let Some(item) = by.item {
+                let (contract, token_id) = split_item(&item)?;
+                ("by_item", contract.to_string(), token_id.to_string())
+            } else if !by.user.is_empty() {
+                ("by_user", String::new(), String::new())
+            } else {
+                ("all", String::new(), String::new())
+            };
+            if args.nft {
+                let mut types = args
+                    .types
+                    .iter()
+                    .map(|t| match t {
+                        ActivityType::Transfer => Ok(ActivityTypes::Transfer),
+                        ActivityType::Mint => Ok(ActivityTypes::Mint),
+                        ActivityType::Burn => Ok(ActivityTypes::Burn),
+                        _ => anyhow::bail!("nft activities are transfers, mints or burns"),
+                    })
+                    .collect::<anyhow::Result<Vec<_>>>()?;
+                if types.is_empty() {
+                    types = vec![
+                        ActivityTypes::Transfer,
+                        ActivityTypes::Mint,
+                        ActivityTypes::Burn,
+                    ];
+                }
+                let filter = NftActivityFilter {
+                    _type: _type.to_string(),
+                    types,
+                    users: by.user,
+                    contract,
+                    token_id,
+                };
+                let activities = client
+                    .get_nft_activities(filter, continuation, size)
+                    .await?;
+                print(output, &activities.items)?;
+                print_continuation(activities.continuation.as_deref());
+            } else {
+                let mut types = args
+                    .types
+                    .iter()
+                    .map(|t| match t {
+                        ActivityType::Bid => Ok(OrderActivityTypes::Bid),
+                        ActivityType::List => Ok(OrderActivityTypes::List),
+                        ActivityType::Match => Ok(OrderActivityTypes::_Match),
+                        _ => anyhow::bail!(
+                            "order activities are bids, lists or matches, use `--nft` for transfers, mints and burns"
+                        ),
+                    })
+                    .collect::<anyhow::Result<Vec<_>>>()?;
+                if types.is_empty() {
+                    types = vec![
+                        OrderActivityTypes::List,
+                        OrderActivityTypes::Bid,
+                        OrderActivityTypes::_Match,
+                    ];
+                }
+                let filter = OrderActivityFilter {
+                    _type: _type.to_string(),
+                    types,
+                    users: by.user,
+                    contract,
+                    token_id,
+                };
+                let activities = client
+                    .get_order_activities(filter, continuation, size)
+                    .await?;
+                print(output, &activities.items)?;
+                print_continuation(activities.continuation.as_deref());
+            }
+            Ok(())
+        }
+    }
+}
+
+async fn run(cli: Cli) -> anyhow::Result<()> {
+    match cli.command {
+        Command::Opensea(command) => {
+            let mut builder = ApiClient::builder();
+            if let Some(key) = cli.opensea_api_key {
+                builder.headers_mut().insert("X-API-KEY", key.parse()?);
+            }
+            let client = builder.build(cli.opensea_url.as_str())?;
+            run_opensea(&client, &cli.output, command).await
+        }
+        Command::Rarible(command) => {
+            let client = ApiClient::builder().build(cli.rarible_url.as_str())?;
+            run_rarible(&client, &cli.output, command).await
+        }
+    }
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    match run(Cli::parse()).await {
+        // the output was piped into something like `head` that exited early
+        Err(err)
+            if err
+                .downcast_ref::<io::Error>()
+                .map(|err| err.kind() == io::ErrorKind::BrokenPipe)
+                .unwrap_or_default() =>
+        {
+            Ok(())
+        }
+        res => res,
+    }
+}
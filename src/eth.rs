@@ -0,0 +1,319 @@
+//! Ethereum primitives used to hash, encode and verify marketplace orders
+//! locally.
+
+use std::collections::BTreeSet;
+use std::str::FromStr;
+
+use ethabi::Token;
+use serde_json::Value;
+use tiny_keccak::{Hasher, Keccak};
+
+pub use ethereum_types::{Address, H256, U256};
+
+/// The type of the EIP-712 domain used by both marketplaces
+pub const EIP712_DOMAIN_TYPE: &str =
+    "EIP712Domain(string name,string version,uint256 chainId,address verifyingContract)";
+
+/// Computes the keccak256 hash of `data`
+pub fn keccak256(data: impl AsRef<[u8]>) -> [u8; 32] {
+    let mut hasher = Keccak::v256();
+    hasher.update(data.as_ref());
+    let mut out = [0u8; 32];
+    hasher.finalize(&mut out);
+    out
+}
+
+/// The first four bytes of the keccak256 hash of `data`, used for function
+/// selectors and Rarible's asset classes
+pub fn id(data: impl AsRef<[u8]>) -> [u8; 4] {
+    let hash = keccak256(data);
+    [hash[0], hash[1], hash[2], hash[3]]
+}
+
+/// Decodes a hex string with or without `0x` prefix
+pub fn decode_hex(s: impl AsRef<str>) -> anyhow::Result<Vec<u8>> {
+    let s = s.as_ref();
+    let s = s.strip_prefix("0x").unwrap_or(s);
+    Ok(hex::decode(s)?)
+}
+
+/// Encodes bytes as `0x` prefixed hex string
+pub fn encode_hex(data: impl AsRef<[u8]>) -> String {
+    format!("0x{}", hex::encode(data))
+}
+
+pub fn parse_address(s: impl AsRef<str>) -> anyhow::Result<Address> {
+    let s = s.as_ref();
+    let bytes = decode_hex(s)?;
+    anyhow::ensure!(bytes.len() == 20, "`{}` is not an address", s);
+    Ok(Address::from_slice(&bytes))
+}
+
+/// Parses a decimal or `0x` prefixed hex number
+pub fn parse_u256(s: impl AsRef<str>) -> anyhow::Result<U256> {
+    let s = s.as_ref().trim();
+    if let Some(hex) = s.strip_prefix("0x") {
+        Ok(U256::from_str(hex)?)
+    } else {
+        Ok(U256::from_dec_str(s)?)
+    }
+}
+
+/// Hashes the message the way `eth_sign` does, by prefixing it with
+/// `"\x19Ethereum Signed Message:\n" + len(message)`
+pub fn hash_message(message: impl AsRef<[u8]>) -> H256 {
+    let message = message.as_ref();
+    let mut data = format!("\x19Ethereum Signed Message:\n{}", message.len()).into_bytes();
+    data.extend_from_slice(message);
+    H256(keccak256(data))
+}
+
+/// A random 256 bit salt for new orders.
+///
+/// Hashes the time, a counter and the process' random hasher keys, which is
+/// unpredictable enough to keep otherwise equal orders apart.
+pub fn random_salt() -> U256 {
+    use std::collections::hash_map::RandomState;
+    use std::hash::{BuildHasher, Hasher as _};
+    use std::sync::atomic::{AtomicU64, Ordering};
+
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u64(COUNTER.fetch_add(1, Ordering::Relaxed));
+    let nanos = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or_default();
+    let mut data = nanos.to_be_bytes().to_vec();
+    data.extend_from_slice(&hasher.finish().to_be_bytes());
+    data.extend_from_slice(&std::process::id().to_be_bytes());
+    U256::from_big_endian(&keccak256(data))
+}
+
+/// Computes the EIP-712 domain separator
+pub fn domain_separator(
+    name: &str,
+    version: &str,
+    chain_id: U256,
+    verifying_contract: Address,
+) -> H256 {
+    H256(keccak256(ethabi::encode(&[
+        Token::FixedBytes(keccak256(EIP712_DOMAIN_TYPE).to_vec()),
+        Token::FixedBytes(keccak256(name).to_vec()),
+        Token::FixedBytes(keccak256(version).to_vec()),
+        Token::Uint(chain_id),
+        Token::Address(verifying_contract),
+    ])))
+}
+
+/// The digest that is signed for an EIP-712 struct:
+/// `keccak256("\x19\x01" ‖ domainSeparator ‖ hashStruct(message))`
+pub fn eip712_digest(domain_separator: H256, struct_hash: H256) -> H256 {
+    let mut data = Vec::with_capacity(66);
+    data.extend_from_slice(b"\x19\x01");
+    data.extend_from_slice(domain_separator.as_bytes());
+    data.extend_from_slice(struct_hash.as_bytes());
+    H256(keccak256(data))
+}
+
+/// Hashes the struct tokens according to EIP-712: `keccak256(typeHash ‖
+/// encodeData(s))`, where all dynamic members must already be hashed.
+pub fn hash_struct(type_hash: [u8; 32], members: Vec<Token>) -> H256 {
+    let mut tokens = Vec::with_capacity(members.len() + 1);
+    tokens.push(Token::FixedBytes(type_hash.to_vec()));
+    tokens.extend(members);
+    H256(keccak256(ethabi::encode(&tokens)))
+}
+
+/// Hashes a struct described by a JSON `types` definition as it is used by
+/// `eth_signTypedData`, e.g. the typed data returned by a marketplace.
+///
+/// `types` maps struct names to arrays of `{ "name": .., "type": .. }` members.
+pub fn hash_typed_struct(primary_type: &str, types: &Value, data: &Value) -> anyhow::Result<H256> {
+    let mut members = Vec::new();
+    for (name, ty) in struct_members(primary_type, types)? {
+        let value = data.get(&name).unwrap_or(&Value::Null);
+        members.push(encode_typed_value(&ty, types, value)?);
+    }
+    Ok(hash_struct(
+        keccak256(encode_type(primary_type, types)?),
+        members,
+    ))
+}
+
+/// The encoded type of the struct: `Name(type member,..)` followed by all
+/// referenced structs sorted by name
+pub fn encode_type(primary_type: &str, types: &Value) -> anyhow::Result<String> {
+    let mut deps = BTreeSet::new();
+    collect_dependencies(primary_type, types, &mut deps)?;
+    deps.remove(primary_type);
+
+    let mut encoded = String::new();
+    for ty in std::iter::once(primary_type).chain(deps.iter().map(String::as_str)) {
+        let members = struct_members(ty, types)?
+            .into_iter()
+            .map(|(name, ty)| format!("{} {}", ty, name))
+            .collect::<Vec<_>>()
+            .join(",");
+        encoded.push_str(&format!("{}({})", ty, members));
+    }
+    Ok(encoded)
+}
+
+fn struct_members(name: &str, types: &Value) -> anyhow::Result<Vec<(String, String)>> {
+    let members = types
+        .get(name)
+        .and_then(Value::as_array)
+        .ok_or_else(|| anyhow::anyhow!("missing type definition for `{}`", name))?;
+    members
+        .iter()
+        .map(|member| {
+            let name = member.get("name").and_then(Value::as_str);
+            let ty = member.get("type").and_then(Value::as_str);
+            match (name, ty) {
+                (Some(name), Some(ty)) => Ok((name.to_string(), ty.to_string())),
+                _ => anyhow::bail!("invalid member `{}`", member),
+            }
+        })
+        .collect()
+}
+
+fn collect_dependencies(
+    name: &str,
+    types: &Value,
+    deps: &mut BTreeSet<String>,
+) -> anyhow::Result<()> {
+    if deps.contains(name) || types.get(name).is_none() {
+        return Ok(());
+    }
+    deps.insert(name.to_string());
+    for (_, ty) in struct_members(name, types)? {
+        collect_dependencies(ty.trim_end_matches("[]"), types, deps)?;
+    }
+    Ok(())
+}
+
+fn encode_typed_value(ty: &str, types: &Value, value: &Value) -> anyhow::Result<Token> {
+    if let Some(inner) = ty.strip_suffix("[]") {
+        let items = value
+            .as_array()
+            .ok_or_else(|| anyhow::anyhow!("expected array for `{}`", ty))?;
+        let mut encoded = Vec::with_capacity(items.len() * 32);
+        for item in items {
+            match encode_typed_value(inner, types, item)? {
+                Token::FixedBytes(bytes) => encoded.extend(bytes),
+                token => encoded.extend(ethabi::encode(&[token])),
+            }
+        }
+        return Ok(Token::FixedBytes(keccak256(encoded).to_vec()));
+    }
+    if types.get(ty).is_some() {
+        return Ok(Token::FixedBytes(
+            hash_typed_struct(ty, types, value)?.as_bytes().to_vec(),
+        ));
+    }
+    let token = match ty {
+        "address" => Token::Address(parse_address(json_str(value)?)?),
+        "bool" => Token::Bool(value.as_bool().unwrap_or_default()),
+        "string" => Token::FixedBytes(keccak256(json_str(value)?).to_vec()),
+        "bytes" => Token::FixedBytes(keccak256(decode_hex(json_str(value)?)?).to_vec()),
+        ty if ty.starts_with("uint") || ty.starts_with("int") => Token::Uint(json_u256(value)?),
+        ty if ty.starts_with("bytes") => {
+            let mut bytes = decode_hex(json_str(value)?)?;
+            bytes.resize(32, 0);
+            Token::FixedBytes(bytes)
+        }
+        ty => anyhow::bail!("unsupported type `{}`", ty),
+    };
+    Ok(token)
+}
+
+fn json_str(value: &Value) -> anyhow::Result<&str> {
+    value
+        .as_str()
+        .ok_or_else(|| anyhow::anyhow!("expected string, got `{}`", value))
+}
+
+fn json_u256(value: &Value) -> anyhow::Result<U256> {
+    match value {
+        Value::Number(n) => n
+            .as_u64()
+            .map(U256::from)
+            .ok_or_else(|| anyhow::anyhow!("`{}` is not an unsigned integer", n)),
+        Value::String(s) => parse_u256(s),
+        Value::Null => Ok(U256::zero()),
+        value => anyhow::bail!("expected number, got `{}`", value),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    /// The `Mail` example of the EIP-712 specification
+    fn mail() -> (Value, Value) {
+        let types = json!({
+            "Person": [
+                { "name": "name", "type": "string" },
+                { "name": "wallet", "type": "address" }
+            ],
+            "Mail": [
+                { "name": "from", "type": "Person" },
+                { "name": "to", "type": "Person" },
+                { "name": "contents", "type": "string" }
+            ]
+        });
+        let message = json!({
+            "from": { "name": "Cow", "wallet": "0xCD2a3d9F938E13CD947Ec05AbC7FE734Df8DD826" },
+            "to": { "name": "Bob", "wallet": "0xbBbBBBBbbBBBbbbBbbBbbbbBBbBbbbbBbBbbBBbB" },
+            "contents": "Hello, Bob!"
+        });
+        (types, message)
+    }
+
+    fn hash(s: &str) -> H256 {
+        H256::from_slice(&decode_hex(s).unwrap())
+    }
+
+    #[test]
+    fn encodes_the_type_with_its_dependencies() {
+        let (types, _) = mail();
+        assert_eq!(
+            encode_type("Mail", &types).unwrap(),
+            "Mail(Person from,Person to,string contents)Person(string name,address wallet)"
+        );
+    }
+
+    #[test]
+    fn hashes_the_eip712_example() {
+        let (types, message) = mail();
+        let domain = domain_separator(
+            "Ether Mail",
+            "1",
+            U256::one(),
+            parse_address("0xCcCCccccCCCCcCCCCCCcCcCccCcCCCcCcccccccC").unwrap(),
+        );
+        assert_eq!(
+            domain,
+            hash("0xf2cee375fa42b42143804025fc449deafd50cc031ca257e0b194a650a912090f")
+        );
+        let struct_hash = hash_typed_struct("Mail", &types, &message).unwrap();
+        assert_eq!(
+            struct_hash,
+            hash("0xc52c0ee5d84264471806290a3f2c4cecfc5490626bf912d01f240d7a274b371e")
+        );
+        assert_eq!(
+            eip712_digest(domain, struct_hash),
+            hash("0xbe609aee343fb3c4b28e1df9e632fca64fcfaede20f02e86244efddf30957bd2")
+        );
+    }
+
+    #[test]
+    fn hashes_messages_like_eth_sign() {
+        assert_eq!(
+            hash_message("Some data"),
+            hash("0x1da44b586eb0729ff70a73c326926f6ed5a25f5b056e7f47fbc6e58d86871655")
+        );
+    }
+}
@@ -0,0 +1,1330 @@
+//! Flat exports of assets, orders and activities as CSV, NDJSON or Parquet.
+//!
+//! Every exported type implements [`Record`], which flattens the nested
+//! models into a fixed list of columns: the owner's address instead of the
+//! owner account, the collection's slug, prices as exact decimals in the
+//! unit of their currency. The columns only depend on the type, so exports
+//! of the same type always share the same schema, fields the API did not
+//! report are written as nulls.
+//!
+//! Traits (OpenSea) and attributes (Rarible) vary per item, they are only
+//! exported as `trait.<name>` columns if they were added to the [`Schema`]
+//! up front, either by name or collected from the records.
+//!
+//! Decimals keep their exact value in CSV and NDJSON. Parquet stores them as
+//! `DECIMAL(38, 18)`, which Arrow reads as `Decimal128`, so every decimal
+//! column has the same type: values are exact up to 18 decimals, finer ones
+//! are rounded, and values with more than 20 integer digits fail the export.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::io::Write;
+use std::path::Path;
+use std::str::FromStr;
+
+use futures::{pin_mut, Stream, StreamExt};
+use serde::Serialize;
+
+use crate::market::models::{opensea_nft, parse_decimal, Amount};
+#[cfg(feature = "rarible")]
+use crate::market::models::{rarible_currency, rarible_nft, RARIBLE_CURRENCY_DECIMALS};
+use crate::opensea::models::{
+    self as opensea, AssetEvent, OpenSeaAsset, OpenSeaAssetBundle, OpenSeaFungibleToken,
+};
+#[cfg(feature = "rarible")]
+use crate::rarible::models::{
+    self as rarible, Activity, NftActivity, NftCollection, NftItem, OrderActivity,
+};
+
+/// Prefix of the columns the traits are exported as
+pub const TRAIT_PREFIX: &str = "trait.";
+
+/// The type of a column
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+pub enum ColumnType {
+    Bool,
+    Int,
+    /// An exact decimal number, like a price in ETH
+    Decimal,
+    Text,
+}
+
+/// A named, typed column of an export
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+pub struct Column {
+    pub name: String,
+    pub kind: ColumnType,
+}
+
+impl Column {
+    pub fn new(name: impl Into<String>, kind: ColumnType) -> Self {
+        Self {
+            name: name.into(),
+            kind,
+        }
+    }
+}
+
+/// A single value of a row
+#[derive(Clone, Debug, PartialEq)]
+pub enum Cell {
+    Null,
+    Bool(bool),
+    Int(i64),
+    Decimal(Amount),
+    Text(String),
+}
+
+impl Cell {
+    /// A text cell, `Null` for `None`
+    fn text<T: ToString>(value: Option<T>) -> Self {
+        value
+            .map(|v| Cell::Text(v.to_string()))
+            .unwrap_or(Cell::Null)
+    }
+
+    /// A lowercased address cell, `Null` for `None`
+    fn address(value: Option<&str>) -> Self {
+        value
+            .map(|v| Cell::Text(v.to_lowercase()))
+            .unwrap_or(Cell::Null)
+    }
+
+    /// Integer amounts like quantities or supplies
+    fn integer(value: Option<&str>) -> Self {
+        value
+            .and_then(|v| Amount::from_base_units(v, 0).ok())
+            .map(Cell::Decimal)
+            .unwrap_or(Cell::Null)
+    }
+
+    /// Amounts in base units of a token with `decimals`
+    fn base_units(value: Option<&str>, decimals: u32) -> Self {
+        value
+            .and_then(|v| Amount::from_base_units(v, decimals).ok())
+            .map(Cell::Decimal)
+            .unwrap_or(Cell::Null)
+    }
+
+    /// Human readable decimals like `"0.25"` or `"1.0E-4"`
+    fn decimal(value: Option<&str>) -> Self {
+        value
+            .and_then(parse_decimal)
+            .and_then(Amount::from_decimal)
+            .map(Cell::Decimal)
+            .unwrap_or(Cell::Null)
+    }
+
+    /// The serialized name of a unit enum variant
+    fn label<T: Serialize>(value: &T) -> Self {
+        match serde_json::to_value(value) {
+            Ok(serde_json::Value::String(s)) => Cell::Text(s),
+            _ => Cell::Null,
+        }
+    }
+
+    pub fn is_null(&self) -> bool {
+        matches!(self, Cell::Null)
+    }
+}
+
+impl fmt::Display for Cell {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Cell::Null => Ok(()),
+            Cell::Bool(b) => write!(f, "{}", b),
+            Cell::Int(i) => write!(f, "{}", i),
+            Cell::Decimal(d) => write!(f, "{}", d),
+            Cell::Text(s) => f.write_str(s),
+        }
+    }
+}
+
+/// A type that can be exported as a flat row
+pub trait Record {
+    /// The columns of this type, the same for every record
+    fn columns() -> Vec<Column>;
+
+    /// The values of the [`Record::columns`], in the same order
+    fn cells(&self) -> Vec<Cell>;
+
+    /// The `(name, value)` pairs of the traits of this record
+    fn traits(&self) -> Vec<(String, String)> {
+        Vec::new()
+    }
+}
+
+/// The columns of an export: the columns of the record type followed by the
+/// selected trait columns.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Schema {
+    columns: Vec<Column>,
+    traits: Vec<String>,
+}
+
+impl Schema {
+    /// The schema of `R` without any trait columns
+    pub fn of<R: Record>() -> Self {
+        Self {
+            columns: R::columns(),
+            traits: Vec::new(),
+        }
+    }
+
+    /// The schema of `R` with a column for every trait that appears in
+    /// `records`, sorted by name.
+    pub fn of_records<'a, R, I>(records: I) -> Self
+    where
+        R: Record + 'a,
+        I: IntoIterator<Item = &'a R>,
+    {
+        let traits = records
+            .into_iter()
+            .flat_map(|r| r.traits().into_iter().map(|(name, _)| name))
+            .collect::<BTreeSet<_>>();
+        Self::of::<R>().with_traits(traits)
+    }
+
+    /// Adds a `trait.<name>` column for each trait, names that are already
+    /// part of the schema are skipped.
+    pub fn with_traits<I, S>(mut self, traits: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        for name in traits {
+            let name = name.into();
+            if !self.traits.contains(&name) {
+                self.columns.push(Column::new(
+                    format!("{}{}", TRAIT_PREFIX, name),
+                    ColumnType::Text,
+                ));
+                self.traits.push(name);
+            }
+        }
+        self
+    }
+
+    pub fn columns(&self) -> &[Column] {
+        &self.columns
+    }
+
+    /// The traits that are exported as columns
+    pub fn traits(&self) -> &[String] {
+        &self.traits
+    }
+
+    /// Flattens the record into a row of this schema.
+    ///
+    /// Traits without a column are dropped, multiple values of the same
+    /// trait are joined with `|`.
+    pub fn row<R: Record>(&self, record: &R) -> Vec<Cell> {
+        let mut row = record.cells();
+        if self.traits.is_empty() {
+            return row;
+        }
+        let mut values: BTreeMap<String, Vec<String>> = BTreeMap::new();
+        for (name, value) in record.traits() {
+            values.entry(name).or_default().push(value);
+        }
+        row.extend(self.traits.iter().map(|name| {
+            values
+                .get(name)
+                .map(|v| Cell::Text(v.join("|")))
+                .unwrap_or(Cell::Null)
+        }));
+        row
+    }
+}
+
+/// The file formats records can be exported as
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+pub enum Format {
+    Csv,
+    /// Newline delimited json, one object per record
+    Ndjson,
+    #[cfg(feature = "parquet-export")]
+    Parquet,
+}
+
+impl Format {
+    /// The format of a path by its extension
+    pub fn from_path(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let ext = path
+            .as_ref()
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .ok_or_else(|| anyhow::anyhow!("{} has no extension", path.as_ref().display()))?;
+        ext.parse()
+    }
+}
+
+impl FromStr for Format {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "csv" => Ok(Format::Csv),
+            "ndjson" | "jsonl" => Ok(Format::Ndjson),
+            #[cfg(feature = "parquet-export")]
+            "parquet" => Ok(Format::Parquet),
+            _ => anyhow::bail!("unsupported export format `{}`", s),
+        }
+    }
+}
+
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Format::Csv => f.write_str("csv"),
+            Format::Ndjson => f.write_str("ndjson"),
+            #[cfg(feature = "parquet-export")]
+            Format::Parquet => f.write_str("parquet"),
+        }
+    }
+}
+
+enum Sink<W: Write + Send> {
+    Csv(Box<csv::Writer<W>>),
+    Ndjson(W),
+    #[cfg(feature = "parquet-export")]
+    Parquet(Box<parquet_sink::ParquetSink<W>>),
+}
+
+/// Writes records of a [`Schema`] in one of the supported [`Format`]s.
+///
+/// The output is only complete after [`Exporter::finish`].
+pub struct Exporter<W: Write + Send> {
+    schema: Schema,
+    sink: Sink<W>,
+    rows: usize,
+}
+
+impl<W: Write + Send> Exporter<W> {
+    /// Creates an exporter and writes the header, if the format has one
+    pub fn new(format: Format, schema: Schema, writer: W) -> anyhow::Result<Self> {
+        let sink = match format {
+            Format::Csv => {
+                let mut csv = csv::Writer::from_writer(writer);
+                csv.write_record(schema.columns.iter().map(|c| c.name.as_str()))?;
+                Sink::Csv(Box::new(csv))
+            }
+            Format::Ndjson => Sink::Ndjson(writer),
+            #[cfg(feature = "parquet-export")]
+            Format::Parquet => {
+                Sink::Parquet(Box::new(parquet_sink::ParquetSink::new(&schema, writer)?))
+            }
+        };
+        Ok(Self {
+            schema,
+            sink,
+            rows: 0,
+        })
+    }
+
+    pub fn schema(&self) -> &Schema {
+        &self.schema
+    }
+
+    /// The number of records written so far
+    pub fn rows(&self) -> usize {
+        self.rows
+    }
+
+    pub fn write<R: Record>(&mut self, record: &R) -> anyhow::Result<()> {
+        let row = self.schema.row(record);
+        match &mut self.sink {
+            Sink::Csv(csv) => csv.write_record(row.iter().map(|cell| cell.to_string()))?,
+            Sink::Ndjson(writer) => {
+                let mut line = String::from("{");
+                for (idx, (column, cell)) in self.schema.columns.iter().zip(&row).enumerate() {
+                    if idx > 0 {
+                        line.push(',');
+                    }
+                    line.push_str(&serde_json::to_string(&column.name)?);
+                    line.push(':');
+                    match cell {
+                        Cell::Null => line.push_str("null"),
+                        Cell::Text(s) => line.push_str(&serde_json::to_string(s)?),
+                        cell => line.push_str(&cell.to_string()),
+                    }
+                }
+                line.push_str("}\n");
+                writer.write_all(line.as_bytes())?;
+            }
+            #[cfg(feature = "parquet-export")]
+            Sink::Parquet(parquet) => parquet.push(row)?,
+        }
+        self.rows += 1;
+        Ok(())
+    }
+
+    /// Writes all records and returns how many were written
+    pub fn write_all<'a, R, I>(&mut self, records: I) -> anyhow::Result<usize>
+    where
+        R: Record + 'a,
+        I: IntoIterator<Item = &'a R>,
+    {
+        let mut written = 0;
+        for record in records {
+            self.write(record)?;
+            written += 1;
+        }
+        Ok(written)
+    }
+
+    /// Writes the records of the stream until it ends or yields an error
+    pub async fn write_stream<R, S>(&mut self, stream: S) -> anyhow::Result<usize>
+    where
+        R: Record,
+        S: Stream<Item = anyhow::Result<R>>,
+    {
+        pin_mut!(stream);
+        let mut written = 0;
+        while let Some(record) = stream.next().await {
+            self.write(&record?)?;
+            written += 1;
+        }
+        Ok(written)
+    }
+
+    /// Flushes the remaining rows, writes the footer if the format has one
+    /// and returns the writer.
+    pub fn finish(self) -> anyhow::Result<W> {
+        match self.sink {
+            Sink::Csv(csv) => csv
+                .into_inner()
+                .map_err(|err| anyhow::anyhow!("failed to flush csv: {}", err.error())),
+            Sink::Ndjson(mut writer) => {
+                writer.flush()?;
+                Ok(writer)
+            }
+            #[cfg(feature = "parquet-export")]
+            Sink::Parquet(parquet) => parquet.finish(),
+        }
+    }
+}
+
+#[cfg(feature = "parquet-export")]
+mod parquet_sink {
+    use std::io::Write;
+    use std::sync::Arc;
+
+    use ethereum_types::U256;
+    use parquet::basic::{LogicalType, Repetition, Type as PhysicalType};
+    use parquet::column::writer::ColumnWriter;
+    use parquet::data_type::{ByteArray, FixedLenByteArray};
+    use parquet::file::properties::WriterProperties;
+    use parquet::file::writer::SerializedFileWriter;
+    use parquet::schema::types::Type;
+
+    use super::{Amount, Cell, ColumnType, Schema};
+
+    /// Rows are buffered and written as a row group once this many are
+    /// collected.
+    const ROW_GROUP_SIZE: usize = 8192;
+
+    /// Digits of the decimal columns, the most a 16 byte `Decimal128` holds
+    pub(super) const DECIMAL_PRECISION: i32 = 38;
+
+    /// Decimals of the decimal columns, those of ETH
+    pub(super) const DECIMAL_SCALE: i32 = 18;
+
+    /// The amount rescaled to [`DECIMAL_SCALE`] as 16 big-endian bytes,
+    /// rounding half up
+    pub(super) fn decimal(amount: &Amount) -> anyhow::Result<FixedLenByteArray> {
+        let scale = DECIMAL_SCALE as u32;
+        let value = if amount.decimals <= scale {
+            amount
+                .value
+                .checked_mul(U256::exp10((scale - amount.decimals) as usize))
+        } else {
+            let divisor = U256::exp10((amount.decimals - scale) as usize);
+            let half = divisor / 2;
+            (amount.value / divisor).checked_add(U256::from((amount.value % divisor >= half) as u8))
+        };
+        let value = value
+            .filter(|value| *value < U256::exp10(DECIMAL_PRECISION as usize))
+            .ok_or_else(|| {
+                anyhow::anyhow!(
+                    "{} does not fit into a decimal({}, {})",
+                    amount,
+                    DECIMAL_PRECISION,
+                    DECIMAL_SCALE
+                )
+            })?;
+        Ok(FixedLenByteArray::from(
+            value.as_u128().to_be_bytes().to_vec(),
+        ))
+    }
+
+    pub(super) struct ParquetSink<W: Write + Send> {
+        writer: SerializedFileWriter<W>,
+        kinds: Vec<ColumnType>,
+        rows: Vec<Vec<Cell>>,
+    }
+
+    impl<W: Write + Send> ParquetSink<W> {
+        pub(super) fn new(schema: &Schema, writer: W) -> anyhow::Result<Self> {
+            let fields = schema
+                .columns()
+                .iter()
+                .map(|column| {
+                    let builder = match column.kind {
+                        ColumnType::Bool => {
+                            Type::primitive_type_builder(&column.name, PhysicalType::BOOLEAN)
+                        }
+                        ColumnType::Int => {
+                            Type::primitive_type_builder(&column.name, PhysicalType::INT64)
+                        }
+                        ColumnType::Decimal => Type::primitive_type_builder(
+                            &column.name,
+                            PhysicalType::FIXED_LEN_BYTE_ARRAY,
+                        )
+                        .with_length(16)
+                        .with_logical_type(Some(LogicalType::Decimal {
+                            scale: DECIMAL_SCALE,
+                            precision: DECIMAL_PRECISION,
+                        }))
+                        .with_precision(DECIMAL_PRECISION)
+                        .with_scale(DECIMAL_SCALE),
+                        ColumnType::Text => {
+                            Type::primitive_type_builder(&column.name, PhysicalType::BYTE_ARRAY)
+                                .with_logical_type(Some(LogicalType::String))
+                        }
+                    };
+                    builder
+                        .with_repetition(Repetition::OPTIONAL)
+                        .build()
+                        .map(Arc::new)
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            let root = Type::group_type_builder("schema")
+                .with_fields(fields)
+                .build()?;
+            let writer = SerializedFileWriter::new(
+                writer,
+                Arc::new(root),
+                Arc::new(WriterProperties::builder().build()),
+            )?;
+            Ok(Self {
+                writer,
+                kinds: schema.columns().iter().map(|c| c.kind).collect(),
+                rows: Vec::new(),
+            })
+        }
+
+        pub(super) fn push(&mut self, row: Vec<Cell>) -> anyhow::Result<()> {
+            self.rows.push(row);
+            if self.rows.len() >= ROW_GROUP_SIZE {
+                self.flush()?;
+            }
+            Ok(())
+        }
+
+        fn flush(&mut self) -> anyhow::Result<()> {
+            if self.rows.is_empty() {
+                return Ok(());
+            }
+            let rows = std::mem::take(&mut self.rows);
+            let mut group = self.writer.next_row_group()?;
+            let mut idx = 0;
+            while let Some(mut column) = group.next_column()? {
+                let cells = rows.iter().map(|row| &row[idx]);
+                let levels = cells
+                    .clone()
+                    .map(|cell| if cell.is_null() { 0 } else { 1 })
+                    .collect::<Vec<i16>>();
+                match (column.untyped(), self.kinds[idx]) {
+                    (ColumnWriter::BoolColumnWriter(w), ColumnType::Bool) => {
+                        let values = cells
+                            .filter_map(|cell| match cell {
+                                Cell::Bool(b) => Some(*b),
+                                _ => None,
+                            })
+                            .collect::<Vec<_>>();
+                        w.write_batch(&values, Some(&levels), None)?;
+                    }
+                    (ColumnWriter::Int64ColumnWriter(w), ColumnType::Int) => {
+                        let values = cells
+                            .filter_map(|cell| match cell {
+                                Cell::Int(i) => Some(*i),
+                                _ => None,
+                            })
+                            .collect::<Vec<_>>();
+                        w.write_batch(&values, Some(&levels), None)?;
+                    }
+                    (ColumnWriter::FixedLenByteArrayColumnWriter(w), ColumnType::Decimal) => {
+                        let values = cells
+                            .filter_map(|cell| match cell {
+                                Cell::Decimal(amount) => Some(decimal(amount)),
+                                _ => None,
+                            })
+                            .collect::<anyhow::Result<Vec<_>>>()?;
+                        w.write_batch(&values, Some(&levels), None)?;
+                    }
+                    (ColumnWriter::ByteArrayColumnWriter(w), ColumnType::Text) => {
+                        let values = cells
+                            .filter(|cell| !cell.is_null())
+                            .map(|cell| ByteArray::from(cell.to_string().into_bytes()))
+                            .collect::<Vec<_>>();
+                        w.write_batch(&values, Some(&levels), None)?;
+                    }
+                    (_, kind) => anyhow::bail!("unexpected parquet writer for {:?} column", kind),
+                }
+                column.close()?;
+                idx += 1;
+            }
+            group.close()?;
+            Ok(())
+        }
+
+        pub(super) fn finish(mut self) -> anyhow::Result<W> {
+            self.flush()?;
+            Ok(self.writer.into_inner()?)
+        }
+    }
+}
+
+fn trait_value(value: &serde_json::Value) -> Option<String> {
+    match value {
+        serde_json::Value::Null => None,
+        serde_json::Value::String(s) => Some(s.clone()),
+        value => Some(value.to_string()),
+    }
+}
+
+impl Record for OpenSeaAsset {
+    fn columns() -> Vec<Column> {
+        use ColumnType::*;
+        vec![
+            Column::new("id", Int),
+            Column::new("contract", Text),
+            Column::new("token_id", Text),
+            Column::new("name", Text),
+            Column::new("collection", Text),
+            Column::new("owner", Text),
+            Column::new("creator", Text),
+            Column::new("num_sales", Int),
+            Column::new("last_sale_price", Decimal),
+            Column::new("last_sale_currency", Text),
+            Column::new("last_sale_date", Text),
+            Column::new("listing_price", Decimal),
+            Column::new("listing_currency", Text),
+            Column::new("permalink", Text),
+            Column::new("image_url", Text),
+        ]
+    }
+
+    fn cells(&self) -> Vec<Cell> {
+        let last_sale = self.last_sale.as_ref();
+        let last_sale_token = last_sale.and_then(|sale| sale.payment_token.as_ref());
+        // the cheapest sell order, if the asset is listed
+        let listing = self
+            .sell_orders
+            .iter()
+            .flatten()
+            .filter_map(|order| {
+                let decimals = order.payment_token_contract.decimals as u32;
+                Amount::from_base_units(&order.current_price, decimals)
+                    .ok()
+                    .map(|price| (price, order))
+            })
+            .min_by(|(a, _), (b, _)| a.to_f64().total_cmp(&b.to_f64()));
+        vec![
+            self.id.map(Cell::Int).unwrap_or(Cell::Null),
+            Cell::address(Some(&self.asset_contract.address)),
+            Cell::text(self.token_id.as_ref()),
+            Cell::text(self.name.as_ref()),
+            Cell::Text(self.collection.slug.clone()),
+            Cell::address(Some(&self.owner.address)),
+            Cell::address(self.creator.as_ref().map(|c| c.address.as_str())),
+            Cell::Int(self.num_sales),
+            Cell::base_units(
+                last_sale.map(|sale| sale.total_price.as_str()),
+                last_sale_token.map(|token| token.decimals).unwrap_or(18),
+            ),
+            Cell::text(last_sale_token.and_then(|token| token.symbol.as_ref())),
+            Cell::text(last_sale.map(|sale| &sale.event_timestamp)),
+            listing
+                .as_ref()
+                .map(|(price, _)| Cell::Decimal(*price))
+                .unwrap_or(Cell::Null),
+            Cell::text(
+                listing
+                    .as_ref()
+                    .map(|(_, order)| &order.payment_token_contract.symbol),
+            ),
+            Cell::Text(self.permalink.clone()),
+            Cell::Text(self.image_url.clone()),
+        ]
+    }
+
+    fn traits(&self) -> Vec<(String, String)> {
+        self.traits
+            .iter()
+            .flatten()
+            .filter_map(|t| {
+                let value = t.value.as_ref().and_then(trait_value)?;
+                Some((t.trait_type.clone(), value))
+            })
+            .collect()
+    }
+}
+
+impl Record for opensea::Order {
+    fn columns() -> Vec<Column> {
+        use ColumnType::*;
+        vec![
+            Column::new("hash", Text),
+            Column::new("side", Text),
+            Column::new("sale_kind", Int),
+            Column::new("contract", Text),
+            Column::new("token_id", Text),
+            Column::new("quantity", Decimal),
+            Column::new("maker", Text),
+            Column::new("taker", Text),
+            Column::new("price", Decimal),
+            Column::new("base_price", Decimal),
+            Column::new("currency", Text),
+            Column::new("payment_token", Text),
+            Column::new("created_date", Text),
+            Column::new("listing_time", Int),
+            Column::new("expiration_time", Int),
+            Column::new("cancelled", Bool),
+            Column::new("finalized", Bool),
+            Column::new("marked_invalid", Bool),
+        ]
+    }
+
+    fn cells(&self) -> Vec<Cell> {
+        let nft = opensea_nft(self).ok();
+        let decimals = self.payment_token_contract.decimals as u32;
+        let side = if self.side == opensea::OrderSide::Sell as i64 {
+            "sell"
+        } else {
+            "buy"
+        };
+        vec![
+            Cell::Text(self.order_hash.clone()),
+            Cell::Text(side.to_string()),
+            Cell::Int(self.sale_kind),
+            Cell::text(nft.as_ref().map(|(nft, _)| &nft.contract)),
+            Cell::text(nft.as_ref().map(|(nft, _)| &nft.token_id)),
+            nft.map(|(_, quantity)| Cell::Decimal(Amount::new(quantity, 0)))
+                .unwrap_or(Cell::Null),
+            Cell::address(Some(&self.maker.address)),
+            Cell::address(Some(&self.taker.address)),
+            Cell::base_units(Some(&self.current_price), decimals),
+            Cell::base_units(Some(&self.base_price), decimals),
+            Cell::Text(self.payment_token_contract.symbol.clone()),
+            Cell::address(Some(&self.payment_token)),
+            Cell::Text(self.created_date.clone()),
+            Cell::Int(self.listing_time),
+            Cell::Int(self.expiration_time),
+            Cell::Bool(self.cancelled),
+            Cell::Bool(self.finalized),
+            Cell::Bool(self.marked_invalid),
+        ]
+    }
+}
+
+impl Record for OpenSeaAssetBundle {
+    fn columns() -> Vec<Column> {
+        use ColumnType::*;
+        vec![
+            Column::new("slug", Text),
+            Column::new("name", Text),
+            Column::new("maker", Text),
+            Column::new("contract", Text),
+            Column::new("asset_count", Int),
+            Column::new("token_ids", Text),
+            Column::new("listing_price", Decimal),
+            Column::new("listing_currency", Text),
+            Column::new("permalink", Text),
+        ]
+    }
+
+    fn cells(&self) -> Vec<Cell> {
+        let listing = self.sell_orders.iter().flatten().find_map(|order| {
+            let decimals = order.payment_token_contract.decimals as u32;
+            Amount::from_base_units(&order.current_price, decimals)
+                .ok()
+                .map(|price| (price, order))
+        });
+        let token_ids = self
+            .assets
+            .iter()
+            .filter_map(|asset| asset.token_id.as_deref())
+            .collect::<Vec<_>>();
+        vec![
+            Cell::Text(self.slug.clone()),
+            Cell::Text(self.name.clone()),
+            Cell::address(Some(&self.maker.address)),
+            Cell::address(self.asset_contract.as_ref().map(|c| c.address.as_str())),
+            Cell::Int(self.assets.len() as i64),
+            Cell::Text(token_ids.join("|")),
+            listing
+                .as_ref()
+                .map(|(price, _)| Cell::Decimal(*price))
+                .unwrap_or(Cell::Null),
+            Cell::text(
+                listing
+                    .as_ref()
+                    .map(|(_, order)| &order.payment_token_contract.symbol),
+            ),
+            Cell::Text(self.permalink.clone()),
+        ]
+    }
+}
+
+impl Record for OpenSeaFungibleToken {
+    fn columns() -> Vec<Column> {
+        use ColumnType::*;
+        vec![
+            Column::new("symbol", Text),
+            Column::new("name", Text),
+            Column::new("address", Text),
+            Column::new("decimals", Int),
+            Column::new("eth_price", Decimal),
+            Column::new("usd_price", Decimal),
+        ]
+    }
+
+    fn cells(&self) -> Vec<Cell> {
+        vec![
+            Cell::text(self.symbol.as_ref()),
+            Cell::text(self.name.as_ref()),
+            Cell::address(Some(&self.address)),
+            Cell::Int(self.decimals as i64),
+            Cell::decimal(self.eth_price.as_deref()),
+            Cell::decimal(self.usd_price.as_deref()),
+        ]
+    }
+}
+
+impl Record for AssetEvent {
+    fn columns() -> Vec<Column> {
+        use ColumnType::*;
+        vec![
+            Column::new("id", Int),
+            Column::new("event_type", Text),
+            Column::new("created_date", Text),
+            Column::new("collection", Text),
+            Column::new("contract", Text),
+            Column::new("token_id", Text),
+            Column::new("quantity", Decimal),
+            Column::new("total_price", Decimal),
+            Column::new("bid_amount", Decimal),
+            Column::new("starting_price", Decimal),
+            Column::new("ending_price", Decimal),
+            Column::new("currency", Text),
+            Column::new("seller", Text),
+            Column::new("winner", Text),
+            Column::new("from", Text),
+            Column::new("to", Text),
+            Column::new("transaction_hash", Text),
+        ]
+    }
+
+    fn cells(&self) -> Vec<Cell> {
+        let token = self.payment_token.as_ref();
+        let decimals = token.map(|token| token.decimals).unwrap_or(18);
+        let account = |account: &Option<opensea::OpenSeaAccount>| {
+            Cell::address(account.as_ref().map(|a| a.address.as_str()))
+        };
+        vec![
+            Cell::Int(self.id),
+            Cell::label(&self.event_type),
+            Cell::Text(self.created_date.clone()),
+            Cell::text(self.collection_slug.as_ref()),
+            Cell::address(self.contract_address.as_deref()),
+            Cell::text(self.asset.as_ref().map(|asset| &asset.token_id)),
+            Cell::integer(self.quantity.as_deref()),
+            Cell::base_units(self.total_price.as_deref(), decimals),
+            Cell::base_units(self.bid_amount.as_deref(), decimals),
+            Cell::base_units(self.starting_price.as_deref(), decimals),
+            Cell::base_units(self.ending_price.as_deref(), decimals),
+            Cell::text(token.and_then(|token| token.symbol.as_ref())),
+            account(&self.seller),
+            account(&self.winner_account),
+            account(&self.from_account),
+            account(&self.to_account),
+            Cell::text(self.transaction.as_ref().map(|tx| &tx.transaction_hash)),
+        ]
+    }
+}
+
+#[cfg(feature = "rarible")]
+impl Record for NftItem {
+    fn columns() -> Vec<Column> {
+        use ColumnType::*;
+        vec![
+            Column::new("id", Text),
+            Column::new("contract", Text),
+            Column::new("token_id", Text),
+            Column::new("name", Text),
+            Column::new("creator", Text),
+            Column::new("supply", Decimal),
+            Column::new("lazy_supply", Decimal),
+            Column::new("owner_count", Int),
+            Column::new("owners", Text),
+            Column::new("royalties_bps", Int),
+            Column::new("date", Text),
+            Column::new("deleted", Bool),
+        ]
+    }
+
+    fn cells(&self) -> Vec<Cell> {
+        vec![
+            Cell::Text(self.id.clone()),
+            Cell::address(Some(&self.contract)),
+            Cell::Text(self.token_id.clone()),
+            Cell::text(self.meta.as_ref().map(|meta| &meta.name)),
+            Cell::address(self.creators.first().map(|c| c.account.as_str())),
+            Cell::integer(Some(&self.supply)),
+            Cell::integer(Some(&self.lazy_supply)),
+            Cell::Int(self.owners.len() as i64),
+            Cell::Text(self.owners.join("|").to_lowercase()),
+            Cell::Int(self.royalties.iter().map(|r| r.value as i64).sum()),
+            Cell::text(self.date.as_ref()),
+            Cell::Bool(self.deleted.unwrap_or_default()),
+        ]
+    }
+
+    fn traits(&self) -> Vec<(String, String)> {
+        self.meta
+            .iter()
+            .flat_map(|meta| meta.attributes.iter().flatten())
+            .filter_map(|attr| Some((attr.key.clone(), attr.value.clone()?)))
+            .collect()
+    }
+}
+
+#[cfg(feature = "rarible")]
+impl Record for NftCollection {
+    fn columns() -> Vec<Column> {
+        use ColumnType::*;
+        vec![
+            Column::new("id", Text),
+            Column::new("type", Text),
+            Column::new("name", Text),
+            Column::new("symbol", Text),
+            Column::new("owner", Text),
+            Column::new("features", Text),
+        ]
+    }
+
+    fn cells(&self) -> Vec<Cell> {
+        let features = self
+            .features
+            .iter()
+            .filter_map(|feature| match Cell::label(feature) {
+                Cell::Text(feature) => Some(feature),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        vec![
+            Cell::address(Some(&self.id)),
+            Cell::label(&self._type),
+            Cell::Text(self.name.clone()),
+            Cell::text(self.symbol.as_ref()),
+            Cell::address(self.owner.as_deref()),
+            Cell::Text(features.join("|")),
+        ]
+    }
+}
+
+#[cfg(feature = "rarible")]
+impl Record for rarible::Order {
+    fn columns() -> Vec<Column> {
+        use ColumnType::*;
+        vec![
+            Column::new("hash", Text),
+            Column::new("type", Text),
+            Column::new("side", Text),
+            Column::new("contract", Text),
+            Column::new("token_id", Text),
+            Column::new("quantity", Decimal),
+            Column::new("maker", Text),
+            Column::new("taker", Text),
+            Column::new("price", Decimal),
+            Column::new("currency", Text),
+            Column::new("price_usd", Decimal),
+            Column::new("fill", Decimal),
+            Column::new("make_stock", Decimal),
+            Column::new("start", Int),
+            Column::new("end", Int),
+            Column::new("cancelled", Bool),
+            Column::new("created_at", Text),
+            Column::new("last_update_at", Text),
+        ]
+    }
+
+    fn cells(&self) -> Vec<Cell> {
+        // sell orders make the nft, bids take it
+        let (side, nft, nft_side, payment, price_usd) = match rarible_nft(&self.make.asset_type) {
+            Some(nft) => (
+                "sell",
+                Some(nft),
+                &self.make,
+                &self.take,
+                &self.take_price_usd,
+            ),
+            None => (
+                "buy",
+                rarible_nft(&self.take.asset_type),
+                &self.take,
+                &self.make,
+                &self.make_price_usd,
+            ),
+        };
+        let currency = rarible_currency(&payment.asset_type);
+        vec![
+            Cell::Text(self.hash.clone()),
+            Cell::Text(self._type.to_string()),
+            Cell::Text(side.to_string()),
+            Cell::text(nft.as_ref().map(|nft| &nft.contract)),
+            Cell::text(nft.as_ref().map(|nft| &nft.token_id)),
+            Cell::integer(Some(&nft_side.value)),
+            Cell::address(Some(&self.maker)),
+            Cell::address(self.taker.as_deref()),
+            Cell::base_units(Some(&payment.value), RARIBLE_CURRENCY_DECIMALS),
+            Cell::text(currency),
+            Cell::decimal(price_usd.as_deref()),
+            Cell::integer(Some(&self.fill)),
+            Cell::integer(Some(&self.make_stock)),
+            self.start.map(Cell::Int).unwrap_or(Cell::Null),
+            self.end.map(Cell::Int).unwrap_or(Cell::Null),
+            Cell::Bool(self.cancelled),
+            Cell::Text(self.created_at.clone()),
+            Cell::Text(self.last_update_at.clone()),
+        ]
+    }
+}
+
+#[cfg(feature = "rarible")]
+impl Record for NftActivity {
+    fn columns() -> Vec<Column> {
+        use ColumnType::*;
+        vec![
+            Column::new("type", Text),
+            Column::new("contract", Text),
+            Column::new("token_id", Text),
+            Column::new("value", Decimal),
+            Column::new("from", Text),
+            Column::new("owner", Text),
+            Column::new("transaction_hash", Text),
+            Column::new("block_number", Int),
+            Column::new("log_index", Int),
+        ]
+    }
+
+    fn cells(&self) -> Vec<Cell> {
+        vec![
+            Cell::Text(self._type.clone()),
+            Cell::address(Some(&self.contract)),
+            Cell::Text(self.token_id.clone()),
+            Cell::integer(Some(&self.value)),
+            Cell::address(Some(&self.from)),
+            Cell::address(Some(&self.owner)),
+            Cell::Text(self.transaction_hash.clone()),
+            Cell::Int(self.block_number),
+            Cell::Int(self.log_index as i64),
+        ]
+    }
+}
+
+/// The columns shared by the match activities
+#[cfg(feature = "rarible")]
+fn match_columns() -> Vec<Column> {
+    use ColumnType::*;
+    vec![
+        Column::new("id", Text),
+        Column::new("type", Text),
+        Column::new("date", Text),
+        Column::new("contract", Text),
+        Column::new("token_id", Text),
+        Column::new("price", Decimal),
+        Column::new("price_usd", Decimal),
+        Column::new("left_maker", Text),
+        Column::new("right_maker", Text),
+        Column::new("maker", Text),
+        Column::new("transaction_hash", Text),
+        Column::new("block_number", Int),
+        Column::new("log_index", Int),
+    ]
+}
+
+#[cfg(feature = "rarible")]
+#[allow(clippy::too_many_arguments)]
+fn match_cells(
+    id: &str,
+    _type: &str,
+    date: &str,
+    make: &rarible::AssetType,
+    take: &rarible::AssetType,
+    price: &str,
+    price_usd: Option<&str>,
+    left: &rarible::OrderActivityMatchSide,
+    right: &rarible::OrderActivityMatchSide,
+    maker: &str,
+    transaction_hash: &str,
+    block_number: i64,
+    log_index: i32,
+) -> Vec<Cell> {
+    let nft = rarible_nft(make).or_else(|| rarible_nft(take));
+    vec![
+        Cell::Text(id.to_string()),
+        Cell::Text(_type.to_string()),
+        Cell::Text(date.to_string()),
+        Cell::text(nft.as_ref().map(|nft| &nft.contract)),
+        Cell::text(nft.as_ref().map(|nft| &nft.token_id)),
+        Cell::decimal(Some(price)),
+        Cell::decimal(price_usd),
+        Cell::address(Some(&left.maker)),
+        Cell::address(Some(&right.maker)),
+        Cell::address(Some(maker)),
+        Cell::Text(transaction_hash.to_string()),
+        Cell::Int(block_number),
+        Cell::Int(log_index as i64),
+    ]
+}
+
+#[cfg(feature = "rarible")]
+impl Record for OrderActivity {
+    fn columns() -> Vec<Column> {
+        let mut columns = match_columns();
+        columns.push(Column::new("source", ColumnType::Text));
+        columns
+    }
+
+    fn cells(&self) -> Vec<Cell> {
+        let mut cells = match_cells(
+            &self.id,
+            &self._type,
+            &self.date,
+            &self.make,
+            &self.take,
+            &self.price,
+            self.price_usd.as_deref(),
+            &self.left,
+            &self.right,
+            &self.maker,
+            &self.transaction_hash,
+            self.block_number,
+            self.log_index,
+        );
+        cells.push(Cell::label(&self.source));
+        cells
+    }
+}
+
+#[cfg(feature = "rarible")]
+impl Record for Activity {
+    fn columns() -> Vec<Column> {
+        let mut columns = match_columns();
+        columns.push(Column::new("from", ColumnType::Text));
+        columns
+    }
+
+    fn cells(&self) -> Vec<Cell> {
+        let mut cells = match_cells(
+            &self.id,
+            &self._type,
+            &self.date,
+            &self.make,
+            &self.take,
+            &self.price,
+            self.price_usd.as_deref(),
+            &self.left,
+            &self.right,
+            &self.maker,
+            &self.transaction_hash,
+            self.block_number,
+            self.log_index,
+        );
+        cells.push(Cell::address(Some(&self.from)));
+        cells
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ASSET: &str = include_str!("../examples/responses/opensea-asset.json");
+    const ORDERBOOK: &str = include_str!("../examples/responses/opensea-orderbook.json");
+
+    fn orders() -> Vec<opensea::Order> {
+        let book: opensea::OrderBook = serde_json::from_str(ORDERBOOK).unwrap();
+        book.orders.into_iter().take(2).collect()
+    }
+
+    fn export<R: Record>(format: Format, schema: Schema, records: &[R]) -> Vec<u8> {
+        let mut exporter = Exporter::new(format, schema, Vec::new()).unwrap();
+        assert_eq!(exporter.write_all(records).unwrap(), records.len());
+        assert_eq!(exporter.rows(), records.len());
+        exporter.finish().unwrap()
+    }
+
+    #[test]
+    fn exports_csv() {
+        let csv = export(Format::Csv, Schema::of::<opensea::Order>(), &orders());
+        assert_eq!(
+            String::from_utf8(csv).unwrap(),
+            include_str!("../tests/fixtures/export/opensea-orders.csv")
+        );
+    }
+
+    #[test]
+    fn exports_ndjson_with_traits() {
+        let asset: OpenSeaAsset = serde_json::from_str(ASSET).unwrap();
+        let schema = Schema::of_records(std::iter::once(&asset));
+        assert_eq!(schema.traits(), ["accessory", "type"]);
+        let ndjson = export(Format::Ndjson, schema, &[asset]);
+        assert_eq!(
+            String::from_utf8(ndjson).unwrap(),
+            include_str!("../tests/fixtures/export/opensea-asset.ndjson")
+        );
+    }
+
+    #[cfg(feature = "rarible")]
+    #[test]
+    fn exports_ndjson_of_rarible_orders() {
+        // constructed, see `rarible::eip712`
+        let order: rarible::Order =
+            serde_json::from_str(include_str!("../tests/fixtures/rarible-order.json")).unwrap();
+        let ndjson = export(Format::Ndjson, Schema::of::<rarible::Order>(), &[order]);
+        assert_eq!(
+            String::from_utf8(ndjson).unwrap(),
+            include_str!("../tests/fixtures/export/rarible-order.ndjson")
+        );
+    }
+
+    #[cfg(feature = "parquet-export")]
+    #[test]
+    fn exports_parquet() {
+        use parquet::basic::{LogicalType, Type as PhysicalType};
+        use parquet::file::reader::{FileReader, SerializedFileReader};
+
+        let asset: OpenSeaAsset = serde_json::from_str(ASSET).unwrap();
+        let schema = Schema::of_records(std::iter::once(&asset));
+        let path =
+            std::env::temp_dir().join(format!("nftscape-export-{}.parquet", std::process::id()));
+        let file = std::fs::File::create(&path).unwrap();
+        let mut exporter = Exporter::new(Format::Parquet, schema, file).unwrap();
+        exporter.write(&asset).unwrap();
+        exporter.finish().unwrap();
+
+        let reader = SerializedFileReader::new(std::fs::File::open(&path).unwrap()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let descr = reader.metadata().file_metadata().schema_descr_ptr();
+        let price = descr.column(8);
+        assert_eq!(price.name(), "last_sale_price");
+        assert_eq!(price.physical_type(), PhysicalType::FIXED_LEN_BYTE_ARRAY);
+        assert_eq!(
+            price.logical_type(),
+            Some(LogicalType::Decimal {
+                scale: 18,
+                precision: 38
+            })
+        );
+        assert_eq!(descr.column(0).physical_type(), PhysicalType::INT64);
+        assert_eq!(descr.column(1).logical_type(), Some(LogicalType::String));
+
+        let rows = reader
+            .get_row_iter(None)
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(rows.len(), 1);
+        let fields = rows[0]
+            .get_column_iter()
+            .map(|(name, field)| (name.as_str(), field.to_string()))
+            .collect::<Vec<_>>();
+        let field = |name: &str| {
+            fields
+                .iter()
+                .find(|(column, _)| *column == name)
+                .map(|(_, field)| field.as_str())
+                .unwrap()
+        };
+        assert_eq!(field("id"), "176533");
+        assert_eq!(field("collection"), "\"cryptopunks\"");
+        assert_eq!(field("num_sales"), "3");
+        assert_eq!(field("last_sale_price"), "0.000000000000000000");
+        assert_eq!(field("listing_price"), "null");
+        assert_eq!(
+            field("trait.accessory"),
+            "\"Green Eye Shadow|Earring|Blonde Bob\""
+        );
+        assert_eq!(fields.len(), 17);
+    }
+
+    #[cfg(feature = "parquet-export")]
+    #[test]
+    fn exports_parquet_decimals_at_a_fixed_scale() {
+        use parquet::file::reader::{FileReader, SerializedFileReader};
+        use std::convert::TryInto;
+
+        let path =
+            std::env::temp_dir().join(format!("nftscape-orders-{}.parquet", std::process::id()));
+        let file = std::fs::File::create(&path).unwrap();
+        let mut exporter =
+            Exporter::new(Format::Parquet, Schema::of::<opensea::Order>(), file).unwrap();
+        exporter.write_all(&orders()).unwrap();
+        exporter.finish().unwrap();
+        let reader = SerializedFileReader::new(std::fs::File::open(&path).unwrap()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let values = reader
+            .get_row_iter(None)
+            .unwrap()
+            .map(|row| {
+                let row = row.unwrap();
+                let field = |idx: usize| row.get_column_iter().nth(idx).unwrap().1.to_string();
+                (field(5), field(8), field(15))
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            values,
+            vec![
+                (
+                    "1.000000000000000000".to_string(),
+                    "2.508000000000000000".to_string(),
+                    "false".to_string()
+                ),
+                (
+                    "1.000000000000000000".to_string(),
+                    "1.123000000000000000".to_string(),
+                    "false".to_string()
+                ),
+            ]
+        );
+
+        // amounts with more decimals are rounded, too large ones fail
+        let decimal = |value: u64, decimals: u32| {
+            let bytes = parquet_sink::decimal(&Amount::new(value, decimals)).unwrap();
+            u128::from_be_bytes(bytes.data().try_into().unwrap())
+        };
+        assert_eq!(decimal(1, 0), 10u128.pow(18));
+        assert_eq!(decimal(2_508, 3), 2_508 * 10u128.pow(15));
+        assert_eq!(decimal(14, 19), 1);
+        assert_eq!(decimal(15, 19), 2);
+        assert_eq!(decimal(15, 21), 0);
+
+        let mut order = orders().remove(0);
+        order.current_price = format!("1{}", "0".repeat(38));
+        order.payment_token_contract.decimals = 18;
+        let mut exporter =
+            Exporter::new(Format::Parquet, Schema::of::<opensea::Order>(), Vec::new()).unwrap();
+        exporter.write(&order).unwrap();
+        let err = exporter.finish().unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("does not fit into a decimal(38, 18)"),
+            "{}",
+            err
+        );
+    }
+}
@@ -10,13 +10,30 @@ use std::io::Write;
 use std::path::PathBuf;
 use std::sync::Arc;
 
+pub mod eth;
+pub mod market;
 pub mod opensea;
 
 #[cfg(feature = "rarible")]
 pub mod rarible;
 
+#[cfg(feature = "rpc")]
+pub mod rpc;
+
+#[cfg(feature = "signer")]
+pub mod signer;
+
+#[cfg(feature = "store")]
+pub mod store;
+
+#[cfg(feature = "export")]
+pub mod export;
+
 mod error;
 
+#[cfg(test)]
+mod mock;
+
 #[derive(Clone)]
 pub struct ApiClient {
     /// The client that executes the http requests
@@ -54,7 +71,6 @@ impl ApiClient {
         }
     }
 
-    #[allow(unused)]
     async fn post_json<T, U, B, E>(&self, url: U, body: Option<&B>) -> anyhow::Result<T>
     where
         T: DeserializeOwned,
@@ -110,10 +126,12 @@ impl ApiClient {
 
 #[derive(Default, Debug, Clone)]
 pub struct ApiClientBuilder {
+    #[allow(unused)]
     base_path: Option<Url>,
     user_agent: Option<String>,
     client: Option<Arc<reqwest::Client>>,
     headers: Option<HeaderMap>,
+    #[allow(unused)]
     download_dir: Option<PathBuf>,
 }
 
@@ -0,0 +1,901 @@
+//! Rule based alerts on marketplace events.
+//!
+//! OpenSea orders and events, and Rarible activities are normalized into
+//! [`MarketEvent`]s and evaluated against the [`AlertRule`]s of an
+//! [`AlertEngine`]. Every rule that matches an event triggers an [`Alert`]
+//! that is sent to all [`AlertSink`]s of the engine.
+//!
+//! Rarible order activities are only supported for matches, i.e. sales:
+//! [`OrderActivity`] requires both
+//! sides of a match, so Rarible listings and bids are skipped with a warning
+//! and rules on listings or bids only fire for OpenSea orders and events.
+//!
+//! None of the sources carry the traits of the traded token, rules with
+//! trait filters only match tokens whose traits were passed to the engine,
+//! see [`AlertEngine::traits`].
+//!
+//! Rules relative to the floor use the [`SharedFloors`] of the engine. Listings
+//! below a known floor lower it as they are observed, any other change, like
+//! the floor listing being sold, must be set by whatever tracks the floors,
+//! e.g. a [`StatsTracker`](crate::market::stats::StatsTracker) polling next to
+//! the engine.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::convert::TryFrom;
+use std::fmt;
+use std::io::Write;
+use std::sync::{Arc, RwLock};
+
+use chrono::{DateTime, Utc};
+use ethereum_types::U256;
+use futures::channel::mpsc;
+use futures::future::BoxFuture;
+use futures::{FutureExt, Stream, StreamExt};
+use log::warn;
+use reqwest::{IntoUrl, Url};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+
+use crate::market::models::{
+    opensea_date, opensea_nft, parse_decimal, timestamp, Amount, Currency, Marketplace, NftId,
+    Trade, NULL_ADDRESS,
+};
+use crate::market::rarity::TokenTraits;
+use crate::opensea::models::{self as opensea, AssetEvent, AssetEventType, OrderSide};
+#[cfg(feature = "watch")]
+use crate::opensea::watch::Change;
+#[cfg(feature = "rarible")]
+use crate::rarible::models::{Activity, NftActivity, OrderActivity};
+
+/// What happened on a marketplace
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Serialize, Deserialize)]
+pub enum EventKind {
+    Listing,
+    Bid,
+    Sale,
+    /// A listing or bid was cancelled
+    Cancel,
+    Transfer,
+    Mint,
+    Burn,
+}
+
+impl fmt::Display for EventKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EventKind::Listing => f.write_str("listing"),
+            EventKind::Bid => f.write_str("bid"),
+            EventKind::Sale => f.write_str("sale"),
+            EventKind::Cancel => f.write_str("cancel"),
+            EventKind::Transfer => f.write_str("transfer"),
+            EventKind::Mint => f.write_str("mint"),
+            EventKind::Burn => f.write_str("burn"),
+        }
+    }
+}
+
+/// A marketplace event rules are evaluated against
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct MarketEvent {
+    pub kind: EventKind,
+    pub marketplace: Marketplace,
+    /// Order hash, event or activity id on the marketplace
+    pub id: String,
+    pub nft: Option<NftId>,
+    /// Price of a single unit in ETH, `None` if the event has no price or
+    /// its currency can't be converted
+    pub price: Option<Decimal>,
+    pub currency: Option<Currency>,
+    /// The maker, seller or sender, lowercase
+    pub from: Option<String>,
+    /// The buyer or receiver, lowercase
+    pub to: Option<String>,
+    pub timestamp: Option<DateTime<Utc>>,
+}
+
+impl MarketEvent {
+    /// The wallets involved in the event
+    pub fn wallets(&self) -> impl Iterator<Item = &str> {
+        self.from.iter().chain(self.to.iter()).map(String::as_str)
+    }
+}
+
+/// Converts the unit price into ETH, with the ETH price of the currency if
+/// it isn't ether
+fn eth_price(price: Option<Decimal>, currency: &Currency, rate: Option<&str>) -> Option<Decimal> {
+    if currency.is_ether() {
+        price
+    } else {
+        Some(price? * parse_decimal(rate?)?)
+    }
+}
+
+fn address(address: &str) -> Option<String> {
+    let address = address.trim().to_lowercase();
+    if address.is_empty() || address == NULL_ADDRESS {
+        None
+    } else {
+        Some(address)
+    }
+}
+
+impl TryFrom<&opensea::Order> for MarketEvent {
+    type Error = anyhow::Error;
+
+    fn try_from(order: &opensea::Order) -> Result<Self, Self::Error> {
+        let (nft, quantity) = opensea_nft(order)?;
+        let token = &order.payment_token_contract;
+        let currency = Currency::from_address(&order.payment_token);
+        let price = Amount::from_base_units(&order.current_price, token.decimals as u32)?
+            .per_unit(quantity)
+            .to_decimal();
+        let (kind, from, to) = if order.cancelled || order.marked_invalid {
+            (EventKind::Cancel, address(&order.maker.address), None)
+        } else if order.side == OrderSide::Sell as i64 {
+            (
+                EventKind::Listing,
+                address(&order.maker.address),
+                address(&order.taker.address),
+            )
+        } else {
+            (
+                EventKind::Bid,
+                address(&order.maker.address),
+                address(&order.taker.address),
+            )
+        };
+        Ok(Self {
+            kind,
+            marketplace: Marketplace::OpenSea,
+            id: order.order_hash.clone(),
+            nft: Some(nft),
+            price: eth_price(price, &currency, Some(&token.eth_price)),
+            currency: Some(currency),
+            from,
+            to,
+            timestamp: timestamp(order.listing_time),
+        })
+    }
+}
+
+#[cfg(feature = "watch")]
+impl TryFrom<&Change<opensea::Order>> for MarketEvent {
+    type Error = anyhow::Error;
+
+    fn try_from(change: &Change<opensea::Order>) -> Result<Self, Self::Error> {
+        let mut event = Self::try_from(change.item())?;
+        if let Change::Cancelled(_) = change {
+            event.kind = EventKind::Cancel;
+            event.to = None;
+        }
+        Ok(event)
+    }
+}
+
+#[cfg(feature = "watch")]
+impl TryFrom<&Change<AssetEvent>> for MarketEvent {
+    type Error = anyhow::Error;
+
+    fn try_from(change: &Change<AssetEvent>) -> Result<Self, Self::Error> {
+        Self::try_from(change.item())
+    }
+}
+
+impl TryFrom<&AssetEvent> for MarketEvent {
+    type Error = anyhow::Error;
+
+    fn try_from(event: &AssetEvent) -> Result<Self, Self::Error> {
+        let account = |account: &Option<opensea::OpenSeaAccount>| {
+            account.as_ref().and_then(|a| address(&a.address))
+        };
+        let (kind, price, from, to) = match event.event_type {
+            AssetEventType::Created => (
+                EventKind::Listing,
+                event.starting_price.as_deref(),
+                account(&event.seller),
+                None,
+            ),
+            AssetEventType::Successful => (
+                EventKind::Sale,
+                event.total_price.as_deref(),
+                account(&event.seller),
+                account(&event.winner_account),
+            ),
+            AssetEventType::BidEntered | AssetEventType::OfferEntered => (
+                EventKind::Bid,
+                event.bid_amount.as_deref(),
+                account(&event.from_account),
+                None,
+            ),
+            AssetEventType::Cancelled => (
+                EventKind::Cancel,
+                None,
+                account(&event.seller).or_else(|| account(&event.from_account)),
+                None,
+            ),
+            AssetEventType::BidWithdrawn => {
+                (EventKind::Cancel, None, account(&event.from_account), None)
+            }
+            AssetEventType::Transfer => (
+                EventKind::Transfer,
+                None,
+                account(&event.from_account),
+                account(&event.to_account),
+            ),
+            AssetEventType::Approve | AssetEventType::Other => {
+                anyhow::bail!("{:?} events are not supported", event.event_type)
+            }
+        };
+        let nft = match (event.contract_address.as_ref(), event.asset.as_ref()) {
+            (Some(contract), Some(asset)) => Some(NftId::new(contract, asset.token_id.clone())),
+            _ => None,
+        };
+        let token = event.payment_token.as_ref();
+        let currency = token.map(|token| Currency::from_address(&token.address));
+        let quantity = event
+            .quantity
+            .as_deref()
+            .and_then(|quantity| U256::from_dec_str(quantity).ok())
+            .unwrap_or_else(U256::one);
+        let price = match (price, token, currency.as_ref()) {
+            (Some(price), Some(token), Some(currency)) => {
+                let price = Amount::from_base_units(price, token.decimals)?
+                    .per_unit(quantity)
+                    .to_decimal();
+                eth_price(price, currency, token.eth_price.as_deref())
+            }
+            _ => None,
+        };
+        Ok(Self {
+            kind,
+            marketplace: Marketplace::OpenSea,
+            id: event.id.to_string(),
+            nft,
+            price,
+            currency,
+            from,
+            to,
+            timestamp: opensea_date(&event.created_date),
+        })
+    }
+}
+
+impl From<&Trade> for MarketEvent {
+    fn from(trade: &Trade) -> Self {
+        Self {
+            kind: EventKind::Sale,
+            marketplace: trade.marketplace,
+            id: trade.id.clone(),
+            nft: Some(trade.nft.clone()),
+            price: trade.price,
+            currency: Some(trade.currency.clone()),
+            from: address(&trade.seller),
+            to: address(&trade.buyer),
+            timestamp: Some(trade.timestamp),
+        }
+    }
+}
+
+#[cfg(feature = "rarible")]
+impl TryFrom<&OrderActivity> for MarketEvent {
+    type Error = anyhow::Error;
+
+    fn try_from(activity: &OrderActivity) -> Result<Self, Self::Error> {
+        Ok(Self::from(&Trade::try_from(activity)?))
+    }
+}
+
+#[cfg(feature = "rarible")]
+impl TryFrom<&Activity> for MarketEvent {
+    type Error = anyhow::Error;
+
+    fn try_from(activity: &Activity) -> Result<Self, Self::Error> {
+        Ok(Self::from(&Trade::try_from(activity)?))
+    }
+}
+
+#[cfg(feature = "rarible")]
+impl TryFrom<&NftActivity> for MarketEvent {
+    type Error = anyhow::Error;
+
+    fn try_from(activity: &NftActivity) -> Result<Self, Self::Error> {
+        let (kind, from, to) = match activity._type.to_lowercase().as_str() {
+            "mint" => (EventKind::Mint, None, address(&activity.owner)),
+            "burn" => (EventKind::Burn, address(&activity.owner), None),
+            "transfer" => (
+                EventKind::Transfer,
+                address(&activity.from),
+                address(&activity.owner),
+            ),
+            other => anyhow::bail!("{} activities are not supported", other),
+        };
+        Ok(MarketEvent {
+            kind,
+            marketplace: Marketplace::Rarible,
+            id: format!("{}:{}", activity.transaction_hash, activity.log_index),
+            nft: Some(NftId::new(&activity.contract, activity.token_id.clone())),
+            price: None,
+            currency: None,
+            from,
+            to,
+            timestamp: None,
+        })
+    }
+}
+
+impl TryFrom<&MarketEvent> for MarketEvent {
+    type Error = anyhow::Error;
+
+    fn try_from(event: &MarketEvent) -> Result<Self, Self::Error> {
+        Ok(event.clone())
+    }
+}
+
+/// A condition on the ETH price of an event
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum PriceCondition {
+    Below(Decimal),
+    Above(Decimal),
+    /// Below the floor times the factor, `0.9` is 10% below the floor
+    BelowFloor(Decimal),
+    /// Above the floor times the factor, `1` is any price above the floor
+    AboveFloor(Decimal),
+}
+
+impl PriceCondition {
+    /// Whether the price matches, conditions relative to the floor never
+    /// match if the floor is unknown
+    pub fn matches(&self, price: Decimal, floor: Option<Decimal>) -> bool {
+        match *self {
+            PriceCondition::Below(limit) => price < limit,
+            PriceCondition::Above(limit) => price > limit,
+            PriceCondition::BelowFloor(factor) => floor.is_some_and(|f| price < f * factor),
+            PriceCondition::AboveFloor(factor) => floor.is_some_and(|f| price > f * factor),
+        }
+    }
+}
+
+/// The side of an event a wallet must be on
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Serialize, Deserialize)]
+pub enum WalletRole {
+    /// Either side
+    Any,
+    /// The maker of a listing or bid, the seller of a sale or the sender of
+    /// a transfer, see [`MarketEvent::from`]
+    Sender,
+    /// The buyer of a sale, the receiver of a transfer or the taker an order
+    /// is restricted to, see [`MarketEvent::to`]
+    Receiver,
+}
+
+impl WalletRole {
+    /// Whether `wallet` is on this side of the event
+    pub fn matches(&self, event: &MarketEvent, wallet: &str) -> bool {
+        let is = |side: &Option<String>| side.as_deref() == Some(wallet);
+        match self {
+            WalletRole::Any => is(&event.from) || is(&event.to),
+            WalletRole::Sender => is(&event.from),
+            WalletRole::Receiver => is(&event.to),
+        }
+    }
+}
+
+/// Which events trigger an alert, all conditions must match, an empty
+/// condition matches any event
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct AlertRule {
+    pub name: String,
+    pub kinds: BTreeSet<EventKind>,
+    /// Contracts of the collections, lowercase
+    pub collections: BTreeSet<String>,
+    /// `(trait type, value)` pairs the token must have
+    pub traits: Vec<(String, String)>,
+    pub price: Option<PriceCondition>,
+    /// Wallets of which one must be involved in its role, lowercase
+    pub wallets: BTreeMap<String, WalletRole>,
+}
+
+impl AlertRule {
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            kinds: BTreeSet::new(),
+            collections: BTreeSet::new(),
+            traits: Vec::new(),
+            price: None,
+            wallets: BTreeMap::new(),
+        }
+    }
+
+    pub fn kind<T: Into<EventKind>>(mut self, value: T) -> Self {
+        self.kinds.insert(value.into());
+        self
+    }
+
+    pub fn collection<T: AsRef<str>>(mut self, value: T) -> Self {
+        self.collections.insert(value.as_ref().to_lowercase());
+        self
+    }
+
+    pub fn with_trait(mut self, trait_type: impl Into<String>, value: impl Into<String>) -> Self {
+        self.traits.push((trait_type.into(), value.into()));
+        self
+    }
+
+    pub fn price<T: Into<PriceCondition>>(mut self, value: T) -> Self {
+        self.price = Some(value.into());
+        self
+    }
+
+    /// Matches events the wallet is involved in on either side
+    pub fn wallet<T: AsRef<str>>(self, value: T) -> Self {
+        self.wallet_as(value, WalletRole::Any)
+    }
+
+    /// Matches events the wallet is involved in as `role`, e.g. only the
+    /// sales of a wallet with [`WalletRole::Sender`]
+    pub fn wallet_as<T: AsRef<str>>(mut self, value: T, role: WalletRole) -> Self {
+        self.wallets.insert(value.as_ref().to_lowercase(), role);
+        self
+    }
+
+    /// Whether the event matches the rule, `floor` is the floor of the
+    /// event's collection and `traits` the traits of its token, if known
+    pub fn matches(
+        &self,
+        event: &MarketEvent,
+        floor: Option<Decimal>,
+        traits: Option<&[(String, String)]>,
+    ) -> bool {
+        if !self.kinds.is_empty() && !self.kinds.contains(&event.kind) {
+            return false;
+        }
+        if !self.collections.is_empty()
+            && !event
+                .nft
+                .as_ref()
+                .is_some_and(|nft| self.collections.contains(&nft.contract))
+        {
+            return false;
+        }
+        if !self.traits.is_empty() {
+            let traits = match traits {
+                Some(traits) => traits,
+                None => return false,
+            };
+            if !self.traits.iter().all(|(t, v)| {
+                traits
+                    .iter()
+                    .any(|(tt, tv)| t.eq_ignore_ascii_case(tt) && v.eq_ignore_ascii_case(tv))
+            }) {
+                return false;
+            }
+        }
+        if let Some(condition) = self.price.as_ref() {
+            match event.price {
+                Some(price) if condition.matches(price, floor) => {}
+                _ => return false,
+            }
+        }
+        if !self.wallets.is_empty()
+            && !self
+                .wallets
+                .iter()
+                .any(|(wallet, role)| role.matches(event, wallet))
+        {
+            return false;
+        }
+        true
+    }
+}
+
+/// An event that matched a rule
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Alert {
+    /// Name of the rule that matched
+    pub rule: String,
+    pub event: MarketEvent,
+    /// The floor of the collection when the rule matched, if known
+    pub floor: Option<Decimal>,
+    pub triggered_at: DateTime<Utc>,
+}
+
+/// Where alerts are sent to
+pub trait AlertSink: Send + Sync {
+    fn send<'a>(&'a self, alert: &'a Alert) -> BoxFuture<'a, anyhow::Result<()>>;
+}
+
+/// Sends alerts to a channel
+#[derive(Clone, Debug)]
+pub struct ChannelSink {
+    sender: mpsc::UnboundedSender<Alert>,
+}
+
+impl ChannelSink {
+    /// Creates the sink and the receiver of its alerts
+    pub fn new() -> (Self, mpsc::UnboundedReceiver<Alert>) {
+        let (sender, receiver) = mpsc::unbounded();
+        (Self { sender }, receiver)
+    }
+}
+
+impl AlertSink for ChannelSink {
+    fn send<'a>(&'a self, alert: &'a Alert) -> BoxFuture<'a, anyhow::Result<()>> {
+        let res = self
+            .sender
+            .unbounded_send(alert.clone())
+            .map_err(|_| anyhow::anyhow!("alert receiver was dropped"));
+        futures::future::ready(res).boxed()
+    }
+}
+
+/// POSTs alerts as json to a url
+#[derive(Clone, Debug)]
+pub struct WebhookSink {
+    client: reqwest::Client,
+    url: Url,
+}
+
+impl WebhookSink {
+    pub fn new(url: impl IntoUrl) -> anyhow::Result<Self> {
+        Ok(Self::with_client(reqwest::Client::new(), url.into_url()?))
+    }
+
+    pub fn with_client(client: reqwest::Client, url: Url) -> Self {
+        Self { client, url }
+    }
+}
+
+impl AlertSink for WebhookSink {
+    fn send<'a>(&'a self, alert: &'a Alert) -> BoxFuture<'a, anyhow::Result<()>> {
+        async move {
+            self.client
+                .post(self.url.clone())
+                .json(alert)
+                .send()
+                .await?
+                .error_for_status()?;
+            Ok(())
+        }
+        .boxed()
+    }
+}
+
+/// Prints alerts to stdout as a json line
+#[derive(Clone, Copy, Debug, Default)]
+pub struct StdoutSink;
+
+impl AlertSink for StdoutSink {
+    fn send<'a>(&'a self, alert: &'a Alert) -> BoxFuture<'a, anyhow::Result<()>> {
+        let res = serde_json::to_string(alert)
+            .map_err(anyhow::Error::from)
+            .and_then(|line| Ok(writeln!(std::io::stdout().lock(), "{}", line)?));
+        futures::future::ready(res).boxed()
+    }
+}
+
+/// Floors in ETH by contract, shared between engines and whatever keeps
+/// them up to date
+#[derive(Clone, Debug, Default)]
+pub struct SharedFloors(Arc<RwLock<HashMap<String, Decimal>>>);
+
+impl SharedFloors {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&self, contract: &str) -> Option<Decimal> {
+        let floors = self.0.read().unwrap_or_else(|err| err.into_inner());
+        floors.get(&contract.to_lowercase()).copied()
+    }
+
+    /// Sets the floor of the collection, in ETH
+    pub fn set(&self, contract: impl AsRef<str>, floor: Decimal) {
+        let mut floors = self.0.write().unwrap_or_else(|err| err.into_inner());
+        floors.insert(contract.as_ref().to_lowercase(), floor);
+    }
+
+    /// Lowers the known floor of the collection to `price`, returns whether
+    /// it was lowered
+    pub fn lower(&self, contract: impl AsRef<str>, price: Decimal) -> bool {
+        let mut floors = self.0.write().unwrap_or_else(|err| err.into_inner());
+        match floors.get_mut(&contract.as_ref().to_lowercase()) {
+            Some(floor) if price < *floor => {
+                *floor = price;
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Evaluates rules against events and sends the alerts to the sinks
+#[derive(Default)]
+pub struct AlertEngine {
+    rules: Vec<AlertRule>,
+    sinks: Vec<Box<dyn AlertSink>>,
+    floors: SharedFloors,
+    /// token -> traits
+    traits: HashMap<NftId, Vec<(String, String)>>,
+}
+
+impl AlertEngine {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn rule<T: Into<AlertRule>>(mut self, value: T) -> Self {
+        self.rules.push(value.into());
+        self
+    }
+
+    pub fn sink(mut self, sink: impl AlertSink + 'static) -> Self {
+        self.sinks.push(Box::new(sink));
+        self
+    }
+
+    /// Sets the floor of the collection, in ETH
+    pub fn floor(self, contract: impl AsRef<str>, floor: Decimal) -> Self {
+        self.set_floor(contract, floor);
+        self
+    }
+
+    /// Uses floors that are kept up to date elsewhere, replaces the floors
+    /// set so far
+    pub fn shared_floors(mut self, floors: SharedFloors) -> Self {
+        self.floors = floors;
+        self
+    }
+
+    /// The traits of the tokens rules with trait filters are matched with
+    pub fn traits(mut self, tokens: impl IntoIterator<Item = TokenTraits>) -> Self {
+        self.set_traits(tokens);
+        self
+    }
+
+    pub fn rules(&self) -> &[AlertRule] {
+        &self.rules
+    }
+
+    /// Updates the floor of the collection, in ETH
+    pub fn set_floor(&self, contract: impl AsRef<str>, floor: Decimal) {
+        self.floors.set(contract, floor);
+    }
+
+    /// The floors the engine evaluates rules with
+    pub fn floors(&self) -> &SharedFloors {
+        &self.floors
+    }
+
+    pub fn set_traits(&mut self, tokens: impl IntoIterator<Item = TokenTraits>) {
+        self.traits
+            .extend(tokens.into_iter().map(|token| (token.nft, token.traits)));
+    }
+
+    pub fn floor_of(&self, contract: &str) -> Option<Decimal> {
+        self.floors.get(contract)
+    }
+
+    /// The alerts of all rules that match the event
+    pub fn evaluate(&self, event: &MarketEvent) -> Vec<Alert> {
+        let floor = event
+            .nft
+            .as_ref()
+            .and_then(|nft| self.floors.get(&nft.contract));
+        let traits = event
+            .nft
+            .as_ref()
+            .and_then(|nft| self.traits.get(nft))
+            .map(Vec::as_slice);
+        let now = Utc::now();
+        self.rules
+            .iter()
+            .filter(|rule| rule.matches(event, floor, traits))
+            .map(|rule| Alert {
+                rule: rule.name.clone(),
+                event: event.clone(),
+                floor,
+                triggered_at: now,
+            })
+            .collect()
+    }
+
+    /// Evaluates the event and sends its alerts to all sinks.
+    ///
+    /// A listing below the known floor lowers it after the event was
+    /// evaluated, so the listing itself is still compared with the old floor.
+    /// Sinks that fail are logged and skipped, so a failing webhook doesn't
+    /// hold back the others.
+    pub async fn notify(&self, event: &MarketEvent) -> Vec<Alert> {
+        let alerts = self.evaluate(event);
+        if let (EventKind::Listing, Some(nft), Some(price)) =
+            (event.kind, event.nft.as_ref(), event.price)
+        {
+            self.floors.lower(&nft.contract, price);
+        }
+        for alert in &alerts {
+            for sink in &self.sinks {
+                if let Err(err) = sink.send(alert).await {
+                    warn!("Failed to send alert `{}`: {}", alert.rule, err);
+                }
+            }
+        }
+        alerts
+    }
+
+    /// Evaluates every event of the stream until it ends.
+    ///
+    /// Errors of the stream and events that can't be normalized are logged
+    /// and skipped, like the failed polls of a
+    /// [`Watcher`](crate::opensea::watch::Watcher).
+    pub async fn run<S, T>(&self, events: S) -> usize
+    where
+        S: Stream<Item = anyhow::Result<T>>,
+        for<'a> MarketEvent: TryFrom<&'a T, Error = anyhow::Error>,
+    {
+        futures::pin_mut!(events);
+        let mut triggered = 0;
+        while let Some(item) = events.next().await {
+            let event = match item.and_then(|item| MarketEvent::try_from(&item)) {
+                Ok(event) => event,
+                Err(err) => {
+                    warn!("Skipping event: {}", err);
+                    continue;
+                }
+            };
+            triggered += self.notify(&event).await.len();
+        }
+        triggered
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const COLLECTION: &str = "0x00000000000000000000000000000000000000aa";
+
+    fn event(kind: EventKind, price: Decimal) -> MarketEvent {
+        MarketEvent {
+            kind,
+            marketplace: Marketplace::OpenSea,
+            id: "1".to_string(),
+            nft: Some(NftId::new(COLLECTION, "1")),
+            price: Some(price),
+            currency: Some(Currency::Eth),
+            from: Some("0xa1".to_string()),
+            to: Some("0xb1".to_string()),
+            timestamp: None,
+        }
+    }
+
+    fn eth(tenths: i64) -> Decimal {
+        Decimal::new(tenths, 1)
+    }
+
+    #[test]
+    fn matches_kinds_and_collections() {
+        let rule = AlertRule::new("sales")
+            .kind(EventKind::Sale)
+            .collection(COLLECTION.to_uppercase());
+        assert!(rule.matches(&event(EventKind::Sale, eth(10)), None, None));
+        assert!(!rule.matches(&event(EventKind::Listing, eth(10)), None, None));
+        let mut other = event(EventKind::Sale, eth(10));
+        other.nft = Some(NftId::new("0xbb", "1"));
+        assert!(!rule.matches(&other, None, None));
+        other.nft = None;
+        assert!(!rule.matches(&other, None, None));
+        assert!(AlertRule::new("any").matches(&other, None, None));
+    }
+
+    #[test]
+    fn matches_prices_relative_to_the_floor() {
+        let listing = event(EventKind::Listing, eth(8));
+        let below = AlertRule::new("below").price(PriceCondition::Below(eth(10)));
+        assert!(below.matches(&listing, None, None));
+        let above = AlertRule::new("above").price(PriceCondition::Above(eth(8)));
+        assert!(!above.matches(&listing, None, None));
+
+        let below_floor =
+            AlertRule::new("10% below floor").price(PriceCondition::BelowFloor(eth(9)));
+        assert!(below_floor.matches(&listing, Some(eth(10)), None));
+        assert!(!below_floor.matches(&listing, Some(eth(8)), None));
+        // no floor, no match
+        assert!(!below_floor.matches(&listing, None, None));
+        let above_floor = AlertRule::new("above floor").price(PriceCondition::AboveFloor(eth(10)));
+        assert!(above_floor.matches(&listing, Some(eth(7)), None));
+        assert!(!above_floor.matches(&listing, Some(eth(8)), None));
+
+        let mut unpriced = listing;
+        unpriced.price = None;
+        assert!(!below.matches(&unpriced, None, None));
+    }
+
+    #[test]
+    fn matches_wallets_in_their_role() {
+        let sale = event(EventKind::Sale, eth(10));
+        assert!(AlertRule::new("any")
+            .wallet("0xA1")
+            .matches(&sale, None, None));
+        assert!(AlertRule::new("any")
+            .wallet("0xb1")
+            .matches(&sale, None, None));
+        let seller = AlertRule::new("seller").wallet_as("0xa1", WalletRole::Sender);
+        assert!(seller.matches(&sale, None, None));
+        let buyer = AlertRule::new("buyer").wallet_as("0xa1", WalletRole::Receiver);
+        assert!(!buyer.matches(&sale, None, None));
+        // one of the wallets is enough
+        let either = buyer.wallet_as("0xb1", WalletRole::Receiver);
+        assert!(either.matches(&sale, None, None));
+        assert!(!AlertRule::new("other")
+            .wallet("0xc1")
+            .matches(&sale, None, None));
+    }
+
+    #[test]
+    fn matches_traits_of_known_tokens() {
+        let sale = event(EventKind::Sale, eth(10));
+        let rule = AlertRule::new("aliens")
+            .with_trait("Type", "Alien")
+            .with_trait("Hat", "Cap");
+        let traits = vec![
+            ("type".to_string(), "alien".to_string()),
+            ("hat".to_string(), "cap".to_string()),
+            ("eyes".to_string(), "blue".to_string()),
+        ];
+        assert!(rule.matches(&sale, None, Some(&traits)));
+        assert!(!rule.matches(&sale, None, Some(&traits[..1])));
+        assert!(!rule.matches(&sale, None, None));
+    }
+
+    #[tokio::test]
+    async fn lowers_the_floor_after_evaluating_listings() {
+        let (sink, mut alerts) = ChannelSink::new();
+        let engine = AlertEngine::new()
+            .rule(
+                AlertRule::new("below floor")
+                    .kind(EventKind::Listing)
+                    .price(PriceCondition::BelowFloor(Decimal::ONE)),
+            )
+            .rule(AlertRule::new("bids").kind(EventKind::Bid))
+            .sink(sink)
+            .floor(COLLECTION, eth(10));
+
+        let triggered = engine.notify(&event(EventKind::Listing, eth(8))).await;
+        assert_eq!(triggered.len(), 1);
+        // compared with the floor before the listing
+        assert_eq!(triggered[0].floor, Some(eth(10)));
+        assert_eq!(alerts.try_recv().unwrap(), triggered[0]);
+        assert_eq!(engine.floor_of(COLLECTION), Some(eth(8)));
+
+        // the listing is the floor now
+        assert!(engine
+            .notify(&event(EventKind::Listing, eth(8)))
+            .await
+            .is_empty());
+        assert!(engine
+            .notify(&event(EventKind::Listing, eth(9)))
+            .await
+            .is_empty());
+        assert_eq!(engine.floor_of(COLLECTION), Some(eth(8)));
+
+        // bids don't lower it
+        let triggered = engine.notify(&event(EventKind::Bid, eth(5))).await;
+        assert_eq!(triggered.len(), 1);
+        assert_eq!(triggered[0].floor, Some(eth(8)));
+        assert_eq!(engine.floor_of(COLLECTION), Some(eth(8)));
+        assert!(alerts.try_recv().is_ok());
+        assert!(alerts.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn keeps_unknown_floors_unknown() {
+        let floors = SharedFloors::new();
+        let engine = AlertEngine::new().shared_floors(floors.clone());
+        engine.notify(&event(EventKind::Listing, eth(8))).await;
+        assert_eq!(floors.get(COLLECTION), None);
+        floors.set(COLLECTION.to_uppercase(), eth(10));
+        engine.notify(&event(EventKind::Listing, eth(8))).await;
+        assert_eq!(floors.get(COLLECTION), Some(eth(8)));
+    }
+}
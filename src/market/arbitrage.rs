@@ -0,0 +1,484 @@
+//! Price differences of tokens between OpenSea and Rarible.
+//!
+//! A token is an arbitrage opportunity if buying it from a listing on one
+//! marketplace and selling it into an offer on the other leaves a profit
+//! after all fees: what the buyer pays on top of the listing, and the
+//! marketplace fees and creator royalties deducted from the offer, see
+//! [`FeeBreakdown`]. ETH and WETH are treated as the same currency, orders
+//! priced in other currencies are ignored.
+
+use std::collections::BTreeMap;
+
+use chrono::Utc;
+use ethereum_types::U256;
+use futures::StreamExt;
+use log::debug;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+
+use crate::market::fees::{FeeBreakdown, RARIBLE_PROTOCOL_FEE_BPS};
+use crate::market::models::{
+    AggregatedBook, Amount, Listing, Marketplace, NativeOrder, NftId, Offer,
+};
+use crate::market::MarketClient;
+use crate::rarible::models::Part;
+
+/// Number of tokens whose books are fetched concurrently
+const CONCURRENT_REQUESTS: usize = 4;
+
+/// Which tokens are scanned and which opportunities are reported
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ArbitrageConfig {
+    /// Listed tokens scanned at most, the cheapest first
+    pub max_tokens: usize,
+    /// Pages of collection listings read at most from each marketplace
+    pub max_pages: u32,
+    /// Profit per unit an opportunity needs at least, in wei
+    pub min_profit: Amount,
+    /// Estimated gas for buying and selling a unit, in wei
+    pub gas_cost: Amount,
+    /// Protocol fee of the Rarible exchange in basis points
+    pub protocol_fee_bps: u64,
+}
+
+impl ArbitrageConfig {
+    pub fn max_tokens<T: Into<usize>>(mut self, value: T) -> Self {
+        self.max_tokens = value.into();
+        self
+    }
+
+    pub fn max_pages<T: Into<u32>>(mut self, value: T) -> Self {
+        self.max_pages = value.into();
+        self
+    }
+
+    pub fn min_profit<T: Into<Amount>>(mut self, value: T) -> Self {
+        self.min_profit = value.into();
+        self
+    }
+
+    pub fn gas_cost<T: Into<Amount>>(mut self, value: T) -> Self {
+        self.gas_cost = value.into();
+        self
+    }
+
+    pub fn protocol_fee_bps<T: Into<u64>>(mut self, value: T) -> Self {
+        self.protocol_fee_bps = value.into();
+        self
+    }
+}
+
+impl Default for ArbitrageConfig {
+    fn default() -> Self {
+        Self {
+            max_tokens: 50,
+            max_pages: 20,
+            min_profit: Amount::wei(0),
+            gas_cost: Amount::wei(0),
+            protocol_fee_bps: RARIBLE_PROTOCOL_FEE_BPS,
+        }
+    }
+}
+
+/// Buying a token from a listing and selling it into an offer on another
+/// marketplace, all amounts are in wei per unit
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ArbitrageOpportunity {
+    pub nft: NftId,
+    /// The listing to buy from
+    pub buy: Listing,
+    /// The offer to sell into
+    pub sell: Offer,
+    /// Units both orders can be filled for
+    pub quantity: U256,
+    /// The unit price of the listing plus the fees the buyer pays
+    pub cost: Amount,
+    /// What the seller receives from the offer after fees and royalties
+    pub proceeds: Amount,
+    /// `proceeds - cost - gas_cost`
+    pub profit: Amount,
+}
+
+impl ArbitrageOpportunity {
+    pub fn buy_marketplace(&self) -> Marketplace {
+        self.buy.marketplace
+    }
+
+    pub fn sell_marketplace(&self) -> Marketplace {
+        self.sell.marketplace
+    }
+
+    /// The profit of all units
+    pub fn total_profit(&self) -> Amount {
+        Amount::wei(self.profit.value.saturating_mul(self.quantity))
+    }
+
+    /// The profit relative to the cost, `0.1` is 10%
+    pub fn return_on_cost(&self) -> Option<Decimal> {
+        let cost = self.cost.to_decimal()?;
+        if cost.is_zero() {
+            return None;
+        }
+        Some(self.profit.to_decimal()? / cost)
+    }
+}
+
+/// Finds the opportunities within the book.
+///
+/// For every token and marketplace to buy from, the most profitable pair of
+/// a listing and an offer on another marketplace is reported, ordered by
+/// profit. `royalties` are the creator royalties of the token, Rarible
+/// deducts them when an order is filled.
+pub fn find_opportunities(
+    book: &AggregatedBook,
+    royalties: &BTreeMap<NftId, Vec<Part>>,
+    config: &ArbitrageConfig,
+) -> Vec<ArbitrageOpportunity> {
+    let mut best = BTreeMap::<(NftId, Marketplace), ArbitrageOpportunity>::new();
+    let listings = book.listings.iter().filter(|l| l.currency.is_ether());
+    for listing in listings {
+        let offers = book.offers.iter().filter(|o| {
+            o.currency.is_ether()
+                && o.nft == listing.nft
+                && o.marketplace != listing.marketplace
+                && !o.maker.eq_ignore_ascii_case(&listing.maker)
+        });
+        let royalties = royalties
+            .get(&listing.nft)
+            .map(Vec::as_slice)
+            .unwrap_or_default();
+        for offer in offers {
+            let opportunity = match evaluate(listing, offer, royalties, config) {
+                Ok(Some(opportunity)) => opportunity,
+                Ok(None) => continue,
+                Err(err) => {
+                    debug!("Skipping {} against {}: {}", listing.hash, offer.hash, err);
+                    continue;
+                }
+            };
+            let key = (listing.nft.clone(), listing.marketplace);
+            match best.get(&key) {
+                Some(current) if current.profit.value >= opportunity.profit.value => {}
+                _ => {
+                    best.insert(key, opportunity);
+                }
+            }
+        }
+    }
+    let mut opportunities = best.into_values().collect::<Vec<_>>();
+    opportunities.sort_by_key(|o| std::cmp::Reverse(o.profit.value));
+    opportunities
+}
+
+/// The opportunity of buying from the listing and selling into the offer,
+/// `None` if it isn't profitable enough
+fn evaluate(
+    listing: &Listing,
+    offer: &Offer,
+    royalties: &[Part],
+    config: &ArbitrageConfig,
+) -> anyhow::Result<Option<ArbitrageOpportunity>> {
+    let buy = breakdown(
+        &listing.order,
+        listing.unit_price().value,
+        royalties,
+        config,
+    )?;
+    let sell = breakdown(&offer.order, offer.unit_price().value, royalties, config)?;
+    let cost = buy.buyer_pays.value;
+    let proceeds = sell.seller_proceeds().value;
+    let total_cost = cost
+        .checked_add(config.gas_cost.value)
+        .ok_or_else(|| anyhow::anyhow!("cost of listing {} overflows", listing.hash))?;
+    let profit = match proceeds.checked_sub(total_cost) {
+        Some(profit) if !profit.is_zero() && profit >= config.min_profit.value => profit,
+        _ => return Ok(None),
+    };
+    Ok(Some(ArbitrageOpportunity {
+        nft: listing.nft.clone(),
+        buy: listing.clone(),
+        sell: offer.clone(),
+        quantity: listing.quantity.min(offer.quantity),
+        cost: Amount::wei(cost),
+        proceeds: Amount::wei(proceeds),
+        profit: Amount::wei(profit),
+    }))
+}
+
+fn breakdown(
+    order: &NativeOrder,
+    price: U256,
+    royalties: &[Part],
+    config: &ArbitrageConfig,
+) -> anyhow::Result<FeeBreakdown> {
+    match order {
+        NativeOrder::OpenSea(order) => FeeBreakdown::opensea(order, price, None),
+        NativeOrder::Rarible(order) => {
+            FeeBreakdown::rarible(order, price, royalties, config.protocol_fee_bps)
+        }
+        NativeOrder::RaribleBid(bid) => {
+            anyhow::bail!("bid {} has no order data", bid.order_hash)
+        }
+    }
+}
+
+impl MarketClient {
+    /// Scans the cheapest listed tokens of the collection for arbitrage
+    pub async fn arbitrage(&self, contract: &str) -> anyhow::Result<Vec<ArbitrageOpportunity>> {
+        self.arbitrage_with_config(contract, &ArbitrageConfig::default())
+            .await
+    }
+
+    /// Scans the listed tokens of the collection for arbitrage.
+    ///
+    /// The listings of up to `max_pages` pages per marketplace are read to
+    /// find the `max_tokens` cheapest tokens. The book of every token is
+    /// fetched on its own, since Rarible only reports bids per token. Tokens
+    /// whose book or royalties can't be fetched are skipped.
+    pub async fn arbitrage_with_config(
+        &self,
+        contract: &str,
+        config: &ArbitrageConfig,
+    ) -> anyhow::Result<Vec<ArbitrageOpportunity>> {
+        let now = Utc::now();
+        let mut book = AggregatedBook {
+            listings: self.collection_listings(contract, config.max_pages).await?,
+            offers: Vec::new(),
+        };
+        book.retain_active(now);
+
+        let mut listings = book
+            .listings
+            .iter()
+            .filter(|l| l.currency.is_ether())
+            .collect::<Vec<_>>();
+        listings.sort_by_key(|l| l.unit_price().value);
+        let mut tokens = Vec::new();
+        for listing in listings {
+            if tokens.len() == config.max_tokens {
+                break;
+            }
+            if !tokens.contains(&listing.nft) {
+                tokens.push(listing.nft.clone());
+            }
+        }
+
+        let books = futures::stream::iter(tokens)
+            .map(|nft| async move {
+                match self.arbitrage_book(&nft).await {
+                    Ok((mut book, royalties)) => {
+                        book.retain_active(now);
+                        Some((nft, book, royalties))
+                    }
+                    Err(err) => {
+                        debug!("Failed to fetch the book of {}: {}", nft.item_id(), err);
+                        None
+                    }
+                }
+            })
+            .buffer_unordered(CONCURRENT_REQUESTS)
+            .collect::<Vec<_>>()
+            .await;
+
+        let mut merged = AggregatedBook::default();
+        let mut royalties = BTreeMap::new();
+        for (nft, book, parts) in books.into_iter().flatten() {
+            merged.extend(book);
+            royalties.insert(nft, parts);
+        }
+        Ok(find_opportunities(&merged, &royalties, config))
+    }
+
+    /// The book of the token and its royalties, which are only fetched if
+    /// a Rarible order is involved
+    async fn arbitrage_book(&self, nft: &NftId) -> anyhow::Result<(AggregatedBook, Vec<Part>)> {
+        let book = self.token_book(&nft.contract, &nft.token_id).await?;
+        let rarible = book
+            .listings
+            .iter()
+            .map(|l| l.marketplace)
+            .chain(book.offers.iter().map(|o| o.marketplace))
+            .any(|marketplace| marketplace == Marketplace::Rarible);
+        let royalties = if rarible {
+            self.rarible
+                .get_nft_item_by_id(&nft.item_id(), Some(false))
+                .await?
+                .royalties
+        } else {
+            Vec::new()
+        };
+        Ok((book, royalties))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::opensea::models as opensea;
+    use crate::rarible::models as rarible;
+    use serde_json::{json, Value};
+    use std::convert::TryFrom;
+
+    const ORDERBOOK: &str = include_str!("../../examples/responses/opensea-orderbook.json");
+    // constructed, see `rarible::eip712`
+    const RARIBLE_ORDER: &str = include_str!("../../tests/fixtures/rarible-order.json");
+
+    const MEEBITS: &str = "0x7bd29408f11d2bfc23c34f18275bbf23bb716bc7";
+    const WETH: &str = "0xc02aaa39b223fe8d0a0e5c4f27ead9083c756cc2";
+    const ETHER: u64 = 1_000_000_000_000_000_000;
+
+    fn nft() -> NftId {
+        NftId::new(MEEBITS, "7325")
+    }
+
+    fn eth(millis: u64) -> U256 {
+        U256::from(millis) * U256::from(ETHER / 1000)
+    }
+
+    /// The OpenSea bid of the fixture for Meebit 7325, 2.508 WETH of which
+    /// the seller pays 2.5% to OpenSea
+    fn opensea_offer() -> Offer {
+        let mut book: Value = serde_json::from_str(ORDERBOOK).unwrap();
+        let order: opensea::Order = serde_json::from_value(book["orders"][0].take()).unwrap();
+        Offer::try_from(&order).unwrap()
+    }
+
+    /// An OpenSea listing of Meebit 7325 for `price`, the seller pays 2.5%
+    fn opensea_listing(price: U256) -> Listing {
+        let mut book: Value = serde_json::from_str(ORDERBOOK).unwrap();
+        let mut order = book["orders"][0].take();
+        order["side"] = json!(1);
+        order["order_hash"] = json!("0x01");
+        order["maker"]["address"] = json!("0x00000000000000000000000000000000000000a1");
+        order["maker_relayer_fee"] = json!("250");
+        order["taker_relayer_fee"] = json!("0");
+        order["current_price"] = json!(price.to_string());
+        let order: opensea::Order = serde_json::from_value(order).unwrap();
+        Listing::try_from(&order).unwrap()
+    }
+
+    /// The Rarible order of the fixture for Meebit 7325, `price` in ETH and
+    /// a 2.5% origin fee
+    fn rarible_order(price: U256) -> Value {
+        let mut order: Value = serde_json::from_str(RARIBLE_ORDER).unwrap();
+        order["hash"] = json!("0x02");
+        order["make"]["assetType"]["contract"] = json!(MEEBITS);
+        order["make"]["assetType"]["tokenId"] = json!("7325");
+        order["take"]["value"] = json!(price.to_string());
+        order
+    }
+
+    fn rarible_listing(price: U256) -> Listing {
+        let order: rarible::Order = serde_json::from_value(rarible_order(price)).unwrap();
+        Listing::try_from(&order).unwrap()
+    }
+
+    /// A Rarible bid of `price` WETH, the buyer pays the origin fee
+    fn rarible_offer(price: U256) -> Offer {
+        let mut order = rarible_order(price);
+        let nft = order["make"].take();
+        order["make"] = json!({
+            "assetType": { "assetClass": "ERC20", "contract": WETH },
+            "value": price.to_string(),
+        });
+        order["take"] = nft;
+        let order: rarible::Order = serde_json::from_value(order).unwrap();
+        Offer::try_from(&order).unwrap()
+    }
+
+    fn book(listings: Vec<Listing>, offers: Vec<Offer>) -> AggregatedBook {
+        AggregatedBook { listings, offers }
+    }
+
+    fn royalties(bps: i32) -> BTreeMap<NftId, Vec<Part>> {
+        let royalty = Part {
+            account: "0x00000000000000000000000000000000000000c1".to_string(),
+            value: bps,
+        };
+        vec![(nft(), vec![royalty])].into_iter().collect()
+    }
+
+    #[test]
+    fn finds_profitable_pairs() {
+        let book = book(
+            vec![rarible_listing(eth(1000)), rarible_listing(eth(2000))],
+            vec![opensea_offer()],
+        );
+        let opportunities = find_opportunities(&book, &BTreeMap::new(), &Default::default());
+        assert_eq!(opportunities.len(), 1);
+        let opportunity = &opportunities[0];
+        assert_eq!(opportunity.nft, nft());
+        assert_eq!(opportunity.buy_marketplace(), Marketplace::Rarible);
+        assert_eq!(opportunity.sell_marketplace(), Marketplace::OpenSea);
+        assert_eq!(opportunity.buy.price.value, eth(1000));
+        assert_eq!(opportunity.quantity, U256::one());
+        // 1 ETH and the 2.5% protocol fee
+        assert_eq!(opportunity.cost.value, eth(1025));
+        // 2.508 WETH less the 2.5% OpenSea fee
+        assert_eq!(
+            opportunity.proceeds.value,
+            U256::from(2_445_300_000_000_000_000u64)
+        );
+        assert_eq!(
+            opportunity.profit.value,
+            U256::from(1_420_300_000_000_000_000u64)
+        );
+        assert_eq!(opportunity.total_profit(), opportunity.profit);
+    }
+
+    #[test]
+    fn skips_pairs_unprofitable_after_fees_and_royalties() {
+        // 2.46 ETH with the protocol fee, more than the 2.4453 of the offer
+        let book = book(vec![rarible_listing(eth(2400))], vec![opensea_offer()]);
+        assert!(find_opportunities(&book, &BTreeMap::new(), &Default::default()).is_empty());
+
+        // selling for 1.1 ETH on Rarible leaves 1.0725 after the protocol fee
+        let book = self::book(
+            vec![opensea_listing(eth(1000))],
+            vec![rarible_offer(eth(1100))],
+        );
+        let opportunities = find_opportunities(&book, &BTreeMap::new(), &Default::default());
+        assert_eq!(opportunities.len(), 1);
+        assert_eq!(opportunities[0].cost.value, eth(1000));
+        assert_eq!(
+            opportunities[0].profit.value,
+            U256::from(72_500_000_000_000_000u64)
+        );
+        // and 0.9625 after a 10% royalty
+        assert!(find_opportunities(&book, &royalties(1000), &Default::default()).is_empty());
+    }
+
+    #[test]
+    fn skips_orders_of_the_same_maker_or_marketplace() {
+        let mut listing = rarible_listing(eth(1000));
+        listing.maker = "0x3C6137504C38215FEA30605B3E364A23C1D3E14F".to_string();
+        let book = book(vec![listing], vec![opensea_offer()]);
+        assert!(find_opportunities(&book, &BTreeMap::new(), &Default::default()).is_empty());
+
+        let book = self::book(vec![opensea_listing(eth(1000))], vec![opensea_offer()]);
+        assert!(find_opportunities(&book, &BTreeMap::new(), &Default::default()).is_empty());
+    }
+
+    #[test]
+    fn applies_the_profit_and_gas_thresholds() {
+        let book = book(vec![rarible_listing(eth(1000))], vec![opensea_offer()]);
+        let profit = U256::from(1_420_300_000_000_000_000u64);
+        let find = |config: ArbitrageConfig| find_opportunities(&book, &BTreeMap::new(), &config);
+
+        assert_eq!(
+            find(ArbitrageConfig::default().min_profit(Amount::wei(profit))).len(),
+            1
+        );
+        let config = ArbitrageConfig::default().min_profit(Amount::wei(profit + 1));
+        assert!(find(config).is_empty());
+
+        let opportunities = find(ArbitrageConfig::default().gas_cost(Amount::wei(eth(420))));
+        assert_eq!(opportunities[0].profit.value, profit - eth(420));
+        let config = ArbitrageConfig::default()
+            .gas_cost(Amount::wei(eth(420)))
+            .min_profit(Amount::wei(eth(1001)));
+        assert!(find(config).is_empty());
+        // no profit at all
+        assert!(find(ArbitrageConfig::default().gas_cost(Amount::wei(profit))).is_empty());
+    }
+}
@@ -0,0 +1,652 @@
+//! Breakdown of a sale price into the payouts to the seller, creators and
+//! marketplaces
+
+use std::convert::TryFrom;
+use std::fmt;
+
+use ethereum_types::{U256, U512};
+use serde::{Deserialize, Serialize};
+
+use crate::market::models::{Amount, Currency, Marketplace};
+use crate::opensea::models as opensea;
+use crate::opensea::wyvern::INVERSE_BASIS_POINT;
+#[cfg(feature = "rarible")]
+use crate::{market::models::RARIBLE_CURRENCY_DECIMALS, rarible::models as rarible};
+
+/// Protocol fee the Rarible exchange charges both buyer and seller, in basis
+/// points
+pub const RARIBLE_PROTOCOL_FEE_BPS: u64 = 250;
+
+/// `value * bps / 10000`, fails if the result doesn't fit into 256 bits
+pub fn apply_bps(value: U256, bps: u64) -> anyhow::Result<U256> {
+    let result = value.full_mul(U256::from(bps)) / U512::from(INVERSE_BASIS_POINT);
+    U256::try_from(result)
+        .map_err(|_| anyhow::anyhow!("{} basis points of {} overflow", bps, value))
+}
+
+/// The fees OpenSea charges for a collection, in basis points.
+///
+/// The dev fees are the creator royalties, which OpenSea collects and
+/// forwards to the `payout_address`.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct CollectionFees {
+    pub opensea_buyer_fee_bps: u64,
+    pub opensea_seller_fee_bps: u64,
+    pub dev_buyer_fee_bps: u64,
+    pub dev_seller_fee_bps: u64,
+    pub payout_address: Option<String>,
+}
+
+fn bps_from_i64(bps: i64) -> u64 {
+    bps.max(0) as u64
+}
+
+/// Parses the basis points of a `Collection`, which are reported as strings
+pub fn parse_bps(bps: impl AsRef<str>) -> anyhow::Result<u64> {
+    let bps = bps.as_ref().trim();
+    if bps.is_empty() {
+        return Ok(0);
+    }
+    bps.parse()
+        .map_err(|_| anyhow::anyhow!("`{}` are no basis points", bps))
+}
+
+impl From<&opensea::AssetContract> for CollectionFees {
+    fn from(contract: &opensea::AssetContract) -> Self {
+        Self {
+            opensea_buyer_fee_bps: bps_from_i64(contract.opensea_buyer_fee_basis_points),
+            opensea_seller_fee_bps: bps_from_i64(contract.opensea_seller_fee_basis_points),
+            dev_buyer_fee_bps: bps_from_i64(contract.dev_buyer_fee_basis_points),
+            dev_seller_fee_bps: bps_from_i64(contract.dev_seller_fee_basis_points),
+            payout_address: contract.payout_address.clone(),
+        }
+    }
+}
+
+impl From<&opensea::PrimaryAssetContract> for CollectionFees {
+    fn from(contract: &opensea::PrimaryAssetContract) -> Self {
+        Self {
+            opensea_buyer_fee_bps: bps_from_i64(contract.opensea_buyer_fee_basis_points),
+            opensea_seller_fee_bps: bps_from_i64(contract.opensea_seller_fee_basis_points),
+            dev_buyer_fee_bps: bps_from_i64(contract.dev_buyer_fee_basis_points),
+            dev_seller_fee_bps: bps_from_i64(contract.dev_seller_fee_basis_points),
+            payout_address: contract.payout_address.clone(),
+        }
+    }
+}
+
+impl TryFrom<&opensea::Collection> for CollectionFees {
+    type Error = anyhow::Error;
+
+    fn try_from(collection: &opensea::Collection) -> Result<Self, Self::Error> {
+        Ok(Self {
+            opensea_buyer_fee_bps: parse_bps(&collection.opensea_buyer_fee_basis_points)?,
+            opensea_seller_fee_bps: parse_bps(&collection.opensea_seller_fee_basis_points)?,
+            dev_buyer_fee_bps: parse_bps(&collection.dev_buyer_fee_basis_points)?,
+            dev_seller_fee_bps: parse_bps(&collection.dev_seller_fee_basis_points)?,
+            payout_address: collection.payout_address.clone(),
+        })
+    }
+}
+
+/// Who receives a payout
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub enum PayoutKind {
+    /// The seller's proceeds
+    Seller,
+    /// Creator royalty
+    Royalty,
+    /// Fee of the marketplace
+    Marketplace,
+    /// Fee set by the order creator, e.g. for the frontend that created it
+    OriginFee,
+}
+
+impl fmt::Display for PayoutKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PayoutKind::Seller => f.write_str("seller"),
+            PayoutKind::Royalty => f.write_str("royalty"),
+            PayoutKind::Marketplace => f.write_str("marketplace"),
+            PayoutKind::OriginFee => f.write_str("origin_fee"),
+        }
+    }
+}
+
+/// A single transfer of a sale
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Payout {
+    pub kind: PayoutKind,
+    /// Address of the recipient, `None` if it is not known from the order
+    pub recipient: Option<String>,
+    pub amount: Amount,
+    /// Whether the buyer pays this on top of the price, otherwise it's
+    /// deducted from the seller's proceeds
+    pub paid_by_buyer: bool,
+}
+
+/// Where the money of a sale goes
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct FeeBreakdown {
+    pub marketplace: Marketplace,
+    pub currency: Currency,
+    /// The sale price
+    pub price: Amount,
+    /// The price plus all fees the buyer pays on top
+    pub buyer_pays: Amount,
+    pub payouts: Vec<Payout>,
+}
+
+impl FeeBreakdown {
+    fn new(marketplace: Marketplace, currency: Currency, price: Amount) -> Self {
+        Self {
+            marketplace,
+            currency,
+            price,
+            buyer_pays: price,
+            payouts: Vec::new(),
+        }
+    }
+
+    fn push(
+        &mut self,
+        kind: PayoutKind,
+        recipient: Option<&str>,
+        amount: U256,
+        paid_by_buyer: bool,
+    ) {
+        if amount.is_zero() {
+            return;
+        }
+        if paid_by_buyer {
+            self.buyer_pays.value = self.buyer_pays.value.saturating_add(amount);
+        }
+        self.payouts.push(Payout {
+            kind,
+            recipient: recipient.map(str::to_lowercase),
+            amount: Amount::new(amount, self.price.decimals),
+            paid_by_buyer,
+        });
+    }
+
+    /// The fees the seller pays
+    fn seller_fees(&self) -> U256 {
+        self.payouts
+            .iter()
+            .filter(|p| p.kind != PayoutKind::Seller && !p.paid_by_buyer)
+            .fold(U256::zero(), |acc, p| acc.saturating_add(p.amount.value))
+    }
+
+    /// The breakdown of an OpenSea order matched at `price`.
+    ///
+    /// The order only knows the total fee that goes to its `fee_recipient`,
+    /// with the `fees` of the collection it is split into the creator
+    /// royalty and OpenSea's fee. Orders that pay their fees in protocol
+    /// tokens have no fees in the payment token.
+    pub fn opensea(
+        order: &opensea::Order,
+        price: U256,
+        fees: Option<&CollectionFees>,
+    ) -> anyhow::Result<Self> {
+        let order_fees = order.fees_for(price)?;
+        let decimals = order.payment_token_contract.decimals.max(0) as u32;
+        let mut breakdown = Self::new(
+            Marketplace::OpenSea,
+            Currency::from_address(&order.payment_token),
+            Amount::new(price, decimals),
+        );
+        if order_fees.fee_method == opensea::FeeMethod::SplitFee {
+            let fee_recipient = Some(order.fee_recipient.address.as_str());
+            let payout_address = fees.and_then(|fees| fees.payout_address.as_deref());
+            for (relayer_fee, dev_bps, protocol_fee, paid_by_buyer) in [
+                (
+                    order_fees.seller_relayer_fee,
+                    fees.map(|fees| fees.dev_seller_fee_bps),
+                    order_fees.seller_protocol_fee,
+                    false,
+                ),
+                (
+                    order_fees.buyer_relayer_fee,
+                    fees.map(|fees| fees.dev_buyer_fee_bps),
+                    order_fees.buyer_protocol_fee,
+                    true,
+                ),
+            ] {
+                let royalty = apply_bps(price, dev_bps.unwrap_or_default())?.min(relayer_fee);
+                breakdown.push(PayoutKind::Royalty, payout_address, royalty, paid_by_buyer);
+                breakdown.push(
+                    PayoutKind::Marketplace,
+                    fee_recipient,
+                    relayer_fee - royalty,
+                    paid_by_buyer,
+                );
+                breakdown.push(PayoutKind::Marketplace, None, protocol_fee, paid_by_buyer);
+            }
+        }
+
+        let seller = if order.side == opensea::OrderSide::Sell as i64 {
+            Some(order.maker.address.as_str())
+        } else {
+            None
+        };
+        let proceeds = price.saturating_sub(breakdown.seller_fees());
+        breakdown.push(PayoutKind::Seller, seller, proceeds, false);
+        Ok(breakdown)
+    }
+
+    /// The breakdown of a Rarible order matched at `price`, like the
+    /// `ExchangeV2` contract transfers it.
+    ///
+    /// All fees are basis points of the price: the protocol fee is charged
+    /// to both sides, the `royalties` of the item and the origin fees of the
+    /// sell order are deducted from the seller's proceeds and the origin
+    /// fees of a bid are paid by the buyer. The proceeds are split among the
+    /// `payouts` of a sell order. The `fee` of legacy orders is paid by the
+    /// buyer to the marketplace.
+    #[cfg(feature = "rarible")]
+    pub fn rarible(
+        order: &rarible::Order,
+        price: U256,
+        royalties: &[rarible::Part],
+        protocol_fee_bps: u64,
+    ) -> anyhow::Result<Self> {
+        let is_sell = crate::market::models::rarible_nft(&order.make.asset_type).is_some();
+        let currency_asset = if is_sell { &order.take } else { &order.make };
+        let currency = crate::market::models::rarible_currency(&currency_asset.asset_type)
+            .ok_or_else(|| anyhow::anyhow!("order {} is not paid in a currency", order.hash))?;
+        let mut breakdown = Self::new(
+            Marketplace::Rarible,
+            currency,
+            Amount::new(price, RARIBLE_CURRENCY_DECIMALS),
+        );
+
+        let part_bps = |part: &rarible::Part| -> anyhow::Result<u64> {
+            u64::try_from(part.value)
+                .map_err(|_| anyhow::anyhow!("negative value of part {}", part.account))
+        };
+
+        let mut payouts: &[rarible::Part] = &[];
+        match &*order.data {
+            rarible::OrderData::OrderRaribleV2DataV1 {
+                payouts: order_payouts,
+                origin_fees,
+            } => {
+                breakdown.push(
+                    PayoutKind::Marketplace,
+                    None,
+                    apply_bps(price, protocol_fee_bps)?,
+                    true,
+                );
+                breakdown.push(
+                    PayoutKind::Marketplace,
+                    None,
+                    apply_bps(price, protocol_fee_bps)?,
+                    false,
+                );
+                for royalty in royalties {
+                    let amount = apply_bps(price, part_bps(royalty)?)?;
+                    breakdown.push(PayoutKind::Royalty, Some(&royalty.account), amount, false);
+                }
+                for fee in origin_fees {
+                    let amount = apply_bps(price, part_bps(fee)?)?;
+                    breakdown.push(PayoutKind::OriginFee, Some(&fee.account), amount, !is_sell);
+                }
+                if is_sell {
+                    payouts = order_payouts;
+                }
+            }
+            rarible::OrderData::OrderDataLegacy { fee } => {
+                let fee = u64::try_from(*fee)
+                    .map_err(|_| anyhow::anyhow!("negative fee of order {}", order.hash))?;
+                breakdown.push(PayoutKind::Marketplace, None, apply_bps(price, fee)?, true);
+                for royalty in royalties {
+                    let amount = apply_bps(price, part_bps(royalty)?)?;
+                    breakdown.push(PayoutKind::Royalty, Some(&royalty.account), amount, false);
+                }
+            }
+        }
+
+        let seller_fees = breakdown.seller_fees();
+        anyhow::ensure!(
+            seller_fees <= price,
+            "fees of order {} exceed the price",
+            order.hash
+        );
+        let mut rest = price - seller_fees;
+        if payouts.is_empty() {
+            let seller = if is_sell {
+                Some(order.maker.as_str())
+            } else {
+                None
+            };
+            breakdown.push(PayoutKind::Seller, seller, rest, false);
+        } else {
+            // the last payout receives the remainder
+            let proceeds = rest;
+            for (idx, payout) in payouts.iter().enumerate() {
+                let amount = if idx + 1 == payouts.len() {
+                    rest
+                } else {
+                    apply_bps(proceeds, part_bps(payout)?)?.min(rest)
+                };
+                rest -= amount;
+                breakdown.push(PayoutKind::Seller, Some(&payout.account), amount, false);
+            }
+        }
+        Ok(breakdown)
+    }
+
+    /// The payouts of a certain kind
+    pub fn payouts_of(&self, kind: PayoutKind) -> impl Iterator<Item = &Payout> + '_ {
+        self.payouts.iter().filter(move |p| p.kind == kind)
+    }
+
+    /// The sum of all payouts of a certain kind
+    pub fn total(&self, kind: PayoutKind) -> Amount {
+        let value = self
+            .payouts_of(kind)
+            .fold(U256::zero(), |acc, p| acc.saturating_add(p.amount.value));
+        Amount::new(value, self.price.decimals)
+    }
+
+    /// What the seller receives after all fees
+    pub fn seller_proceeds(&self) -> Amount {
+        self.total(PayoutKind::Seller)
+    }
+
+    pub fn royalties(&self) -> Amount {
+        self.total(PayoutKind::Royalty)
+    }
+
+    pub fn marketplace_fees(&self) -> Amount {
+        self.total(PayoutKind::Marketplace)
+    }
+
+    pub fn origin_fees(&self) -> Amount {
+        self.total(PayoutKind::OriginFee)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ORDERBOOK: &str = include_str!("../../examples/responses/opensea-orderbook.json");
+
+    const MAKER: &str = "0x3c6137504c38215fea30605b3e364a23c1d3e14f";
+    const FEE_RECIPIENT: &str = "0x5b3256965e7c3cf26e11fcaf296dfc8807c01073";
+    const CREATOR: &str = "0x00000000000000000000000000000000000000c1";
+
+    fn eth(micros: u64) -> U256 {
+        U256::from(micros) * U256::exp10(12)
+    }
+
+    /// `(kind, recipient, amount, paid by buyer)` of every payout
+    fn payouts(breakdown: &FeeBreakdown) -> Vec<(PayoutKind, Option<&str>, U256, bool)> {
+        breakdown
+            .payouts
+            .iter()
+            .map(|p| {
+                (
+                    p.kind,
+                    p.recipient.as_deref(),
+                    p.amount.value,
+                    p.paid_by_buyer,
+                )
+            })
+            .collect()
+    }
+
+    /// The bid of the fixture, the seller pays 2.5% to OpenSea
+    fn opensea_order() -> opensea::Order {
+        let book: opensea::OrderBook = serde_json::from_str(ORDERBOOK).unwrap();
+        book.orders.into_iter().next().unwrap()
+    }
+
+    /// A sell order of the fixture with 7.5% maker fees and 1% taker fees
+    fn opensea_sell_order() -> opensea::Order {
+        let mut order = opensea_order();
+        order.side = opensea::OrderSide::Sell as i64;
+        order.maker_relayer_fee = "750".to_string();
+        order.taker_relayer_fee = "100".to_string();
+        order
+    }
+
+    fn collection_fees(dev_seller_fee_bps: u64) -> CollectionFees {
+        CollectionFees {
+            opensea_buyer_fee_bps: 0,
+            opensea_seller_fee_bps: 250,
+            dev_buyer_fee_bps: 0,
+            dev_seller_fee_bps,
+            payout_address: Some(CREATOR.to_uppercase()),
+        }
+    }
+
+    #[test]
+    fn applies_basis_points() {
+        assert_eq!(apply_bps(eth(1_000_000), 250).unwrap(), eth(25_000));
+        assert_eq!(apply_bps(U256::from(9_999), 1).unwrap(), U256::zero());
+        // no overflow of the intermediate product
+        assert_eq!(apply_bps(U256::MAX, 5_000).unwrap(), U256::MAX / 2);
+        assert_eq!(apply_bps(U256::MAX, 10_000).unwrap(), U256::MAX);
+        let err = apply_bps(U256::MAX, 10_001).unwrap_err();
+        assert!(err.to_string().contains("overflow"), "{}", err);
+
+        assert_eq!(parse_bps(" 250 ").unwrap(), 250);
+        assert_eq!(parse_bps("").unwrap(), 0);
+        assert!(parse_bps("2.5%").is_err());
+    }
+
+    #[test]
+    fn splits_opensea_fees_into_royalties_and_marketplace_fees() {
+        let order = opensea_sell_order();
+        let breakdown =
+            FeeBreakdown::opensea(&order, eth(1_000_000), Some(&collection_fees(500))).unwrap();
+        assert_eq!(breakdown.marketplace, Marketplace::OpenSea);
+        assert_eq!(breakdown.price.value, eth(1_000_000));
+        assert_eq!(breakdown.buyer_pays.value, eth(1_010_000));
+        assert_eq!(
+            payouts(&breakdown),
+            vec![
+                (PayoutKind::Royalty, Some(CREATOR), eth(50_000), false),
+                (
+                    PayoutKind::Marketplace,
+                    Some(FEE_RECIPIENT),
+                    eth(25_000),
+                    false
+                ),
+                (
+                    PayoutKind::Marketplace,
+                    Some(FEE_RECIPIENT),
+                    eth(10_000),
+                    true
+                ),
+                (PayoutKind::Seller, Some(MAKER), eth(925_000), false),
+            ]
+        );
+        assert_eq!(breakdown.royalties().value, eth(50_000));
+        assert_eq!(breakdown.marketplace_fees().value, eth(35_000));
+        assert_eq!(breakdown.seller_proceeds().value, eth(925_000));
+
+        // the royalty can't exceed the fee of the order
+        let breakdown =
+            FeeBreakdown::opensea(&order, eth(1_000_000), Some(&collection_fees(1_000))).unwrap();
+        assert_eq!(breakdown.royalties().value, eth(75_000));
+        assert_eq!(breakdown.seller_proceeds().value, eth(925_000));
+
+        // without the collection fees all of it goes to OpenSea
+        let breakdown = FeeBreakdown::opensea(&order, eth(1_000_000), None).unwrap();
+        assert!(breakdown.royalties().is_zero());
+        assert_eq!(breakdown.marketplace_fees().value, eth(85_000));
+    }
+
+    #[test]
+    fn charges_the_seller_of_opensea_bids() {
+        let breakdown =
+            FeeBreakdown::opensea(&opensea_order(), eth(2_508_000), Some(&collection_fees(0)))
+                .unwrap();
+        assert_eq!(breakdown.currency, Currency::from_address(WETH));
+        assert_eq!(breakdown.buyer_pays.value, eth(2_508_000));
+        assert_eq!(
+            payouts(&breakdown),
+            vec![
+                (
+                    PayoutKind::Marketplace,
+                    Some(FEE_RECIPIENT),
+                    eth(62_700),
+                    false
+                ),
+                // the seller is the taker
+                (PayoutKind::Seller, None, eth(2_445_300), false),
+            ]
+        );
+
+        let mut order = opensea_order();
+        order.fee_method = opensea::FeeMethod::ProtocolFee as i64;
+        let breakdown = FeeBreakdown::opensea(&order, eth(2_508_000), None).unwrap();
+        assert_eq!(
+            payouts(&breakdown),
+            vec![(PayoutKind::Seller, None, eth(2_508_000), false)]
+        );
+    }
+
+    #[test]
+    fn fails_on_overflowing_opensea_royalties() {
+        let mut order = opensea_sell_order();
+        order.maker_relayer_fee = "0".to_string();
+        order.taker_relayer_fee = "0".to_string();
+        let err =
+            FeeBreakdown::opensea(&order, U256::MAX, Some(&collection_fees(20_000))).unwrap_err();
+        assert!(err.to_string().contains("overflow"), "{}", err);
+    }
+
+    const WETH: &str = "0xc02aaa39b223fe8d0a0e5c4f27ead9083c756cc2";
+
+    #[cfg(feature = "rarible")]
+    mod rarible_orders {
+        use super::*;
+        use serde_json::{json, Value};
+
+        // constructed by hand, not recorded from the API
+        const ORDER: &str = include_str!("../../tests/fixtures/rarible-order.json");
+        const ORIGIN: &str = "0x1cf0df2a5a20cd61d68d4489eebbf85b8d39e18a";
+        const SELLER: &str = "0x2c7536e3605d9c16a7a3d7b1898e529396a65c23";
+
+        fn part(account: &str, value: i32) -> rarible::Part {
+            rarible::Part {
+                account: account.to_string(),
+                value,
+            }
+        }
+
+        /// The sell order of the fixture for 1 ETH with a 2.5% origin fee
+        fn order(edit: impl FnOnce(&mut Value)) -> rarible::Order {
+            let mut order: Value = serde_json::from_str(ORDER).unwrap();
+            edit(&mut order);
+            serde_json::from_value(order).unwrap()
+        }
+
+        /// The order turned into a bid of 1 WETH
+        fn bid(order: &mut Value) {
+            let nft = order["make"].take();
+            order["make"] = json!({
+                "assetType": { "assetClass": "ERC20", "contract": WETH },
+                "value": "1000000000000000000",
+            });
+            order["take"] = nft;
+        }
+
+        #[test]
+        fn deducts_royalties_and_origin_fees_from_sales() {
+            let royalties = [part(CREATOR, 1_000)];
+            let breakdown = FeeBreakdown::rarible(
+                &order(|_| {}),
+                eth(1_000_000),
+                &royalties,
+                RARIBLE_PROTOCOL_FEE_BPS,
+            )
+            .unwrap();
+            assert_eq!(breakdown.marketplace, Marketplace::Rarible);
+            assert_eq!(breakdown.currency, Currency::Eth);
+            assert_eq!(breakdown.buyer_pays.value, eth(1_025_000));
+            assert_eq!(
+                payouts(&breakdown),
+                vec![
+                    (PayoutKind::Marketplace, None, eth(25_000), true),
+                    (PayoutKind::Marketplace, None, eth(25_000), false),
+                    (PayoutKind::Royalty, Some(CREATOR), eth(100_000), false),
+                    (PayoutKind::OriginFee, Some(ORIGIN), eth(25_000), false),
+                    (PayoutKind::Seller, Some(SELLER), eth(850_000), false),
+                ]
+            );
+        }
+
+        #[test]
+        fn splits_the_proceeds_among_the_payouts() {
+            let order = order(|order| {
+                order["data"]["payouts"] = json!([
+                    { "account": CREATOR, "value": 3_333 },
+                    { "account": SELLER, "value": 6_667 },
+                ]);
+            });
+            let breakdown = FeeBreakdown::rarible(&order, eth(1_000_000), &[], 250).unwrap();
+            let sellers = breakdown
+                .payouts_of(PayoutKind::Seller)
+                .map(|p| (p.recipient.as_deref(), p.amount.value))
+                .collect::<Vec<_>>();
+            // 95% of the price, the last payout receives the remainder
+            assert_eq!(
+                sellers,
+                vec![(Some(CREATOR), eth(316_635)), (Some(SELLER), eth(633_365))]
+            );
+            assert_eq!(breakdown.seller_proceeds().value, eth(950_000));
+        }
+
+        #[test]
+        fn charges_the_origin_fees_of_bids_to_the_buyer() {
+            let royalties = [part(CREATOR, 1_000)];
+            let breakdown =
+                FeeBreakdown::rarible(&order(bid), eth(1_000_000), &royalties, 250).unwrap();
+            assert_eq!(breakdown.currency, Currency::from_address(WETH));
+            assert_eq!(breakdown.buyer_pays.value, eth(1_050_000));
+            assert_eq!(breakdown.origin_fees().value, eth(25_000));
+            assert_eq!(
+                breakdown.payouts_of(PayoutKind::Seller).collect::<Vec<_>>()[0].recipient,
+                None
+            );
+            assert_eq!(breakdown.seller_proceeds().value, eth(875_000));
+        }
+
+        #[test]
+        fn charges_the_fee_of_legacy_orders_to_the_buyer() {
+            let order = order(|order| order["data"] = json!({ "dataType": "LEGACY", "fee": 300 }));
+            let royalties = [part(CREATOR, 1_000)];
+            let breakdown = FeeBreakdown::rarible(&order, eth(1_000_000), &royalties, 250).unwrap();
+            assert_eq!(
+                payouts(&breakdown),
+                vec![
+                    (PayoutKind::Marketplace, None, eth(30_000), true),
+                    (PayoutKind::Royalty, Some(CREATOR), eth(100_000), false),
+                    (PayoutKind::Seller, Some(SELLER), eth(900_000), false),
+                ]
+            );
+        }
+
+        #[test]
+        fn fails_on_fees_exceeding_the_price() {
+            let err =
+                FeeBreakdown::rarible(&order(|_| {}), eth(1_000_000), &[part(CREATOR, 9_800)], 250)
+                    .unwrap_err();
+            assert!(err.to_string().contains("exceed the price"), "{}", err);
+
+            let err = FeeBreakdown::rarible(&order(|_| {}), U256::MAX, &[part(CREATOR, 20_000)], 0)
+                .unwrap_err();
+            assert!(err.to_string().contains("overflow"), "{}", err);
+
+            let err =
+                FeeBreakdown::rarible(&order(|_| {}), eth(1_000_000), &[part(CREATOR, -1)], 0)
+                    .unwrap_err();
+            assert!(err.to_string().contains("negative"), "{}", err);
+        }
+    }
+}
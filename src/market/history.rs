@@ -0,0 +1,435 @@
+//! Sale histories of tokens and collections across OpenSea and Rarible.
+//!
+//! A [`PriceHistory`] is a time series of [`Trade`]s in which every sale is
+//! recorded once, even if both marketplaces report it. Sales are identified
+//! by their transaction hash and log index; OpenSea doesn't report the log
+//! index, so its sales match any sale of the same token in the same
+//! transaction. [`PriceHistory::candles`] aggregates the series into OHLC
+//! buckets.
+
+use std::collections::{HashMap, HashSet};
+use std::convert::TryFrom;
+use std::path::Path;
+use std::time::Duration;
+
+use chrono::{DateTime, TimeZone, Utc};
+use ethereum_types::U256;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+
+use crate::market::models::{NftId, Trade};
+#[cfg(feature = "rarible")]
+use crate::market::{MarketClient, OPENSEA_PAGE_SIZE, RARIBLE_PAGE_SIZE};
+#[cfg(feature = "rarible")]
+use crate::opensea::query::{EventTypeQuery, OpenSeaEventsQuery};
+#[cfg(feature = "rarible")]
+use crate::rarible::models::{OrderActivityFilter, OrderActivityTypes};
+
+/// Longest candle interval, about 100 years
+const MAX_INTERVAL_SECS: i64 = 100 * 366 * 24 * 60 * 60;
+
+/// Sales ordered by time, without duplicates
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct PriceHistory {
+    trades: Vec<Trade>,
+}
+
+impl PriceHistory {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Reads the history from a json file, an empty history if it doesn't
+    /// exist
+    pub async fn load(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        match tokio::fs::read(path.as_ref()).await {
+            Ok(content) => Ok(serde_json::from_slice(&content)?),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Writes the history as json, replacing the file atomically
+    pub async fn save(&self, path: impl AsRef<Path>) -> anyhow::Result<()> {
+        let path = path.as_ref();
+        let tmp = path.with_extension("tmp");
+        tokio::fs::write(&tmp, serde_json::to_vec(self)?).await?;
+        tokio::fs::rename(&tmp, path).await?;
+        Ok(())
+    }
+
+    /// Adds the trade unless it's already recorded, returns whether it was
+    /// added
+    pub fn insert(&mut self, trade: Trade) -> bool {
+        self.extend(std::iter::once(trade)) == 1
+    }
+
+    /// Adds the trades that aren't recorded yet, returns how many were added
+    pub fn extend(&mut self, trades: impl IntoIterator<Item = Trade>) -> usize {
+        // log indices of the recorded sales by transaction and token
+        let mut recorded = HashMap::<_, Vec<Option<i64>>>::new();
+        let mut ids = HashSet::new();
+        for trade in &self.trades {
+            match trade_key(trade) {
+                Some(key) => recorded.entry(key).or_default().push(trade.log_index),
+                None => {
+                    ids.insert((trade.marketplace, trade.id.clone()));
+                }
+            }
+        }
+
+        let before = self.trades.len();
+        for trade in trades {
+            let duplicate = match trade_key(&trade) {
+                Some(key) => {
+                    let logs = recorded.entry(key).or_default();
+                    let duplicate = match trade.log_index {
+                        Some(_) => logs.contains(&trade.log_index) || logs.contains(&None),
+                        None => !logs.is_empty(),
+                    };
+                    logs.push(trade.log_index);
+                    duplicate
+                }
+                None => !ids.insert((trade.marketplace, trade.id.clone())),
+            };
+            if !duplicate {
+                self.trades.push(trade);
+            }
+        }
+        self.trades.sort_by_key(|trade| trade.timestamp);
+        self.trades.len() - before
+    }
+
+    pub fn trades(&self) -> &[Trade] {
+        &self.trades
+    }
+
+    pub fn len(&self) -> usize {
+        self.trades.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.trades.is_empty()
+    }
+
+    pub fn latest(&self) -> Option<&Trade> {
+        self.trades.last()
+    }
+
+    /// The sales of a single token
+    pub fn token(&self, nft: &NftId) -> PriceHistory {
+        Self {
+            trades: self
+                .trades
+                .iter()
+                .filter(|trade| &trade.nft == nft)
+                .cloned()
+                .collect(),
+        }
+    }
+
+    /// The sales from `start` up to but excluding `end`
+    pub fn between(&self, start: DateTime<Utc>, end: DateTime<Utc>) -> &[Trade] {
+        let from = self.trades.partition_point(|t| t.timestamp < start);
+        let to = self.trades.partition_point(|t| t.timestamp < end);
+        &self.trades[from..to.max(from)]
+    }
+
+    /// Aggregates the sales priced in ETH into buckets of `interval`.
+    ///
+    /// Buckets are aligned to multiples of the interval since the unix
+    /// epoch, buckets without sales are left out. Fails if the interval
+    /// is shorter than a second or longer than the time range of
+    /// `DateTime`.
+    pub fn candles(&self, interval: Duration) -> anyhow::Result<Vec<Candle>> {
+        let secs = i64::try_from(interval.as_secs())
+            .ok()
+            .filter(|secs| (1..=MAX_INTERVAL_SECS).contains(secs))
+            .ok_or_else(|| anyhow::anyhow!("invalid candle interval {:?}", interval))?;
+        let mut candles: Vec<Candle> = Vec::new();
+        for trade in &self.trades {
+            let price = match trade.price {
+                Some(price) => price,
+                None => continue,
+            };
+            let start = trade.timestamp.timestamp().div_euclid(secs) * secs;
+            match candles.last_mut() {
+                Some(candle) if candle.start.timestamp() == start => candle.add(trade, price),
+                _ => {
+                    let end = start
+                        .checked_add(secs)
+                        .and_then(|end| Utc.timestamp_opt(end, 0).single());
+                    let (start, end) = match (Utc.timestamp_opt(start, 0).single(), end) {
+                        (Some(start), Some(end)) => (start, end),
+                        _ => anyhow::bail!("candle at {} is out of range", start),
+                    };
+                    candles.push(Candle::new(start, end, trade, price));
+                }
+            }
+        }
+        Ok(candles)
+    }
+}
+
+/// Identifies sales by transaction and token
+fn trade_key(trade: &Trade) -> Option<(String, NftId)> {
+    let hash = trade.transaction_hash.as_ref()?;
+    Some((hash.to_lowercase(), trade.nft.clone()))
+}
+
+/// The quantity as `Decimal`, saturating at its maximum
+fn to_decimal(quantity: U256) -> Decimal {
+    if quantity.bits() > 64 {
+        Decimal::MAX
+    } else {
+        Decimal::from(quantity.low_u64())
+    }
+}
+
+fn sale_volume(price: Decimal, quantity: U256) -> Decimal {
+    price.saturating_mul(to_decimal(quantity))
+}
+
+/// The sales within an interval
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Candle {
+    pub start: DateTime<Utc>,
+    /// Exclusive
+    pub end: DateTime<Utc>,
+    /// Unit prices in ETH
+    pub open: Decimal,
+    pub high: Decimal,
+    pub low: Decimal,
+    pub close: Decimal,
+    /// Sum of the sale prices in ETH
+    pub volume: Decimal,
+    /// Number of sales
+    pub trades: usize,
+    /// Number of tokens sold
+    pub quantity: U256,
+}
+
+impl Candle {
+    fn new(start: DateTime<Utc>, end: DateTime<Utc>, trade: &Trade, price: Decimal) -> Self {
+        Self {
+            start,
+            end,
+            open: price,
+            high: price,
+            low: price,
+            close: price,
+            volume: sale_volume(price, trade.quantity),
+            trades: 1,
+            quantity: trade.quantity,
+        }
+    }
+
+    fn add(&mut self, trade: &Trade, price: Decimal) {
+        self.high = self.high.max(price);
+        self.low = self.low.min(price);
+        self.close = price;
+        self.volume = self
+            .volume
+            .saturating_add(sale_volume(price, trade.quantity));
+        self.trades += 1;
+        self.quantity = self.quantity.saturating_add(trade.quantity);
+    }
+
+    /// The average unit price, weighted by quantity
+    pub fn average(&self) -> Option<Decimal> {
+        let quantity = to_decimal(self.quantity);
+        if quantity.is_zero() {
+            None
+        } else {
+            Some(self.volume / quantity)
+        }
+    }
+}
+
+#[cfg(feature = "rarible")]
+impl MarketClient {
+    /// The sales of a token on both marketplaces, fetching at most
+    /// `max_pages` pages from each
+    pub async fn token_price_history(
+        &self,
+        contract: &str,
+        token_id: &str,
+        max_pages: u32,
+    ) -> anyhow::Result<PriceHistory> {
+        let query = OpenSeaEventsQuery::default()
+            .asset_contract_address(contract)
+            .token_id(token_id);
+        self.price_history(query, contract, Some(token_id), max_pages)
+            .await
+    }
+
+    /// The sales of all tokens of a collection on both marketplaces,
+    /// fetching at most `max_pages` pages from each
+    pub async fn collection_price_history(
+        &self,
+        contract: &str,
+        max_pages: u32,
+    ) -> anyhow::Result<PriceHistory> {
+        let query = OpenSeaEventsQuery::default().asset_contract_address(contract);
+        self.price_history(query, contract, None, max_pages).await
+    }
+
+    async fn price_history(
+        &self,
+        query: OpenSeaEventsQuery,
+        contract: &str,
+        token_id: Option<&str>,
+        max_pages: u32,
+    ) -> anyhow::Result<PriceHistory> {
+        let (opensea, rarible) = futures::try_join!(
+            self.opensea_sales(query, max_pages),
+            self.rarible_sales(contract, token_id, max_pages),
+        )?;
+        let mut history = PriceHistory::new();
+        // Rarible reports log indices, so its sales are preferred. It also
+        // indexes OpenSea sales, which keep OpenSea as their marketplace.
+        history.extend(rarible.into_iter().chain(opensea));
+        Ok(history)
+    }
+
+    async fn opensea_sales(
+        &self,
+        query: OpenSeaEventsQuery,
+        max_pages: u32,
+    ) -> anyhow::Result<Vec<Trade>> {
+        let mut trades = Vec::new();
+        for page in 0..max_pages {
+            let query = query
+                .clone()
+                .event_type(EventTypeQuery::Successful)
+                .limit(OPENSEA_PAGE_SIZE)
+                .offset(page * OPENSEA_PAGE_SIZE);
+            let events = self.opensea.get_events(&query).await?.asset_events;
+            let done = events.len() < OPENSEA_PAGE_SIZE as usize;
+            trades.extend(events.iter().filter_map(|event| {
+                Trade::try_from(event)
+                    .map_err(|err| log::debug!("Skipping OpenSea sale: {}", err))
+                    .ok()
+            }));
+            if done {
+                break;
+            }
+        }
+        Ok(trades)
+    }
+
+    async fn rarible_sales(
+        &self,
+        contract: &str,
+        token_id: Option<&str>,
+        max_pages: u32,
+    ) -> anyhow::Result<Vec<Trade>> {
+        let filter = OrderActivityFilter {
+            _type: if token_id.is_some() {
+                "by_item"
+            } else {
+                "by_collection"
+            }
+            .to_string(),
+            types: vec![OrderActivityTypes::_Match],
+            users: Vec::new(),
+            contract: contract.to_string(),
+            token_id: token_id.unwrap_or_default().to_string(),
+        };
+        let mut trades = Vec::new();
+        let mut continuation = None;
+        for _ in 0..max_pages {
+            let page = self
+                .rarible
+                .get_order_activities(
+                    filter.clone(),
+                    continuation.as_deref(),
+                    Some(RARIBLE_PAGE_SIZE),
+                )
+                .await?;
+            let done = page.items.is_empty() || page.continuation.is_none();
+            trades.extend(page.items.iter().filter_map(|activity| {
+                Trade::try_from(activity)
+                    .map_err(|err| log::debug!("Skipping Rarible sale: {}", err))
+                    .ok()
+            }));
+            continuation = page.continuation;
+            if done {
+                break;
+            }
+        }
+        Ok(trades)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::market::models::{Currency, Marketplace};
+
+    const TX: &str = "0xAbCdEf0000000000000000000000000000000000000000000000000000000001";
+
+    fn trade(
+        marketplace: Marketplace,
+        id: &str,
+        tx: Option<&str>,
+        log_index: Option<i64>,
+    ) -> Trade {
+        Trade {
+            marketplace,
+            id: id.to_string(),
+            nft: NftId::new("0x00000000000000000000000000000000000000aa", "1"),
+            seller: "0x0000000000000000000000000000000000000001".to_string(),
+            buyer: "0x0000000000000000000000000000000000000002".to_string(),
+            quantity: U256::one(),
+            price: Some(Decimal::ONE),
+            currency: Currency::Eth,
+            timestamp: Utc.timestamp_opt(1_600_000_000, 0).unwrap(),
+            transaction_hash: tx.map(str::to_string),
+            log_index,
+        }
+    }
+
+    #[test]
+    fn records_sales_reported_by_both_marketplaces_once() {
+        let mut history = PriceHistory::new();
+        let rarible = trade(Marketplace::Rarible, "r1", Some(TX), Some(3));
+        let opensea = trade(Marketplace::OpenSea, "o1", Some(&TX.to_lowercase()), None);
+        assert_eq!(history.extend(vec![rarible.clone(), opensea]), 1);
+        assert_eq!(history.trades(), &[rarible]);
+    }
+
+    #[test]
+    fn sales_without_log_index_match_any_log() {
+        let mut history = PriceHistory::new();
+        assert!(history.insert(trade(Marketplace::OpenSea, "o1", Some(TX), None)));
+        assert!(!history.insert(trade(Marketplace::Rarible, "r1", Some(TX), Some(3))));
+        assert!(!history.insert(trade(Marketplace::Rarible, "r2", Some(TX), Some(4))));
+        assert_eq!(history.len(), 1);
+    }
+
+    #[test]
+    fn keeps_sales_of_other_logs_in_the_same_transaction() {
+        let mut history = PriceHistory::new();
+        let trades = vec![
+            trade(Marketplace::Rarible, "r1", Some(TX), Some(3)),
+            trade(
+                Marketplace::Rarible,
+                "r2",
+                Some(&TX.to_uppercase()),
+                Some(4),
+            ),
+            trade(Marketplace::Rarible, "r1", Some(TX), Some(3)),
+        ];
+        assert_eq!(history.extend(trades), 2);
+    }
+
+    #[test]
+    fn identifies_sales_without_transaction_by_id() {
+        let mut history = PriceHistory::new();
+        assert!(history.insert(trade(Marketplace::OpenSea, "1", None, None)));
+        assert!(!history.insert(trade(Marketplace::OpenSea, "1", None, None)));
+        assert!(history.insert(trade(Marketplace::Rarible, "1", None, None)));
+        assert_eq!(history.len(), 2);
+    }
+}
@@ -0,0 +1,551 @@
+//! Distribution of a collection's tokens among its holders.
+//!
+//! A [`HolderSnapshot`] records the balance of every wallet holding tokens of
+//! a collection at a point in time, and derives how concentrated the
+//! collection is: the number of unique holders, the top holders and their
+//! share of the supply, and the Gini coefficient of the balances. Two
+//! snapshots of the same collection are compared with
+//! [`HolderSnapshot::changes_since`].
+//!
+//! [`MarketClient::holders`] snapshots a collection from the ownerships
+//! Rarible reports, completed by the owners of the assets OpenSea lists for
+//! tokens Rarible doesn't know.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::Path;
+
+use chrono::{DateTime, Utc};
+use ethereum_types::U256;
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "rarible")]
+use crate::market::models::NULL_ADDRESS;
+#[cfg(feature = "rarible")]
+use crate::market::{MarketClient, OPENSEA_PAGE_SIZE, RARIBLE_PAGE_SIZE};
+#[cfg(feature = "rarible")]
+use crate::opensea::models::OpenSeaAsset;
+#[cfg(feature = "rarible")]
+use crate::opensea::query::OpenSeaAssetsQuery;
+#[cfg(feature = "rarible")]
+use crate::rarible::models::NftItem;
+
+/// Number of items whose ownerships are fetched concurrently
+#[cfg(feature = "rarible")]
+const CONCURRENT_REQUESTS: usize = 4;
+
+/// The tokens of a collection held by a wallet
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WalletHolding {
+    /// Address of the wallet, lowercase
+    pub owner: String,
+    /// Balance per token id
+    pub tokens: BTreeMap<String, U256>,
+}
+
+impl WalletHolding {
+    /// The number of tokens held, ERC1155 tokens count with their balance
+    pub fn balance(&self) -> U256 {
+        self.tokens
+            .values()
+            .fold(U256::zero(), |sum, balance| sum.saturating_add(*balance))
+    }
+}
+
+/// The holders of a collection at a point in time
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HolderSnapshot {
+    /// Address of the collection's contract, lowercase
+    pub contract: String,
+    pub timestamp: DateTime<Utc>,
+    /// Holdings by lowercase wallet address
+    pub holders: BTreeMap<String, WalletHolding>,
+}
+
+impl HolderSnapshot {
+    pub fn new(contract: impl AsRef<str>, timestamp: DateTime<Utc>) -> Self {
+        Self {
+            contract: contract.as_ref().to_lowercase(),
+            timestamp,
+            holders: BTreeMap::new(),
+        }
+    }
+
+    /// Reads a snapshot from a json file
+    pub async fn load(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        Ok(serde_json::from_slice(
+            &tokio::fs::read(path.as_ref()).await?,
+        )?)
+    }
+
+    /// Writes the snapshot as json, replacing the file atomically
+    pub async fn save(&self, path: impl AsRef<Path>) -> anyhow::Result<()> {
+        let path = path.as_ref();
+        let tmp = path.with_extension("tmp");
+        tokio::fs::write(&tmp, serde_json::to_vec(self)?).await?;
+        tokio::fs::rename(&tmp, path).await?;
+        Ok(())
+    }
+
+    /// Records that `owner` holds `balance` of the token, empty balances are
+    /// ignored
+    pub fn insert(&mut self, owner: impl AsRef<str>, token_id: impl Into<String>, balance: U256) {
+        if balance.is_zero() {
+            return;
+        }
+        let owner = owner.as_ref().to_lowercase();
+        self.holders
+            .entry(owner.clone())
+            .or_insert_with(|| WalletHolding {
+                owner,
+                tokens: BTreeMap::new(),
+            })
+            .tokens
+            .insert(token_id.into(), balance);
+    }
+
+    pub fn unique_holders(&self) -> usize {
+        self.holders.len()
+    }
+
+    /// The number of distinct tokens held
+    pub fn tokens(&self) -> usize {
+        self.holders
+            .values()
+            .flat_map(|holding| holding.tokens.keys())
+            .collect::<BTreeSet<_>>()
+            .len()
+    }
+
+    /// The sum of all balances
+    pub fn supply(&self) -> U256 {
+        self.holders.values().fold(U256::zero(), |sum, holding| {
+            sum.saturating_add(holding.balance())
+        })
+    }
+
+    pub fn holding(&self, owner: &str) -> Option<&WalletHolding> {
+        self.holders.get(&owner.to_lowercase())
+    }
+
+    /// The `n` wallets with the highest balances, highest first
+    pub fn top_holders(&self, n: usize) -> Vec<&WalletHolding> {
+        let mut holders = self.holders.values().collect::<Vec<_>>();
+        holders.sort_by_key(|holding| std::cmp::Reverse(holding.balance()));
+        holders.truncate(n);
+        holders
+    }
+
+    /// The share of the supply held by the `n` wallets with the highest
+    /// balances, `0.25` is 25%
+    pub fn top_share(&self, n: usize) -> f64 {
+        let supply = to_f64(self.supply());
+        if supply == 0. {
+            return 0.;
+        }
+        let top = self
+            .top_holders(n)
+            .iter()
+            .map(|holding| to_f64(holding.balance()))
+            .sum::<f64>();
+        top / supply
+    }
+
+    /// The number of wallets by the number of tokens they hold
+    pub fn distribution(&self) -> BTreeMap<U256, usize> {
+        let mut distribution = BTreeMap::new();
+        for holding in self.holders.values() {
+            *distribution.entry(holding.balance()).or_insert(0) += 1;
+        }
+        distribution
+    }
+
+    /// The Gini coefficient of the balances, 0 if every wallet holds the
+    /// same number of tokens, approaching 1 the more tokens are held by a
+    /// single wallet
+    pub fn gini(&self) -> f64 {
+        let mut balances = self
+            .holders
+            .values()
+            .map(|holding| to_f64(holding.balance()))
+            .collect::<Vec<_>>();
+        let total = balances.iter().sum::<f64>();
+        if balances.is_empty() || total == 0. {
+            return 0.;
+        }
+        balances.sort_by(f64::total_cmp);
+        let n = balances.len() as f64;
+        let weighted = balances
+            .iter()
+            .enumerate()
+            .map(|(idx, balance)| (idx + 1) as f64 * balance)
+            .sum::<f64>();
+        2. * weighted / (n * total) - (n + 1.) / n
+    }
+
+    /// How the balances changed from the `earlier` snapshot to this one
+    pub fn changes_since(&self, earlier: &HolderSnapshot) -> HolderChanges {
+        let owners = self
+            .holders
+            .keys()
+            .chain(earlier.holders.keys())
+            .collect::<BTreeSet<_>>();
+        let mut changes = owners
+            .into_iter()
+            .filter_map(|owner| {
+                let before = earlier
+                    .holders
+                    .get(owner)
+                    .map(WalletHolding::balance)
+                    .unwrap_or_default();
+                let after = self
+                    .holders
+                    .get(owner)
+                    .map(WalletHolding::balance)
+                    .unwrap_or_default();
+                if before == after {
+                    return None;
+                }
+                Some(HolderChange {
+                    owner: owner.clone(),
+                    before,
+                    after,
+                })
+            })
+            .collect::<Vec<_>>();
+        changes.sort_by_key(|change| std::cmp::Reverse(change.amount()));
+        HolderChanges {
+            start: earlier.timestamp,
+            end: self.timestamp,
+            holders_before: earlier.unique_holders(),
+            holders_after: self.unique_holders(),
+            changes,
+        }
+    }
+}
+
+fn to_f64(value: U256) -> f64 {
+    if value > U256::from(u128::MAX) {
+        u128::MAX as f64
+    } else {
+        value.as_u128() as f64
+    }
+}
+
+/// How the balance of a wallet changed between two snapshots
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HolderChange {
+    pub owner: String,
+    pub before: U256,
+    pub after: U256,
+}
+
+impl HolderChange {
+    /// The wallet held nothing before
+    pub fn is_new(&self) -> bool {
+        self.before.is_zero()
+    }
+
+    /// The wallet holds nothing anymore
+    pub fn is_exit(&self) -> bool {
+        self.after.is_zero()
+    }
+
+    pub fn is_increase(&self) -> bool {
+        self.after > self.before
+    }
+
+    /// The absolute change of the balance
+    pub fn amount(&self) -> U256 {
+        if self.after > self.before {
+            self.after - self.before
+        } else {
+            self.before - self.after
+        }
+    }
+}
+
+/// The changes between two snapshots
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HolderChanges {
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+    pub holders_before: usize,
+    pub holders_after: usize,
+    /// Wallets whose balance changed, largest change first
+    pub changes: Vec<HolderChange>,
+}
+
+impl HolderChanges {
+    pub fn new_holders(&self) -> impl Iterator<Item = &HolderChange> {
+        self.changes.iter().filter(|change| change.is_new())
+    }
+
+    pub fn exited_holders(&self) -> impl Iterator<Item = &HolderChange> {
+        self.changes.iter().filter(|change| change.is_exit())
+    }
+
+    /// Wallets that increased their balance
+    pub fn accumulating(&self) -> impl Iterator<Item = &HolderChange> {
+        self.changes.iter().filter(|change| change.is_increase())
+    }
+
+    /// Wallets that decreased their balance
+    pub fn distributing(&self) -> impl Iterator<Item = &HolderChange> {
+        self.changes.iter().filter(|change| !change.is_increase())
+    }
+}
+
+#[cfg(feature = "rarible")]
+impl MarketClient {
+    /// Snapshots the holders of the collection, fetching at most 200 pages
+    /// of tokens from each marketplace
+    pub async fn holders(&self, contract: &str) -> anyhow::Result<HolderSnapshot> {
+        self.holders_with_pages(contract, 200).await
+    }
+
+    /// Snapshots the holders of the collection.
+    ///
+    /// Balances of tokens with several owners are fetched from Rarible's
+    /// ownerships, tokens Rarible doesn't know are added with the owner
+    /// OpenSea reports.
+    pub async fn holders_with_pages(
+        &self,
+        contract: &str,
+        max_pages: u32,
+    ) -> anyhow::Result<HolderSnapshot> {
+        use futures::{StreamExt, TryStreamExt};
+
+        let contract = contract.to_lowercase();
+        let (items, assets) = futures::try_join!(
+            self.collection_items(&contract, max_pages),
+            self.collection_assets(&contract, max_pages),
+        )?;
+        let mut snapshot = HolderSnapshot::new(&contract, Utc::now());
+
+        // Rarible doesn't return deleted items by collection, tokens burned
+        // since Rarible indexed them are only skipped if OpenSea reports the
+        // null address as their owner
+        let known = items
+            .iter()
+            .map(|item| item.token_id.clone())
+            .collect::<BTreeSet<_>>();
+        let ownerships =
+            futures::stream::iter(items.iter().filter(|item| item.deleted != Some(true)))
+                .map(|item| self.item_balances(item))
+                .buffer_unordered(CONCURRENT_REQUESTS)
+                .try_collect::<Vec<_>>()
+                .await?;
+        for (token_id, owner, balance) in ownerships.into_iter().flatten() {
+            snapshot.insert(owner, token_id, balance);
+        }
+
+        for asset in &assets {
+            let token_id = match asset.token_id.as_deref() {
+                Some(token_id) if !known.contains(token_id) => token_id,
+                _ => continue,
+            };
+            if asset.asset_contract.address.to_lowercase() == contract
+                && asset.owner.address != NULL_ADDRESS
+            {
+                snapshot.insert(&asset.owner.address, token_id, U256::one());
+            }
+        }
+        Ok(snapshot)
+    }
+
+    async fn collection_items(
+        &self,
+        contract: &str,
+        max_pages: u32,
+    ) -> anyhow::Result<Vec<NftItem>> {
+        let mut items = Vec::new();
+        let mut continuation = None;
+        for _ in 0..max_pages {
+            let page = self
+                .rarible
+                .get_nft_items_by_collection(
+                    contract,
+                    continuation.as_deref(),
+                    Some(RARIBLE_PAGE_SIZE),
+                    Some(false),
+                )
+                .await?;
+            let done = page.items.is_empty() || page.continuation.is_none();
+            items.extend(page.items);
+            continuation = page.continuation;
+            if done {
+                break;
+            }
+        }
+        Ok(items)
+    }
+
+    async fn collection_assets(
+        &self,
+        contract: &str,
+        max_pages: u32,
+    ) -> anyhow::Result<Vec<OpenSeaAsset>> {
+        let mut assets = Vec::new();
+        for page in 0..max_pages {
+            let query = OpenSeaAssetsQuery::default()
+                .asset_contract_address(contract)
+                .limit(OPENSEA_PAGE_SIZE)
+                .offset(page * OPENSEA_PAGE_SIZE);
+            let batch = self.opensea.get_assets(&query).await?.assets;
+            let done = batch.len() < OPENSEA_PAGE_SIZE as usize;
+            assets.extend(batch);
+            if done {
+                break;
+            }
+        }
+        Ok(assets)
+    }
+
+    /// The balances of the owners of the item as `(token_id, owner,
+    /// balance)`, items with a single owner hold their whole supply
+    async fn item_balances(&self, item: &NftItem) -> anyhow::Result<Vec<(String, String, U256)>> {
+        if let [owner] = item.owners.as_slice() {
+            let supply = U256::from_dec_str(&item.supply).unwrap_or_else(|_| U256::one());
+            return Ok(vec![(item.token_id.clone(), owner.clone(), supply)]);
+        }
+        let mut balances = Vec::new();
+        let mut continuation = None;
+        loop {
+            let page = self
+                .rarible
+                .get_nft_ownerships_by_item(
+                    &item.contract,
+                    &item.token_id,
+                    continuation.as_deref(),
+                    Some(RARIBLE_PAGE_SIZE),
+                )
+                .await?;
+            let done = page.ownerships.is_empty() || page.continuation.is_none();
+            balances.extend(page.ownerships.into_iter().filter_map(|ownership| {
+                let balance = U256::from_dec_str(&ownership.value).ok()?;
+                Some((item.token_id.clone(), ownership.owner, balance))
+            }));
+            continuation = page.continuation;
+            if done {
+                break;
+            }
+        }
+        Ok(balances)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    const COLLECTION: &str = "0x00000000000000000000000000000000000000aa";
+
+    /// A snapshot of the wallets holding a token each for every balance
+    fn snapshot(days: i64, balances: &[(&str, u64)]) -> HolderSnapshot {
+        let timestamp = Utc
+            .timestamp_opt(1_600_000_000 + days * 24 * 60 * 60, 0)
+            .unwrap();
+        let mut snapshot = HolderSnapshot::new(COLLECTION, timestamp);
+        for (owner, balance) in balances {
+            for token in 0..*balance {
+                snapshot.insert(owner, format!("{}-{}", owner, token), U256::one());
+            }
+        }
+        snapshot
+    }
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!(
+            (actual - expected).abs() < 1e-9,
+            "{} != {}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn counts_holders_and_balances() {
+        let mut snapshot = snapshot(0, &[("0xA1", 2), ("0xb1", 1)]);
+        // an ERC1155 balance and an empty one
+        snapshot.insert("0xc1", "edition", U256::from(5));
+        snapshot.insert("0xd1", "edition", U256::zero());
+        assert_eq!(snapshot.unique_holders(), 3);
+        assert_eq!(snapshot.tokens(), 4);
+        assert_eq!(snapshot.supply(), U256::from(8));
+        assert_eq!(snapshot.holding("0xa1").unwrap().balance(), U256::from(2));
+        assert_eq!(
+            snapshot
+                .top_holders(2)
+                .iter()
+                .map(|holding| holding.owner.as_str())
+                .collect::<Vec<_>>(),
+            vec!["0xc1", "0xa1"]
+        );
+        assert_eq!(
+            snapshot.distribution(),
+            vec![(U256::one(), 1), (U256::from(2), 1), (U256::from(5), 1)]
+                .into_iter()
+                .collect()
+        );
+    }
+
+    #[test]
+    fn computes_the_gini_coefficient() {
+        assert_eq!(snapshot(0, &[]).gini(), 0.);
+        assert_close(snapshot(0, &[("0xa1", 3)]).gini(), 0.);
+        assert_close(
+            snapshot(0, &[("0xa1", 2), ("0xb1", 2), ("0xc1", 2)]).gini(),
+            0.,
+        );
+        // sum of |x_i - x_j| / (2 n^2 mean) = 4 / (2 * 4 * 2)
+        assert_close(snapshot(0, &[("0xa1", 1), ("0xb1", 3)]).gini(), 0.25);
+        // 6 * 8 / (2 * 16 * 3)
+        let whale = snapshot(0, &[("0xa1", 1), ("0xb1", 1), ("0xc1", 1), ("0xd1", 9)]);
+        assert_close(whale.gini(), 0.5);
+    }
+
+    #[test]
+    fn computes_the_share_of_the_top_holders() {
+        assert_eq!(snapshot(0, &[]).top_share(1), 0.);
+        let whale = snapshot(0, &[("0xa1", 1), ("0xb1", 1), ("0xc1", 1), ("0xd1", 9)]);
+        assert_close(whale.top_share(1), 0.75);
+        assert_close(whale.top_share(2), 10. / 12.);
+        assert_close(whale.top_share(10), 1.);
+    }
+
+    #[test]
+    fn compares_snapshots() {
+        let earlier = snapshot(0, &[("0xa1", 2), ("0xb1", 1), ("0xc1", 1)]);
+        let later = snapshot(7, &[("0xa1", 1), ("0xc1", 4), ("0xd1", 2)]);
+        let changes = later.changes_since(&earlier);
+        assert_eq!(changes.start, earlier.timestamp);
+        assert_eq!(changes.end, later.timestamp);
+        assert_eq!((changes.holders_before, changes.holders_after), (3, 3));
+
+        let owners = |changes: Vec<&HolderChange>| {
+            changes
+                .iter()
+                .map(|change| change.owner.clone())
+                .collect::<Vec<_>>()
+        };
+        // largest change first
+        assert_eq!(
+            owners(changes.changes.iter().collect()),
+            vec!["0xc1", "0xd1", "0xa1", "0xb1"]
+        );
+        assert_eq!(owners(changes.new_holders().collect()), vec!["0xd1"]);
+        assert_eq!(owners(changes.exited_holders().collect()), vec!["0xb1"]);
+        assert_eq!(
+            owners(changes.accumulating().collect()),
+            vec!["0xc1", "0xd1"]
+        );
+        assert_eq!(
+            owners(changes.distributing().collect()),
+            vec!["0xa1", "0xb1"]
+        );
+        assert_eq!(changes.changes[0].amount(), U256::from(3));
+
+        assert!(later.changes_since(&later).changes.is_empty());
+    }
+}
@@ -0,0 +1,247 @@
+//! Marketplace independent view on the OpenSea and Rarible order books
+
+pub mod alerts;
+#[cfg(feature = "rarible")]
+pub mod arbitrage;
+pub mod fees;
+pub mod history;
+pub mod holders;
+pub mod models;
+#[cfg(feature = "rarible")]
+pub mod portfolio;
+pub mod rarity;
+pub mod stats;
+pub mod wash;
+
+#[cfg(feature = "rarible")]
+use crate::market::models::*;
+#[cfg(feature = "rarible")]
+use crate::opensea::models::OrderSide;
+#[cfg(feature = "rarible")]
+use crate::opensea::query::OrderQuery;
+#[cfg(feature = "rarible")]
+use crate::ApiClient;
+#[cfg(feature = "rarible")]
+use log::debug;
+#[cfg(feature = "rarible")]
+use std::convert::TryFrom;
+
+/// Maximum page size the OpenSea orderbook supports
+#[cfg(feature = "rarible")]
+const OPENSEA_PAGE_SIZE: u32 = 50;
+
+/// Page size used for Rarible order requests
+#[cfg(feature = "rarible")]
+const RARIBLE_PAGE_SIZE: i32 = 50;
+
+/// Queries the order books of OpenSea and Rarible and merges them into
+/// [`AggregatedBook`]s.
+#[cfg(feature = "rarible")]
+#[derive(Clone)]
+pub struct MarketClient {
+    /// Client for the OpenSea API
+    opensea: ApiClient,
+    /// Client for the Rarible protocol API
+    rarible: ApiClient,
+}
+
+#[cfg(feature = "rarible")]
+impl MarketClient {
+    pub fn new(opensea: ApiClient, rarible: ApiClient) -> Self {
+        Self { opensea, rarible }
+    }
+
+    /// Creates a client for the mainnet APIs of both marketplaces
+    pub fn mainnet() -> anyhow::Result<Self> {
+        Ok(Self::new(
+            ApiClient::builder().build(crate::opensea::API_BASE_MAINNET)?,
+            ApiClient::builder().build(crate::rarible::API_BASE_MAINNET)?,
+        ))
+    }
+
+    pub fn opensea(&self) -> &ApiClient {
+        &self.opensea
+    }
+
+    pub fn rarible(&self) -> &ApiClient {
+        &self.rarible
+    }
+
+    /// Fetches listings and offers for a single token from both marketplaces
+    pub async fn token_book(
+        &self,
+        contract: &str,
+        token_id: &str,
+    ) -> anyhow::Result<AggregatedBook> {
+        let query = OrderQuery::default()
+            .asset_contract_address(contract)
+            .token_id(token_id)
+            .limit(OPENSEA_PAGE_SIZE);
+        let (opensea, rarible_sells, rarible_bids) = futures::try_join!(
+            self.opensea_book(query),
+            self.rarible.get_sell_orders_by_item(
+                contract,
+                token_id,
+                None,
+                None,
+                None,
+                Some(RARIBLE_PAGE_SIZE)
+            ),
+            self.rarible.get_order_bids_by_item(
+                contract,
+                token_id,
+                None,
+                None,
+                None,
+                Some(RARIBLE_PAGE_SIZE)
+            ),
+        )?;
+
+        let mut book = opensea;
+        book.listings
+            .extend(normalize::<Listing, _>(&rarible_sells.orders));
+        book.offers
+            .extend(normalize::<Offer, _>(&rarible_bids.orders));
+        Ok(book)
+    }
+
+    /// Fetches listings and offers for all tokens of a collection.
+    ///
+    /// Rarible has no endpoint for bids of a whole collection, so offers are
+    /// only fetched from OpenSea.
+    pub async fn collection_book(&self, contract: &str) -> anyhow::Result<AggregatedBook> {
+        let query = OrderQuery::default()
+            .asset_contract_address(contract)
+            .limit(OPENSEA_PAGE_SIZE);
+        let (opensea, rarible_sells) = futures::try_join!(
+            self.opensea_book(query),
+            self.rarible.get_sell_orders_by_collection(
+                contract,
+                None,
+                None,
+                Some(RARIBLE_PAGE_SIZE)
+            ),
+        )?;
+
+        let mut book = opensea;
+        book.listings
+            .extend(normalize::<Listing, _>(&rarible_sells.orders));
+        Ok(book)
+    }
+
+    /// Fetches the listings of all tokens of a collection, reading at most
+    /// `max_pages` pages from each marketplace.
+    ///
+    /// Neither marketplace sorts the listings by price, so the floor is only
+    /// known once all pages are read.
+    pub async fn collection_listings(
+        &self,
+        contract: &str,
+        max_pages: u32,
+    ) -> anyhow::Result<Vec<Listing>> {
+        let (mut opensea, rarible) = futures::try_join!(
+            self.opensea_collection_listings(contract, max_pages),
+            self.rarible_collection_listings(contract, max_pages),
+        )?;
+        opensea.extend(rarible);
+        Ok(opensea)
+    }
+
+    async fn opensea_collection_listings(
+        &self,
+        contract: &str,
+        max_pages: u32,
+    ) -> anyhow::Result<Vec<Listing>> {
+        let mut listings = Vec::new();
+        for page in 0..max_pages {
+            let query = OrderQuery::default()
+                .asset_contract_address(contract)
+                .side(OrderSide::Sell)
+                .limit(OPENSEA_PAGE_SIZE)
+                .offset(page * OPENSEA_PAGE_SIZE);
+            let orders = self.opensea.get_orders(&query).await?.orders;
+            listings.extend(normalize::<Listing, _>(&orders));
+            if orders.len() < OPENSEA_PAGE_SIZE as usize {
+                break;
+            }
+        }
+        Ok(listings)
+    }
+
+    async fn rarible_collection_listings(
+        &self,
+        contract: &str,
+        max_pages: u32,
+    ) -> anyhow::Result<Vec<Listing>> {
+        let mut listings = Vec::new();
+        let mut continuation = None;
+        for _ in 0..max_pages {
+            let page = self
+                .rarible
+                .get_sell_orders_by_collection(
+                    contract,
+                    None,
+                    continuation.as_deref(),
+                    Some(RARIBLE_PAGE_SIZE),
+                )
+                .await?;
+            listings.extend(normalize::<Listing, _>(&page.orders));
+            if page.orders.is_empty() || page.continuation.is_none() {
+                break;
+            }
+            continuation = page.continuation;
+        }
+        Ok(listings)
+    }
+
+    /// The cheapest active listing of the token across all marketplaces
+    pub async fn best_ask(
+        &self,
+        contract: &str,
+        token_id: &str,
+    ) -> anyhow::Result<Option<Listing>> {
+        let mut book = self.token_book(contract, token_id).await?;
+        book.retain_active(chrono::Utc::now());
+        Ok(book.best_ask().cloned())
+    }
+
+    /// The highest active offer for the token across all marketplaces
+    pub async fn best_bid(&self, contract: &str, token_id: &str) -> anyhow::Result<Option<Offer>> {
+        let mut book = self.token_book(contract, token_id).await?;
+        book.retain_active(chrono::Utc::now());
+        Ok(book.best_bid().cloned())
+    }
+
+    /// Fetches sell and buy orders from the OpenSea orderbook
+    async fn opensea_book(&self, query: OrderQuery) -> anyhow::Result<AggregatedBook> {
+        let sells = query.clone().side(OrderSide::Sell);
+        let buys = query.side(OrderSide::Buy);
+        let (sells, buys) = futures::try_join!(
+            self.opensea.get_orders(&sells),
+            self.opensea.get_orders(&buys),
+        )?;
+        Ok(AggregatedBook {
+            listings: normalize(&sells.orders),
+            offers: normalize(&buys.orders),
+        })
+    }
+}
+
+/// Converts all orders that can be represented as `T`, other orders, like
+/// bundles, are skipped.
+#[cfg(feature = "rarible")]
+fn normalize<'a, T, O>(orders: &'a [O]) -> Vec<T>
+where
+    T: TryFrom<&'a O, Error = anyhow::Error>,
+{
+    orders
+        .iter()
+        .filter_map(|order| match T::try_from(order) {
+            Ok(order) => Some(order),
+            Err(err) => {
+                debug!("Skipping order: {}", err);
+                None
+            }
+        })
+        .collect()
+}
@@ -0,0 +1,778 @@
+use std::cmp::Ordering;
+use std::convert::TryFrom;
+use std::fmt;
+
+use chrono::{DateTime, TimeZone, Utc};
+use ethereum_types::U256;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+
+use crate::opensea::models as opensea;
+#[cfg(feature = "rarible")]
+use crate::rarible::models as rarible;
+
+/// Address of the WETH contract on mainnet
+pub const WETH_MAINNET: &str = "0xc02aaa39b223fe8d0a0e5c4f27ead9083c756cc2";
+
+/// The null address, used by both marketplaces for ether
+pub const NULL_ADDRESS: &str = "0x0000000000000000000000000000000000000000";
+
+/// The marketplaces an order can originate from
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Serialize, Deserialize)]
+pub enum Marketplace {
+    OpenSea,
+    Rarible,
+}
+
+impl fmt::Display for Marketplace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Marketplace::OpenSea => f.write_str("OpenSea"),
+            Marketplace::Rarible => f.write_str("Rarible"),
+        }
+    }
+}
+
+/// Identifies a single token of a contract
+#[derive(Clone, Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Serialize, Deserialize)]
+pub struct NftId {
+    /// Address of the token contract, lowercase
+    pub contract: String,
+    pub token_id: String,
+}
+
+impl NftId {
+    pub fn new(contract: impl AsRef<str>, token_id: impl Into<String>) -> Self {
+        Self {
+            contract: contract.as_ref().to_lowercase(),
+            token_id: token_id.into(),
+        }
+    }
+
+    /// The item id as used by the Rarible API: `<contract>:<token_id>`
+    pub fn item_id(&self) -> String {
+        format!("{}:{}", self.contract, self.token_id)
+    }
+}
+
+impl fmt::Display for NftId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.contract, self.token_id)
+    }
+}
+
+/// The currency a price is denominated in
+#[derive(Clone, Debug, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub enum Currency {
+    /// Native ether
+    Eth,
+    /// An ERC20 token, identified by its lowercase contract address
+    Erc20(String),
+}
+
+impl Currency {
+    /// Creates the currency for the token address, the null address is ether
+    pub fn from_address(address: impl AsRef<str>) -> Self {
+        let address = address.as_ref().to_lowercase();
+        if address == NULL_ADDRESS {
+            Currency::Eth
+        } else {
+            Currency::Erc20(address)
+        }
+    }
+
+    /// Whether this is ether or WETH, which can be compared one to one
+    pub fn is_ether(&self) -> bool {
+        match self {
+            Currency::Eth => true,
+            Currency::Erc20(address) => address == WETH_MAINNET,
+        }
+    }
+}
+
+impl fmt::Display for Currency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Currency::Eth => f.write_str("ETH"),
+            Currency::Erc20(address) => f.write_str(address),
+        }
+    }
+}
+
+/// A token amount in its base unit together with the token's decimals
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct Amount {
+    pub value: U256,
+    pub decimals: u32,
+}
+
+impl Amount {
+    pub fn new(value: impl Into<U256>, decimals: u32) -> Self {
+        Self {
+            value: value.into(),
+            decimals,
+        }
+    }
+
+    /// An amount of wei
+    pub fn wei(value: impl Into<U256>) -> Self {
+        Self::new(value, 18)
+    }
+
+    /// Parses an amount in base units, like `"2508000000000000000"`.
+    ///
+    /// The OpenSea API reports some base unit amounts with a fractional part
+    /// of zeros (`"2508000000000000000.000000000"`), which is discarded.
+    pub fn from_base_units(value: impl AsRef<str>, decimals: u32) -> anyhow::Result<Self> {
+        let value = value.as_ref().trim();
+        let int = value.split('.').next().unwrap_or_default();
+        let value = if int.is_empty() {
+            U256::zero()
+        } else {
+            U256::from_dec_str(int)?
+        };
+        Ok(Self::new(value, decimals))
+    }
+
+    /// Parses a human readable amount like `"1.5"` into base units
+    pub fn from_decimal_str(value: impl AsRef<str>, decimals: u32) -> anyhow::Result<Self> {
+        let value = value.as_ref().trim();
+        let (int, frac) = match value.find('.') {
+            Some(idx) => (&value[..idx], &value[idx + 1..]),
+            None => (value, ""),
+        };
+        anyhow::ensure!(
+            frac.len() <= decimals as usize,
+            "`{}` has more than {} decimals",
+            value,
+            decimals
+        );
+        let digits = format!("{}{:0<width$}", int, frac, width = decimals as usize);
+        let digits = digits.trim_start_matches('0');
+        let value = if digits.is_empty() {
+            U256::zero()
+        } else {
+            U256::from_dec_str(digits)?
+        };
+        Ok(Self::new(value, decimals))
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.value.is_zero()
+    }
+
+    /// Converts the amount into a float, this may lose precision
+    pub fn to_f64(&self) -> f64 {
+        self.to_string().parse().unwrap_or_default()
+    }
+
+    /// Converts the amount into a decimal, `None` if it has more than 28
+    /// significant digits
+    pub fn to_decimal(&self) -> Option<Decimal> {
+        Decimal::from_str_exact(&self.to_string()).ok()
+    }
+
+    /// Converts a decimal into an amount with its scale as decimals, `None`
+    /// if it is negative
+    pub fn from_decimal(value: Decimal) -> Option<Self> {
+        let mantissa = u128::try_from(value.mantissa()).ok()?;
+        Some(Self::new(mantissa, value.scale()))
+    }
+
+    /// Divides the amount by `quantity`, used to get a per unit price
+    pub fn per_unit(&self, quantity: U256) -> Self {
+        if quantity.is_zero() {
+            *self
+        } else {
+            Self::new(self.value / quantity, self.decimals)
+        }
+    }
+}
+
+impl PartialOrd for Amount {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        if self.decimals == other.decimals {
+            Some(self.value.cmp(&other.value))
+        } else {
+            None
+        }
+    }
+}
+
+impl fmt::Display for Amount {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let digits = self.value.to_string();
+        let decimals = self.decimals as usize;
+        if decimals == 0 {
+            return f.write_str(&digits);
+        }
+        let (int, frac) = if digits.len() > decimals {
+            let (int, frac) = digits.split_at(digits.len() - decimals);
+            (int.to_string(), frac.to_string())
+        } else {
+            (
+                "0".to_string(),
+                format!("{:0>width$}", digits, width = decimals),
+            )
+        };
+        let frac = frac.trim_end_matches('0');
+        if frac.is_empty() {
+            f.write_str(&int)
+        } else {
+            write!(f, "{}.{}", int, frac)
+        }
+    }
+}
+
+/// Reference to the order as it was returned by the marketplace
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum NativeOrder {
+    OpenSea(Box<opensea::Order>),
+    #[cfg(feature = "rarible")]
+    Rarible(Box<rarible::Order>),
+    #[cfg(feature = "rarible")]
+    RaribleBid(Box<rarible::OrderBid>),
+}
+
+/// An NFT offered for sale, an ask
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Listing {
+    pub marketplace: Marketplace,
+    /// Hash of the order on its marketplace
+    pub hash: String,
+    pub maker: String,
+    pub nft: NftId,
+    /// Number of units offered, always `1` for ERC721
+    pub quantity: U256,
+    /// Total price for all units
+    pub price: Amount,
+    pub currency: Currency,
+    pub listed_at: Option<DateTime<Utc>>,
+    /// When the order expires, `None` if it never does
+    pub expiry: Option<DateTime<Utc>>,
+    pub order: NativeOrder,
+}
+
+impl Listing {
+    /// The price of a single unit
+    pub fn unit_price(&self) -> Amount {
+        self.price.per_unit(self.quantity)
+    }
+
+    /// Whether the listing is past its expiry at `now`
+    pub fn is_expired_at(&self, now: DateTime<Utc>) -> bool {
+        self.expiry.map(|expiry| expiry <= now).unwrap_or_default()
+    }
+}
+
+/// A bid for an NFT
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Offer {
+    pub marketplace: Marketplace,
+    /// Hash of the order on its marketplace
+    pub hash: String,
+    pub maker: String,
+    pub nft: NftId,
+    /// Number of units the bid is for, always `1` for ERC721
+    pub quantity: U256,
+    /// Total price offered for all units
+    pub price: Amount,
+    pub currency: Currency,
+    pub listed_at: Option<DateTime<Utc>>,
+    /// When the order expires, `None` if it never does
+    pub expiry: Option<DateTime<Utc>>,
+    pub order: NativeOrder,
+}
+
+impl Offer {
+    /// The price offered for a single unit
+    pub fn unit_price(&self) -> Amount {
+        self.price.per_unit(self.quantity)
+    }
+
+    /// Whether the offer is past its expiry at `now`
+    pub fn is_expired_at(&self, now: DateTime<Utc>) -> bool {
+        self.expiry.map(|expiry| expiry <= now).unwrap_or_default()
+    }
+}
+
+/// Listings and offers merged from all marketplaces
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct AggregatedBook {
+    pub listings: Vec<Listing>,
+    pub offers: Vec<Offer>,
+}
+
+impl AggregatedBook {
+    /// The cheapest listing per unit that is priced in ETH or WETH.
+    pub fn best_ask(&self) -> Option<&Listing> {
+        self.listings
+            .iter()
+            .filter(|l| l.currency.is_ether())
+            .min_by(|a, b| a.unit_price().value.cmp(&b.unit_price().value))
+    }
+
+    /// The highest offer per unit that is priced in ETH or WETH.
+    pub fn best_bid(&self) -> Option<&Offer> {
+        self.offers
+            .iter()
+            .filter(|o| o.currency.is_ether())
+            .max_by(|a, b| a.unit_price().value.cmp(&b.unit_price().value))
+    }
+
+    /// The cheapest listing of `nft`
+    pub fn best_ask_for(&self, nft: &NftId) -> Option<&Listing> {
+        self.listings
+            .iter()
+            .filter(|l| &l.nft == nft && l.currency.is_ether())
+            .min_by(|a, b| a.unit_price().value.cmp(&b.unit_price().value))
+    }
+
+    /// The highest offer for `nft`
+    pub fn best_bid_for(&self, nft: &NftId) -> Option<&Offer> {
+        self.offers
+            .iter()
+            .filter(|o| &o.nft == nft && o.currency.is_ether())
+            .max_by(|a, b| a.unit_price().value.cmp(&b.unit_price().value))
+    }
+
+    /// Removes all orders that are expired at `now`
+    pub fn retain_active(&mut self, now: DateTime<Utc>) {
+        self.listings.retain(|l| !l.is_expired_at(now));
+        self.offers.retain(|o| !o.is_expired_at(now));
+    }
+
+    pub fn extend(&mut self, other: AggregatedBook) {
+        self.listings.extend(other.listings);
+        self.offers.extend(other.offers);
+    }
+}
+
+/// A sale of an NFT
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Trade {
+    pub marketplace: Marketplace,
+    /// Id of the sale on its marketplace
+    pub id: String,
+    pub nft: NftId,
+    /// Address of the seller, lowercase
+    pub seller: String,
+    /// Address of the buyer, lowercase
+    pub buyer: String,
+    pub quantity: U256,
+    /// Price of a single unit in ETH, `None` if the sale was paid in a
+    /// currency that can't be converted
+    pub price: Option<Decimal>,
+    pub currency: Currency,
+    pub timestamp: DateTime<Utc>,
+    pub transaction_hash: Option<String>,
+    pub log_index: Option<i64>,
+}
+
+impl Trade {
+    /// Whether seller and buyer are the same wallet
+    pub fn is_self_trade(&self) -> bool {
+        self.seller == self.buyer
+    }
+}
+
+/// Parses the dates of the OpenSea API, which are UTC without a timezone
+pub(crate) fn opensea_date(date: &str) -> Option<DateTime<Utc>> {
+    chrono::NaiveDateTime::parse_from_str(date, "%Y-%m-%dT%H:%M:%S%.f")
+        .ok()
+        .map(|date| Utc.from_utc_datetime(&date))
+}
+
+impl TryFrom<&opensea::AssetEvent> for Trade {
+    type Error = anyhow::Error;
+
+    fn try_from(event: &opensea::AssetEvent) -> Result<Self, Self::Error> {
+        anyhow::ensure!(
+            event.event_type == opensea::AssetEventType::Successful,
+            "event {} is not a sale",
+            event.id
+        );
+        let contract = event
+            .contract_address
+            .as_ref()
+            .ok_or_else(|| anyhow::anyhow!("sale {} without contract", event.id))?;
+        let asset = event
+            .asset
+            .as_ref()
+            .ok_or_else(|| anyhow::anyhow!("sale {} is not for a single asset", event.id))?;
+        let tx = event.transaction.as_ref();
+        let seller = event
+            .seller
+            .as_ref()
+            .map(|account| account.address.to_lowercase())
+            .ok_or_else(|| anyhow::anyhow!("sale {} without seller", event.id))?;
+        // the buyer sends the transaction that matches the orders
+        let buyer = event
+            .winner_account
+            .as_ref()
+            .or_else(|| tx.map(|tx| &tx.from_account))
+            .map(|account| account.address.to_lowercase())
+            .ok_or_else(|| anyhow::anyhow!("sale {} without buyer", event.id))?;
+        let timestamp = tx
+            .and_then(|tx| opensea_date(&tx.timestamp))
+            .or_else(|| opensea_date(&event.created_date))
+            .ok_or_else(|| anyhow::anyhow!("sale {} without date", event.id))?;
+        let quantity = event
+            .quantity
+            .as_deref()
+            .and_then(|quantity| U256::from_dec_str(quantity).ok())
+            .unwrap_or_else(U256::one);
+        let token = event.payment_token.as_ref();
+        let currency = token
+            .map(|token| Currency::from_address(&token.address))
+            .unwrap_or(Currency::Eth);
+        let price = match (event.total_price.as_deref(), token) {
+            (Some(total), Some(token)) => {
+                let price = Amount::from_base_units(total, token.decimals)?
+                    .per_unit(quantity)
+                    .to_decimal();
+                if currency.is_ether() {
+                    price
+                } else {
+                    token
+                        .eth_price
+                        .as_deref()
+                        .and_then(parse_decimal)
+                        .and_then(|rate| Some(price? * rate))
+                }
+            }
+            _ => None,
+        };
+        Ok(Trade {
+            marketplace: Marketplace::OpenSea,
+            id: event.id.to_string(),
+            nft: NftId::new(contract, asset.token_id.clone()),
+            seller,
+            buyer,
+            quantity,
+            price,
+            currency,
+            timestamp,
+            transaction_hash: tx.map(|tx| tx.transaction_hash.clone()),
+            log_index: None,
+        })
+    }
+}
+
+/// Parses a decimal like `"1.5"` or `"1.5e-7"`
+pub(crate) fn parse_decimal(value: &str) -> Option<Decimal> {
+    let value = value.trim();
+    value
+        .parse()
+        .or_else(|_| Decimal::from_scientific(value))
+        .ok()
+}
+
+pub(crate) fn timestamp(secs: i64) -> Option<DateTime<Utc>> {
+    if secs <= 0 {
+        None
+    } else {
+        Utc.timestamp_opt(secs, 0).single()
+    }
+}
+
+/// The nft and quantity an OpenSea order is for
+pub(crate) fn opensea_nft(order: &opensea::Order) -> anyhow::Result<(NftId, U256)> {
+    let asset = match &order.metadata {
+        opensea::ExchangeMetadata::Asset(meta) => &meta.asset,
+        opensea::ExchangeMetadata::Bundle(_) => anyhow::bail!("bundle orders are not supported"),
+    };
+    let quantity = U256::from_dec_str(&order.quantity).unwrap_or_else(|_| U256::one());
+    match asset {
+        opensea::WyvernAsset::NFT(nft) => Ok((NftId::new(&nft.address, nft.id.clone()), quantity)),
+        opensea::WyvernAsset::FT(ft) => {
+            let id = ft
+                .id
+                .clone()
+                .ok_or_else(|| anyhow::anyhow!("fungible asset without token id"))?;
+            Ok((NftId::new(&ft.address, id), quantity))
+        }
+    }
+}
+
+fn opensea_price(order: &opensea::Order) -> anyhow::Result<Amount> {
+    Amount::from_base_units(
+        &order.current_price,
+        order.payment_token_contract.decimals as u32,
+    )
+}
+
+impl TryFrom<&opensea::Order> for Listing {
+    type Error = anyhow::Error;
+
+    fn try_from(order: &opensea::Order) -> Result<Self, Self::Error> {
+        anyhow::ensure!(
+            order.side == opensea::OrderSide::Sell as i64,
+            "not a sell order"
+        );
+        let (nft, quantity) = opensea_nft(order)?;
+        Ok(Listing {
+            marketplace: Marketplace::OpenSea,
+            hash: order.order_hash.clone(),
+            maker: order.maker.address.to_lowercase(),
+            nft,
+            quantity,
+            price: opensea_price(order)?,
+            currency: Currency::from_address(&order.payment_token),
+            listed_at: timestamp(order.listing_time),
+            expiry: timestamp(order.expiration_time),
+            order: NativeOrder::OpenSea(Box::new(order.clone())),
+        })
+    }
+}
+
+impl TryFrom<&opensea::Order> for Offer {
+    type Error = anyhow::Error;
+
+    fn try_from(order: &opensea::Order) -> Result<Self, Self::Error> {
+        anyhow::ensure!(
+            order.side == opensea::OrderSide::Buy as i64,
+            "not a buy order"
+        );
+        let (nft, quantity) = opensea_nft(order)?;
+        Ok(Offer {
+            marketplace: Marketplace::OpenSea,
+            hash: order.order_hash.clone(),
+            maker: order.maker.address.to_lowercase(),
+            nft,
+            quantity,
+            price: opensea_price(order)?,
+            currency: Currency::from_address(&order.payment_token),
+            listed_at: timestamp(order.listing_time),
+            expiry: timestamp(order.expiration_time),
+            order: NativeOrder::OpenSea(Box::new(order.clone())),
+        })
+    }
+}
+
+/// The nft of a Rarible asset type, `None` for currencies
+#[cfg(feature = "rarible")]
+pub(crate) fn rarible_nft(asset_type: &rarible::AssetType) -> Option<NftId> {
+    match asset_type {
+        rarible::AssetType::Erc721 { contract, token_id }
+        | rarible::AssetType::Erc1155 { contract, token_id }
+        | rarible::AssetType::Erc721Lazy {
+            contract, token_id, ..
+        }
+        | rarible::AssetType::Erc1155Lazy {
+            contract, token_id, ..
+        } => Some(NftId::new(contract, token_id.clone())),
+        _ => None,
+    }
+}
+
+/// The currency of a Rarible asset type, `None` for nfts.
+#[cfg(feature = "rarible")]
+pub(crate) fn rarible_currency(asset_type: &rarible::AssetType) -> Option<Currency> {
+    match asset_type {
+        rarible::AssetType::Eth => Some(Currency::Eth),
+        rarible::AssetType::Erc20 { contract } => Some(Currency::from_address(contract)),
+        _ => None,
+    }
+}
+
+/// Rarible does not report the decimals of ERC20 tokens, all supported
+/// currencies (ETH, WETH, ...) use 18.
+#[cfg(feature = "rarible")]
+pub(crate) const RARIBLE_CURRENCY_DECIMALS: u32 = 18;
+
+#[cfg(feature = "rarible")]
+fn rarible_listing(
+    hash: &str,
+    make: &rarible::Asset,
+    take: &rarible::Asset,
+) -> anyhow::Result<(NftId, U256, Amount, Currency)> {
+    let nft = rarible_nft(&make.asset_type)
+        .ok_or_else(|| anyhow::anyhow!("order {} does not sell an nft", hash))?;
+    let currency = rarible_currency(&take.asset_type)
+        .ok_or_else(|| anyhow::anyhow!("order {} is not paid in a currency", hash))?;
+    Ok((
+        nft,
+        U256::from_dec_str(&make.value)?,
+        Amount::from_base_units(&take.value, RARIBLE_CURRENCY_DECIMALS)?,
+        currency,
+    ))
+}
+
+#[cfg(feature = "rarible")]
+fn rarible_offer(
+    hash: &str,
+    make: &rarible::Asset,
+    take: &rarible::Asset,
+) -> anyhow::Result<(NftId, U256, Amount, Currency)> {
+    let nft = rarible_nft(&take.asset_type)
+        .ok_or_else(|| anyhow::anyhow!("order {} does not bid for an nft", hash))?;
+    let currency = rarible_currency(&make.asset_type)
+        .ok_or_else(|| anyhow::anyhow!("order {} is not paid in a currency", hash))?;
+    Ok((
+        nft,
+        U256::from_dec_str(&take.value)?,
+        Amount::from_base_units(&make.value, RARIBLE_CURRENCY_DECIMALS)?,
+        currency,
+    ))
+}
+
+#[cfg(feature = "rarible")]
+fn rarible_date(date: &str) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc3339(date)
+        .ok()
+        .map(|d| d.with_timezone(&Utc))
+}
+
+#[cfg(feature = "rarible")]
+impl TryFrom<&rarible::Order> for Listing {
+    type Error = anyhow::Error;
+
+    fn try_from(order: &rarible::Order) -> Result<Self, Self::Error> {
+        let (nft, quantity, price, currency) =
+            rarible_listing(&order.hash, &order.make, &order.take)?;
+        Ok(Listing {
+            marketplace: Marketplace::Rarible,
+            hash: order.hash.clone(),
+            maker: order.maker.to_lowercase(),
+            nft,
+            quantity,
+            price,
+            currency,
+            listed_at: order
+                .start
+                .and_then(timestamp)
+                .or_else(|| rarible_date(&order.created_at)),
+            expiry: order.end.and_then(timestamp),
+            order: NativeOrder::Rarible(Box::new(order.clone())),
+        })
+    }
+}
+
+#[cfg(feature = "rarible")]
+impl TryFrom<&rarible::Order> for Offer {
+    type Error = anyhow::Error;
+
+    fn try_from(order: &rarible::Order) -> Result<Self, Self::Error> {
+        let (nft, quantity, price, currency) =
+            rarible_offer(&order.hash, &order.make, &order.take)?;
+        Ok(Offer {
+            marketplace: Marketplace::Rarible,
+            hash: order.hash.clone(),
+            maker: order.maker.to_lowercase(),
+            nft,
+            quantity,
+            price,
+            currency,
+            listed_at: order
+                .start
+                .and_then(timestamp)
+                .or_else(|| rarible_date(&order.created_at)),
+            expiry: order.end.and_then(timestamp),
+            order: NativeOrder::Rarible(Box::new(order.clone())),
+        })
+    }
+}
+
+#[cfg(feature = "rarible")]
+impl TryFrom<&rarible::OrderBid> for Offer {
+    type Error = anyhow::Error;
+
+    fn try_from(bid: &rarible::OrderBid) -> Result<Self, Self::Error> {
+        let (nft, quantity, price, currency) =
+            rarible_offer(&bid.order_hash, &bid.make, &bid.take)?;
+        Ok(Offer {
+            marketplace: Marketplace::Rarible,
+            hash: bid.order_hash.clone(),
+            maker: bid.maker.to_lowercase(),
+            nft,
+            quantity,
+            price,
+            currency,
+            listed_at: rarible_date(&bid.created_at),
+            expiry: None,
+            order: NativeOrder::RaribleBid(Box::new(bid.clone())),
+        })
+    }
+}
+
+/// A sale matched from the `left` and `right` orders of an activity
+#[cfg(feature = "rarible")]
+fn rarible_trade(
+    id: &str,
+    date: &str,
+    left: &rarible::OrderActivityMatchSide,
+    right: &rarible::OrderActivityMatchSide,
+    price: &str,
+    transaction_hash: &str,
+    log_index: i32,
+) -> anyhow::Result<Trade> {
+    // the seller's side of the match is the nft
+    let (seller, buyer) = if rarible_nft(&left.asset.asset_type).is_some() {
+        (left, right)
+    } else {
+        (right, left)
+    };
+    let nft = rarible_nft(&seller.asset.asset_type)
+        .ok_or_else(|| anyhow::anyhow!("activity {} does not trade an nft", id))?;
+    let currency = rarible_currency(&buyer.asset.asset_type)
+        .ok_or_else(|| anyhow::anyhow!("activity {} is not paid in a currency", id))?;
+    let timestamp =
+        rarible_date(date).ok_or_else(|| anyhow::anyhow!("activity {} without date", id))?;
+    Ok(Trade {
+        marketplace: Marketplace::Rarible,
+        id: id.to_string(),
+        nft,
+        seller: seller.maker.to_lowercase(),
+        buyer: buyer.maker.to_lowercase(),
+        quantity: U256::from_dec_str(&seller.asset.value).unwrap_or_else(|_| U256::one()),
+        price: if currency.is_ether() {
+            parse_decimal(price)
+        } else {
+            None
+        },
+        currency,
+        timestamp,
+        transaction_hash: Some(transaction_hash.to_string()),
+        log_index: Some(log_index as i64),
+    })
+}
+
+#[cfg(feature = "rarible")]
+impl TryFrom<&rarible::Activity> for Trade {
+    type Error = anyhow::Error;
+
+    fn try_from(activity: &rarible::Activity) -> Result<Self, Self::Error> {
+        rarible_trade(
+            &activity.id,
+            &activity.date,
+            &activity.left,
+            &activity.right,
+            &activity.price,
+            &activity.transaction_hash,
+            activity.log_index,
+        )
+    }
+}
+
+#[cfg(feature = "rarible")]
+impl TryFrom<&rarible::OrderActivity> for Trade {
+    type Error = anyhow::Error;
+
+    /// Sales Rarible indexed from OpenSea are attributed to OpenSea
+    fn try_from(activity: &rarible::OrderActivity) -> Result<Self, Self::Error> {
+        let mut trade = rarible_trade(
+            &activity.id,
+            &activity.date,
+            &activity.left,
+            &activity.right,
+            &activity.price,
+            &activity.transaction_hash,
+            activity.log_index,
+        )?;
+        if activity.source == rarible::Source::Opensea {
+            trade.marketplace = Marketplace::OpenSea;
+        }
+        Ok(trade)
+    }
+}
@@ -0,0 +1,642 @@
+//! Valuation of the NFTs held by a wallet across OpenSea and Rarible.
+//!
+//! The holdings of both marketplaces are merged by contract and token id and
+//! valued in ETH by the first of the configured [`ValuationMethod`]s that
+//! yields a price. The floor is the one OpenSea reports for the collection,
+//! or the cheapest listing of the collection's contract on either
+//! marketplace, unless the contract is shared by several collections, like
+//! OpenSea's shared storefront. Listings and bids are taken from the first page of each
+//! contract's orders, see [`MarketClient::collection_book`]. ERC1155
+//! holdings are valued with the balance of the wallet.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+use chrono::Utc;
+use ethereum_types::U256;
+use futures::StreamExt;
+use log::debug;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+
+use crate::market::models::{
+    parse_decimal, AggregatedBook, Amount, Currency, Marketplace, NftId, NULL_ADDRESS,
+};
+use crate::market::{MarketClient, OPENSEA_PAGE_SIZE, RARIBLE_PAGE_SIZE};
+use crate::opensea::models::{LastSale, OpenSeaAsset};
+use crate::opensea::query::OpenSeaAssetsQuery;
+use crate::rarible::models::NftItem;
+
+/// Number of collections that are valued concurrently
+const CONCURRENT_REQUESTS: usize = 4;
+
+/// The ERC1155 contract of OpenSea's shared storefront, which holds the
+/// tokens of many collections
+const OPENSEA_SHARED_STOREFRONT: &str = "0x495f947276749ce646f68ac8c248420045cb7b5e";
+
+/// How a holding is valued
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub enum ValuationMethod {
+    /// The floor of the collection
+    Floor,
+    /// The price the token was last sold for on OpenSea
+    LastSale,
+    /// The highest active offer for the token
+    BestBid,
+}
+
+impl fmt::Display for ValuationMethod {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ValuationMethod::Floor => f.write_str("floor"),
+            ValuationMethod::LastSale => f.write_str("last sale"),
+            ValuationMethod::BestBid => f.write_str("best bid"),
+        }
+    }
+}
+
+/// How holdings are fetched and valued
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ValuationConfig {
+    /// Tried in order until one yields a price
+    pub methods: Vec<ValuationMethod>,
+    /// Pages of holdings fetched from each marketplace at most
+    pub max_pages: u32,
+}
+
+impl ValuationConfig {
+    pub fn methods<T: Into<Vec<ValuationMethod>>>(mut self, value: T) -> Self {
+        self.methods = value.into();
+        self
+    }
+
+    pub fn max_pages<T: Into<u32>>(mut self, value: T) -> Self {
+        self.max_pages = value.into().max(1);
+        self
+    }
+}
+
+impl Default for ValuationConfig {
+    fn default() -> Self {
+        Self {
+            methods: vec![
+                ValuationMethod::Floor,
+                ValuationMethod::LastSale,
+                ValuationMethod::BestBid,
+            ],
+            max_pages: 20,
+        }
+    }
+}
+
+/// A single token held by the wallet, all prices are in ETH per unit
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Holding {
+    pub nft: NftId,
+    /// Number of units the wallet holds, more than one only for ERC1155
+    /// tokens
+    pub quantity: U256,
+    pub name: Option<String>,
+    /// OpenSea slug of the collection
+    pub collection: Option<String>,
+    /// The marketplaces that reported the token
+    pub sources: BTreeSet<Marketplace>,
+    pub floor: Option<Decimal>,
+    pub last_sale: Option<Decimal>,
+    pub best_bid: Option<Decimal>,
+    /// Value of all units by the first method that yields a price
+    pub value: Option<Decimal>,
+    pub method: Option<ValuationMethod>,
+    pub value_usd: Option<Decimal>,
+}
+
+impl Holding {
+    fn new(nft: NftId) -> Self {
+        Self {
+            nft,
+            quantity: U256::one(),
+            name: None,
+            collection: None,
+            sources: BTreeSet::new(),
+            floor: None,
+            last_sale: None,
+            best_bid: None,
+            value: None,
+            method: None,
+            value_usd: None,
+        }
+    }
+
+    /// The price by the method
+    pub fn price(&self, method: ValuationMethod) -> Option<Decimal> {
+        match method {
+            ValuationMethod::Floor => self.floor,
+            ValuationMethod::LastSale => self.last_sale,
+            ValuationMethod::BestBid => self.best_bid,
+        }
+    }
+}
+
+/// The valued holdings of a wallet
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Portfolio {
+    /// Address of the wallet, lowercase
+    pub owner: String,
+    /// Ordered by value, highest first
+    pub holdings: Vec<Holding>,
+    /// Price of one ETH in USD, `None` if no rate was available
+    pub eth_usd: Option<Decimal>,
+    pub total_eth: Decimal,
+    pub total_usd: Option<Decimal>,
+}
+
+impl Portfolio {
+    /// The holdings no price was found for
+    pub fn unvalued(&self) -> impl Iterator<Item = &Holding> {
+        self.holdings.iter().filter(|h| h.value.is_none())
+    }
+
+    /// The total value of the holdings of each collection contract
+    pub fn totals_by_contract(&self) -> BTreeMap<&str, Decimal> {
+        let mut totals = BTreeMap::new();
+        for holding in &self.holdings {
+            *totals
+                .entry(holding.nft.contract.as_str())
+                .or_insert_with(Decimal::default) += holding.value.unwrap_or_default();
+        }
+        totals
+    }
+}
+
+impl MarketClient {
+    /// Fetches and values the holdings of `owner` with the default config
+    pub async fn portfolio(&self, owner: &str) -> anyhow::Result<Portfolio> {
+        self.portfolio_with_config(owner, &ValuationConfig::default())
+            .await
+    }
+
+    pub async fn portfolio_with_config(
+        &self,
+        owner: &str,
+        config: &ValuationConfig,
+    ) -> anyhow::Result<Portfolio> {
+        let owner = owner.to_lowercase();
+        let (assets, items) = futures::try_join!(
+            self.opensea_holdings(&owner, config.max_pages),
+            self.rarible_holdings(&owner, config.max_pages),
+        )?;
+
+        let mut holdings = BTreeMap::new();
+        for asset in &assets {
+            let token_id = match asset.token_id.clone() {
+                Some(token_id) => token_id,
+                None => continue,
+            };
+            let nft = NftId::new(&asset.asset_contract.address, token_id);
+            let holding = holdings
+                .entry(nft.clone())
+                .or_insert_with(|| Holding::new(nft));
+            holding.sources.insert(Marketplace::OpenSea);
+            holding.name = asset.name.clone();
+            holding.collection = Some(asset.collection.slug.clone());
+            holding.last_sale = asset.last_sale.as_ref().and_then(last_sale_eth);
+            if let Some(quantity) = opensea_balance(asset, &owner) {
+                holding.quantity = quantity;
+            }
+        }
+        let balances = self.rarible_balances(&owner, &items).await;
+        for item in items.iter().filter(|item| item.deleted != Some(true)) {
+            let nft = NftId::new(&item.contract, item.token_id.clone());
+            let holding = holdings
+                .entry(nft.clone())
+                .or_insert_with(|| Holding::new(nft));
+            holding.sources.insert(Marketplace::Rarible);
+            if let Some(quantity) = balances.get(&holding.nft) {
+                holding.quantity = *quantity;
+            }
+            if holding.name.is_none() {
+                holding.name = item.meta.as_ref().map(|meta| meta.name.clone());
+            }
+        }
+
+        let contracts = holdings
+            .keys()
+            .map(|nft| nft.contract.clone())
+            .collect::<BTreeSet<_>>();
+        let slugs = holdings
+            .values()
+            .filter_map(|h| h.collection.clone())
+            .collect::<BTreeSet<_>>();
+        let shared = shared_contracts(holdings.values());
+        let (books, floors, eth_usd) = futures::join!(
+            self.collection_books(contracts),
+            self.opensea_floors(slugs),
+            self.eth_usd_rate(),
+        );
+        let eth_usd = eth_usd.or_else(|| assets.iter().find_map(last_sale_eth_usd));
+
+        let mut holdings = holdings.into_values().collect::<Vec<_>>();
+        for holding in &mut holdings {
+            let book = books.get(&holding.nft.contract);
+            let opensea_floor = holding
+                .collection
+                .as_ref()
+                .and_then(|slug| floors.get(slug).copied());
+            holding.floor = opensea_floor.or_else(|| {
+                // the cheapest listing of a shared contract can be of any
+                // of its collections
+                if shared.contains(&holding.nft.contract) {
+                    return None;
+                }
+                let listing = book?.best_ask()?;
+                listing.unit_price().to_decimal()
+            });
+            holding.best_bid = book
+                .and_then(|book| book.best_bid_for(&holding.nft))
+                .and_then(|offer| offer.unit_price().to_decimal());
+            if let Some((method, value)) = config.methods.iter().find_map(|method| {
+                let total = holding
+                    .price(*method)?
+                    .checked_mul(to_decimal(holding.quantity)?)?;
+                Some((*method, total))
+            }) {
+                holding.method = Some(method);
+                holding.value = Some(value);
+                holding.value_usd = eth_usd.map(|rate| value * rate);
+            }
+        }
+        holdings.sort_by(|a, b| b.value.cmp(&a.value).then_with(|| a.nft.cmp(&b.nft)));
+
+        let total_eth = holdings.iter().filter_map(|h| h.value).sum::<Decimal>();
+        Ok(Portfolio {
+            owner,
+            holdings,
+            eth_usd,
+            total_eth,
+            total_usd: eth_usd.map(|rate| total_eth * rate),
+        })
+    }
+
+    async fn opensea_holdings(
+        &self,
+        owner: &str,
+        max_pages: u32,
+    ) -> anyhow::Result<Vec<OpenSeaAsset>> {
+        let mut assets = Vec::new();
+        for page in 0..max_pages {
+            let query = OpenSeaAssetsQuery::default()
+                .owner(owner)
+                .limit(OPENSEA_PAGE_SIZE)
+                .offset(page * OPENSEA_PAGE_SIZE);
+            let batch = self.opensea.get_assets(&query).await?.assets;
+            let done = batch.len() < OPENSEA_PAGE_SIZE as usize;
+            assets.extend(batch);
+            if done {
+                break;
+            }
+        }
+        Ok(assets)
+    }
+
+    async fn rarible_holdings(&self, owner: &str, max_pages: u32) -> anyhow::Result<Vec<NftItem>> {
+        let mut items = Vec::new();
+        let mut continuation = None;
+        for _ in 0..max_pages {
+            let page = self
+                .rarible
+                .get_nft_items_by_owner(
+                    owner,
+                    continuation.as_deref(),
+                    Some(RARIBLE_PAGE_SIZE),
+                    Some(true),
+                )
+                .await?;
+            let done = page.items.is_empty() || page.continuation.is_none();
+            items.extend(page.items);
+            continuation = page.continuation;
+            if done {
+                break;
+            }
+        }
+        Ok(items)
+    }
+
+    /// The balances of the items with a supply of more than one, items
+    /// whose ownership can't be fetched are skipped
+    async fn rarible_balances(&self, owner: &str, items: &[NftItem]) -> BTreeMap<NftId, U256> {
+        let items = items.iter().filter(|item| {
+            item.deleted != Some(true)
+                && U256::from_dec_str(&item.supply).is_ok_and(|supply| supply > U256::one())
+        });
+        futures::stream::iter(items)
+            .map(|item| async move {
+                let id = format!("{}:{}:{}", item.contract, item.token_id, owner);
+                match self.rarible.get_nft_ownership_by_id(&id).await {
+                    Ok(ownership) => U256::from_dec_str(&ownership.value)
+                        .ok()
+                        .filter(|value| !value.is_zero())
+                        .map(|value| (NftId::new(&item.contract, item.token_id.clone()), value)),
+                    Err(err) => {
+                        debug!("Failed to fetch the ownership {}: {}", id, err);
+                        None
+                    }
+                }
+            })
+            .buffer_unordered(CONCURRENT_REQUESTS)
+            .filter_map(|balance| async move { balance })
+            .collect()
+            .await
+    }
+
+    /// The order books of the collections, collections whose book can't be
+    /// fetched are skipped
+    async fn collection_books(
+        &self,
+        contracts: BTreeSet<String>,
+    ) -> BTreeMap<String, AggregatedBook> {
+        let now = Utc::now();
+        futures::stream::iter(contracts)
+            .map(|contract| async move {
+                match self.collection_book(&contract).await {
+                    Ok(mut book) => {
+                        book.retain_active(now);
+                        Some((contract, book))
+                    }
+                    Err(err) => {
+                        debug!("Failed to fetch the book of {}: {}", contract, err);
+                        None
+                    }
+                }
+            })
+            .buffer_unordered(CONCURRENT_REQUESTS)
+            .filter_map(|book| async move { book })
+            .collect()
+            .await
+    }
+
+    /// The floors OpenSea reports for the collections, collections without
+    /// a floor are skipped
+    async fn opensea_floors(&self, slugs: BTreeSet<String>) -> BTreeMap<String, Decimal> {
+        futures::stream::iter(slugs)
+            .map(|slug| async move {
+                match self.opensea.get_collection_stats(&slug).await {
+                    Ok(stats) => stats
+                        .stats
+                        .floor_price
+                        .filter(|floor| !floor.is_zero())
+                        .map(|floor| (slug, floor)),
+                    Err(err) => {
+                        debug!("Failed to fetch the stats of {}: {}", slug, err);
+                        None
+                    }
+                }
+            })
+            .buffer_unordered(CONCURRENT_REQUESTS)
+            .filter_map(|floor| async move { floor })
+            .collect()
+            .await
+    }
+
+    /// The current price of ETH in USD from Rarible
+    async fn eth_usd_rate(&self) -> Option<Decimal> {
+        let now = Utc::now().timestamp_millis() as u64;
+        match self
+            .rarible
+            .get_currency_rate("ETHEREUM", NULL_ADDRESS, now)
+            .await
+        {
+            Ok(rate) => parse_decimal(&rate.rate),
+            Err(err) => {
+                debug!("Failed to fetch the ETH rate: {}", err);
+                None
+            }
+        }
+    }
+}
+
+/// The contracts of the holdings that are shared by several collections
+fn shared_contracts<'a>(holdings: impl Iterator<Item = &'a Holding>) -> BTreeSet<String> {
+    let mut slugs = BTreeMap::<&str, BTreeSet<&str>>::new();
+    for holding in holdings {
+        let contract_slugs = slugs.entry(&holding.nft.contract).or_default();
+        if let Some(slug) = holding.collection.as_deref() {
+            contract_slugs.insert(slug);
+        }
+    }
+    slugs
+        .into_iter()
+        .filter(|(contract, slugs)| *contract == OPENSEA_SHARED_STOREFRONT || slugs.len() > 1)
+        .map(|(contract, _)| contract.to_string())
+        .collect()
+}
+
+/// The unit price of the sale in ETH, converted with the payment token's ETH
+/// price
+fn last_sale_eth(sale: &LastSale) -> Option<Decimal> {
+    let token = sale.payment_token.as_ref()?;
+    let quantity = U256::from_dec_str(&sale.quantity).unwrap_or_else(|_| U256::one());
+    let price = Amount::from_base_units(&sale.total_price, token.decimals)
+        .ok()?
+        .per_unit(quantity)
+        .to_decimal()?;
+    if Currency::from_address(&token.address).is_ether() {
+        return Some(price);
+    }
+    Some(price * parse_decimal(token.eth_price.as_deref()?)?)
+}
+
+/// The balance of `owner` among the top ownerships of the asset
+fn opensea_balance(asset: &OpenSeaAsset, owner: &str) -> Option<U256> {
+    asset
+        .top_ownerships
+        .as_ref()?
+        .iter()
+        .find(|ownership| ownership.owner.address.eq_ignore_ascii_case(owner))
+        .and_then(|ownership| U256::from_dec_str(&ownership.quantity).ok())
+        .filter(|quantity| !quantity.is_zero())
+}
+
+/// The quantity as `Decimal`, `None` if it doesn't fit
+fn to_decimal(quantity: U256) -> Option<Decimal> {
+    if quantity.bits() > 64 {
+        return None;
+    }
+    Some(Decimal::from(quantity.low_u64()))
+}
+
+/// The USD price of ETH as reported for the payment token of a sale in ETH
+fn last_sale_eth_usd(asset: &OpenSeaAsset) -> Option<Decimal> {
+    let token = asset.last_sale.as_ref()?.payment_token.as_ref()?;
+    if token.address.to_lowercase() != NULL_ADDRESS {
+        return None;
+    }
+    parse_decimal(token.usd_price.as_deref()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::MockServer;
+    use crate::ApiClient;
+    use serde_json::{json, Value};
+
+    const ASSETS: &str = include_str!("../../examples/responses/opensea-assets-list.json");
+    // constructed, see `rarible::eip712`
+    const RARIBLE_ORDER: &str = include_str!("../../tests/fixtures/rarible-order.json");
+
+    const OWNER: &str = "0x00000000000000000000000000000000000000a1";
+    const CRYPTOTUNERS: &str = "0x6e9ce8c208393244b7adbbe09e3cbb8be1cf1034";
+    const EDITIONS: &str = "0x00000000000000000000000000000000000000e1";
+
+    /// A Rarible listing of a token for `price` wei that never expires
+    fn listing(contract: &str, token_id: &str, price: &str) -> Value {
+        let mut order: Value = serde_json::from_str(RARIBLE_ORDER).unwrap();
+        order["hash"] = json!(format!("0x{}", token_id));
+        order["make"]["assetType"]["contract"] = json!(contract);
+        order["make"]["assetType"]["tokenId"] = json!(token_id);
+        order["take"]["value"] = json!(price);
+        order["end"] = Value::Null;
+        order
+    }
+
+    fn item(contract: &str, token_id: &str, supply: &str, deleted: bool) -> Value {
+        json!({
+            "id": format!("{}:{}", contract, token_id),
+            "contract": contract,
+            "tokenId": token_id,
+            "creators": [],
+            "supply": supply,
+            "lazySupply": "0",
+            "owners": [OWNER],
+            "royalties": [],
+            "deleted": deleted,
+            "meta": { "name": format!("Edition #{}", token_id) },
+        })
+    }
+
+    fn respond(path: &str) -> (u16, String) {
+        let body = if path.starts_with("/api/v1/assets?") {
+            // a token of the shared storefront and one of CryptoTuners
+            let assets: Value = serde_json::from_str(ASSETS).unwrap();
+            json!({ "assets": [assets["assets"][0], assets["assets"][11]] })
+        } else if path.starts_with("/api/v1/collection/cryptotuners/stats") {
+            json!({ "stats": { "floor_price": 0.5 } })
+        } else if path.starts_with("/api/v1/collection/") {
+            json!({ "stats": {} })
+        } else if path.starts_with("/wyvern/v1/orders?") {
+            json!({ "orders": [], "count": 0 })
+        } else if path.starts_with("/protocol/v0.1/ethereum/nft/items/byOwner?") {
+            json!({
+                "total": 3,
+                "items": [
+                    item(CRYPTOTUNERS, "3815", "1", false),
+                    item(EDITIONS, "1", "10", false),
+                    item(EDITIONS, "2", "1", true),
+                ],
+            })
+        } else if path.starts_with("/protocol/v0.1/ethereum/nft/ownerships/") {
+            json!({
+                "id": "ownership",
+                "contract": EDITIONS,
+                "tokenId": "1",
+                "owner": OWNER,
+                "creators": [],
+                "value": "3",
+                "lazyValue": "0",
+                "date": "2021-08-17T21:40:24Z",
+                "pending": [],
+            })
+        } else if path.starts_with("/protocol/v0.1/ethereum/order/orders/sell/byCollection?") {
+            let orders = if path.contains(OPENSEA_SHARED_STOREFRONT) {
+                // of another collection of the shared storefront
+                vec![listing(OPENSEA_SHARED_STOREFRONT, "1", "10000000000000000")]
+            } else if path.contains(EDITIONS) {
+                vec![listing(EDITIONS, "1", "200000000000000000")]
+            } else {
+                vec![]
+            };
+            json!({ "orders": orders })
+        } else if path.starts_with("/protocol/v0.1/ethereum/currency/rate?") {
+            json!({
+                "fromCurrencyId": "ETH",
+                "toCurrencyId": "USD",
+                "rate": "2000",
+                "date": "2021-08-17T21:40:24Z",
+            })
+        } else {
+            return (404, "{}".to_string());
+        };
+        (200, body.to_string())
+    }
+
+    #[tokio::test]
+    async fn values_the_merged_holdings() {
+        let server = MockServer::start(|request| respond(&request.path));
+        let api = || ApiClient::builder().build(server.url().clone()).unwrap();
+        let client = MarketClient::new(api(), api());
+
+        let portfolio = client.portfolio(&OWNER.to_uppercase()).await.unwrap();
+        assert_eq!(portfolio.owner, OWNER);
+        let holdings = portfolio
+            .holdings
+            .iter()
+            .map(|h| (h.nft.to_string(), h))
+            .collect::<BTreeMap<_, _>>();
+        assert_eq!(holdings.len(), 3, "{:?}", holdings.keys());
+
+        // reported by both marketplaces, valued at the OpenSea floor
+        let tuner = holdings[&format!("{}:3815", CRYPTOTUNERS)];
+        assert_eq!(
+            tuner.sources,
+            vec![Marketplace::OpenSea, Marketplace::Rarible]
+                .into_iter()
+                .collect()
+        );
+        assert_eq!(tuner.collection.as_deref(), Some("cryptotuners"));
+        assert_eq!(tuner.floor, Some(Decimal::new(5, 1)));
+        assert_eq!(tuner.value, Some(Decimal::new(5, 1)));
+        assert_eq!(tuner.method, Some(ValuationMethod::Floor));
+
+        // three units of the edition at its cheapest listing
+        let edition = holdings[&format!("{}:1", EDITIONS)];
+        assert_eq!(edition.sources.len(), 1);
+        assert_eq!(edition.quantity, U256::from(3));
+        assert_eq!(edition.name.as_deref(), Some("Edition #1"));
+        assert_eq!(edition.floor, Some(Decimal::new(2, 1)));
+        assert_eq!(edition.value, Some(Decimal::new(6, 1)));
+        assert_eq!(edition.value_usd, Some(Decimal::from(1200)));
+
+        // the listing of the shared storefront is not its floor
+        let shared = portfolio.unvalued().collect::<Vec<_>>();
+        assert_eq!(shared.len(), 1);
+        assert_eq!(shared[0].nft.contract, OPENSEA_SHARED_STOREFRONT);
+        assert_eq!(shared[0].floor, None);
+
+        assert_eq!(portfolio.holdings[0].nft.contract, EDITIONS);
+        assert_eq!(portfolio.total_eth, Decimal::new(11, 1));
+        assert_eq!(portfolio.eth_usd, Some(Decimal::from(2000)));
+        assert_eq!(portfolio.total_usd, Some(Decimal::from(2200)));
+    }
+
+    #[test]
+    fn finds_shared_contracts() {
+        let holding = |contract: &str, token_id: &str, slug: Option<&str>| {
+            let mut holding = Holding::new(NftId::new(contract, token_id));
+            holding.collection = slug.map(str::to_string);
+            holding
+        };
+        let holdings = [
+            holding(CRYPTOTUNERS, "1", Some("cryptotuners")),
+            holding(CRYPTOTUNERS, "2", Some("cryptotuners")),
+            holding(EDITIONS, "1", Some("editions")),
+            holding(EDITIONS, "2", Some("other-editions")),
+            holding(OPENSEA_SHARED_STOREFRONT, "1", None),
+        ];
+        assert_eq!(
+            shared_contracts(holdings.iter()),
+            vec![EDITIONS.to_string(), OPENSEA_SHARED_STOREFRONT.to_string()]
+                .into_iter()
+                .collect()
+        );
+    }
+}
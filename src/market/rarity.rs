@@ -0,0 +1,543 @@
+//! Rarity scores and ranks of the tokens of a collection, based on how often
+//! their traits appear in the collection.
+//!
+//! The frequency tables count every value of every trait type. Tokens that
+//! lack a trait type count towards its [`TraitValue::Missing`] value, and
+//! the number of traits of a token is counted as an additional trait type,
+//! [`TRAIT_COUNT`], both can be turned off in the [`RarityConfig`].
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::convert::TryFrom;
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use crate::market::models::NftId;
+use crate::opensea::models::OpenSeaAsset;
+#[cfg(feature = "rarible")]
+use crate::rarible::models::NftItem;
+
+/// The trait type the number of traits of a token is counted as
+pub const TRAIT_COUNT: &str = "Trait Count";
+
+/// The traits of a single token
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct TokenTraits {
+    pub nft: NftId,
+    /// `(trait type, value)` pairs
+    pub traits: Vec<(String, String)>,
+}
+
+impl TokenTraits {
+    pub fn new(nft: NftId) -> Self {
+        Self {
+            nft,
+            traits: Vec::new(),
+        }
+    }
+
+    pub fn with_trait(mut self, trait_type: impl Into<String>, value: impl Into<String>) -> Self {
+        self.traits.push((trait_type.into(), value.into()));
+        self
+    }
+}
+
+impl TryFrom<&OpenSeaAsset> for TokenTraits {
+    type Error = anyhow::Error;
+
+    fn try_from(asset: &OpenSeaAsset) -> Result<Self, Self::Error> {
+        let token_id = asset
+            .token_id
+            .clone()
+            .ok_or_else(|| anyhow::anyhow!("asset without token id"))?;
+        let traits = asset
+            .traits
+            .iter()
+            .flatten()
+            .filter_map(|t| {
+                let value = match t.value.as_ref()? {
+                    serde_json::Value::Null => return None,
+                    serde_json::Value::String(s) => s.trim().to_string(),
+                    value => value.to_string(),
+                };
+                Some((t.trait_type.trim().to_string(), value))
+            })
+            .collect();
+        Ok(Self {
+            nft: NftId::new(&asset.asset_contract.address, token_id),
+            traits,
+        })
+    }
+}
+
+#[cfg(feature = "rarible")]
+impl From<&NftItem> for TokenTraits {
+    fn from(item: &NftItem) -> Self {
+        let traits = item
+            .meta
+            .iter()
+            .flat_map(|meta| meta.attributes.iter().flatten())
+            .filter_map(|attr| {
+                let value = attr.value.as_ref()?.trim().to_string();
+                Some((attr.key.trim().to_string(), value))
+            })
+            .collect();
+        Self {
+            nft: NftId::new(&item.contract, item.token_id.clone()),
+            traits,
+        }
+    }
+}
+
+/// A value of a trait type
+#[derive(Clone, Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Serialize, Deserialize)]
+pub enum TraitValue {
+    Value(String),
+    /// The token does not have the trait type
+    Missing,
+}
+
+impl fmt::Display for TraitValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TraitValue::Value(value) => f.write_str(value),
+            TraitValue::Missing => f.write_str("<missing>"),
+        }
+    }
+}
+
+/// Which traits are counted
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct RarityConfig {
+    /// Count tokens without a trait type as [`TraitValue::Missing`]
+    pub missing_traits: bool,
+    /// Count the number of traits of a token as [`TRAIT_COUNT`] trait
+    pub trait_count: bool,
+    /// Trait types that are not counted
+    pub ignored: BTreeSet<String>,
+}
+
+impl RarityConfig {
+    pub fn missing_traits<T: Into<bool>>(mut self, value: T) -> Self {
+        self.missing_traits = value.into();
+        self
+    }
+
+    pub fn trait_count<T: Into<bool>>(mut self, value: T) -> Self {
+        self.trait_count = value.into();
+        self
+    }
+
+    pub fn ignore<T: Into<String>>(mut self, trait_type: T) -> Self {
+        self.ignored.insert(trait_type.into());
+        self
+    }
+}
+
+impl Default for RarityConfig {
+    fn default() -> Self {
+        Self {
+            missing_traits: true,
+            trait_count: true,
+            ignored: BTreeSet::new(),
+        }
+    }
+}
+
+/// The methods to score the rarity of a token with, for all of them a
+/// higher score means rarer.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub enum RarityMethod {
+    /// The inverse of the probability of the token's trait combination, the
+    /// product of its trait frequencies.
+    Statistical,
+    /// The sum of the inverse frequencies of the token's traits
+    InverseFrequency,
+    /// The information content of the token's traits, `-log2` of their
+    /// frequencies, relative to the entropy of the collection.
+    InformationContent,
+}
+
+impl fmt::Display for RarityMethod {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RarityMethod::Statistical => f.write_str("statistical"),
+            RarityMethod::InverseFrequency => f.write_str("inverse frequency"),
+            RarityMethod::InformationContent => f.write_str("information content"),
+        }
+    }
+}
+
+/// The score and rank of a single token
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RarityScore {
+    pub nft: NftId,
+    pub score: f64,
+    /// 1 is the rarest, tokens with the same score share a rank
+    pub rank: usize,
+}
+
+/// The tokens of a collection ordered by rarity
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RarityRanking {
+    pub method: RarityMethod,
+    /// Ordered by rank
+    pub scores: Vec<RarityScore>,
+}
+
+impl RarityRanking {
+    pub fn get(&self, nft: &NftId) -> Option<&RarityScore> {
+        self.scores.iter().find(|score| &score.nft == nft)
+    }
+
+    /// The `n` rarest tokens
+    pub fn top(&self, n: usize) -> &[RarityScore] {
+        &self.scores[..n.min(self.scores.len())]
+    }
+}
+
+/// Trait frequencies of a collection
+#[derive(Clone, Debug, PartialEq)]
+pub struct TraitFrequencies {
+    config: RarityConfig,
+    tokens: Vec<TokenTraits>,
+    /// All trait types of the collection, without the ignored ones
+    trait_types: BTreeSet<String>,
+    /// trait type -> value -> number of tokens with that value
+    counts: BTreeMap<String, BTreeMap<TraitValue, usize>>,
+}
+
+impl TraitFrequencies {
+    /// Counts the traits of all `tokens` of a collection with the default
+    /// config.
+    pub fn new(tokens: impl IntoIterator<Item = TokenTraits>) -> Self {
+        Self::with_config(tokens, RarityConfig::default())
+    }
+
+    pub fn with_config(
+        tokens: impl IntoIterator<Item = TokenTraits>,
+        config: RarityConfig,
+    ) -> Self {
+        // tokens that were passed twice are only counted once
+        let mut seen = BTreeSet::new();
+        let tokens = tokens
+            .into_iter()
+            .filter(|token| seen.insert(token.nft.clone()))
+            .collect::<Vec<_>>();
+        let trait_types = tokens
+            .iter()
+            .flat_map(|token| token.traits.iter().map(|(t, _)| t.clone()))
+            .filter(|t| !config.ignored.contains(t))
+            .collect::<BTreeSet<_>>();
+        let mut freqs = Self {
+            config,
+            tokens: Vec::new(),
+            trait_types,
+            counts: BTreeMap::new(),
+        };
+        for token in &tokens {
+            for (trait_type, value) in freqs.traits_of(token) {
+                *freqs
+                    .counts
+                    .entry(trait_type)
+                    .or_default()
+                    .entry(value)
+                    .or_default() += 1;
+            }
+        }
+        freqs.tokens = tokens;
+        freqs
+    }
+
+    /// All counted traits of the token: its own, the missing trait types
+    /// and the trait count.
+    fn traits_of(&self, token: &TokenTraits) -> Vec<(String, TraitValue)> {
+        let mut traits = token
+            .traits
+            .iter()
+            .filter(|(t, _)| !self.config.ignored.contains(t))
+            .map(|(t, v)| (t.clone(), TraitValue::Value(v.clone())))
+            .collect::<Vec<_>>();
+        traits.sort();
+        traits.dedup();
+        let present = traits
+            .iter()
+            .map(|(t, _)| t.clone())
+            .collect::<BTreeSet<_>>();
+        let count = present.len();
+        if self.config.missing_traits {
+            traits.extend(
+                self.trait_types
+                    .difference(&present)
+                    .map(|t| (t.clone(), TraitValue::Missing)),
+            );
+        }
+        if self.config.trait_count && !self.config.ignored.contains(TRAIT_COUNT) {
+            traits.push((
+                TRAIT_COUNT.to_string(),
+                TraitValue::Value(count.to_string()),
+            ));
+        }
+        traits
+    }
+
+    /// Number of tokens in the collection
+    pub fn total(&self) -> usize {
+        self.tokens.len()
+    }
+
+    pub fn tokens(&self) -> &[TokenTraits] {
+        &self.tokens
+    }
+
+    /// The trait types of the collection, without [`TRAIT_COUNT`]
+    pub fn trait_types(&self) -> &BTreeSet<String> {
+        &self.trait_types
+    }
+
+    /// The number of tokens for every value of every trait type
+    pub fn counts(&self) -> &BTreeMap<String, BTreeMap<TraitValue, usize>> {
+        &self.counts
+    }
+
+    /// The number of tokens with the value
+    pub fn count(&self, trait_type: &str, value: &TraitValue) -> usize {
+        self.counts
+            .get(trait_type)
+            .and_then(|values| values.get(value))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// The share of tokens with the value
+    pub fn frequency(&self, trait_type: &str, value: &TraitValue) -> f64 {
+        if self.tokens.is_empty() {
+            return 0.;
+        }
+        self.count(trait_type, value) as f64 / self.tokens.len() as f64
+    }
+
+    /// The entropy of the collection's traits in bits, the sum of the
+    /// entropies of all trait types.
+    pub fn entropy(&self) -> f64 {
+        let total = self.tokens.len() as f64;
+        self.counts
+            .values()
+            .flat_map(|values| values.values())
+            .map(|count| {
+                let p = *count as f64 / total;
+                -p * p.log2()
+            })
+            .sum()
+    }
+
+    /// The frequencies of the counted traits of the token
+    fn frequencies(&self, token: &TokenTraits) -> Vec<f64> {
+        self.traits_of(token)
+            .iter()
+            .map(|(t, v)| self.frequency(t, v))
+            .collect()
+    }
+
+    /// The score of the token, `None` if it's not part of the collection
+    pub fn score(&self, nft: &NftId, method: RarityMethod) -> Option<f64> {
+        let token = self.tokens.iter().find(|token| &token.nft == nft)?;
+        Some(self.score_token(token, method, self.entropy()))
+    }
+
+    fn score_token(&self, token: &TokenTraits, method: RarityMethod, entropy: f64) -> f64 {
+        let freqs = self.frequencies(token);
+        match method {
+            RarityMethod::Statistical => 1. / freqs.iter().product::<f64>(),
+            RarityMethod::InverseFrequency => freqs.iter().map(|p| 1. / p).sum(),
+            RarityMethod::InformationContent => {
+                let content = freqs.iter().map(|p| -p.log2()).sum::<f64>();
+                if entropy > 0. {
+                    content / entropy
+                } else {
+                    0.
+                }
+            }
+        }
+    }
+
+    /// Scores and ranks all tokens
+    pub fn rank(&self, method: RarityMethod) -> RarityRanking {
+        let entropy = self.entropy();
+        let mut scores = self
+            .tokens
+            .iter()
+            .map(|token| RarityScore {
+                nft: token.nft.clone(),
+                score: self.score_token(token, method, entropy),
+                rank: 0,
+            })
+            .collect::<Vec<_>>();
+        scores.sort_by(|a, b| b.score.total_cmp(&a.score).then_with(|| a.nft.cmp(&b.nft)));
+        for idx in 0..scores.len() {
+            scores[idx].rank = if idx > 0 && scores[idx].score == scores[idx - 1].score {
+                scores[idx - 1].rank
+            } else {
+                idx + 1
+            };
+        }
+        RarityRanking { method, scores }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const COLLECTION: &str = "0x00000000000000000000000000000000000000aa";
+
+    fn nft(token_id: &str) -> NftId {
+        NftId::new(COLLECTION, token_id)
+    }
+
+    /// Hat: Cap 2, Crown 1, missing 1; Eyes: Blue 3, Red 1; trait count: 2
+    /// traits 3, 1 trait 1
+    fn tokens() -> Vec<TokenTraits> {
+        vec![
+            TokenTraits::new(nft("1"))
+                .with_trait("Hat", "Cap")
+                .with_trait("Eyes", "Blue"),
+            TokenTraits::new(nft("2"))
+                .with_trait("Eyes", "Blue")
+                .with_trait("Hat", "Cap"),
+            TokenTraits::new(nft("3"))
+                .with_trait("Hat", "Crown")
+                .with_trait("Eyes", "Blue"),
+            TokenTraits::new(nft("4")).with_trait("Eyes", "Red"),
+        ]
+    }
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!(
+            (actual - expected).abs() < 1e-9,
+            "{} != {}",
+            actual,
+            expected
+        );
+    }
+
+    fn value(value: &str) -> TraitValue {
+        TraitValue::Value(value.to_string())
+    }
+
+    #[test]
+    fn counts_missing_traits_and_the_trait_count() {
+        // the first token twice
+        let freqs = TraitFrequencies::new(tokens().into_iter().chain(tokens().into_iter().take(1)));
+        assert_eq!(freqs.total(), 4);
+        assert_eq!(
+            freqs.trait_types().iter().collect::<Vec<_>>(),
+            vec!["Eyes", "Hat"]
+        );
+        assert_eq!(freqs.count("Hat", &value("Cap")), 2);
+        assert_eq!(freqs.count("Hat", &TraitValue::Missing), 1);
+        assert_eq!(freqs.count("Eyes", &TraitValue::Missing), 0);
+        assert_eq!(freqs.count(TRAIT_COUNT, &value("2")), 3);
+        assert_eq!(freqs.count(TRAIT_COUNT, &value("1")), 1);
+        assert_close(freqs.frequency("Eyes", &value("Blue")), 0.75);
+
+        let freqs = TraitFrequencies::with_config(
+            tokens(),
+            RarityConfig::default()
+                .missing_traits(false)
+                .trait_count(false),
+        );
+        assert_eq!(freqs.count("Hat", &TraitValue::Missing), 0);
+        assert!(!freqs.counts().contains_key(TRAIT_COUNT));
+
+        let freqs = TraitFrequencies::with_config(tokens(), RarityConfig::default().ignore("Hat"));
+        assert!(!freqs.counts().contains_key("Hat"));
+        // the ignored trait isn't counted either
+        assert_eq!(freqs.count(TRAIT_COUNT, &value("1")), 4);
+    }
+
+    #[test]
+    fn scores_statistical_rarity() {
+        let freqs = TraitFrequencies::new(tokens());
+        let score = |id| freqs.score(&nft(id), RarityMethod::Statistical).unwrap();
+        assert_close(score("1"), 1. / (0.5 * 0.75 * 0.75));
+        assert_close(score("3"), 1. / (0.25 * 0.75 * 0.75));
+        // red eyes, no hat and a single trait
+        assert_close(score("4"), 1. / (0.25 * 0.25 * 0.25));
+        assert_eq!(freqs.score(&nft("5"), RarityMethod::Statistical), None);
+
+        let freqs = TraitFrequencies::with_config(
+            tokens(),
+            RarityConfig::default()
+                .missing_traits(false)
+                .trait_count(false),
+        );
+        let score = |id| freqs.score(&nft(id), RarityMethod::Statistical).unwrap();
+        assert_close(score("1"), 1. / (0.5 * 0.75));
+        assert_close(score("4"), 1. / 0.25);
+    }
+
+    #[test]
+    fn scores_inverse_frequencies() {
+        let freqs = TraitFrequencies::new(tokens());
+        let score = |id| {
+            freqs
+                .score(&nft(id), RarityMethod::InverseFrequency)
+                .unwrap()
+        };
+        assert_close(score("1"), 2. + 4. / 3. + 4. / 3.);
+        assert_close(score("3"), 4. + 4. / 3. + 4. / 3.);
+        assert_close(score("4"), 4. + 4. + 4.);
+    }
+
+    #[test]
+    fn scores_information_content() {
+        let freqs = TraitFrequencies::new(tokens());
+        // bits of a value of a quarter and of three quarters of the tokens
+        let (quarter, three_quarters) = (2., (4f64 / 3.).log2());
+        let eyes = 0.75 * three_quarters + 0.25 * quarter;
+        let entropy = (0.5 * 1. + 0.25 * quarter + 0.25 * quarter) + eyes + eyes;
+        assert_close(freqs.entropy(), entropy);
+
+        let score = |id| {
+            freqs
+                .score(&nft(id), RarityMethod::InformationContent)
+                .unwrap()
+        };
+        assert_close(score("1"), (1. + 2. * three_quarters) / entropy);
+        assert_close(score("3"), (quarter + 2. * three_quarters) / entropy);
+        assert_close(score("4"), 3. * quarter / entropy);
+
+        let single = TraitFrequencies::new(tokens().into_iter().take(1));
+        assert_eq!(
+            single.score(&nft("1"), RarityMethod::InformationContent),
+            Some(0.)
+        );
+    }
+
+    #[test]
+    fn ranks_tied_tokens_the_same() {
+        let freqs = TraitFrequencies::new(tokens());
+        for method in [
+            RarityMethod::Statistical,
+            RarityMethod::InverseFrequency,
+            RarityMethod::InformationContent,
+        ] {
+            let ranking = freqs.rank(method);
+            let ranks = ranking
+                .scores
+                .iter()
+                .map(|score| (score.nft.token_id.as_str(), score.rank))
+                .collect::<Vec<_>>();
+            assert_eq!(
+                ranks,
+                vec![("4", 1), ("3", 2), ("1", 3), ("2", 3)],
+                "{}",
+                method
+            );
+            assert_eq!(ranking.get(&nft("2")).unwrap().rank, 3);
+            assert_eq!(ranking.top(2).len(), 2);
+            assert_eq!(ranking.top(10).len(), 4);
+        }
+    }
+}
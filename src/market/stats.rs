@@ -0,0 +1,453 @@
+//! Time series of collection statistics and floor prices.
+//!
+//! A [`StatsTracker`] periodically snapshots the statistics OpenSea reports
+//! for a collection together with the floor computed from the active
+//! listings on OpenSea and Rarible. The snapshots are collected in a
+//! [`StatsHistory`] that answers how a value changed over a window, like the
+//! floor over the last hour.
+
+use std::path::Path;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+
+use crate::market::models::{AggregatedBook, Marketplace, NftId};
+#[cfg(feature = "rarible")]
+use crate::market::MarketClient;
+use crate::opensea::models::Stats;
+#[cfg(feature = "rarible")]
+use std::path::PathBuf;
+
+/// The statistics of a collection at a point in time
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StatsSnapshot {
+    pub timestamp: DateTime<Utc>,
+    /// Statistics as reported by OpenSea
+    pub stats: Stats,
+    /// Unit price in ETH of the cheapest active listing priced in ETH or
+    /// WETH across all marketplaces, `None` if nothing is listed
+    pub floor: Option<Decimal>,
+    pub floor_marketplace: Option<Marketplace>,
+    pub floor_nft: Option<NftId>,
+    /// Number of active listings priced in ETH or WETH
+    pub listings: usize,
+}
+
+impl StatsSnapshot {
+    /// Snapshots the stats and the floor of the active listings of the book
+    pub fn new(timestamp: DateTime<Utc>, stats: Stats, book: &AggregatedBook) -> Self {
+        let mut book = book.clone();
+        book.retain_active(timestamp);
+        let mut snapshot = Self {
+            timestamp,
+            stats,
+            floor: None,
+            floor_marketplace: None,
+            floor_nft: None,
+            listings: book
+                .listings
+                .iter()
+                .filter(|l| l.currency.is_ether())
+                .count(),
+        };
+        if let Some(listing) = book.best_ask() {
+            snapshot.floor = listing.unit_price().to_decimal();
+            snapshot.floor_marketplace = Some(listing.marketplace);
+            snapshot.floor_nft = Some(listing.nft.clone());
+        }
+        snapshot
+    }
+}
+
+/// How a value changed over a window
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct WindowChange {
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+    pub from: Decimal,
+    pub to: Decimal,
+}
+
+impl WindowChange {
+    /// The absolute change, `to - from`
+    pub fn delta(&self) -> Decimal {
+        self.to - self.from
+    }
+
+    /// The change relative to `from`, `0.1` is a 10% increase, `None` if
+    /// `from` is zero
+    pub fn relative(&self) -> Option<Decimal> {
+        if self.from.is_zero() {
+            None
+        } else {
+            Some(self.delta() / self.from)
+        }
+    }
+}
+
+/// Snapshots of a collection ordered by time
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct StatsHistory {
+    snapshots: Vec<StatsSnapshot>,
+}
+
+impl StatsHistory {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Reads the history from a json file, an empty history if it doesn't
+    /// exist
+    pub async fn load(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        match tokio::fs::read(path.as_ref()).await {
+            Ok(content) => Ok(serde_json::from_slice(&content)?),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Writes the history as json, replacing the file atomically
+    pub async fn save(&self, path: impl AsRef<Path>) -> anyhow::Result<()> {
+        let path = path.as_ref();
+        let tmp = path.with_extension("tmp");
+        tokio::fs::write(&tmp, serde_json::to_vec(self)?).await?;
+        tokio::fs::rename(&tmp, path).await?;
+        Ok(())
+    }
+
+    /// Adds the snapshot, keeping the history ordered by time
+    pub fn push(&mut self, snapshot: StatsSnapshot) {
+        let idx = self
+            .snapshots
+            .partition_point(|s| s.timestamp <= snapshot.timestamp);
+        self.snapshots.insert(idx, snapshot);
+    }
+
+    /// Removes all snapshots before `time`
+    pub fn prune(&mut self, time: DateTime<Utc>) {
+        let idx = self.snapshots.partition_point(|s| s.timestamp < time);
+        self.snapshots.drain(..idx);
+    }
+
+    pub fn snapshots(&self) -> &[StatsSnapshot] {
+        &self.snapshots
+    }
+
+    pub fn len(&self) -> usize {
+        self.snapshots.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.snapshots.is_empty()
+    }
+
+    pub fn latest(&self) -> Option<&StatsSnapshot> {
+        self.snapshots.last()
+    }
+
+    /// The latest snapshot taken at or before `time`
+    pub fn at(&self, time: DateTime<Utc>) -> Option<&StatsSnapshot> {
+        let idx = self.snapshots.partition_point(|s| s.timestamp <= time);
+        idx.checked_sub(1).map(|idx| &self.snapshots[idx])
+    }
+
+    /// The snapshots taken within `window` before the latest one
+    pub fn window(&self, window: Duration) -> &[StatsSnapshot] {
+        let start = match self.latest() {
+            Some(latest) => window_start(latest.timestamp, window),
+            None => return &[],
+        };
+        let idx = self.snapshots.partition_point(|s| s.timestamp < start);
+        &self.snapshots[idx..]
+    }
+
+    /// How the `metric` changed over `window` up to its latest value.
+    ///
+    /// The change starts at the last value before the window, or the first
+    /// value within the window if the history is shorter than the window.
+    /// `None` if there are less than two values.
+    pub fn change_by<F>(&self, window: Duration, metric: F) -> Option<WindowChange>
+    where
+        F: Fn(&StatsSnapshot) -> Option<Decimal>,
+    {
+        let values = self
+            .snapshots
+            .iter()
+            .filter_map(|s| Some((s.timestamp, metric(s)?)))
+            .collect::<Vec<_>>();
+        let (end, to) = *values.last()?;
+        let start = window_start(end, window);
+        let idx = values.partition_point(|(time, _)| *time <= start);
+        let (start, from) = if idx > 0 { values[idx - 1] } else { values[0] };
+        if start == end {
+            return None;
+        }
+        Some(WindowChange {
+            start,
+            end,
+            from,
+            to,
+        })
+    }
+
+    /// How the floor of the active listings changed over `window`
+    pub fn floor_change(&self, window: Duration) -> Option<WindowChange> {
+        self.change_by(window, |s| s.floor)
+    }
+
+    /// How the floor reported by OpenSea changed over `window`
+    pub fn opensea_floor_change(&self, window: Duration) -> Option<WindowChange> {
+        self.change_by(window, |s| s.stats.floor_price)
+    }
+
+    /// The total volume over `window`, `delta` is the volume traded within
+    /// the window
+    pub fn volume_change(&self, window: Duration) -> Option<WindowChange> {
+        self.change_by(window, |s| Some(s.stats.total_volume))
+    }
+
+    /// The total sales over `window`, `delta` is the number of sales within
+    /// the window
+    pub fn sales_change(&self, window: Duration) -> Option<WindowChange> {
+        self.change_by(window, |s| Some(s.stats.total_sales))
+    }
+
+    pub fn owners_change(&self, window: Duration) -> Option<WindowChange> {
+        self.change_by(window, |s| Some(s.stats.num_owners))
+    }
+
+    /// The lowest and highest floor within `window`
+    pub fn floor_range(&self, window: Duration) -> Option<(Decimal, Decimal)> {
+        let mut floors = self.window(window).iter().filter_map(|s| s.floor);
+        let first = floors.next()?;
+        Some(floors.fold((first, first), |(min, max), floor| {
+            (min.min(floor), max.max(floor))
+        }))
+    }
+}
+
+fn window_start(end: DateTime<Utc>, window: Duration) -> DateTime<Utc> {
+    chrono::Duration::from_std(window)
+        .ok()
+        .and_then(|window| end.checked_sub_signed(window))
+        .unwrap_or(DateTime::<Utc>::MIN_UTC)
+}
+
+/// Periodically snapshots the stats and floor of a collection
+#[cfg(feature = "rarible")]
+pub struct StatsTracker {
+    market: MarketClient,
+    /// OpenSea slug of the collection
+    slug: String,
+    /// Address of the collection's contract
+    contract: String,
+    history: StatsHistory,
+    /// File the history is persisted to after every poll
+    history_file: Option<PathBuf>,
+    interval: Duration,
+    /// Snapshots older than this are dropped
+    retention: Duration,
+    /// Pages of listings read at most from each marketplace
+    max_pages: u32,
+}
+
+#[cfg(feature = "rarible")]
+impl StatsTracker {
+    pub fn new(market: MarketClient, slug: impl Into<String>, contract: impl AsRef<str>) -> Self {
+        Self {
+            market,
+            slug: slug.into(),
+            contract: contract.as_ref().to_lowercase(),
+            history: StatsHistory::default(),
+            history_file: None,
+            interval: Duration::from_secs(60),
+            retention: Duration::from_secs(60 * 60 * 24 * 7),
+            max_pages: 20,
+        }
+    }
+
+    pub fn interval(mut self, value: Duration) -> Self {
+        self.interval = value;
+        self
+    }
+
+    pub fn retention(mut self, value: Duration) -> Self {
+        self.retention = value;
+        self
+    }
+
+    pub fn max_pages<T: Into<u32>>(mut self, value: T) -> Self {
+        self.max_pages = value.into();
+        self
+    }
+
+    /// Continues the history
+    pub fn history<V: Into<StatsHistory>>(mut self, value: V) -> Self {
+        self.history = value.into();
+        self
+    }
+
+    /// Continues the history stored in the file, if it exists, and saves the
+    /// history to it after every poll
+    pub async fn history_file(mut self, path: impl Into<PathBuf>) -> anyhow::Result<Self> {
+        let path = path.into();
+        self.history = StatsHistory::load(&path).await?;
+        self.history_file = Some(path);
+        Ok(self)
+    }
+
+    pub fn current_history(&self) -> &StatsHistory {
+        &self.history
+    }
+
+    /// Fetches the current stats and floor, and adds them to the history.
+    ///
+    /// The floor is computed from up to `max_pages` pages of listings of
+    /// each marketplace, which are not sorted by price.
+    pub async fn poll(&mut self) -> anyhow::Result<StatsSnapshot> {
+        let (stats, listings) = futures::try_join!(
+            self.market.opensea().get_collection_stats(&self.slug),
+            self.market
+                .collection_listings(&self.contract, self.max_pages),
+        )?;
+        let book = AggregatedBook {
+            listings,
+            offers: Vec::new(),
+        };
+        let snapshot = StatsSnapshot::new(Utc::now(), stats.stats, &book);
+
+        self.history.push(snapshot.clone());
+        self.history
+            .prune(window_start(snapshot.timestamp, self.retention));
+        if let Some(path) = self.history_file.as_ref() {
+            self.history.save(path).await?;
+        }
+        Ok(snapshot)
+    }
+
+    /// Polls in the interval and yields every snapshot together with the
+    /// history up to it.
+    ///
+    /// Failed polls are yielded as errors and retried in the next interval.
+    #[cfg(feature = "watch")]
+    pub fn track(
+        self,
+    ) -> futures::stream::BoxStream<'static, anyhow::Result<(StatsSnapshot, StatsHistory)>> {
+        use futures::StreamExt;
+        futures::stream::unfold((self, false), |(mut tracker, polled)| async move {
+            if polled {
+                tokio::time::sleep(tracker.interval).await;
+            }
+            let item = tracker
+                .poll()
+                .await
+                .map(|snapshot| (snapshot, tracker.history.clone()));
+            Some((item, (tracker, true)))
+        })
+        .boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    const MINUTE: Duration = Duration::from_secs(60);
+
+    fn time(minutes: i64) -> DateTime<Utc> {
+        Utc.timestamp_opt(1_600_000_000 + minutes * 60, 0).unwrap()
+    }
+
+    fn snapshot(minutes: i64, floor: Option<i64>) -> StatsSnapshot {
+        let mut snapshot =
+            StatsSnapshot::new(time(minutes), Stats::default(), &AggregatedBook::default());
+        snapshot.floor = floor.map(Decimal::from);
+        snapshot
+    }
+
+    /// Floors of 10, 11, 12 and 13 ETH every 10 minutes, pushed out of order
+    fn history() -> StatsHistory {
+        let mut history = StatsHistory::new();
+        for (minutes, floor) in [(30, 13), (0, 10), (20, 12), (10, 11)] {
+            history.push(snapshot(minutes, Some(floor)));
+        }
+        history
+    }
+
+    #[test]
+    fn changes_from_the_last_value_before_the_window() {
+        let history = history();
+        let change = history.floor_change(15 * MINUTE).unwrap();
+        assert_eq!((change.start, change.end), (time(10), time(30)));
+        assert_eq!(
+            (change.from, change.to),
+            (Decimal::from(11), Decimal::from(13))
+        );
+        assert_eq!(change.delta(), Decimal::from(2));
+        assert_eq!(
+            change.relative(),
+            Some(Decimal::from(2) / Decimal::from(11))
+        );
+        // a value at the start of the window is the last one before it
+        assert_eq!(history.floor_change(20 * MINUTE).unwrap().start, time(10));
+        assert_eq!(history.window(20 * MINUTE).len(), 3);
+        assert_eq!(history.window(15 * MINUTE).len(), 2);
+        assert_eq!(
+            history.floor_range(15 * MINUTE),
+            Some((Decimal::from(12), Decimal::from(13)))
+        );
+    }
+
+    #[test]
+    fn changes_from_the_first_value_of_a_shorter_history() {
+        let history = history();
+        let change = history.floor_change(120 * MINUTE).unwrap();
+        assert_eq!((change.start, change.end), (time(0), time(30)));
+        assert_eq!(
+            (change.from, change.to),
+            (Decimal::from(10), Decimal::from(13))
+        );
+        assert_eq!(history.window(120 * MINUTE).len(), 4);
+        assert_eq!(
+            history.window(120 * MINUTE)[0].timestamp,
+            history.snapshots()[0].timestamp
+        );
+    }
+
+    #[test]
+    fn has_no_change_of_a_single_value() {
+        assert_eq!(StatsHistory::new().floor_change(MINUTE), None);
+        assert!(StatsHistory::new().window(MINUTE).is_empty());
+
+        let mut history = StatsHistory::new();
+        history.push(snapshot(0, Some(10)));
+        assert_eq!(history.floor_change(120 * MINUTE), None);
+        // snapshots without a floor are no values of it
+        history.push(snapshot(10, None));
+        history.push(snapshot(20, None));
+        assert_eq!(history.floor_change(120 * MINUTE), None);
+        assert_eq!(
+            history.floor_range(120 * MINUTE),
+            Some((10.into(), 10.into()))
+        );
+        assert!(history.volume_change(120 * MINUTE).is_some());
+    }
+
+    #[test]
+    fn prunes_snapshots_before_the_retention() {
+        let mut history = history();
+        // a retention of 20 minutes keeps the snapshot taken exactly then
+        history.prune(window_start(time(30), 20 * MINUTE));
+        assert_eq!(history.len(), 3);
+        assert_eq!(history.snapshots()[0].timestamp, time(10));
+        assert_eq!(history.at(time(15)).unwrap().timestamp, time(10));
+        assert_eq!(history.at(time(5)), None);
+
+        history.prune(time(30) + chrono::Duration::seconds(1));
+        assert!(history.is_empty());
+        assert_eq!(history.latest(), None);
+    }
+}
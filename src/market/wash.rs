@@ -0,0 +1,622 @@
+//! Detection of wash trading and self dealing in sale histories.
+//!
+//! The [`WashTradeDetector`] looks for patterns in a set of [`Trade`]s that
+//! are typical for trades that only inflate volume or prices:
+//!
+//! - tokens that return to a wallet that sold them shortly before
+//! - wallets that trade with each other in both directions
+//! - buyers that were funded shortly before the sale, e.g. by the seller
+//! - prices far from the floor that are resold shortly after
+//!
+//! Every pattern is a [`WashSignal`] with a weight, the score of a trade
+//! combines the weights of its signals to a value between 0 and 1.
+//!
+//! The marketplaces don't report where a wallet's ether came from, funding
+//! has to be passed to the detector, see [`WashTradeDetector::funding`].
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+
+use crate::market::models::Trade;
+
+/// A suspicious pattern a trade is part of
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum WashSignal {
+    /// Seller and buyer are the same wallet
+    SelfTrade,
+    /// The token returned to the buyer, who sold it before, `trades` are the
+    /// ids of the trades of the cycle
+    RoundTrip {
+        wallets: Vec<String>,
+        trades: Vec<String>,
+    },
+    /// Seller and buyer also traded another token in the other direction
+    MutualTrades { counterparty: String, trade: String },
+    /// The buyer was funded shortly before the trade
+    FundedBuyer {
+        funder: Option<String>,
+        funded_at: DateTime<Utc>,
+        /// Whether the seller funded the buyer
+        by_seller: bool,
+    },
+    /// The price is far from the floor and the token was resold shortly
+    /// after
+    OffFloorResale {
+        floor: Decimal,
+        /// `(price - floor) / floor`
+        deviation: Decimal,
+        /// Seconds until the token was sold again
+        resold_after: i64,
+    },
+}
+
+impl WashSignal {
+    /// How strongly the signal indicates a wash trade, between 0 and 1
+    pub fn weight(&self) -> f64 {
+        match self {
+            WashSignal::SelfTrade => 1.,
+            WashSignal::RoundTrip { .. } => 0.8,
+            WashSignal::MutualTrades { .. } => 0.5,
+            WashSignal::FundedBuyer {
+                by_seller: true, ..
+            } => 0.7,
+            WashSignal::FundedBuyer { .. } => 0.3,
+            WashSignal::OffFloorResale { .. } => 0.4,
+        }
+    }
+}
+
+/// A trade with the suspicious patterns it's part of
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ScoredTrade {
+    pub trade: Trade,
+    /// 0 if no pattern was found, approaches 1 the more patterns were found
+    pub score: f64,
+    pub signals: Vec<WashSignal>,
+}
+
+impl ScoredTrade {
+    fn new(trade: Trade) -> Self {
+        Self {
+            trade,
+            score: 0.,
+            signals: Vec::new(),
+        }
+    }
+
+    fn flag(&mut self, signal: WashSignal) {
+        if !self.signals.contains(&signal) {
+            self.signals.push(signal);
+        }
+    }
+
+    fn score(&mut self) {
+        self.score = 1.
+            - self
+                .signals
+                .iter()
+                .map(|signal| 1. - signal.weight())
+                .product::<f64>();
+    }
+}
+
+/// The trades of a wash trading analysis
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct WashReport {
+    /// Ordered by score, highest first
+    pub trades: Vec<ScoredTrade>,
+}
+
+impl WashReport {
+    /// The trades with a score of at least `min_score`
+    pub fn suspicious(&self, min_score: f64) -> impl Iterator<Item = &ScoredTrade> {
+        self.trades
+            .iter()
+            .filter(move |trade| trade.score > 0. && trade.score >= min_score)
+    }
+
+    /// The volume in ETH of all trades
+    pub fn total_volume(&self) -> Decimal {
+        volume(self.trades.iter())
+    }
+
+    /// The volume in ETH of the trades with a score of at least `min_score`
+    pub fn suspicious_volume(&self, min_score: f64) -> Decimal {
+        volume(self.suspicious(min_score))
+    }
+
+    /// The volume in ETH without the trades with a score of at least
+    /// `min_score`
+    pub fn organic_volume(&self, min_score: f64) -> Decimal {
+        self.total_volume() - self.suspicious_volume(min_score)
+    }
+}
+
+/// Sums up price times quantity, trades whose volume doesn't fit into a
+/// `Decimal` are skipped
+fn volume<'a>(trades: impl Iterator<Item = &'a ScoredTrade>) -> Decimal {
+    trades
+        .filter_map(|t| {
+            if t.trade.quantity.bits() > 64 {
+                return None;
+            }
+            let quantity = Decimal::from(t.trade.quantity.low_u64());
+            t.trade.price?.checked_mul(quantity)
+        })
+        .fold(Decimal::ZERO, |acc, volume| acc.saturating_add(volume))
+}
+
+/// Time windows and thresholds of the patterns
+#[derive(Clone, Debug, PartialEq)]
+pub struct WashConfig {
+    /// How long a token can take to return to a wallet
+    pub round_trip_window: Duration,
+    /// How long before a trade the buyer was funded
+    pub funding_window: Duration,
+    /// How long after a trade the token is resold
+    pub resale_window: Duration,
+    /// How far from the floor a price is, `1` flags prices of at least
+    /// twice or at most half the floor
+    pub floor_deviation: Decimal,
+}
+
+impl WashConfig {
+    pub fn round_trip_window(mut self, value: Duration) -> Self {
+        self.round_trip_window = value;
+        self
+    }
+
+    pub fn funding_window(mut self, value: Duration) -> Self {
+        self.funding_window = value;
+        self
+    }
+
+    pub fn resale_window(mut self, value: Duration) -> Self {
+        self.resale_window = value;
+        self
+    }
+
+    pub fn floor_deviation<T: Into<Decimal>>(mut self, value: T) -> Self {
+        self.floor_deviation = value.into();
+        self
+    }
+}
+
+impl Default for WashConfig {
+    fn default() -> Self {
+        Self {
+            round_trip_window: Duration::from_secs(60 * 60 * 24 * 30),
+            funding_window: Duration::from_secs(60 * 60 * 24 * 7),
+            resale_window: Duration::from_secs(60 * 60 * 24),
+            floor_deviation: Decimal::ONE,
+        }
+    }
+}
+
+/// When a wallet received the ether it trades with
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Funding {
+    pub funded_at: DateTime<Utc>,
+    /// The wallet that sent the ether, lowercase
+    pub funder: Option<String>,
+}
+
+/// Scores trades by the wash trading patterns they're part of
+#[derive(Clone, Debug, Default)]
+pub struct WashTradeDetector {
+    config: WashConfig,
+    /// contract -> floor in ETH
+    floors: HashMap<String, Decimal>,
+    /// wallet -> funding
+    funding: HashMap<String, Funding>,
+}
+
+impl WashTradeDetector {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_config(config: WashConfig) -> Self {
+        Self {
+            config,
+            ..Self::default()
+        }
+    }
+
+    /// Sets the floor of the collection, in ETH.
+    ///
+    /// Collections without a floor use the median price of their trades.
+    pub fn floor(mut self, contract: impl AsRef<str>, floor: Decimal) -> Self {
+        self.floors.insert(contract.as_ref().to_lowercase(), floor);
+        self
+    }
+
+    /// Records that `wallet` was funded at `funded_at`, by `funder` if known
+    pub fn funding(
+        mut self,
+        wallet: impl AsRef<str>,
+        funded_at: DateTime<Utc>,
+        funder: Option<&str>,
+    ) -> Self {
+        self.funding.insert(
+            wallet.as_ref().to_lowercase(),
+            Funding {
+                funded_at,
+                funder: funder.map(str::to_lowercase),
+            },
+        );
+        self
+    }
+
+    /// Scores the trades.
+    ///
+    /// Trades reported by both marketplaces are only counted once, by their
+    /// transaction hash.
+    pub fn analyze(&self, trades: impl IntoIterator<Item = Trade>) -> WashReport {
+        let mut seen = HashSet::new();
+        let mut trades = trades
+            .into_iter()
+            .filter(|trade| {
+                let key = match trade.transaction_hash.as_ref() {
+                    Some(hash) => format!("{}:{}", hash.to_lowercase(), trade.nft),
+                    None => format!("{}:{}", trade.marketplace, trade.id),
+                };
+                seen.insert(key)
+            })
+            .map(ScoredTrade::new)
+            .collect::<Vec<_>>();
+        trades.sort_by_key(|t| t.trade.timestamp);
+
+        // indices of the trades of every token, ordered by time
+        let mut tokens = BTreeMap::<_, Vec<usize>>::new();
+        for (idx, trade) in trades.iter().enumerate() {
+            tokens.entry(trade.trade.nft.clone()).or_default().push(idx);
+        }
+
+        for trade in &mut trades {
+            if trade.trade.is_self_trade() {
+                trade.flag(WashSignal::SelfTrade);
+            }
+            self.flag_funding(trade);
+        }
+        let floors = self.floors(&trades);
+        for indices in tokens.values() {
+            self.flag_round_trips(&mut trades, indices);
+            self.flag_resales(&mut trades, indices, &floors);
+        }
+        self.flag_mutual_trades(&mut trades);
+
+        for trade in &mut trades {
+            trade.score();
+        }
+        trades.sort_by(|a, b| {
+            b.score
+                .total_cmp(&a.score)
+                .then_with(|| a.trade.timestamp.cmp(&b.trade.timestamp))
+        });
+        WashReport { trades }
+    }
+
+    fn flag_funding(&self, trade: &mut ScoredTrade) {
+        let funding = match self.funding.get(&trade.trade.buyer) {
+            Some(funding) => funding,
+            None => return,
+        };
+        if within(
+            funding.funded_at,
+            trade.trade.timestamp,
+            self.config.funding_window,
+        ) {
+            trade.flag(WashSignal::FundedBuyer {
+                funder: funding.funder.clone(),
+                funded_at: funding.funded_at,
+                by_seller: funding.funder.as_ref() == Some(&trade.trade.seller),
+            });
+        }
+    }
+
+    /// Flags the trades of a token that bring it back to a wallet that sold
+    /// it before
+    fn flag_round_trips(&self, trades: &mut [ScoredTrade], indices: &[usize]) {
+        for (pos, &end) in indices.iter().enumerate() {
+            let buyer = trades[end].trade.buyer.clone();
+            let returned_at = trades[end].trade.timestamp;
+            // the latest sale by the buyer is the shortest cycle
+            let start = indices[..pos].iter().rev().copied().find(|&idx| {
+                trades[idx].trade.seller == buyer
+                    && within(
+                        trades[idx].trade.timestamp,
+                        returned_at,
+                        self.config.round_trip_window,
+                    )
+            });
+            let start = match start {
+                Some(start) => start,
+                None => continue,
+            };
+            let cycle = indices
+                .iter()
+                .copied()
+                .filter(|&idx| idx >= start && idx <= end)
+                .collect::<Vec<_>>();
+            let signal = WashSignal::RoundTrip {
+                wallets: cycle
+                    .iter()
+                    .map(|&idx| trades[idx].trade.seller.clone())
+                    .collect(),
+                trades: cycle
+                    .iter()
+                    .map(|&idx| trades[idx].trade.id.clone())
+                    .collect(),
+            };
+            for idx in cycle {
+                trades[idx].flag(signal.clone());
+            }
+        }
+    }
+
+    /// Flags trades far from the `floors` of their collection that are
+    /// resold shortly after
+    fn flag_resales(
+        &self,
+        trades: &mut [ScoredTrade],
+        indices: &[usize],
+        floors: &HashMap<String, Decimal>,
+    ) {
+        for pair in indices.windows(2) {
+            let (current, next) = (pair[0], pair[1]);
+            let trade = &trades[current].trade;
+            let (price, floor) = match (trade.price, floors.get(&trade.nft.contract)) {
+                (Some(price), Some(&floor)) if !floor.is_zero() => (price, floor),
+                _ => continue,
+            };
+            let next_at = trades[next].trade.timestamp;
+            if !within(trade.timestamp, next_at, self.config.resale_window) {
+                continue;
+            }
+            let ratio = price / floor;
+            let limit = Decimal::ONE + self.config.floor_deviation;
+            if ratio >= limit || ratio * limit <= Decimal::ONE {
+                let resold_after = (next_at - trade.timestamp).num_seconds();
+                trades[current].flag(WashSignal::OffFloorResale {
+                    floor,
+                    deviation: ratio - Decimal::ONE,
+                    resold_after,
+                });
+            }
+        }
+    }
+
+    /// Flags pairs of wallets that sold each other different tokens
+    fn flag_mutual_trades(&self, trades: &mut [ScoredTrade]) {
+        let mut by_pair = HashMap::<(&str, &str), Vec<usize>>::new();
+        for (idx, trade) in trades.iter().enumerate() {
+            by_pair
+                .entry((&trade.trade.seller, &trade.trade.buyer))
+                .or_default()
+                .push(idx);
+        }
+        let mut flags = Vec::new();
+        for (&(seller, buyer), indices) in &by_pair {
+            if seller == buyer {
+                continue;
+            }
+            let reverse = match by_pair.get(&(buyer, seller)) {
+                Some(reverse) => reverse,
+                None => continue,
+            };
+            for &idx in indices {
+                let trade = &trades[idx].trade;
+                let other = reverse.iter().copied().find(|&other| {
+                    let other = &trades[other].trade;
+                    other.nft != trade.nft
+                        && (within(
+                            trade.timestamp,
+                            other.timestamp,
+                            self.config.round_trip_window,
+                        ) || within(
+                            other.timestamp,
+                            trade.timestamp,
+                            self.config.round_trip_window,
+                        ))
+                });
+                if let Some(other) = other {
+                    flags.push((
+                        idx,
+                        WashSignal::MutualTrades {
+                            counterparty: buyer.to_string(),
+                            trade: trades[other].trade.id.clone(),
+                        },
+                    ));
+                }
+            }
+        }
+        for (idx, signal) in flags {
+            trades[idx].flag(signal);
+        }
+    }
+
+    /// The floors of the collections of the trades: the configured floor,
+    /// or the median price of its trades
+    fn floors(&self, trades: &[ScoredTrade]) -> HashMap<String, Decimal> {
+        let mut prices = HashMap::<&str, Vec<Decimal>>::new();
+        for t in trades {
+            if let Some(price) = t.trade.price {
+                if !self.floors.contains_key(&t.trade.nft.contract) {
+                    prices.entry(&t.trade.nft.contract).or_default().push(price);
+                }
+            }
+        }
+        let mut floors = self.floors.clone();
+        for (contract, mut prices) in prices {
+            prices.sort();
+            let mid = prices.len() / 2;
+            let median = if prices.len() % 2 == 0 {
+                (prices[mid - 1] + prices[mid]) / Decimal::TWO
+            } else {
+                prices[mid]
+            };
+            floors.insert(contract.to_string(), median);
+        }
+        floors
+    }
+}
+
+/// Whether `end` is at most `window` after `start`
+fn within(start: DateTime<Utc>, end: DateTime<Utc>, window: Duration) -> bool {
+    let elapsed = end - start;
+    elapsed >= chrono::Duration::zero()
+        && chrono::Duration::from_std(window).map_or(true, |window| elapsed <= window)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::market::models::{Currency, Marketplace, NftId};
+    use chrono::TimeZone;
+    use ethereum_types::U256;
+
+    const COLLECTION: &str = "0x00000000000000000000000000000000000000aa";
+    const DAY: i64 = 24 * 60 * 60;
+
+    /// A sale of token `token_id` for `price` ETH, `days` after the start
+    fn trade(id: &str, token_id: &str, seller: &str, buyer: &str, price: i64, days: i64) -> Trade {
+        Trade {
+            marketplace: Marketplace::OpenSea,
+            id: id.to_string(),
+            nft: NftId::new(COLLECTION, token_id),
+            seller: seller.to_string(),
+            buyer: buyer.to_string(),
+            quantity: U256::one(),
+            price: Some(Decimal::from(price)),
+            currency: Currency::Eth,
+            timestamp: Utc.timestamp_opt(1_600_000_000 + days * DAY, 0).unwrap(),
+            transaction_hash: None,
+            log_index: None,
+        }
+    }
+
+    fn signals(report: &WashReport, id: &str) -> Vec<WashSignal> {
+        report
+            .trades
+            .iter()
+            .find(|t| t.trade.id == id)
+            .unwrap()
+            .signals
+            .clone()
+    }
+
+    #[test]
+    fn flags_self_trades() {
+        let report = WashTradeDetector::new().analyze(vec![
+            trade("1", "1", "a", "a", 1, 0),
+            trade("2", "2", "a", "b", 1, 0),
+        ]);
+        assert_eq!(signals(&report, "1"), vec![WashSignal::SelfTrade]);
+        assert_eq!(report.trades[0].score, 1.);
+        assert!(signals(&report, "2").is_empty());
+        assert_eq!(report.suspicious(0.5).count(), 1);
+        assert_eq!(report.organic_volume(0.5), Decimal::ONE);
+    }
+
+    #[test]
+    fn flags_round_trips() {
+        let report = WashTradeDetector::new().analyze(vec![
+            trade("1", "1", "a", "b", 1, 0),
+            trade("2", "1", "b", "c", 1, 10),
+            trade("3", "1", "c", "a", 1, 20),
+            // too late to be part of the cycle
+            trade("4", "1", "a", "b", 1, 60),
+        ]);
+        let round_trip = WashSignal::RoundTrip {
+            wallets: vec!["a".to_string(), "b".to_string(), "c".to_string()],
+            trades: vec!["1".to_string(), "2".to_string(), "3".to_string()],
+        };
+        for id in &["1", "2", "3"] {
+            assert_eq!(signals(&report, id), vec![round_trip.clone()]);
+        }
+        assert!(signals(&report, "4").is_empty());
+    }
+
+    #[test]
+    fn flags_mutual_trades() {
+        let report = WashTradeDetector::new().analyze(vec![
+            trade("1", "1", "a", "b", 1, 0),
+            trade("2", "2", "b", "a", 1, 5),
+            trade("3", "3", "a", "c", 1, 5),
+        ]);
+        assert_eq!(
+            signals(&report, "1"),
+            vec![WashSignal::MutualTrades {
+                counterparty: "b".to_string(),
+                trade: "2".to_string()
+            }]
+        );
+        assert_eq!(
+            signals(&report, "2"),
+            vec![WashSignal::MutualTrades {
+                counterparty: "a".to_string(),
+                trade: "1".to_string()
+            }]
+        );
+        assert!(signals(&report, "3").is_empty());
+    }
+
+    #[test]
+    fn flags_funded_buyers() {
+        let start = Utc.timestamp_opt(1_600_000_000, 0).unwrap();
+        let report = WashTradeDetector::new()
+            .funding("B", start - chrono::Duration::days(1), Some("A"))
+            .funding("c", start - chrono::Duration::days(30), None)
+            .analyze(vec![
+                trade("1", "1", "a", "b", 1, 0),
+                trade("2", "2", "a", "c", 1, 0),
+            ]);
+        let signal = WashSignal::FundedBuyer {
+            funder: Some("a".to_string()),
+            funded_at: start - chrono::Duration::days(1),
+            by_seller: true,
+        };
+        assert_eq!(signal.weight(), 0.7);
+        assert_eq!(signals(&report, "1"), vec![signal]);
+        assert!(signals(&report, "2").is_empty());
+    }
+
+    #[test]
+    fn flags_off_floor_resales() {
+        let trades = vec![
+            trade("1", "1", "a", "b", 10, 0),
+            trade("2", "1", "b", "c", 1, 0),
+            trade("3", "2", "d", "e", 1, 0),
+            trade("4", "3", "f", "g", 1, 0),
+        ];
+        // the median price is the floor
+        let report = WashTradeDetector::new().analyze(trades.clone());
+        assert_eq!(
+            signals(&report, "1"),
+            vec![WashSignal::OffFloorResale {
+                floor: Decimal::ONE,
+                deviation: Decimal::from(9),
+                resold_after: 0,
+            }]
+        );
+        assert!(signals(&report, "2").is_empty());
+
+        // not resold within the window
+        let late = vec![trades[0].clone(), trade("2", "1", "b", "c", 1, 2)];
+        let report = WashTradeDetector::new()
+            .floor(COLLECTION.to_uppercase(), Decimal::ONE)
+            .analyze(late);
+        assert!(signals(&report, "1").is_empty());
+
+        // at the configured floor
+        let report = WashTradeDetector::new()
+            .floor(COLLECTION, Decimal::from(10))
+            .analyze(trades);
+        assert!(signals(&report, "1").is_empty());
+    }
+}
@@ -0,0 +1,610 @@
+//! Decoding of the calls Wyvern orders execute.
+//!
+//! The `calldata` of an order is the call the exchange makes to `target`
+//! when the order is matched. Before that, all bytes the
+//! `replacement_pattern` masks are replaced with the counterparty's
+//! calldata, which is how the taker fills in the address of the buyer or
+//! seller.
+
+use ethabi::{ParamType, Token};
+
+use crate::eth::{self, Address, U256};
+use crate::opensea::models::{ExchangeMetadata, Order, OrderSide, WyvernAsset, WyvernSchemaName};
+
+/// Address of the Wyvern atomicizer on mainnet, used for bundles
+pub const ATOMICIZER_MAINNET: &str = "0xc99f70bfd82fb7c8f8191fdfbfb735606b15e5c5";
+
+/// `transferFrom(address,address,uint256)`
+pub const ERC721_TRANSFER_FROM: [u8; 4] = [0x23, 0xb8, 0x72, 0xdd];
+
+/// `safeTransferFrom(address,address,uint256)`
+pub const ERC721_SAFE_TRANSFER_FROM: [u8; 4] = [0x42, 0x84, 0x2e, 0x0e];
+
+/// `safeTransferFrom(address,address,uint256,bytes)`
+pub const ERC721_SAFE_TRANSFER_FROM_DATA: [u8; 4] = [0xb8, 0x8d, 0x4f, 0xde];
+
+/// `safeTransferFrom(address,address,uint256,uint256,bytes)`
+pub const ERC1155_SAFE_TRANSFER_FROM: [u8; 4] = [0xf2, 0x42, 0x43, 0x2a];
+
+/// `atomicize(address[],uint256[],uint256[],bytes)`
+pub const ATOMICIZE: [u8; 4] = [0x68, 0xf0, 0xbc, 0xaa];
+
+/// Wyvern `HowToCall::Call`
+pub(crate) const CALL: i64 = 0;
+
+/// Wyvern `HowToCall::DelegateCall`
+const DELEGATE_CALL: i64 = 1;
+
+/// A token transfer an order executes
+#[derive(Clone, Debug, PartialEq)]
+pub struct AssetTransfer {
+    pub schema: WyvernSchemaName,
+    pub contract: Address,
+    pub token_id: U256,
+    /// Number of tokens, always `1` for ERC721
+    pub quantity: U256,
+    /// The sender, `None` if it is filled in by the counterparty
+    pub from: Option<Address>,
+    /// The recipient, `None` if it is filled in by the counterparty
+    pub to: Option<Address>,
+}
+
+/// Whether any byte of the argument at `index` is replaceable
+fn is_replaceable(pattern: &[u8], index: usize) -> bool {
+    let start = 4 + index * 32;
+    pattern.iter().skip(start).take(32).any(|byte| *byte != 0)
+}
+
+fn address_arg(token: &Token, pattern: &[u8], index: usize) -> anyhow::Result<Option<Address>> {
+    if is_replaceable(pattern, index) {
+        return Ok(None);
+    }
+    token
+        .clone()
+        .into_address()
+        .map(Some)
+        .ok_or_else(|| anyhow::anyhow!("argument {} is not an address", index))
+}
+
+fn uint_arg(token: &Token, pattern: &[u8], index: usize, name: &str) -> anyhow::Result<U256> {
+    anyhow::ensure!(
+        !is_replaceable(pattern, index),
+        "the {} can be replaced by the counterparty",
+        name
+    );
+    token
+        .clone()
+        .into_uint()
+        .ok_or_else(|| anyhow::anyhow!("argument {} is not a uint", index))
+}
+
+/// Decodes a single ERC721 or ERC1155 transfer of `contract`
+pub fn decode_transfer(
+    contract: Address,
+    calldata: &[u8],
+    replacement_pattern: &[u8],
+) -> anyhow::Result<AssetTransfer> {
+    anyhow::ensure!(calldata.len() >= 4, "calldata without function selector");
+    let (selector, args) = calldata.split_at(4);
+    let (schema, params) = match selector {
+        s if s == ERC721_TRANSFER_FROM || s == ERC721_SAFE_TRANSFER_FROM => (
+            WyvernSchemaName::ERC721,
+            vec![ParamType::Address, ParamType::Address, ParamType::Uint(256)],
+        ),
+        s if s == ERC721_SAFE_TRANSFER_FROM_DATA => (
+            WyvernSchemaName::ERC721,
+            vec![
+                ParamType::Address,
+                ParamType::Address,
+                ParamType::Uint(256),
+                ParamType::Bytes,
+            ],
+        ),
+        s if s == ERC1155_SAFE_TRANSFER_FROM => (
+            WyvernSchemaName::ERC1155,
+            vec![
+                ParamType::Address,
+                ParamType::Address,
+                ParamType::Uint(256),
+                ParamType::Uint(256),
+                ParamType::Bytes,
+            ],
+        ),
+        s => anyhow::bail!("unknown function {}", eth::encode_hex(s)),
+    };
+    let tokens = ethabi::decode(&params, args)?;
+    let quantity = if schema == WyvernSchemaName::ERC1155 {
+        uint_arg(&tokens[3], replacement_pattern, 3, "quantity")?
+    } else {
+        U256::one()
+    };
+    Ok(AssetTransfer {
+        schema,
+        contract,
+        token_id: uint_arg(&tokens[2], replacement_pattern, 2, "token id")?,
+        quantity,
+        from: address_arg(&tokens[0], replacement_pattern, 0)?,
+        to: address_arg(&tokens[1], replacement_pattern, 1)?,
+    })
+}
+
+/// Encodes a single ERC721 or ERC1155 transfer, the inverse of
+/// [`decode_transfer`].
+///
+/// Returns the calldata and the replacement pattern, which masks the sender
+/// or recipient that is `None` so the counterparty can fill it in.
+pub fn encode_transfer(
+    schema: &WyvernSchemaName,
+    token_id: U256,
+    quantity: U256,
+    from: Option<Address>,
+    to: Option<Address>,
+) -> anyhow::Result<(Vec<u8>, Vec<u8>)> {
+    let addresses = vec![
+        Token::Address(from.unwrap_or_default()),
+        Token::Address(to.unwrap_or_default()),
+        Token::Uint(token_id),
+    ];
+    let (selector, args) = match schema {
+        WyvernSchemaName::ERC721 => {
+            anyhow::ensure!(
+                quantity == U256::one(),
+                "ERC721 tokens can't be transferred {} times",
+                quantity
+            );
+            (ERC721_TRANSFER_FROM, addresses)
+        }
+        WyvernSchemaName::ERC1155 => {
+            let mut args = addresses;
+            args.push(Token::Uint(quantity));
+            args.push(Token::Bytes(Vec::new()));
+            (ERC1155_SAFE_TRANSFER_FROM, args)
+        }
+        schema => anyhow::bail!("transfers of {:?} assets are not supported", schema),
+    };
+    let mut calldata = selector.to_vec();
+    calldata.extend(ethabi::encode(&args));
+
+    let mut pattern = vec![0u8; calldata.len()];
+    for (index, address) in [from, to].iter().enumerate() {
+        if address.is_none() {
+            let start = 4 + index * 32;
+            pattern[start..start + 32].fill(0xff);
+        }
+    }
+    Ok((calldata, pattern))
+}
+
+/// Decodes the calls of `atomicize(addrs, values, calldataLengths, calldatas)`
+fn decode_atomicized(
+    calldata: &[u8],
+    replacement_pattern: &[u8],
+) -> anyhow::Result<Vec<AssetTransfer>> {
+    let tokens = ethabi::decode(
+        &[
+            ParamType::Array(Box::new(ParamType::Address)),
+            ParamType::Array(Box::new(ParamType::Uint(256))),
+            ParamType::Array(Box::new(ParamType::Uint(256))),
+            ParamType::Bytes,
+        ],
+        &calldata[4..],
+    )?;
+    let mut tokens = tokens.into_iter();
+    let (addrs, values, lengths) = match (tokens.next(), tokens.next(), tokens.next()) {
+        (Some(Token::Array(addrs)), Some(Token::Array(values)), Some(Token::Array(lengths))) => {
+            (addrs, values, lengths)
+        }
+        _ => anyhow::bail!("invalid atomicize call"),
+    };
+    anyhow::ensure!(
+        addrs.len() == values.len() && addrs.len() == lengths.len(),
+        "atomicize arrays differ in length"
+    );
+    anyhow::ensure!(
+        values
+            .iter()
+            .all(|value| value.clone().into_uint() == Some(U256::zero())),
+        "atomicize call transfers ether"
+    );
+
+    // position of the concatenated calldatas, after their length word
+    let offset = U256::from_big_endian(&calldata[4 + 3 * 32..4 + 4 * 32]);
+    anyhow::ensure!(
+        offset < U256::from(calldata.len()),
+        "invalid calldatas offset"
+    );
+    let mut start = 4 + offset.as_usize() + 32;
+
+    let mut transfers = Vec::with_capacity(addrs.len());
+    for (addr, length) in addrs.into_iter().zip(lengths) {
+        let contract = addr
+            .into_address()
+            .ok_or_else(|| anyhow::anyhow!("invalid atomicize address"))?;
+        let length = length
+            .into_uint()
+            .filter(|length| *length <= U256::from(calldata.len()))
+            .ok_or_else(|| anyhow::anyhow!("invalid atomicize calldata length"))?
+            .as_usize();
+        let end = start + length;
+        anyhow::ensure!(end <= calldata.len(), "atomicize calldata out of bounds");
+        let pattern = replacement_pattern.get(start..end.min(replacement_pattern.len()));
+        transfers.push(decode_transfer(
+            contract,
+            &calldata[start..end],
+            pattern.unwrap_or_default(),
+        )?);
+        start = end;
+    }
+    Ok(transfers)
+}
+
+/// Decodes the transfers of a call to `target`, either a direct transfer or
+/// a bundle executed by the atomicizer
+pub fn decode_calldata(
+    target: Address,
+    how_to_call: i64,
+    calldata: &[u8],
+    replacement_pattern: &[u8],
+) -> anyhow::Result<Vec<AssetTransfer>> {
+    match how_to_call {
+        CALL => Ok(vec![decode_transfer(
+            target,
+            calldata,
+            replacement_pattern,
+        )?]),
+        DELEGATE_CALL => {
+            anyhow::ensure!(
+                calldata.starts_with(&ATOMICIZE),
+                "delegate call is not a call of the atomicizer"
+            );
+            decode_atomicized(calldata, replacement_pattern)
+        }
+        how_to_call => anyhow::bail!("unknown call type {}", how_to_call),
+    }
+}
+
+impl Order {
+    /// Decodes the transfers the order executes
+    pub fn decode_calldata(&self) -> anyhow::Result<Vec<AssetTransfer>> {
+        decode_calldata(
+            eth::parse_address(&self.target)?,
+            self.how_to_call,
+            &eth::decode_hex(&self.calldata)?,
+            &eth::decode_hex(&self.replacement_pattern)?,
+        )
+    }
+
+    /// Checks that the calldata transfers exactly the assets claimed in the
+    /// `metadata` from or to the maker and that only the counterparty's
+    /// address can be replaced.
+    pub fn verify_calldata(&self) -> anyhow::Result<Vec<AssetTransfer>> {
+        let transfers = self.decode_calldata()?;
+        let (assets, schemas) = match &self.metadata {
+            ExchangeMetadata::Asset(meta) => {
+                anyhow::ensure!(
+                    self.how_to_call == CALL,
+                    "single asset orders must call the token contract"
+                );
+                (vec![&meta.asset], vec![&meta.schema])
+            }
+            ExchangeMetadata::Bundle(meta) => {
+                anyhow::ensure!(
+                    self.how_to_call == DELEGATE_CALL
+                        && eth::parse_address(&self.target)?
+                            == eth::parse_address(ATOMICIZER_MAINNET)?,
+                    "bundle orders must call the atomicizer"
+                );
+                (
+                    meta.bundle.assets.iter().collect(),
+                    meta.bundle.schemas.iter().collect(),
+                )
+            }
+        };
+        anyhow::ensure!(
+            transfers.len() == assets.len(),
+            "calldata transfers {} assets, metadata claims {}",
+            transfers.len(),
+            assets.len()
+        );
+
+        let maker = eth::parse_address(&self.maker.address)?;
+        let is_sell = self.side == OrderSide::Sell as i64;
+        for (idx, (transfer, asset)) in transfers.iter().zip(assets).enumerate() {
+            let (address, id, quantity) = match asset {
+                WyvernAsset::NFT(nft) => (&nft.address, Some(&nft.id), None),
+                WyvernAsset::FT(ft) => (&ft.address, ft.id.as_ref(), Some(&ft.quantity)),
+            };
+            anyhow::ensure!(
+                transfer.contract == eth::parse_address(address)?,
+                "asset {} is {:?} in calldata, but {} in metadata",
+                idx,
+                transfer.contract,
+                address
+            );
+            if let Some(id) = id {
+                anyhow::ensure!(
+                    transfer.token_id == eth::parse_u256(id)?,
+                    "asset {} has token id {} in calldata, but {} in metadata",
+                    idx,
+                    transfer.token_id,
+                    id
+                );
+            }
+            if let Some(quantity) = quantity {
+                anyhow::ensure!(
+                    transfer.quantity == eth::parse_u256(quantity)?,
+                    "asset {} has quantity {} in calldata, but {} in metadata",
+                    idx,
+                    transfer.quantity,
+                    quantity
+                );
+            }
+            if let Some(schema) = schemas.get(idx) {
+                anyhow::ensure!(
+                    transfer.schema == **schema,
+                    "asset {} is {:?} in calldata, but {:?} in metadata",
+                    idx,
+                    transfer.schema,
+                    schema
+                );
+            }
+            let (own, counterparty) = if is_sell {
+                (transfer.from, transfer.to)
+            } else {
+                (transfer.to, transfer.from)
+            };
+            anyhow::ensure!(
+                own == Some(maker),
+                "asset {} is not transferred {} the maker",
+                idx,
+                if is_sell { "from" } else { "to" }
+            );
+            anyhow::ensure!(
+                counterparty.is_none(),
+                "the counterparty of asset {} can't be filled in",
+                idx
+            );
+        }
+        Ok(transfers)
+    }
+
+    /// The address that receives the assets, `None` if the taker of the
+    /// order does
+    pub fn recipient(&self) -> anyhow::Result<Option<Address>> {
+        Ok(self
+            .decode_calldata()?
+            .into_iter()
+            .next()
+            .and_then(|transfer| transfer.to))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::opensea::models::OrderBook;
+
+    const ORDERBOOK: &str = include_str!("../../examples/responses/opensea-orderbook.json");
+
+    const MEEBITS: &str = "0x7bd29408f11d2bfc23c34f18275bbf23bb716bc7";
+    const OTHER: &str = "0x0000000000000000000000000000000000000002";
+
+    /// The fixture buy order for Meebit #7325
+    fn order() -> Order {
+        serde_json::from_str::<OrderBook>(ORDERBOOK)
+            .unwrap()
+            .orders
+            .remove(0)
+    }
+
+    fn address(s: &str) -> Address {
+        eth::parse_address(s).unwrap()
+    }
+
+    fn error(order: &Order) -> String {
+        order.verify_calldata().unwrap_err().to_string()
+    }
+
+    #[test]
+    fn verifies_the_fixture_order() {
+        let order = order();
+        let maker = address(&order.maker.address);
+        let transfer = AssetTransfer {
+            schema: WyvernSchemaName::ERC721,
+            contract: address(MEEBITS),
+            token_id: U256::from(7325),
+            quantity: U256::one(),
+            from: None,
+            to: Some(maker),
+        };
+        assert_eq!(order.verify_calldata().unwrap(), vec![transfer]);
+        assert_eq!(order.recipient().unwrap(), Some(maker));
+    }
+
+    #[test]
+    fn rejects_metadata_that_differs_from_the_calldata() {
+        let mut order = order();
+        if let ExchangeMetadata::Asset(meta) = &mut order.metadata {
+            if let WyvernAsset::NFT(nft) = &mut meta.asset {
+                nft.id = "7326".to_string();
+            }
+        }
+        assert_eq!(
+            error(&order),
+            "asset 0 has token id 7325 in calldata, but 7326 in metadata"
+        );
+    }
+
+    #[test]
+    fn rejects_altered_calldata() {
+        let original = order();
+        let maker = address(&original.maker.address);
+
+        let (calldata, _) = encode_transfer(
+            &WyvernSchemaName::ERC721,
+            U256::from(7326),
+            U256::one(),
+            None,
+            Some(maker),
+        )
+        .unwrap();
+        let order = Order {
+            calldata: eth::encode_hex(calldata),
+            ..original.clone()
+        };
+        assert!(error(&order).starts_with("asset 0 has token id 7326 in calldata"));
+
+        let (calldata, _) = encode_transfer(
+            &WyvernSchemaName::ERC721,
+            U256::from(7325),
+            U256::one(),
+            None,
+            Some(address(OTHER)),
+        )
+        .unwrap();
+        let order = Order {
+            calldata: eth::encode_hex(calldata),
+            ..original.clone()
+        };
+        assert_eq!(error(&order), "asset 0 is not transferred to the maker");
+
+        let order = Order {
+            target: OTHER.to_string(),
+            ..original.clone()
+        };
+        assert!(error(&order).starts_with("asset 0 is 0x0000"));
+
+        // the taker could replace the token
+        let order = Order {
+            replacement_pattern: format!("0x{}", "ff".repeat(4 + 3 * 32)),
+            ..original
+        };
+        assert_eq!(
+            error(&order),
+            "the token id can be replaced by the counterparty"
+        );
+    }
+
+    #[test]
+    fn roundtrips_transfers() {
+        let from = Some(address(OTHER));
+        let (calldata, pattern) = encode_transfer(
+            &WyvernSchemaName::ERC1155,
+            U256::from(7),
+            U256::from(3),
+            from,
+            None,
+        )
+        .unwrap();
+        assert_eq!(calldata[..4], ERC1155_SAFE_TRANSFER_FROM);
+        assert_eq!(
+            decode_transfer(address(MEEBITS), &calldata, &pattern).unwrap(),
+            AssetTransfer {
+                schema: WyvernSchemaName::ERC1155,
+                contract: address(MEEBITS),
+                token_id: U256::from(7),
+                quantity: U256::from(3),
+                from,
+                to: None,
+            }
+        );
+
+        assert!(encode_transfer(
+            &WyvernSchemaName::ERC721,
+            U256::from(7),
+            U256::from(2),
+            from,
+            None
+        )
+        .is_err());
+        let err = decode_transfer(address(MEEBITS), &[0xa9, 0x05, 0x9c, 0xbb], &[]).unwrap_err();
+        assert_eq!(err.to_string(), "unknown function 0xa9059cbb");
+    }
+
+    /// An atomicize call of the transfers and its replacement pattern
+    fn atomicize(transfers: &[(Address, Vec<u8>, Vec<u8>)], value: u64) -> (Vec<u8>, Vec<u8>) {
+        let tokens = [
+            Token::Array(
+                transfers
+                    .iter()
+                    .map(|(contract, _, _)| Token::Address(*contract))
+                    .collect(),
+            ),
+            Token::Array(vec![Token::Uint(value.into()); transfers.len()]),
+            Token::Array(
+                transfers
+                    .iter()
+                    .map(|(_, calldata, _)| Token::Uint(calldata.len().into()))
+                    .collect(),
+            ),
+            Token::Bytes(
+                transfers
+                    .iter()
+                    .flat_map(|(_, calldata, _)| calldata.clone())
+                    .collect(),
+            ),
+        ];
+        let mut calldata = ATOMICIZE.to_vec();
+        calldata.extend(ethabi::encode(&tokens));
+        let mut pattern = vec![0; calldata.len()];
+        let offset = U256::from_big_endian(&calldata[4 + 3 * 32..4 + 4 * 32]).as_usize();
+        let mut start = 4 + offset + 32;
+        for (_, _, transfer_pattern) in transfers {
+            pattern[start..start + transfer_pattern.len()].copy_from_slice(transfer_pattern);
+            start += transfer_pattern.len();
+        }
+        (calldata, pattern)
+    }
+
+    #[test]
+    fn decodes_atomicized_bundles() {
+        let from = Some(address(OTHER));
+        let transfers = [(U256::from(1), U256::one()), (U256::from(2), U256::from(5))]
+            .iter()
+            .zip(&[WyvernSchemaName::ERC721, WyvernSchemaName::ERC1155])
+            .map(|((token_id, quantity), schema)| {
+                let (calldata, pattern) =
+                    encode_transfer(schema, *token_id, *quantity, from, None).unwrap();
+                (address(MEEBITS), calldata, pattern)
+            })
+            .collect::<Vec<_>>();
+
+        let (calldata, pattern) = atomicize(&transfers, 0);
+        let decoded = decode_calldata(
+            address(ATOMICIZER_MAINNET),
+            DELEGATE_CALL,
+            &calldata,
+            &pattern,
+        )
+        .unwrap();
+        let ids = decoded
+            .iter()
+            .map(|transfer| {
+                (
+                    transfer.token_id,
+                    transfer.quantity,
+                    transfer.from,
+                    transfer.to,
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            ids,
+            vec![
+                (U256::from(1), U256::one(), from, None),
+                (U256::from(2), U256::from(5), from, None)
+            ]
+        );
+
+        let (calldata, pattern) = atomicize(&transfers, 1);
+        assert!(decode_calldata(
+            address(ATOMICIZER_MAINNET),
+            DELEGATE_CALL,
+            &calldata,
+            &pattern
+        )
+        .is_err());
+        // a delegate call must go to the atomicizer
+        assert!(decode_calldata(address(MEEBITS), DELEGATE_CALL, &transfers[0].1, &[]).is_err());
+    }
+}
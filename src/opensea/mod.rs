@@ -1,5 +1,10 @@
+pub mod calldata;
 pub mod models;
+pub mod order;
 pub mod query;
+#[cfg(feature = "watch")]
+pub mod watch;
+pub mod wyvern;
 
 use crate::opensea::models::*;
 use crate::ApiClient;
@@ -31,6 +36,17 @@ impl ApiClient {
         .await
     }
 
+    /// Submits a signed order to the orderbook, returning the order as
+    /// OpenSea stored it
+    pub async fn post_order(&self, order: &NewOrder) -> anyhow::Result<Order> {
+        anyhow::ensure!(order.is_signed(), "order {} is not signed", order.hash);
+        self.post_json::<_, _, _, OpenSeaApiError>(
+            self.join_url("wyvern/v1/orders/post")?,
+            Some(order),
+        )
+        .await
+    }
+
     /// Get a list of orders from the orderbook, returning the page of orders
     /// and the count of total orders found.
     pub async fn get_asset(
@@ -60,6 +76,28 @@ impl ApiClient {
         .await
     }
 
+    /// Fetch a list of events, newest first
+    pub async fn get_events(&self, query: &OpenSeaEventsQuery) -> anyhow::Result<EventList> {
+        Self::request_json_opensea(
+            self.client
+                .get(self.join_url("api/v1/events")?)
+                .query(query),
+        )
+        .await
+    }
+
+    /// Fetch the sales statistics of the collection with the slug
+    pub async fn get_collection_stats(
+        &self,
+        slug: impl AsRef<str>,
+    ) -> anyhow::Result<CollectionStats> {
+        Self::request_json_opensea(
+            self.client
+                .get(self.join_url(format!("api/v1/collection/{}/stats", slug.as_ref()))?),
+        )
+        .await
+    }
+
     /// Fetch list of fungible tokens from the API matching parameters
     pub async fn get_payment_tokens(
         &self,
@@ -97,3 +135,85 @@ impl ApiClient {
         .await
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::eth::U256;
+    use crate::mock::MockServer;
+    use crate::opensea::order::OrderBuilder;
+
+    const ORDERBOOK: &str = include_str!("../../examples/responses/opensea-orderbook.json");
+
+    fn signed_order() -> NewOrder {
+        let mut order = OrderBuilder::sell(
+            "0x3c6137504c38215fea30605b3e364a23c1d3e14f",
+            "0x7bd29408f11d2bfc23c34f18275bbf23bb716bc7",
+            U256::from(7325),
+            U256::exp10(17),
+        )
+        .listing_time(1_600_000_000)
+        .salt(1u64)
+        .build()
+        .unwrap();
+        order.v = Some(27);
+        order.r = Some(format!("0x{}", "11".repeat(32)));
+        order.s = Some(format!("0x{}", "22".repeat(32)));
+        order
+    }
+
+    fn client(server: &MockServer) -> ApiClient {
+        ApiClient::builder().build(server.url().clone()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn post_order_submits_the_signed_order() {
+        let server = MockServer::start(|_| {
+            let book: serde_json::Value = serde_json::from_str(ORDERBOOK).unwrap();
+            (201, book["orders"][0].to_string())
+        });
+        let order = signed_order();
+        let posted = client(&server).post_order(&order).await.unwrap();
+        assert_eq!(
+            posted.order_hash,
+            "0x39de580f0419d99b839d5c4bbb96b8da2295bdc14ffd9079e1ba6be222ae3950"
+        );
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].method, "POST");
+        assert_eq!(requests[0].path, "/wyvern/v1/orders/post");
+        let body: serde_json::Value = serde_json::from_str(&requests[0].body).unwrap();
+        assert_eq!(body["hash"], order.hash.as_str());
+        assert_eq!(body["makerRelayerFee"], "0");
+        assert_eq!(body["basePrice"], "100000000000000000");
+        assert_eq!(body["listingTime"], 1_600_000_000);
+        assert_eq!(body["v"], 27);
+        assert_eq!(body["r"], order.r.as_deref().unwrap());
+        assert_eq!(body["s"], order.s.as_deref().unwrap());
+        let posted: NewOrder = serde_json::from_value(body).unwrap();
+        assert_eq!(posted, order);
+    }
+
+    #[tokio::test]
+    async fn post_order_rejects_unsigned_orders() {
+        let server = MockServer::start(|_| (201, "{}".to_string()));
+        let mut order = signed_order();
+        order.s = None;
+        assert!(client(&server).post_order(&order).await.is_err());
+        assert!(server.requests().is_empty());
+    }
+
+    #[tokio::test]
+    async fn post_order_reports_api_errors() {
+        let server = MockServer::start(|_| (400, r#"{"success":false}"#.to_string()));
+        let err = client(&server)
+            .post_order(&signed_order())
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<crate::error::ApiError>(),
+            Some(crate::error::ApiError::OpenSeaApiError(_))
+        ));
+    }
+}
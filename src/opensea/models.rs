@@ -1,5 +1,6 @@
 use std::collections::BTreeMap;
 
+use rust_decimal::Decimal;
 use serde::{Deserialize, Serialize};
 use serde_repr::*;
 
@@ -59,6 +60,9 @@ pub struct OpenSeaAsset {
     pub token_metadata: Option<String>,
     /// Dictionary of data on the owner
     pub owner: OpenSeaAccount,
+    /// The largest holders of the token with their balances, only reported
+    /// for single assets
+    pub top_ownerships: Option<Vec<AssetOwnership>>,
     pub sell_orders: Option<Vec<Order>>,
     pub creator: Option<OpenSeaAccount>,
     /// A list of traits associated with the item
@@ -71,6 +75,13 @@ pub struct OpenSeaAsset {
     pub transfer_fee: Option<::serde_json::Value>,
 }
 
+/// A holder of a token and their balance
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AssetOwnership {
+    pub owner: OpenSeaAccount,
+    pub quantity: String,
+}
+
 /// Asset contracts contain data about the contract itself, such as the
 /// CryptoKitties contract or the CryptoFighters contract. Here are the field
 /// associated with an asset contract:
@@ -248,6 +259,47 @@ pub struct Order {
     pub prefixed_hash: String,
 }
 
+/// A new order as posted to the orderbook, see
+/// [`OrderBuilder`](crate::opensea::order::OrderBuilder)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NewOrder {
+    pub exchange: String,
+    pub maker: String,
+    pub taker: String,
+    pub maker_relayer_fee: String,
+    pub taker_relayer_fee: String,
+    pub maker_protocol_fee: String,
+    pub taker_protocol_fee: String,
+    pub maker_referrer_fee: String,
+    pub fee_recipient: String,
+    pub fee_method: i64,
+    pub side: i64,
+    pub sale_kind: i64,
+    pub target: String,
+    pub how_to_call: i64,
+    pub calldata: String,
+    pub replacement_pattern: String,
+    pub static_target: String,
+    pub static_extradata: String,
+    pub payment_token: String,
+    pub quantity: String,
+    pub base_price: String,
+    pub extra: String,
+    pub listing_time: i64,
+    pub expiration_time: i64,
+    pub salt: String,
+    pub metadata: ExchangeMetadata,
+    /// The Wyvern hash of the order
+    pub hash: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub v: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub r: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub s: Option<String>,
+}
+
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(untagged)]
 pub enum ExchangeMetadata {
@@ -379,6 +431,57 @@ pub struct LastSale {
     pub quantity: String,
 }
 
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EventList {
+    pub asset_events: Vec<AssetEvent>,
+}
+
+/// An event of the `events` endpoint: a listing, sale, bid, transfer, ...
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AssetEvent {
+    pub id: i64,
+    pub event_type: AssetEventType,
+    /// UTC time of the event, without timezone: `2021-08-20T10:15:30.123456`
+    pub created_date: String,
+    pub asset: Option<Asset>,
+    pub asset_bundle: Option<::serde_json::Value>,
+    pub collection_slug: Option<String>,
+    pub contract_address: Option<String>,
+    pub quantity: Option<String>,
+    pub auction_type: Option<AuctionType>,
+    pub starting_price: Option<String>,
+    pub ending_price: Option<String>,
+    pub total_price: Option<String>,
+    pub bid_amount: Option<String>,
+    pub duration: Option<String>,
+    pub payment_token: Option<OpenSeaFungibleToken>,
+    pub seller: Option<OpenSeaAccount>,
+    pub winner_account: Option<OpenSeaAccount>,
+    pub from_account: Option<OpenSeaAccount>,
+    pub to_account: Option<OpenSeaAccount>,
+    pub transaction: Option<Transaction>,
+    pub is_private: Option<bool>,
+}
+
+/// The kinds of events, the `event_type` filter of the `events` endpoint
+#[derive(Copy, Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AssetEventType {
+    /// New listing or auction
+    Created,
+    /// Sale
+    Successful,
+    /// Cancelled listing
+    Cancelled,
+    BidEntered,
+    BidWithdrawn,
+    Transfer,
+    Approve,
+    OfferEntered,
+    #[serde(other)]
+    Other,
+}
+
 pub type FungibleTokenList = Vec<OpenSeaFungibleToken>;
 
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
@@ -463,35 +566,45 @@ pub struct Range {
     pub max: i64,
 }
 
+/// Sales statistics of a collection, volumes and prices are in ETH
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
 pub struct Stats {
-    pub one_day_volume: i64,
-    pub one_day_change: i64,
-    pub one_day_sales: i64,
-    pub one_day_average_price: i64,
-    pub seven_day_volume: i64,
-    pub seven_day_change: i64,
-    pub seven_day_sales: i64,
-    pub seven_day_average_price: i64,
-    pub thirty_day_volume: i64,
-    pub thirty_day_change: i64,
-    pub thirty_day_sales: i64,
-    pub thirty_day_average_price: i64,
-    pub total_volume: i64,
-    pub total_sales: i64,
-    pub total_supply: i64,
-    pub count: i64,
-    pub num_owners: i64,
-    pub average_price: i64,
-    pub num_reports: i64,
-    pub market_cap: i64,
-    pub floor_price: i64,
+    pub one_day_volume: Decimal,
+    /// Relative change of the volume to the previous day
+    pub one_day_change: Decimal,
+    pub one_day_sales: Decimal,
+    pub one_day_average_price: Decimal,
+    pub seven_day_volume: Decimal,
+    pub seven_day_change: Decimal,
+    pub seven_day_sales: Decimal,
+    pub seven_day_average_price: Decimal,
+    pub thirty_day_volume: Decimal,
+    pub thirty_day_change: Decimal,
+    pub thirty_day_sales: Decimal,
+    pub thirty_day_average_price: Decimal,
+    pub total_volume: Decimal,
+    pub total_sales: Decimal,
+    pub total_supply: Decimal,
+    pub count: Decimal,
+    pub num_owners: Decimal,
+    pub average_price: Decimal,
+    pub num_reports: Decimal,
+    pub market_cap: Decimal,
+    /// `None` if nothing is listed
+    pub floor_price: Option<Decimal>,
+}
+
+/// Response of the collection `stats` endpoint
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CollectionStats {
+    pub stats: Stats,
 }
 
 /// Wyvern order side: buy or sell.
 #[derive(Copy, Clone, Serialize_repr, Deserialize_repr, PartialEq, Debug)]
 #[repr(u8)]
-enum OrderSide {
+pub enum OrderSide {
     Buy = 0,
     Sell = 1,
 }
@@ -591,12 +704,12 @@ pub enum TokenStandardVersion {
 ///     created, confirmed, denied, or failed.
 ///  2. pre-transaction events, which are named (like "WrapEth") and indicate
 ///     that Web3 is asking for a signature on a transaction that needs to occur
-/// before     an order is made or fulfilled. This includes approval events and
-/// account     initialization.
+///     before an order is made or fulfilled. This includes approval events and
+///     account initialization.
 ///  3. Basic actions: matching, cancelling, and creating orders.
 ///     The "CreateOrder" event fires when a signature is being prompted
 ///     to create an off-chain order. The "OrderDenied" event fires when a
-/// signature     request is denied by the user.
+///     signature request is denied by the user.
 ///  4. The "TransferAll" event, which fires when a user is about to directly
 ///     transfer one or more assets to another account
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
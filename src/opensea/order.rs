@@ -0,0 +1,423 @@
+//! Creation of Wyvern orders for the OpenSea orderbook.
+//!
+//! An [`OrderBuilder`] fills in the members of a sell or buy order of a
+//! single ERC721 or ERC1155 token the way OpenSea expects them: the fees go
+//! to OpenSea's fee recipient, the calldata transfers the token from or to
+//! the maker and leaves the counterparty to be filled in by the taker. The
+//! resulting [`NewOrder`] is signed by the maker and submitted with
+//! [`ApiClient::post_order`](crate::ApiClient::post_order).
+
+use chrono::Utc;
+
+use crate::eth::{self, U256};
+use crate::market::fees::CollectionFees;
+use crate::market::models::{NULL_ADDRESS, WETH_MAINNET};
+use crate::opensea::calldata::{self, CALL};
+use crate::opensea::models::{
+    ExchangeMetadata, ExchangeMetadataForAsset, FeeMethod, NewOrder, OrderSide, SaleKind,
+    WyvernAsset, WyvernFTAsset, WyvernNFTAsset, WyvernSchemaName,
+};
+use crate::opensea::wyvern::WYVERN_EXCHANGE_MAINNET;
+
+/// Address OpenSea collects its fees and the creator royalties with
+pub const OPENSEA_FEE_RECIPIENT: &str = "0x5b3256965e7c3cf26e11fcaf296dfc8807c01073";
+
+/// Builds a sell or buy order of a single token.
+///
+/// Prices are in the smallest unit of the payment token and cover all of
+/// `quantity`. Sell orders are paid in ETH by default, buy orders in WETH,
+/// since ETH can't be taken from the maker when the order is matched.
+#[derive(Clone, Debug)]
+pub struct OrderBuilder {
+    side: OrderSide,
+    sale_kind: SaleKind,
+    maker: String,
+    contract: String,
+    token_id: U256,
+    schema: WyvernSchemaName,
+    quantity: U256,
+    base_price: U256,
+    end_price: Option<U256>,
+    payment_token: String,
+    taker: String,
+    seller_fee_bps: u64,
+    buyer_fee_bps: u64,
+    fee_recipient: String,
+    exchange: String,
+    listing_time: Option<i64>,
+    expiration_time: i64,
+    salt: Option<U256>,
+}
+
+impl OrderBuilder {
+    fn new(
+        side: OrderSide,
+        maker: impl Into<String>,
+        contract: impl Into<String>,
+        token_id: U256,
+        price: U256,
+    ) -> Self {
+        let payment_token = match side {
+            OrderSide::Sell => NULL_ADDRESS,
+            OrderSide::Buy => WETH_MAINNET,
+        };
+        Self {
+            side,
+            sale_kind: SaleKind::FixedPrice,
+            maker: maker.into(),
+            contract: contract.into(),
+            token_id,
+            schema: WyvernSchemaName::ERC721,
+            quantity: U256::one(),
+            base_price: price,
+            end_price: None,
+            payment_token: payment_token.to_string(),
+            taker: NULL_ADDRESS.to_string(),
+            seller_fee_bps: 0,
+            buyer_fee_bps: 0,
+            fee_recipient: OPENSEA_FEE_RECIPIENT.to_string(),
+            exchange: WYVERN_EXCHANGE_MAINNET.to_string(),
+            listing_time: None,
+            expiration_time: 0,
+            salt: None,
+        }
+    }
+
+    /// A fixed price listing of the token
+    pub fn sell(
+        maker: impl Into<String>,
+        contract: impl Into<String>,
+        token_id: U256,
+        price: U256,
+    ) -> Self {
+        Self::new(OrderSide::Sell, maker, contract, token_id, price)
+    }
+
+    /// A listing whose price declines linearly from `start_price` at the
+    /// listing time to `end_price` at `expiration_time` (unix seconds)
+    pub fn dutch_auction(
+        maker: impl Into<String>,
+        contract: impl Into<String>,
+        token_id: U256,
+        start_price: U256,
+        end_price: U256,
+        expiration_time: i64,
+    ) -> Self {
+        let mut builder = Self::new(OrderSide::Sell, maker, contract, token_id, start_price);
+        builder.sale_kind = SaleKind::DutchAuction;
+        builder.end_price = Some(end_price);
+        builder.expiration_time = expiration_time;
+        builder
+    }
+
+    /// An offer for the token
+    pub fn buy(
+        maker: impl Into<String>,
+        contract: impl Into<String>,
+        token_id: U256,
+        price: U256,
+    ) -> Self {
+        Self::new(OrderSide::Buy, maker, contract, token_id, price)
+    }
+
+    pub fn schema<T: Into<WyvernSchemaName>>(mut self, value: T) -> Self {
+        self.schema = value.into();
+        self
+    }
+
+    /// Number of tokens, only ERC1155 tokens can be traded more than once
+    pub fn quantity<T: Into<U256>>(mut self, value: T) -> Self {
+        self.quantity = value.into();
+        self
+    }
+
+    /// The ERC20 token the order is paid in, the null address for ETH
+    pub fn payment_token<T: Into<String>>(mut self, value: T) -> Self {
+        self.payment_token = value.into();
+        self
+    }
+
+    /// Restricts the order to a single counterparty
+    pub fn taker<T: Into<String>>(mut self, value: T) -> Self {
+        self.taker = value.into();
+        self
+    }
+
+    /// Fee the seller pays to the fee recipient
+    pub fn seller_fee_bps<T: Into<u64>>(mut self, value: T) -> Self {
+        self.seller_fee_bps = value.into();
+        self
+    }
+
+    /// Fee the buyer pays to the fee recipient on top of the price
+    pub fn buyer_fee_bps<T: Into<u64>>(mut self, value: T) -> Self {
+        self.buyer_fee_bps = value.into();
+        self
+    }
+
+    /// Sets the fees OpenSea charges for the collection, including the
+    /// creator royalties
+    pub fn collection_fees(self, fees: &CollectionFees) -> Self {
+        self.seller_fee_bps(fees.opensea_seller_fee_bps + fees.dev_seller_fee_bps)
+            .buyer_fee_bps(fees.opensea_buyer_fee_bps + fees.dev_buyer_fee_bps)
+    }
+
+    pub fn fee_recipient<T: Into<String>>(mut self, value: T) -> Self {
+        self.fee_recipient = value.into();
+        self
+    }
+
+    pub fn exchange<T: Into<String>>(mut self, value: T) -> Self {
+        self.exchange = value.into();
+        self
+    }
+
+    /// When the order becomes valid in unix seconds, defaults to now
+    pub fn listing_time<T: Into<i64>>(mut self, value: T) -> Self {
+        self.listing_time = Some(value.into());
+        self
+    }
+
+    /// When the order expires in unix seconds, `0` for never
+    pub fn expiration_time<T: Into<i64>>(mut self, value: T) -> Self {
+        self.expiration_time = value.into();
+        self
+    }
+
+    /// Defaults to a random salt
+    pub fn salt<T: Into<U256>>(mut self, value: T) -> Self {
+        self.salt = Some(value.into());
+        self
+    }
+
+    /// Validates the parameters and creates the unsigned order
+    pub fn build(&self) -> anyhow::Result<NewOrder> {
+        let maker = eth::parse_address(&self.maker)?;
+        let contract = eth::parse_address(&self.contract)?;
+        let payment_token = eth::parse_address(&self.payment_token)?;
+        anyhow::ensure!(!self.quantity.is_zero(), "quantity must not be zero");
+        anyhow::ensure!(
+            self.schema != WyvernSchemaName::ERC721 || self.quantity == U256::one(),
+            "ERC721 orders are for a single token, not {}",
+            self.quantity
+        );
+        anyhow::ensure!(!self.base_price.is_zero(), "price must not be zero");
+        anyhow::ensure!(
+            self.side == OrderSide::Sell || !payment_token.is_zero(),
+            "buy orders must be paid in an ERC20 token"
+        );
+
+        let listing_time = self.listing_time.unwrap_or_else(|| Utc::now().timestamp());
+        anyhow::ensure!(listing_time >= 0, "negative listing time {}", listing_time);
+        anyhow::ensure!(
+            self.expiration_time == 0 || self.expiration_time > listing_time,
+            "order expires at {}, before it is listed at {}",
+            self.expiration_time,
+            listing_time
+        );
+
+        let extra = match (self.sale_kind, self.end_price) {
+            (SaleKind::DutchAuction, Some(end_price)) => {
+                anyhow::ensure!(
+                    self.side == OrderSide::Sell,
+                    "only sell orders can be dutch auctions"
+                );
+                anyhow::ensure!(
+                    self.expiration_time != 0,
+                    "dutch auctions need an expiration time"
+                );
+                anyhow::ensure!(
+                    end_price < self.base_price,
+                    "the end price {} of a dutch auction must be below its start price {}",
+                    end_price,
+                    self.base_price
+                );
+                self.base_price - end_price
+            }
+            _ => U256::zero(),
+        };
+
+        let (from, to, maker_fee, taker_fee) = match self.side {
+            OrderSide::Sell => (Some(maker), None, self.seller_fee_bps, self.buyer_fee_bps),
+            OrderSide::Buy => (None, Some(maker), self.buyer_fee_bps, self.seller_fee_bps),
+        };
+        let (data, pattern) =
+            calldata::encode_transfer(&self.schema, self.token_id, self.quantity, from, to)?;
+
+        let asset = match self.schema {
+            WyvernSchemaName::ERC1155 => WyvernAsset::FT(WyvernFTAsset {
+                id: Some(self.token_id.to_string()),
+                address: format!("{:?}", contract),
+                quantity: self.quantity.to_string(),
+            }),
+            _ => WyvernAsset::NFT(WyvernNFTAsset {
+                id: self.token_id.to_string(),
+                address: format!("{:?}", contract),
+            }),
+        };
+
+        let mut order = NewOrder {
+            exchange: format!("{:?}", eth::parse_address(&self.exchange)?),
+            maker: format!("{:?}", maker),
+            taker: format!("{:?}", eth::parse_address(&self.taker)?),
+            maker_relayer_fee: maker_fee.to_string(),
+            taker_relayer_fee: taker_fee.to_string(),
+            maker_protocol_fee: "0".to_string(),
+            taker_protocol_fee: "0".to_string(),
+            maker_referrer_fee: "0".to_string(),
+            fee_recipient: format!("{:?}", eth::parse_address(&self.fee_recipient)?),
+            fee_method: FeeMethod::SplitFee as i64,
+            side: self.side as i64,
+            sale_kind: self.sale_kind as i64,
+            target: format!("{:?}", contract),
+            how_to_call: CALL,
+            calldata: eth::encode_hex(data),
+            replacement_pattern: eth::encode_hex(pattern),
+            static_target: NULL_ADDRESS.to_string(),
+            static_extradata: "0x".to_string(),
+            payment_token: format!("{:?}", payment_token),
+            quantity: self.quantity.to_string(),
+            base_price: self.base_price.to_string(),
+            extra: extra.to_string(),
+            listing_time,
+            expiration_time: self.expiration_time,
+            salt: self.salt.unwrap_or_else(eth::random_salt).to_string(),
+            metadata: ExchangeMetadata::Asset(ExchangeMetadataForAsset {
+                asset,
+                schema: self.schema.clone(),
+                referrer_address: None,
+            }),
+            hash: String::new(),
+            v: None,
+            r: None,
+            s: None,
+        };
+        order.hash = format!("{:?}", order.wyvern_hash()?);
+        Ok(order)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::opensea::models::{Order, OrderBook};
+
+    const ORDERBOOK: &str = include_str!("../../examples/responses/opensea-orderbook.json");
+
+    const MEEBITS: &str = "0x7bd29408f11d2bfc23c34f18275bbf23bb716bc7";
+    const MAKER: &str = "0x3c6137504c38215fea30605b3e364a23c1d3e14f";
+
+    fn fixture() -> Order {
+        serde_json::from_str::<OrderBook>(ORDERBOOK)
+            .unwrap()
+            .orders
+            .remove(0)
+    }
+
+    fn error(builder: OrderBuilder) -> String {
+        builder.build().unwrap_err().to_string()
+    }
+
+    #[test]
+    fn rebuilds_the_fixture_order() {
+        let fixture = fixture();
+        let order = OrderBuilder::buy(
+            MAKER,
+            MEEBITS,
+            U256::from(7325),
+            eth::parse_u256(&fixture.base_price).unwrap(),
+        )
+        .seller_fee_bps(250u64)
+        .listing_time(fixture.listing_time)
+        .expiration_time(fixture.expiration_time)
+        .salt(eth::parse_u256(&fixture.salt).unwrap())
+        .build()
+        .unwrap();
+        assert_eq!(order.calldata, fixture.calldata);
+        assert_eq!(order.replacement_pattern, fixture.replacement_pattern);
+        assert_eq!(order.payment_token, fixture.payment_token);
+        // the buyer's order charges the seller's fee to the taker
+        assert_eq!(order.maker_relayer_fee, fixture.maker_relayer_fee);
+        assert_eq!(order.taker_relayer_fee, fixture.taker_relayer_fee);
+        assert_eq!(order.hash, fixture.order_hash);
+    }
+
+    #[test]
+    fn charges_the_maker_fee_to_the_seller() {
+        let order = OrderBuilder::sell(MAKER, MEEBITS, U256::from(7325), U256::exp10(18))
+            .seller_fee_bps(250u64)
+            .buyer_fee_bps(100u64)
+            .build()
+            .unwrap();
+        assert_eq!(order.maker_relayer_fee, "250");
+        assert_eq!(order.taker_relayer_fee, "100");
+        assert_eq!(order.payment_token, NULL_ADDRESS);
+        let (calldata, pattern) = calldata::encode_transfer(
+            &WyvernSchemaName::ERC721,
+            U256::from(7325),
+            U256::one(),
+            Some(eth::parse_address(MAKER).unwrap()),
+            None,
+        )
+        .unwrap();
+        assert_eq!(order.calldata, eth::encode_hex(calldata));
+        assert_eq!(order.replacement_pattern, eth::encode_hex(pattern));
+    }
+
+    #[test]
+    fn sets_the_price_decline_of_dutch_auctions() {
+        let order = OrderBuilder::dutch_auction(
+            MAKER,
+            MEEBITS,
+            U256::from(7325),
+            U256::from(1000),
+            U256::from(400),
+            1_600_086_400,
+        )
+        .listing_time(1_600_000_000)
+        .build()
+        .unwrap();
+        assert_eq!(order.sale_kind, SaleKind::DutchAuction as i64);
+        assert_eq!(order.base_price, "1000");
+        assert_eq!(order.extra, "600");
+    }
+
+    #[test]
+    fn rejects_unfillable_orders() {
+        let token_id = U256::from(7325);
+        let price = U256::exp10(18);
+        assert_eq!(
+            error(OrderBuilder::buy(MAKER, MEEBITS, token_id, price).payment_token(NULL_ADDRESS)),
+            "buy orders must be paid in an ERC20 token"
+        );
+        let dutch = |end_price: u64, expiration_time: i64| {
+            OrderBuilder::dutch_auction(
+                MAKER,
+                MEEBITS,
+                token_id,
+                U256::from(1000),
+                U256::from(end_price),
+                expiration_time,
+            )
+            .listing_time(1_600_000_000)
+        };
+        assert_eq!(
+            error(dutch(400, 0)),
+            "dutch auctions need an expiration time"
+        );
+        assert_eq!(
+            error(dutch(1000, 1_600_086_400)),
+            "the end price 1000 of a dutch auction must be below its start price 1000"
+        );
+        assert_eq!(
+            error(OrderBuilder::sell(MAKER, MEEBITS, token_id, price).quantity(2u64)),
+            "ERC721 orders are for a single token, not 2"
+        );
+        let order = OrderBuilder::sell(MAKER, MEEBITS, token_id, price)
+            .schema(WyvernSchemaName::ERC1155)
+            .quantity(2u64)
+            .build()
+            .unwrap();
+        assert_eq!(order.quantity, "2");
+    }
+}
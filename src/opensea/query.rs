@@ -1,4 +1,4 @@
-use crate::opensea::models::{AuctionType, SaleKind};
+use crate::opensea::models::{AuctionType, OrderSide, SaleKind};
 use serde::Serialize;
 
 //extends Partial<OrderJSON>
@@ -7,6 +7,8 @@ pub struct OrderQuery {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub owner: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    pub side: Option<OrderSide>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub sale_kind: Option<SaleKind>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub asset_contract_address: Option<String>,
@@ -42,6 +44,11 @@ impl OrderQuery {
         self
     }
 
+    pub fn side<T: Into<OrderSide>>(mut self, value: T) -> Self {
+        self.side = Some(value.into());
+        self
+    }
+
     pub fn sale_kind<T: Into<SaleKind>>(mut self, value: T) -> Self {
         self.sale_kind = Some(value.into());
         self
@@ -117,6 +124,7 @@ impl Default for OrderQuery {
     fn default() -> Self {
         Self {
             owner: None,
+            side: None,
             sale_kind: None,
             asset_contract_address: None,
             payment_token_address: None,
@@ -233,7 +241,7 @@ impl Default for OpenSeaAssetsQuery {
 pub struct OpenSeaAssetQuery {
     pub token_address: String,
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub token_id: Option<u32>,
+    pub token_id: Option<String>,
 }
 
 impl OpenSeaAssetQuery {
@@ -244,10 +252,10 @@ impl OpenSeaAssetQuery {
         }
     }
 
-    pub fn with_token_id(token_address: impl Into<String>, token_id: u32) -> Self {
+    pub fn with_token_id(token_address: impl Into<String>, token_id: impl Into<String>) -> Self {
         Self {
             token_address: token_address.into(),
-            token_id: Some(token_id),
+            token_id: Some(token_id.into()),
         }
     }
 }
@@ -512,7 +520,7 @@ pub struct OpenSeaFungibleTokenQuery {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub offset: Option<u32>,
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub symbol: Option<i32>,
+    pub symbol: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub page: Option<u32>,
 }
@@ -528,7 +536,7 @@ impl OpenSeaFungibleTokenQuery {
         self
     }
 
-    pub fn symbol<T: Into<i32>>(mut self, value: T) -> Self {
+    pub fn symbol<T: Into<String>>(mut self, value: T) -> Self {
         self.symbol = Some(value.into());
         self
     }
@@ -0,0 +1,729 @@
+//! Watching OpenSea for changes by polling the API.
+//!
+//! OpenSea doesn't push updates, so a [`Watcher`] polls orders, assets or
+//! events in an interval and diffs the results with what it has seen
+//! before. Orders and events are polled incrementally from a cursor, the
+//! `listed_after` and `occurred_after` timestamps of the last poll, assets
+//! are fetched and diffed as a whole on every poll. The API returns the
+//! newest items first, a poll pages back until it reaches the cursor and
+//! fails without moving it if that takes more than the watcher's
+//! `max_pages`.
+//!
+//! Orders that were listed before the cursor are refreshed by their token on
+//! every poll until they are sold, cancelled or expire, orders that are no
+//! longer returned at all and orders that expired are cancelled. Only orders
+//! seen since the watcher was created are refreshed, not the ones from
+//! before a restart.
+
+use std::collections::{BTreeSet, HashMap, VecDeque};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use chrono::{NaiveDateTime, TimeZone, Utc};
+use futures::future::BoxFuture;
+use futures::stream::{self, BoxStream};
+use futures::{FutureExt, StreamExt};
+use log::debug;
+use serde::{Deserialize, Serialize};
+
+use crate::opensea::models::{
+    AssetEvent, AssetEventType, ExchangeMetadata, OpenSeaAsset, Order, WyvernAsset,
+};
+use crate::opensea::query::{OpenSeaAssetsQuery, OpenSeaEventsQuery, OrderQuery};
+use crate::ApiClient;
+
+/// A change of a watched item
+#[derive(Debug, Clone, PartialEq)]
+pub enum Change<T> {
+    /// The item was seen for the first time
+    Created(T),
+    /// The item differs from when it was seen before
+    Updated(T),
+    /// The item was cancelled, or no longer matches the query
+    Cancelled(T),
+}
+
+impl<T> Change<T> {
+    pub fn item(&self) -> &T {
+        match self {
+            Change::Created(item) | Change::Updated(item) | Change::Cancelled(item) => item,
+        }
+    }
+
+    pub fn into_item(self) -> T {
+        match self {
+            Change::Created(item) | Change::Updated(item) | Change::Cancelled(item) => item,
+        }
+    }
+}
+
+/// The kind of change, one of the [`Change`] variants
+pub type ChangeKind<T> = fn(T) -> Change<T>;
+
+/// Items a [`Watcher`] can poll
+pub trait Watched: Clone + PartialEq + Send + Sync + 'static {
+    type Query: Clone + Send + Sync + 'static;
+
+    /// Whether the items can be polled from a cursor. Otherwise all items are
+    /// fetched on every poll and items that are gone are cancelled.
+    const INCREMENTAL: bool;
+
+    /// Identifies the item across polls
+    fn key(&self) -> String;
+
+    /// Unix timestamp the cursor advances by
+    fn time(&self) -> anyhow::Result<i64>;
+
+    /// The change of the item since it was seen as `previous`, `None` if
+    /// nothing changed
+    fn change(&self, previous: Option<&Self>) -> Option<ChangeKind<Self>>;
+
+    /// The number of items on a page
+    fn page_size(query: &Self::Query) -> u32;
+
+    /// Fetches the `page`, only items after the timestamp `after` if the
+    /// items are polled incrementally
+    fn fetch<'a>(
+        client: &'a ApiClient,
+        query: &'a Self::Query,
+        after: Option<i64>,
+        page: u32,
+    ) -> BoxFuture<'a, anyhow::Result<Vec<Self>>>;
+
+    /// Whether the item can't change anymore and needn't be refreshed
+    fn is_final(&self) -> bool {
+        true
+    }
+
+    /// Whether the item ran out without being cancelled, it's cancelled once
+    /// and not refreshed anymore
+    fn is_expired(&self) -> bool {
+        false
+    }
+
+    /// Fetches the current version of `known` items that are no longer
+    /// covered by the cursor, at most `max_pages` pages per request. Items
+    /// that are missing from the result are cancelled.
+    fn refresh<'a>(
+        _client: &'a ApiClient,
+        _query: &'a Self::Query,
+        _known: &'a [Self],
+        _max_pages: u32,
+    ) -> BoxFuture<'a, anyhow::Result<Vec<Self>>> {
+        async { Ok(Vec::new()) }.boxed()
+    }
+}
+
+fn is_cancelled(order: &Order) -> bool {
+    order.cancelled || order.marked_invalid
+}
+
+/// The contract and token id of a single asset order, `None` for bundles
+fn order_token(order: &Order) -> Option<(String, String)> {
+    let asset = match &order.metadata {
+        ExchangeMetadata::Asset(meta) => &meta.asset,
+        ExchangeMetadata::Bundle(_) => return None,
+    };
+    match asset {
+        WyvernAsset::NFT(nft) => Some((nft.address.to_lowercase(), nft.id.clone())),
+        WyvernAsset::FT(ft) => Some((ft.address.to_lowercase(), ft.id.clone()?)),
+    }
+}
+
+impl Watched for Order {
+    type Query = OrderQuery;
+
+    const INCREMENTAL: bool = true;
+
+    fn key(&self) -> String {
+        self.order_hash.clone()
+    }
+
+    fn time(&self) -> anyhow::Result<i64> {
+        Ok(self.listing_time)
+    }
+
+    fn change(&self, previous: Option<&Self>) -> Option<ChangeKind<Self>> {
+        match previous {
+            Some(previous) if previous == self => None,
+            Some(previous) if is_cancelled(previous) => Some(Change::Updated),
+            _ if is_cancelled(self) => Some(Change::Cancelled),
+            Some(_) => Some(Change::Updated),
+            None => Some(Change::Created),
+        }
+    }
+
+    fn page_size(query: &Self::Query) -> u32 {
+        query.limit.unwrap_or(20)
+    }
+
+    fn fetch<'a>(
+        client: &'a ApiClient,
+        query: &'a Self::Query,
+        after: Option<i64>,
+        page: u32,
+    ) -> BoxFuture<'a, anyhow::Result<Vec<Self>>> {
+        let mut query = query.clone();
+        if let Some(after) = after {
+            query.listed_after = Some(after.to_string());
+        }
+        query.offset = Some(page * Self::page_size(&query));
+        query.page = None;
+        async move { Ok(client.get_orders(&query).await?.orders) }.boxed()
+    }
+
+    /// Bundles can't be refreshed by their token
+    fn is_final(&self) -> bool {
+        is_cancelled(self) || self.finalized || self.is_expired() || order_token(self).is_none()
+    }
+
+    fn is_expired(&self) -> bool {
+        !is_cancelled(self)
+            && !self.finalized
+            && self.expiration_time != 0
+            && self.expiration_time <= Utc::now().timestamp()
+    }
+
+    /// Fetches all orders of the tokens of the known orders, including
+    /// invalid ones, once per token
+    fn refresh<'a>(
+        client: &'a ApiClient,
+        query: &'a Self::Query,
+        known: &'a [Self],
+        max_pages: u32,
+    ) -> BoxFuture<'a, anyhow::Result<Vec<Self>>> {
+        async move {
+            let tokens = known
+                .iter()
+                .filter_map(order_token)
+                .collect::<BTreeSet<_>>();
+            let mut orders = Vec::new();
+            for (contract, token_id) in tokens {
+                let mut query = query.clone();
+                query.asset_contract_address = Some(contract);
+                query.token_id = Some(token_id);
+                query.token_ids = None;
+                query.include_invalid = Some(true);
+                query.listed_after = None;
+                query.listed_before = None;
+                query.page = None;
+                let page_size = Self::page_size(&query);
+                let mut done = false;
+                for page in 0..max_pages {
+                    query.offset = Some(page * page_size);
+                    let batch = client.get_orders(&query).await?.orders;
+                    done = batch.len() < page_size as usize;
+                    orders.extend(batch);
+                    if done {
+                        break;
+                    }
+                }
+                // the orders on further pages would be cancelled
+                anyhow::ensure!(
+                    done,
+                    "more than {} pages of orders of token {}",
+                    max_pages,
+                    query.token_id.unwrap_or_default()
+                );
+            }
+            Ok(orders)
+        }
+        .boxed()
+    }
+}
+
+impl Watched for OpenSeaAsset {
+    type Query = OpenSeaAssetsQuery;
+
+    const INCREMENTAL: bool = false;
+
+    fn key(&self) -> String {
+        format!(
+            "{}:{}",
+            self.asset_contract.address.to_lowercase(),
+            self.token_id.as_deref().unwrap_or_default()
+        )
+    }
+
+    fn time(&self) -> anyhow::Result<i64> {
+        Ok(0)
+    }
+
+    fn change(&self, previous: Option<&Self>) -> Option<ChangeKind<Self>> {
+        match previous {
+            Some(previous) if previous == self => None,
+            Some(_) => Some(Change::Updated),
+            None => Some(Change::Created),
+        }
+    }
+
+    fn page_size(query: &Self::Query) -> u32 {
+        query.limit.unwrap_or(20)
+    }
+
+    fn fetch<'a>(
+        client: &'a ApiClient,
+        query: &'a Self::Query,
+        _: Option<i64>,
+        page: u32,
+    ) -> BoxFuture<'a, anyhow::Result<Vec<Self>>> {
+        let mut query = query.clone();
+        query.offset = Some(page * Self::page_size(&query));
+        query.page = None;
+        async move { Ok(client.get_assets(&query).await?.assets) }.boxed()
+    }
+}
+
+impl Watched for AssetEvent {
+    type Query = OpenSeaEventsQuery;
+
+    const INCREMENTAL: bool = true;
+
+    fn key(&self) -> String {
+        self.id.to_string()
+    }
+
+    fn time(&self) -> anyhow::Result<i64> {
+        let date = NaiveDateTime::parse_from_str(&self.created_date, "%Y-%m-%dT%H:%M:%S%.f")?;
+        Ok(Utc.from_utc_datetime(&date).timestamp())
+    }
+
+    /// Listings are created, cancelled listings and withdrawn bids
+    /// cancelled, all other events update an asset
+    fn change(&self, previous: Option<&Self>) -> Option<ChangeKind<Self>> {
+        if previous.is_some() {
+            return None;
+        }
+        match self.event_type {
+            AssetEventType::Created => Some(Change::Created),
+            AssetEventType::Cancelled | AssetEventType::BidWithdrawn => Some(Change::Cancelled),
+            _ => Some(Change::Updated),
+        }
+    }
+
+    fn page_size(query: &Self::Query) -> u32 {
+        query.limit.unwrap_or(20)
+    }
+
+    fn fetch<'a>(
+        client: &'a ApiClient,
+        query: &'a Self::Query,
+        after: Option<i64>,
+        page: u32,
+    ) -> BoxFuture<'a, anyhow::Result<Vec<Self>>> {
+        let mut query = query.clone();
+        if let Some(after) = after {
+            query.occurred_after = Some(after.to_string());
+        }
+        query.offset = Some(page * Self::page_size(&query));
+        query.page = None;
+        async move { Ok(client.get_events(&query).await?.asset_events) }.boxed()
+    }
+}
+
+/// Where a [`Watcher`] resumes polling
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WatchCursor {
+    /// Unix timestamp of the newest item seen, `None` before the first poll
+    pub timestamp: Option<i64>,
+    /// Keys of the items seen at `timestamp`, or of all items if they aren't
+    /// polled incrementally
+    pub keys: BTreeSet<String>,
+}
+
+impl WatchCursor {
+    /// Reads the cursor from a json file, the default cursor if it doesn't
+    /// exist
+    pub async fn load(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        match tokio::fs::read(path.as_ref()).await {
+            Ok(content) => Ok(serde_json::from_slice(&content)?),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Writes the cursor as json, replacing the file atomically
+    pub async fn save(&self, path: impl AsRef<Path>) -> anyhow::Result<()> {
+        let path = path.as_ref();
+        let tmp = path.with_extension("tmp");
+        tokio::fs::write(&tmp, serde_json::to_vec(self)?).await?;
+        tokio::fs::rename(&tmp, path).await?;
+        Ok(())
+    }
+}
+
+/// Polls the OpenSea API for changes of orders, assets or events
+pub struct Watcher<T: Watched> {
+    client: ApiClient,
+    query: T::Query,
+    cursor: WatchCursor,
+    /// File the cursor is persisted to after every poll
+    cursor_file: Option<PathBuf>,
+    /// Items of the last poll and known items that are refreshed, to detect
+    /// updates
+    seen: HashMap<String, T>,
+    interval: Duration,
+    /// Pages fetched per poll at most
+    max_pages: u32,
+}
+
+impl Watcher<Order> {
+    /// Watches the orders matching the query, polled by their listing time
+    pub fn orders(client: ApiClient, query: OrderQuery) -> Self {
+        Self::new(client, query)
+    }
+}
+
+impl Watcher<OpenSeaAsset> {
+    /// Watches the assets matching the query
+    pub fn assets(client: ApiClient, query: OpenSeaAssetsQuery) -> Self {
+        Self::new(client, query)
+    }
+}
+
+impl Watcher<AssetEvent> {
+    /// Watches the events matching the query
+    pub fn events(client: ApiClient, query: OpenSeaEventsQuery) -> Self {
+        Self::new(client, query)
+    }
+}
+
+impl<T: Watched> Watcher<T> {
+    pub fn new(client: ApiClient, query: T::Query) -> Self {
+        Self {
+            client,
+            query,
+            cursor: WatchCursor::default(),
+            cursor_file: None,
+            seen: HashMap::new(),
+            interval: Duration::from_secs(30),
+            max_pages: 10,
+        }
+    }
+
+    pub fn interval(mut self, value: Duration) -> Self {
+        self.interval = value;
+        self
+    }
+
+    pub fn max_pages<V: Into<u32>>(mut self, value: V) -> Self {
+        self.max_pages = value.into().max(1);
+        self
+    }
+
+    /// Resumes from the cursor
+    pub fn cursor<V: Into<WatchCursor>>(mut self, value: V) -> Self {
+        self.cursor = value.into();
+        self
+    }
+
+    /// Only watches items after the unix timestamp
+    pub fn since<V: Into<i64>>(mut self, value: V) -> Self {
+        self.cursor = WatchCursor {
+            timestamp: Some(value.into()),
+            keys: BTreeSet::new(),
+        };
+        self
+    }
+
+    /// Resumes from the cursor stored in the file, if it exists, and saves
+    /// the cursor to it after every poll
+    pub async fn cursor_file(mut self, path: impl Into<PathBuf>) -> anyhow::Result<Self> {
+        let path = path.into();
+        self.cursor = WatchCursor::load(&path).await?;
+        self.cursor_file = Some(path);
+        Ok(self)
+    }
+
+    pub fn current_cursor(&self) -> &WatchCursor {
+        &self.cursor
+    }
+
+    async fn fetch_all(&self) -> anyhow::Result<Vec<T>> {
+        // the API returns items strictly after the timestamp, the items at
+        // the cursor are skipped by their keys
+        let after = if T::INCREMENTAL {
+            self.cursor.timestamp.map(|timestamp| timestamp - 1)
+        } else {
+            None
+        };
+        let page_size = T::page_size(&self.query) as usize;
+        let mut items = Vec::new();
+        for page in 0..self.max_pages {
+            let batch = T::fetch(&self.client, &self.query, after, page).await?;
+            let done = batch.len() < page_size;
+            items.extend(batch);
+            if done {
+                return Ok(items);
+            }
+        }
+        // the pages are newest first, the first poll only starts from the
+        // newest items, but later polls would skip the items between the
+        // cursor and the oldest fetched item, and missing assets would be
+        // cancelled
+        if T::INCREMENTAL && self.cursor.timestamp.is_none() {
+            return Ok(items);
+        }
+        anyhow::bail!(
+            "more than {} pages of items changed since the last poll",
+            self.max_pages
+        )
+    }
+
+    /// Refreshes the known items that weren't polled again and can still
+    /// change, adds their changes and the ones still open to `seen`. Items
+    /// that expired are cancelled instead.
+    async fn refresh(
+        &self,
+        seen: &mut HashMap<String, T>,
+        changes: &mut Vec<Change<T>>,
+    ) -> anyhow::Result<()> {
+        let mut known = Vec::new();
+        for item in self.seen.values() {
+            if seen.contains_key(&item.key()) {
+                continue;
+            }
+            if item.is_expired() {
+                changes.push(Change::Cancelled(item.clone()));
+            } else if !item.is_final() {
+                known.push(item.clone());
+            }
+        }
+        if known.is_empty() {
+            return Ok(());
+        }
+        let mut current = T::refresh(&self.client, &self.query, &known, self.max_pages)
+            .await?
+            .into_iter()
+            .map(|item| (item.key(), item))
+            .collect::<HashMap<_, _>>();
+        for previous in known {
+            let key = previous.key();
+            match current.remove(&key) {
+                Some(item) => {
+                    let change = if item.is_expired() {
+                        Some(Change::Cancelled as ChangeKind<T>)
+                    } else {
+                        item.change(Some(&previous))
+                    };
+                    if let Some(change) = change {
+                        changes.push(change(item.clone()));
+                    }
+                    if !item.is_final() {
+                        seen.insert(key, item);
+                    }
+                }
+                None => changes.push(Change::Cancelled(previous)),
+            }
+        }
+        Ok(())
+    }
+
+    /// Polls once and returns the changes since the last poll, oldest first,
+    /// followed by the changes of the refreshed items
+    pub async fn poll(&mut self) -> anyhow::Result<Vec<Change<T>>> {
+        let mut items = Vec::new();
+        for item in self.fetch_all().await? {
+            items.push((item.time()?, item));
+        }
+        items.sort_by_key(|(time, _)| *time);
+        debug!("Polled {} items", items.len());
+
+        let mut changes = Vec::new();
+        let mut seen = HashMap::with_capacity(items.len());
+        let mut cursor = if T::INCREMENTAL {
+            self.cursor.clone()
+        } else {
+            WatchCursor::default()
+        };
+        for (time, item) in items {
+            let key = item.key();
+            if seen.contains_key(&key) {
+                continue;
+            }
+            if T::INCREMENTAL {
+                if matches!(cursor.timestamp, Some(timestamp) if time < timestamp) {
+                    continue;
+                }
+                if cursor.timestamp != Some(time) {
+                    cursor.timestamp = Some(time);
+                    cursor.keys.clear();
+                }
+            }
+            let previous = self.seen.get(&key);
+            // known from before a restart, but not what it looked like
+            let known = previous.is_none() && self.cursor.keys.contains(&key);
+            if !known {
+                if let Some(change) = item.change(previous) {
+                    changes.push(change(item.clone()));
+                }
+            }
+            cursor.keys.insert(key.clone());
+            seen.insert(key, item);
+        }
+        if T::INCREMENTAL {
+            self.refresh(&mut seen, &mut changes).await?;
+        } else {
+            for (key, item) in self.seen.drain() {
+                if !seen.contains_key(&key) {
+                    changes.push(Change::Cancelled(item));
+                }
+            }
+        }
+
+        self.seen = seen;
+        self.cursor = cursor;
+        if let Some(path) = self.cursor_file.as_ref() {
+            self.cursor.save(path).await?;
+        }
+        Ok(changes)
+    }
+
+    /// Polls in the interval and yields the changes.
+    ///
+    /// Failed polls are yielded as errors and retried in the next interval.
+    pub fn watch(self) -> BoxStream<'static, anyhow::Result<Change<T>>> {
+        let state = (self, VecDeque::new(), false);
+        stream::unfold(state, |(mut watcher, mut changes, mut polled)| async move {
+            loop {
+                if let Some(change) = changes.pop_front() {
+                    return Some((Ok(change), (watcher, changes, polled)));
+                }
+                if polled {
+                    tokio::time::sleep(watcher.interval).await;
+                }
+                polled = true;
+                match watcher.poll().await {
+                    Ok(new) => changes.extend(new),
+                    Err(err) => return Some((Err(err), (watcher, changes, polled))),
+                }
+            }
+        })
+        .boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::MockServer;
+    use crate::opensea::models::OrderBook;
+
+    const ORDERBOOK: &str = include_str!("../../examples/responses/opensea-orderbook.json");
+
+    /// The fixture orders, without expiration
+    fn orders() -> Vec<Order> {
+        let mut orders = serde_json::from_str::<OrderBook>(ORDERBOOK).unwrap().orders;
+        for order in &mut orders {
+            order.expiration_time = 0;
+        }
+        orders
+    }
+
+    fn book(orders: Vec<Order>) -> (u16, String) {
+        let count = orders.len() as u64;
+        (
+            200,
+            serde_json::to_string(&OrderBook { orders, count }).unwrap(),
+        )
+    }
+
+    fn client(server: &MockServer) -> ApiClient {
+        ApiClient::builder().build(server.url().clone()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn fails_without_moving_the_cursor_if_the_pages_dont_reach_it() {
+        let server = MockServer::start(|_| book(orders()[..2].to_vec()));
+        let query = OrderQuery::default().limit(2u32);
+        let mut watcher = Watcher::orders(client(&server), query)
+            .since(1_600_000_000)
+            .max_pages(3u32);
+        assert!(watcher.poll().await.is_err());
+        assert_eq!(server.requests().len(), 3);
+        assert_eq!(watcher.current_cursor().timestamp, Some(1_600_000_000));
+    }
+
+    #[tokio::test]
+    async fn refreshes_known_orders_until_they_are_cancelled() {
+        let order = orders().remove(0);
+        let cancelled = Order {
+            cancelled: true,
+            ..order.clone()
+        };
+        let server = MockServer::start(move |request| {
+            if request.path.contains("token_id=7325") {
+                book(vec![cancelled.clone()])
+            } else if request.path.contains("listed_after=") {
+                book(Vec::new())
+            } else {
+                book(vec![order.clone()])
+            }
+        });
+        let mut watcher = Watcher::orders(client(&server), OrderQuery::default());
+
+        let changes = watcher.poll().await.unwrap();
+        assert!(matches!(changes.as_slice(), [Change::Created(_)]));
+        let changes = watcher.poll().await.unwrap();
+        assert!(matches!(changes.as_slice(), [Change::Cancelled(order)] if order.cancelled));
+        assert!(watcher.poll().await.unwrap().is_empty());
+
+        let refreshed = server
+            .requests()
+            .iter()
+            .filter(|request| request.path.contains("token_id=7325"))
+            .count();
+        assert_eq!(refreshed, 1);
+    }
+
+    #[tokio::test]
+    async fn cancels_expired_orders_once() {
+        let order = orders().remove(0);
+        let expired = Order {
+            expiration_time: 1_600_000_000,
+            ..order
+        };
+        let server = MockServer::start(move |request| {
+            if request.path.contains("listed_after=") {
+                book(Vec::new())
+            } else {
+                book(vec![expired.clone()])
+            }
+        });
+        let mut watcher = Watcher::orders(client(&server), OrderQuery::default());
+
+        let changes = watcher.poll().await.unwrap();
+        assert!(matches!(changes.as_slice(), [Change::Created(_)]));
+        let changes = watcher.poll().await.unwrap();
+        assert!(matches!(changes.as_slice(), [Change::Cancelled(order)] if !order.cancelled));
+        assert!(watcher.poll().await.unwrap().is_empty());
+        assert!(!server
+            .requests()
+            .iter()
+            .any(|request| request.path.contains("token_id=")));
+    }
+
+    #[tokio::test]
+    async fn limits_the_pages_of_refreshed_tokens() {
+        let order = orders().remove(0);
+        let server = MockServer::start(move |request| {
+            if request.path.contains("token_id=7325") {
+                book(vec![order.clone(); 2])
+            } else if request.path.contains("listed_after=") {
+                book(Vec::new())
+            } else {
+                book(vec![order.clone()])
+            }
+        });
+        let query = OrderQuery::default().limit(2u32);
+        let mut watcher = Watcher::orders(client(&server), query).max_pages(3u32);
+        watcher.poll().await.unwrap();
+        assert!(watcher.poll().await.is_err());
+        let refreshed = server
+            .requests()
+            .iter()
+            .filter(|request| request.path.contains("token_id=7325"))
+            .count();
+        assert_eq!(refreshed, 3);
+    }
+}
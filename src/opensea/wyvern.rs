@@ -0,0 +1,607 @@
+//! Local hashing and signature checks of Wyvern orders.
+//!
+//! The hash is computed like `hashOrder` of the Wyvern exchange contract:
+//! the keccak256 hash of the tightly packed order members. The maker signs
+//! the `eth_sign` hash of it, the `prefixed_hash` of the API.
+
+use crate::eth::{self, H256, U256};
+use crate::opensea::models::{FeeMethod, NewOrder, Order, OrderSide, SaleKind};
+#[cfg(feature = "signer")]
+use crate::{
+    eth::Address,
+    signer::{Signature, Wallet},
+};
+
+/// Address of the Wyvern exchange used by OpenSea on mainnet
+pub const WYVERN_EXCHANGE_MAINNET: &str = "0x7be8076f4ea4a4ad08075c2508e481d6c946d12b";
+
+/// Fees are denominated in basis points of the price
+pub const INVERSE_BASIS_POINT: u64 = 10_000;
+
+/// Packs the members of an order like solidity's `abi.encodePacked`
+#[derive(Default)]
+struct Packed(Vec<u8>);
+
+impl Packed {
+    fn address(&mut self, address: &str) -> anyhow::Result<&mut Self> {
+        self.0
+            .extend_from_slice(eth::parse_address(address)?.as_bytes());
+        Ok(self)
+    }
+
+    fn uint256(&mut self, value: U256) -> anyhow::Result<&mut Self> {
+        let mut buf = [0u8; 32];
+        value.to_big_endian(&mut buf);
+        self.0.extend_from_slice(&buf);
+        Ok(self)
+    }
+
+    fn uint8(&mut self, value: i64) -> anyhow::Result<&mut Self> {
+        anyhow::ensure!((0..=255).contains(&value), "{} is not a uint8", value);
+        self.0.push(value as u8);
+        Ok(self)
+    }
+
+    fn bytes(&mut self, data: &str) -> anyhow::Result<&mut Self> {
+        self.0.extend(eth::decode_hex(data)?);
+        Ok(self)
+    }
+}
+
+fn timestamp(value: i64) -> anyhow::Result<U256> {
+    anyhow::ensure!(value >= 0, "negative timestamp {}", value);
+    Ok(U256::from(value))
+}
+
+fn order_side(order: &Order) -> anyhow::Result<OrderSide> {
+    match order.side {
+        0 => Ok(OrderSide::Buy),
+        1 => Ok(OrderSide::Sell),
+        side => anyhow::bail!("unknown order side {}", side),
+    }
+}
+
+fn sale_kind(order: &Order) -> anyhow::Result<SaleKind> {
+    match order.sale_kind {
+        0 => Ok(SaleKind::FixedPrice),
+        1 => Ok(SaleKind::DutchAuction),
+        kind => anyhow::bail!("unknown sale kind {}", kind),
+    }
+}
+
+fn fee_method(order: &Order) -> anyhow::Result<FeeMethod> {
+    match order.fee_method {
+        0 => Ok(FeeMethod::ProtocolFee),
+        1 => Ok(FeeMethod::SplitFee),
+        method => anyhow::bail!("unknown fee method {}", method),
+    }
+}
+
+fn parse_hash(hash: &str) -> anyhow::Result<H256> {
+    let bytes = eth::decode_hex(hash)?;
+    anyhow::ensure!(bytes.len() == 32, "`{}` is not a 32 byte hash", hash);
+    Ok(H256::from_slice(&bytes))
+}
+
+/// The members of an order in the order `hashOrder` packs them, borrowed
+/// from an [`Order`] or a [`NewOrder`]
+struct Members<'a> {
+    exchange: &'a str,
+    maker: &'a str,
+    taker: &'a str,
+    maker_relayer_fee: &'a str,
+    taker_relayer_fee: &'a str,
+    maker_protocol_fee: &'a str,
+    taker_protocol_fee: &'a str,
+    fee_recipient: &'a str,
+    fee_method: i64,
+    side: i64,
+    sale_kind: i64,
+    target: &'a str,
+    how_to_call: i64,
+    calldata: &'a str,
+    replacement_pattern: &'a str,
+    static_target: &'a str,
+    static_extradata: &'a str,
+    payment_token: &'a str,
+    base_price: &'a str,
+    extra: &'a str,
+    listing_time: i64,
+    expiration_time: i64,
+    salt: &'a str,
+}
+
+impl Members<'_> {
+    fn wyvern_hash(&self) -> anyhow::Result<H256> {
+        let mut packed = Packed::default();
+        packed
+            .address(self.exchange)?
+            .address(self.maker)?
+            .address(self.taker)?
+            .uint256(eth::parse_u256(self.maker_relayer_fee)?)?
+            .uint256(eth::parse_u256(self.taker_relayer_fee)?)?
+            .uint256(eth::parse_u256(self.maker_protocol_fee)?)?
+            .uint256(eth::parse_u256(self.taker_protocol_fee)?)?
+            .address(self.fee_recipient)?
+            .uint8(self.fee_method)?
+            .uint8(self.side)?
+            .uint8(self.sale_kind)?
+            .address(self.target)?
+            .uint8(self.how_to_call)?
+            .bytes(self.calldata)?
+            .bytes(self.replacement_pattern)?
+            .address(self.static_target)?
+            .bytes(self.static_extradata)?
+            .address(self.payment_token)?
+            .uint256(eth::parse_u256(self.base_price)?)?
+            .uint256(eth::parse_u256(self.extra)?)?
+            .uint256(timestamp(self.listing_time)?)?
+            .uint256(timestamp(self.expiration_time)?)?
+            .uint256(eth::parse_u256(self.salt)?)?;
+        Ok(H256(eth::keccak256(packed.0)))
+    }
+}
+
+impl Order {
+    fn members(&self) -> Members<'_> {
+        Members {
+            exchange: &self.exchange,
+            maker: &self.maker.address,
+            taker: &self.taker.address,
+            maker_relayer_fee: &self.maker_relayer_fee,
+            taker_relayer_fee: &self.taker_relayer_fee,
+            maker_protocol_fee: &self.maker_protocol_fee,
+            taker_protocol_fee: &self.taker_protocol_fee,
+            fee_recipient: &self.fee_recipient.address,
+            fee_method: self.fee_method,
+            side: self.side,
+            sale_kind: self.sale_kind,
+            target: &self.target,
+            how_to_call: self.how_to_call,
+            calldata: &self.calldata,
+            replacement_pattern: &self.replacement_pattern,
+            static_target: &self.static_target,
+            static_extradata: &self.static_extradata,
+            payment_token: &self.payment_token,
+            base_price: &self.base_price,
+            extra: &self.extra,
+            listing_time: self.listing_time,
+            expiration_time: self.expiration_time,
+            salt: &self.salt,
+        }
+    }
+
+    /// Computes the Wyvern hash of the order, which should equal
+    /// `order_hash`
+    pub fn wyvern_hash(&self) -> anyhow::Result<H256> {
+        self.members().wyvern_hash()
+    }
+
+    /// The hash the maker signs, the `eth_sign` hash of the Wyvern hash
+    pub fn hash_to_sign(&self) -> anyhow::Result<H256> {
+        Ok(eth::hash_message(self.wyvern_hash()?))
+    }
+
+    /// Checks that `order_hash` and `prefixed_hash` match the order members
+    pub fn verify_hash(&self) -> anyhow::Result<H256> {
+        let hash = self.wyvern_hash()?;
+        anyhow::ensure!(
+            hash == parse_hash(&self.order_hash)?,
+            "order hash is {}, but the order hashes to {:?}",
+            self.order_hash,
+            hash
+        );
+        let prefixed = eth::hash_message(hash);
+        anyhow::ensure!(
+            prefixed == parse_hash(&self.prefixed_hash)?,
+            "prefixed hash is {}, expected {:?}",
+            self.prefixed_hash,
+            prefixed
+        );
+        Ok(hash)
+    }
+
+    /// The `v`, `r` and `s` signature of the order
+    #[cfg(feature = "signer")]
+    pub fn signature(&self) -> anyhow::Result<Signature> {
+        anyhow::ensure!((0..=255).contains(&self.v), "invalid v value {}", self.v);
+        let mut bytes = Vec::with_capacity(65);
+        bytes.extend(eth::decode_hex(&self.r)?);
+        bytes.extend(eth::decode_hex(&self.s)?);
+        anyhow::ensure!(bytes.len() == 64, "r and s must be 32 bytes each");
+        bytes.push(self.v as u8);
+        Signature::from_bytes(&bytes)
+    }
+
+    /// Recovers the address that signed the order
+    #[cfg(feature = "signer")]
+    pub fn recover_maker(&self) -> anyhow::Result<Address> {
+        self.signature()?.recover(self.hash_to_sign()?)
+    }
+
+    /// Checks that the order hashes to `order_hash` and was signed by
+    /// `maker`.
+    ///
+    /// Orders approved on chain instead of signed are valid without
+    /// signature.
+    #[cfg(feature = "signer")]
+    pub fn verify_signature(&self) -> anyhow::Result<()> {
+        let hash = self.verify_hash()?;
+        if self.approved_on_chain {
+            return Ok(());
+        }
+        let maker = eth::parse_address(&self.maker.address)?;
+        let signer = self.signature()?.recover(eth::hash_message(hash))?;
+        anyhow::ensure!(
+            signer == maker,
+            "order {} was signed by {:?} instead of its maker {:?}",
+            self.order_hash,
+            signer,
+            maker
+        );
+        Ok(())
+    }
+}
+
+impl NewOrder {
+    fn members(&self) -> Members<'_> {
+        Members {
+            exchange: &self.exchange,
+            maker: &self.maker,
+            taker: &self.taker,
+            maker_relayer_fee: &self.maker_relayer_fee,
+            taker_relayer_fee: &self.taker_relayer_fee,
+            maker_protocol_fee: &self.maker_protocol_fee,
+            taker_protocol_fee: &self.taker_protocol_fee,
+            fee_recipient: &self.fee_recipient,
+            fee_method: self.fee_method,
+            side: self.side,
+            sale_kind: self.sale_kind,
+            target: &self.target,
+            how_to_call: self.how_to_call,
+            calldata: &self.calldata,
+            replacement_pattern: &self.replacement_pattern,
+            static_target: &self.static_target,
+            static_extradata: &self.static_extradata,
+            payment_token: &self.payment_token,
+            base_price: &self.base_price,
+            extra: &self.extra,
+            listing_time: self.listing_time,
+            expiration_time: self.expiration_time,
+            salt: &self.salt,
+        }
+    }
+
+    /// Computes the Wyvern hash of the order, like [`Order::wyvern_hash`]
+    pub fn wyvern_hash(&self) -> anyhow::Result<H256> {
+        self.members().wyvern_hash()
+    }
+
+    /// The hash the maker signs, the `eth_sign` hash of the Wyvern hash
+    pub fn hash_to_sign(&self) -> anyhow::Result<H256> {
+        Ok(eth::hash_message(self.wyvern_hash()?))
+    }
+
+    /// Whether `v`, `r` and `s` are set
+    pub fn is_signed(&self) -> bool {
+        self.v.is_some() && self.r.is_some() && self.s.is_some()
+    }
+}
+
+#[cfg(feature = "signer")]
+impl NewOrder {
+    /// Signs the order and sets `v`, `r` and `s`.
+    ///
+    /// Fails if the wallet is not the `maker` of the order or `hash` doesn't
+    /// match the order members.
+    pub fn sign(&mut self, wallet: &Wallet) -> anyhow::Result<()> {
+        anyhow::ensure!(
+            eth::parse_address(&self.maker)? == wallet.address(),
+            "order is made by {}, not {:?}",
+            self.maker,
+            wallet.address()
+        );
+        let hash = self.wyvern_hash()?;
+        anyhow::ensure!(
+            hash == parse_hash(&self.hash)?,
+            "order hash is {}, but the order hashes to {:?}",
+            self.hash,
+            hash
+        );
+        let signature = wallet.sign_hash(eth::hash_message(hash))?;
+        let bytes = signature.to_bytes();
+        self.v = Some(signature.v);
+        self.r = Some(eth::encode_hex(&bytes[..32]));
+        self.s = Some(eth::encode_hex(&bytes[32..64]));
+        Ok(())
+    }
+}
+
+/// The fees of an order that is matched at a certain price.
+///
+/// With [`FeeMethod::SplitFee`] all fees are paid in the payment token: the
+/// maker's fees are deducted from what the seller receives, the taker's
+/// fees are paid on top by the buyer, or the other way around if the order
+/// is a buy order. With [`FeeMethod::ProtocolFee`] the fees are paid
+/// separately in Wyvern protocol tokens and don't affect the price.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OrderFees {
+    pub fee_method: FeeMethod,
+    /// The price of the order at execution time
+    pub price: U256,
+    /// Fee the seller pays to the `fee_recipient`
+    pub seller_relayer_fee: U256,
+    /// Fee the buyer pays to the `fee_recipient`
+    pub buyer_relayer_fee: U256,
+    /// Fee the seller pays to the protocol
+    pub seller_protocol_fee: U256,
+    /// Fee the buyer pays to the protocol
+    pub buyer_protocol_fee: U256,
+}
+
+impl OrderFees {
+    /// The total amount of payment tokens the buyer pays
+    pub fn buyer_pays(&self) -> U256 {
+        match self.fee_method {
+            FeeMethod::SplitFee => self
+                .price
+                .saturating_add(self.buyer_relayer_fee)
+                .saturating_add(self.buyer_protocol_fee),
+            FeeMethod::ProtocolFee => self.price,
+        }
+    }
+
+    /// The amount of payment tokens the seller receives
+    pub fn seller_receives(&self) -> U256 {
+        match self.fee_method {
+            FeeMethod::SplitFee => self.price.saturating_sub(
+                self.seller_relayer_fee
+                    .saturating_add(self.seller_protocol_fee),
+            ),
+            FeeMethod::ProtocolFee => self.price,
+        }
+    }
+
+    /// All fees paid to the `fee_recipient`
+    pub fn relayer_fees(&self) -> U256 {
+        self.seller_relayer_fee
+            .saturating_add(self.buyer_relayer_fee)
+    }
+
+    /// All fees paid to the protocol
+    pub fn protocol_fees(&self) -> U256 {
+        self.seller_protocol_fee
+            .saturating_add(self.buyer_protocol_fee)
+    }
+}
+
+impl Order {
+    /// The price of the order if it's matched at `timestamp` (unix seconds).
+    ///
+    /// Fixed price orders, which includes English auctions, cost `base_price`.
+    /// The price of dutch auctions moves linearly by `extra` between
+    /// `listing_time` and `expiration_time`: sell orders decline to
+    /// `base_price - extra`, buy orders rise to `base_price + extra`.
+    ///
+    /// Fails if the order can't be matched at `timestamp`.
+    pub fn price_at(&self, timestamp: i64) -> anyhow::Result<U256> {
+        // `canSettleOrder` requires `listingTime < now`
+        anyhow::ensure!(
+            self.listing_time < timestamp,
+            "order is listed at {}",
+            self.listing_time
+        );
+        anyhow::ensure!(
+            self.expiration_time == 0 || timestamp < self.expiration_time,
+            "order expired at {}",
+            self.expiration_time
+        );
+        let base_price = eth::parse_u256(&self.base_price)?;
+        if sale_kind(self)? == SaleKind::FixedPrice {
+            return Ok(base_price);
+        }
+        anyhow::ensure!(
+            self.expiration_time > self.listing_time,
+            "dutch auction without expiration time"
+        );
+        let extra = eth::parse_u256(&self.extra)?;
+        let diff = extra
+            .checked_mul(U256::from(timestamp - self.listing_time))
+            .ok_or_else(|| anyhow::anyhow!("price of dutch auction overflows"))?
+            / U256::from(self.expiration_time - self.listing_time);
+        match order_side(self)? {
+            OrderSide::Sell => base_price
+                .checked_sub(diff)
+                .ok_or_else(|| anyhow::anyhow!("extra exceeds the base price")),
+            OrderSide::Buy => base_price
+                .checked_add(diff)
+                .ok_or_else(|| anyhow::anyhow!("price of dutch auction overflows")),
+        }
+    }
+
+    /// The fees if the order is matched at `timestamp`, assuming this order
+    /// sets the fees, i.e. it has a `fee_recipient`, as OpenSea orders do.
+    pub fn fees_at(&self, timestamp: i64) -> anyhow::Result<OrderFees> {
+        self.fees_for(self.price_at(timestamp)?)
+    }
+
+    /// The fees if the order is matched at `price`
+    pub fn fees_for(&self, price: U256) -> anyhow::Result<OrderFees> {
+        let fee_method = fee_method(self)?;
+        let fee = |value: &str| -> anyhow::Result<U256> {
+            let value = eth::parse_u256(value)?;
+            Ok(match fee_method {
+                FeeMethod::SplitFee => {
+                    value
+                        .checked_mul(price)
+                        .ok_or_else(|| anyhow::anyhow!("fee of {} overflows", value))?
+                        / U256::from(INVERSE_BASIS_POINT)
+                }
+                FeeMethod::ProtocolFee => value,
+            })
+        };
+        let maker_relayer_fee = fee(&self.maker_relayer_fee)?;
+        let taker_relayer_fee = fee(&self.taker_relayer_fee)?;
+        let maker_protocol_fee = fee(&self.maker_protocol_fee)?;
+        let taker_protocol_fee = fee(&self.taker_protocol_fee)?;
+        let fees = match order_side(self)? {
+            OrderSide::Sell => OrderFees {
+                fee_method,
+                price,
+                seller_relayer_fee: maker_relayer_fee,
+                buyer_relayer_fee: taker_relayer_fee,
+                seller_protocol_fee: maker_protocol_fee,
+                buyer_protocol_fee: taker_protocol_fee,
+            },
+            OrderSide::Buy => OrderFees {
+                fee_method,
+                price,
+                seller_relayer_fee: taker_relayer_fee,
+                buyer_relayer_fee: maker_relayer_fee,
+                seller_protocol_fee: taker_protocol_fee,
+                buyer_protocol_fee: maker_protocol_fee,
+            },
+        };
+        Ok(fees)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::opensea::models::OrderBook;
+    use serde_json::{json, Value};
+
+    const ORDERBOOK: &str = include_str!("../../examples/responses/opensea-orderbook.json");
+
+    const LISTED: i64 = 1_000;
+    const EXPIRES: i64 = 2_000;
+
+    fn eth(millis: u64) -> U256 {
+        U256::from(millis) * U256::exp10(15)
+    }
+
+    /// The first order of the fixture as a dutch auction from 2 ETH by 1 ETH
+    /// between `LISTED` and `EXPIRES`, with a 2.5% maker and a 1% taker fee
+    fn auction(side: OrderSide) -> Order {
+        let mut book: Value = serde_json::from_str(ORDERBOOK).unwrap();
+        let mut order = book["orders"][0].take();
+        order["side"] = json!(side as u8);
+        order["sale_kind"] = json!(SaleKind::DutchAuction as u8);
+        order["base_price"] = json!(eth(2_000).to_string());
+        order["extra"] = json!(eth(1_000).to_string());
+        order["listing_time"] = json!(LISTED);
+        order["expiration_time"] = json!(EXPIRES);
+        order["maker_relayer_fee"] = json!("250");
+        order["taker_relayer_fee"] = json!("100");
+        serde_json::from_value(order).unwrap()
+    }
+
+    #[test]
+    fn hashes_recorded_orders() {
+        let book: OrderBook = serde_json::from_str(ORDERBOOK).unwrap();
+        assert!(!book.orders.is_empty());
+        for order in &book.orders {
+            order.verify_hash().unwrap();
+        }
+    }
+
+    #[test]
+    fn declines_the_price_of_dutch_sell_orders() {
+        let order = auction(OrderSide::Sell);
+        // can't be matched in the block it was listed in
+        assert!(order.price_at(LISTED).is_err());
+        assert_eq!(order.price_at(LISTED + 1).unwrap(), eth(1_999));
+        assert_eq!(order.price_at((LISTED + EXPIRES) / 2).unwrap(), eth(1_500));
+        assert_eq!(order.price_at(EXPIRES - 1).unwrap(), eth(1_001));
+        assert!(order.price_at(EXPIRES).is_err());
+    }
+
+    #[test]
+    fn raises_the_price_of_dutch_buy_orders() {
+        let order = auction(OrderSide::Buy);
+        assert!(order.price_at(LISTED).is_err());
+        assert_eq!(order.price_at(LISTED + 1).unwrap(), eth(2_001));
+        assert_eq!(order.price_at((LISTED + EXPIRES) / 2).unwrap(), eth(2_500));
+        assert_eq!(order.price_at(EXPIRES - 1).unwrap(), eth(2_999));
+        assert!(order.price_at(EXPIRES).is_err());
+    }
+
+    #[test]
+    fn prices_fixed_price_orders_at_the_base_price() {
+        let mut order = auction(OrderSide::Sell);
+        order.sale_kind = SaleKind::FixedPrice as i64;
+        assert_eq!(order.price_at(LISTED + 1).unwrap(), eth(2_000));
+        assert_eq!(order.price_at(EXPIRES - 1).unwrap(), eth(2_000));
+        order.expiration_time = 0;
+        assert_eq!(order.price_at(i64::MAX).unwrap(), eth(2_000));
+
+        // dutch auctions need an end and can't decline below zero
+        order.sale_kind = SaleKind::DutchAuction as i64;
+        assert!(order.price_at(LISTED + 1).is_err());
+        let mut order = auction(OrderSide::Sell);
+        order.extra = eth(3_000).to_string();
+        assert!(order.price_at(EXPIRES - 1).is_err());
+    }
+
+    #[test]
+    fn charges_the_maker_fee_to_the_seller_of_sell_orders() {
+        let order = auction(OrderSide::Sell);
+        let midpoint = (LISTED + EXPIRES) / 2;
+        for (timestamp, price) in [
+            (LISTED + 1, eth(1_999)),
+            (midpoint, eth(1_500)),
+            (EXPIRES - 1, eth(1_001)),
+        ] {
+            let fees = order.fees_at(timestamp).unwrap();
+            assert_eq!(fees, order.fees_for(price).unwrap());
+            assert_eq!(fees.fee_method, FeeMethod::SplitFee);
+            assert_eq!(fees.price, price);
+            assert_eq!(fees.seller_relayer_fee, price * 250 / 10_000);
+            assert_eq!(fees.buyer_relayer_fee, price * 100 / 10_000);
+            assert!(fees.seller_protocol_fee.is_zero());
+            assert!(fees.buyer_protocol_fee.is_zero());
+        }
+        let fees = order.fees_at(midpoint).unwrap();
+        assert_eq!(
+            fees.seller_relayer_fee,
+            U256::from(37_500_000_000_000_000u64)
+        );
+        assert_eq!(fees.buyer_relayer_fee, eth(15));
+        assert!(order.fees_at(EXPIRES).is_err());
+    }
+
+    #[test]
+    fn charges_the_maker_fee_to_the_buyer_of_buy_orders() {
+        let order = auction(OrderSide::Buy);
+        for (timestamp, price) in [
+            (LISTED + 1, eth(2_001)),
+            ((LISTED + EXPIRES) / 2, eth(2_500)),
+            (EXPIRES - 1, eth(2_999)),
+        ] {
+            let fees = order.fees_at(timestamp).unwrap();
+            assert_eq!(fees.price, price);
+            assert_eq!(fees.buyer_relayer_fee, price * 250 / 10_000);
+            assert_eq!(fees.seller_relayer_fee, price * 100 / 10_000);
+        }
+        let fees = order.fees_at((LISTED + EXPIRES) / 2).unwrap();
+        assert_eq!(
+            fees.buyer_relayer_fee,
+            U256::from(62_500_000_000_000_000u64)
+        );
+        assert_eq!(fees.seller_relayer_fee, eth(25));
+    }
+
+    #[test]
+    fn charges_protocol_fees_as_absolute_amounts() {
+        let mut order = auction(OrderSide::Sell);
+        order.fee_method = FeeMethod::ProtocolFee as i64;
+        order.maker_protocol_fee = "7".to_string();
+        let fees = order.fees_at((LISTED + EXPIRES) / 2).unwrap();
+        assert_eq!(fees.fee_method, FeeMethod::ProtocolFee);
+        assert_eq!(fees.seller_relayer_fee, U256::from(250));
+        assert_eq!(fees.buyer_relayer_fee, U256::from(100));
+        assert_eq!(fees.seller_protocol_fee, U256::from(7));
+        assert!(fees.buyer_protocol_fee.is_zero());
+    }
+}
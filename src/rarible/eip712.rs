@@ -0,0 +1,738 @@
+//! Local EIP-712 hashing of Rarible exchange v2 orders.
+//!
+//! The hashes match the ones computed by the `ExchangeV2` contract, so a
+//! digest computed here can be signed directly instead of relying on the
+//! encoding returned by `encode_order`.
+
+use ethabi::Token;
+
+use crate::eth::{self, Address, H256, U256};
+use crate::rarible::models::*;
+#[cfg(feature = "signer")]
+use crate::signer::{Signature, Wallet};
+
+/// Address of the Rarible `ExchangeV2` contract on mainnet
+pub const EXCHANGE_V2_MAINNET: &str = "0x9757f2d2b135150bbeb65308d4a91804107cd8d6";
+
+pub const ASSET_TYPE_TYPE: &str = "AssetType(bytes4 assetClass,bytes data)";
+
+pub const ASSET_TYPE: &str =
+    "Asset(AssetType assetType,uint256 value)AssetType(bytes4 assetClass,bytes data)";
+
+pub const ORDER_TYPE: &str = "Order(address maker,Asset makeAsset,address taker,Asset takeAsset,uint256 salt,uint256 start,uint256 end,bytes4 dataType,bytes data)Asset(AssetType assetType,uint256 value)AssetType(bytes4 assetClass,bytes data)";
+
+pub const PART_TYPE: &str = "Part(address account,uint96 value)";
+
+pub const MINT_721_TYPE: &str = "Mint721(uint256 tokenId,string tokenURI,Part[] creators,Part[] royalties)Part(address account,uint96 value)";
+
+pub const MINT_1155_TYPE: &str = "Mint1155(uint256 tokenId,uint256 supply,string tokenURI,Part[] creators,Part[] royalties)Part(address account,uint96 value)";
+
+/// The `dataType` of orders without additional data
+pub const DATA_TYPE_LEGACY: [u8; 4] = [0xff, 0xff, 0xff, 0xff];
+
+impl Eip712Domain {
+    /// The domain of the `ExchangeV2` contract deployed at `verifying_contract`
+    pub fn exchange_v2(chain_id: i32, verifying_contract: impl Into<String>) -> Self {
+        Self {
+            name: "Exchange".to_string(),
+            version: "2".to_string(),
+            chain_id,
+            verifying_contract: verifying_contract.into(),
+        }
+    }
+
+    /// The domain of the `ExchangeV2` contract on mainnet
+    pub fn exchange_v2_mainnet() -> Self {
+        Self::exchange_v2(1, EXCHANGE_V2_MAINNET)
+    }
+
+    /// The domain of the lazy mint of a `Mint721` or `Mint1155` token at
+    /// `verifying_contract`
+    pub fn lazy_mint(
+        chain_id: i32,
+        mint_type: &str,
+        verifying_contract: impl Into<String>,
+    ) -> Self {
+        Self {
+            name: mint_type.to_string(),
+            version: "1".to_string(),
+            chain_id,
+            verifying_contract: verifying_contract.into(),
+        }
+    }
+
+    pub fn separator(&self) -> anyhow::Result<H256> {
+        Ok(eth::domain_separator(
+            &self.name,
+            &self.version,
+            U256::from(self.chain_id),
+            eth::parse_address(&self.verifying_contract)?,
+        ))
+    }
+}
+
+impl Part {
+    pub(crate) fn to_token(&self) -> anyhow::Result<Token> {
+        anyhow::ensure!(self.value >= 0, "negative value of part {}", self.account);
+        Ok(Token::Tuple(vec![
+            Token::Address(eth::parse_address(&self.account)?),
+            Token::Uint(U256::from(self.value)),
+        ]))
+    }
+
+    /// The EIP-712 `hashStruct` of the part
+    pub fn struct_hash(&self) -> anyhow::Result<H256> {
+        anyhow::ensure!(self.value >= 0, "negative value of part {}", self.account);
+        Ok(eth::hash_struct(
+            eth::keccak256(PART_TYPE),
+            vec![
+                Token::Address(eth::parse_address(&self.account)?),
+                Token::Uint(U256::from(self.value)),
+            ],
+        ))
+    }
+}
+
+/// Hashes a `Part[]` member of a struct
+fn parts_hash(parts: &[Part]) -> anyhow::Result<Token> {
+    let mut encoded = Vec::with_capacity(parts.len() * 32);
+    for part in parts {
+        encoded.extend_from_slice(part.struct_hash()?.as_bytes());
+    }
+    Ok(Token::FixedBytes(eth::keccak256(encoded).to_vec()))
+}
+
+pub(crate) fn parts_token(parts: &[Part]) -> anyhow::Result<Token> {
+    Ok(Token::Array(
+        parts.iter().map(Part::to_token).collect::<Result<_, _>>()?,
+    ))
+}
+
+fn signatures_token(signatures: &[String]) -> anyhow::Result<Token> {
+    Ok(Token::Array(
+        signatures
+            .iter()
+            .map(|sig| eth::decode_hex(sig).map(Token::Bytes))
+            .collect::<Result<_, _>>()?,
+    ))
+}
+
+impl AssetType {
+    /// The `bytes4` asset class identifier: `bytes4(keccak256("ERC721"))`, ...
+    pub fn asset_class(&self) -> [u8; 4] {
+        eth::id(self.asset_class_name())
+    }
+
+    /// The name of the asset class as used by the API
+    pub fn asset_class_name(&self) -> &'static str {
+        match self {
+            AssetType::Collection { .. } => "COLLECTION",
+            AssetType::Erc1155 { .. } => "ERC1155",
+            AssetType::Erc1155Lazy { .. } => "ERC1155_LAZY",
+            AssetType::Erc20 { .. } => "ERC20",
+            AssetType::Erc721 { .. } => "ERC721",
+            AssetType::Erc721Lazy { .. } => "ERC721_LAZY",
+            AssetType::Eth => "ETH",
+            AssetType::Flow => "FLOW",
+        }
+    }
+
+    /// The ABI encoded `data` of the asset type
+    pub fn encode_data(&self) -> anyhow::Result<Vec<u8>> {
+        let data = match self {
+            AssetType::Eth => Vec::new(),
+            AssetType::Erc20 { contract } | AssetType::Collection { contract } => {
+                ethabi::encode(&[Token::Address(eth::parse_address(contract)?)])
+            }
+            AssetType::Erc721 { contract, token_id }
+            | AssetType::Erc1155 { contract, token_id } => ethabi::encode(&[
+                Token::Address(eth::parse_address(contract)?),
+                Token::Uint(eth::parse_u256(token_id)?),
+            ]),
+            AssetType::Erc721Lazy {
+                contract,
+                token_id,
+                uri,
+                creators,
+                royalties,
+                signatures,
+            } => ethabi::encode(&[
+                Token::Address(eth::parse_address(contract)?),
+                Token::Tuple(vec![
+                    Token::Uint(eth::parse_u256(token_id)?),
+                    Token::String(uri.clone()),
+                    parts_token(creators)?,
+                    parts_token(royalties)?,
+                    signatures_token(signatures)?,
+                ]),
+            ]),
+            AssetType::Erc1155Lazy {
+                contract,
+                token_id,
+                uri,
+                supply,
+                creators,
+                royalties,
+                signatures,
+            } => ethabi::encode(&[
+                Token::Address(eth::parse_address(contract)?),
+                Token::Tuple(vec![
+                    Token::Uint(eth::parse_u256(token_id)?),
+                    Token::String(uri.clone()),
+                    Token::Uint(eth::parse_u256(supply)?),
+                    parts_token(creators)?,
+                    parts_token(royalties)?,
+                    signatures_token(signatures)?,
+                ]),
+            ]),
+            AssetType::Flow => anyhow::bail!("FLOW assets can't be traded on Ethereum"),
+        };
+        Ok(data)
+    }
+
+    pub fn struct_hash(&self) -> anyhow::Result<H256> {
+        Ok(eth::hash_struct(
+            eth::keccak256(ASSET_TYPE_TYPE),
+            vec![
+                Token::FixedBytes(self.asset_class().to_vec()),
+                Token::FixedBytes(eth::keccak256(self.encode_data()?).to_vec()),
+            ],
+        ))
+    }
+}
+
+impl Asset {
+    pub fn struct_hash(&self) -> anyhow::Result<H256> {
+        Ok(eth::hash_struct(
+            eth::keccak256(ASSET_TYPE),
+            vec![
+                Token::FixedBytes(self.asset_type.struct_hash()?.as_bytes().to_vec()),
+                Token::Uint(eth::parse_u256(&self.value)?),
+            ],
+        ))
+    }
+}
+
+impl OrderData {
+    /// The `bytes4` data type of the order
+    pub fn data_type(&self) -> [u8; 4] {
+        match self {
+            OrderData::OrderDataLegacy { .. } => DATA_TYPE_LEGACY,
+            OrderData::OrderRaribleV2DataV1 { .. } => eth::id("V1"),
+        }
+    }
+
+    /// The ABI encoded order data.
+    ///
+    /// The `fee` of legacy data is not part of v2 orders, their data is empty.
+    pub fn encode(&self) -> anyhow::Result<Vec<u8>> {
+        match self {
+            OrderData::OrderDataLegacy { .. } => Ok(Vec::new()),
+            OrderData::OrderRaribleV2DataV1 {
+                payouts,
+                origin_fees,
+            } => Ok(ethabi::encode(&[Token::Tuple(vec![
+                parts_token(payouts)?,
+                parts_token(origin_fees)?,
+            ])])),
+        }
+    }
+}
+
+/// Hashes the members of an order that are part of its signature
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn order_struct_hash(
+    order_type: OrderType,
+    maker: &str,
+    make: &Asset,
+    taker: Option<&str>,
+    take: &Asset,
+    salt: &str,
+    start: Option<i64>,
+    end: Option<i64>,
+    data: &OrderData,
+) -> anyhow::Result<H256> {
+    anyhow::ensure!(
+        order_type == OrderType::V2,
+        "{} orders are not signed with EIP-712",
+        order_type.to_string()
+    );
+    let taker = match taker {
+        Some(taker) => eth::parse_address(taker)?,
+        None => Address::zero(),
+    };
+    Ok(eth::hash_struct(
+        eth::keccak256(ORDER_TYPE),
+        vec![
+            Token::Address(eth::parse_address(maker)?),
+            Token::FixedBytes(make.struct_hash()?.as_bytes().to_vec()),
+            Token::Address(taker),
+            Token::FixedBytes(take.struct_hash()?.as_bytes().to_vec()),
+            Token::Uint(eth::parse_u256(salt)?),
+            Token::Uint(U256::from(start.unwrap_or_default().max(0))),
+            Token::Uint(U256::from(end.unwrap_or_default().max(0))),
+            Token::FixedBytes(data.data_type().to_vec()),
+            Token::FixedBytes(eth::keccak256(data.encode()?).to_vec()),
+        ],
+    ))
+}
+
+impl OrderForm {
+    /// The EIP-712 `hashStruct` of the order
+    pub fn struct_hash(&self) -> anyhow::Result<H256> {
+        order_struct_hash(
+            self._type,
+            &self.maker,
+            &self.make,
+            self.taker.as_deref(),
+            &self.take,
+            &self.salt,
+            self.start,
+            self.end,
+            &self.data,
+        )
+    }
+
+    /// The digest the maker has to sign for the exchange at `domain`
+    pub fn eip712_hash(&self, domain: &Eip712Domain) -> anyhow::Result<H256> {
+        Ok(eth::eip712_digest(domain.separator()?, self.struct_hash()?))
+    }
+}
+
+impl Order {
+    /// The EIP-712 `hashStruct` of the order
+    pub fn struct_hash(&self) -> anyhow::Result<H256> {
+        order_struct_hash(
+            self._type,
+            &self.maker,
+            &self.make,
+            self.taker.as_deref(),
+            &self.take,
+            &self.salt,
+            self.start,
+            self.end,
+            &self.data,
+        )
+    }
+
+    /// The digest the maker signed for the exchange at `domain`
+    pub fn eip712_hash(&self, domain: &Eip712Domain) -> anyhow::Result<H256> {
+        Ok(eth::eip712_digest(domain.separator()?, self.struct_hash()?))
+    }
+}
+
+impl SignMessage {
+    /// Hashes the typed data returned by the API, `None` if this is not an
+    /// EIP-712 message
+    pub fn eip712_hash(&self) -> anyhow::Result<Option<H256>> {
+        if self._type != "EIP712" {
+            return Ok(None);
+        }
+        let struct_hash = eth::hash_typed_struct(&self.struct_type, &self.types, &self._struct)?;
+        Ok(Some(eth::eip712_digest(
+            self.domain.separator()?,
+            struct_hash,
+        )))
+    }
+}
+
+impl EncodedOrder {
+    /// Checks that the message the server asks to sign is the order form and
+    /// returns the digest to sign.
+    pub fn verify(&self, order_form: &OrderForm) -> anyhow::Result<H256> {
+        let local = order_form.eip712_hash(&self.sign_message.domain)?;
+        let remote = self.sign_message.eip712_hash()?.ok_or_else(|| {
+            anyhow::anyhow!("`{}` is not an EIP-712 message", self.sign_message._type)
+        })?;
+        anyhow::ensure!(
+            local == remote,
+            "server encoded the order as {:?}, expected {:?}",
+            remote,
+            local
+        );
+        Ok(local)
+    }
+}
+
+impl LazyNft {
+    /// Whether this is a `Mint1155` instead of a `Mint721`
+    fn is_erc1155(&self) -> anyhow::Result<bool> {
+        match self._type.as_str() {
+            "ERC721" => Ok(false),
+            "ERC1155" => Ok(true),
+            ty => anyhow::bail!("unsupported lazy mint type `{}`", ty),
+        }
+    }
+
+    /// The domain of the lazy mint contract on chain `chain_id`
+    pub fn domain(&self, chain_id: i32) -> anyhow::Result<Eip712Domain> {
+        let mint_type = if self.is_erc1155()? {
+            "Mint1155"
+        } else {
+            "Mint721"
+        };
+        Ok(Eip712Domain::lazy_mint(
+            chain_id,
+            mint_type,
+            self.contract.clone(),
+        ))
+    }
+
+    /// The EIP-712 `hashStruct` of the mint
+    pub fn struct_hash(&self) -> anyhow::Result<H256> {
+        let token_id = Token::Uint(eth::parse_u256(&self.token_id)?);
+        let uri = Token::FixedBytes(eth::keccak256(&self.uri).to_vec());
+        let creators = parts_hash(&self.creators)?;
+        let royalties = parts_hash(&self.royalties)?;
+        if self.is_erc1155()? {
+            Ok(eth::hash_struct(
+                eth::keccak256(MINT_1155_TYPE),
+                vec![
+                    token_id,
+                    Token::Uint(eth::parse_u256(&self.supply)?),
+                    uri,
+                    creators,
+                    royalties,
+                ],
+            ))
+        } else {
+            Ok(eth::hash_struct(
+                eth::keccak256(MINT_721_TYPE),
+                vec![token_id, uri, creators, royalties],
+            ))
+        }
+    }
+
+    /// The digest every creator has to sign on chain `chain_id`
+    pub fn eip712_hash(&self, chain_id: i32) -> anyhow::Result<H256> {
+        Ok(eth::eip712_digest(
+            self.domain(chain_id)?.separator()?,
+            self.struct_hash()?,
+        ))
+    }
+}
+
+#[cfg(feature = "signer")]
+impl OrderForm {
+    /// Signs the order for the exchange at `domain` and sets its `signature`.
+    ///
+    /// Fails if the wallet is not the `maker` of the order.
+    pub fn sign(&mut self, wallet: &Wallet, domain: &Eip712Domain) -> anyhow::Result<()> {
+        anyhow::ensure!(
+            eth::parse_address(&self.maker)? == wallet.address(),
+            "order is made by {}, not {:?}",
+            self.maker,
+            wallet.address()
+        );
+        let signature = wallet.sign_hash(self.eip712_hash(domain)?)?;
+        self.signature = Some(signature.to_string());
+        Ok(())
+    }
+}
+
+#[cfg(feature = "signer")]
+impl LazyNft {
+    /// Signs the mint on chain `chain_id` and stores the signature at the
+    /// position of the wallet in `creators`.
+    ///
+    /// Signatures of the other creators are kept, missing ones are left empty.
+    pub fn sign(&mut self, wallet: &Wallet, chain_id: i32) -> anyhow::Result<()> {
+        let mut index = None;
+        for (idx, creator) in self.creators.iter().enumerate() {
+            if eth::parse_address(&creator.account)? == wallet.address() {
+                index = Some(idx);
+                break;
+            }
+        }
+        let index =
+            index.ok_or_else(|| anyhow::anyhow!("{:?} is not a creator", wallet.address()))?;
+        let signature = wallet.sign_hash(self.eip712_hash(chain_id)?)?;
+        self.signatures.resize(self.creators.len(), String::new());
+        self.signatures[index] = signature.to_string();
+        Ok(())
+    }
+}
+
+/// Checks that `signature` is a signature of `digest` by `signer`
+#[cfg(feature = "signer")]
+fn verify_signer(signature: &str, digest: H256, signer: &str) -> anyhow::Result<()> {
+    anyhow::ensure!(
+        !signature.trim_start_matches("0x").is_empty(),
+        "missing signature of {}",
+        signer
+    );
+    let recovered = signature.parse::<Signature>()?.recover(digest)?;
+    anyhow::ensure!(
+        recovered == eth::parse_address(signer)?,
+        "signed by {:?} instead of {}",
+        recovered,
+        signer
+    );
+    Ok(())
+}
+
+#[cfg(feature = "signer")]
+impl Order {
+    /// Checks that the order was signed by its `maker` for the exchange at
+    /// `domain`.
+    ///
+    /// Orders of contract wallets (EIP-1271) can't be verified offline and
+    /// are rejected as well.
+    pub fn verify_signature(&self, domain: &Eip712Domain) -> anyhow::Result<()> {
+        let signature = self
+            .signature
+            .as_deref()
+            .ok_or_else(|| anyhow::anyhow!("order {} is not signed", self.hash))?;
+        verify_signer(signature, self.eip712_hash(domain)?, &self.maker)
+            .map_err(|err| anyhow::anyhow!("invalid signature of order {}: {}", self.hash, err))
+    }
+}
+
+#[cfg(feature = "signer")]
+impl LazyNft {
+    /// Checks that every creator signed the mint on chain `chain_id`
+    pub fn verify_signatures(&self, chain_id: i32) -> anyhow::Result<()> {
+        anyhow::ensure!(
+            self.signatures.len() == self.creators.len(),
+            "{} signatures for {} creators",
+            self.signatures.len(),
+            self.creators.len()
+        );
+        let digest = self.eip712_hash(chain_id)?;
+        for (creator, signature) in self.creators.iter().zip(&self.signatures) {
+            verify_signer(signature, digest, &creator.account)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Constructed rather than recorded: an order of the web3.js test key in
+    // the shape of the API, signed locally, and the `encodeOrder` response
+    // for it. They are pinned by the published constants and vectors the
+    // other tests check.
+    const ENCODED_ORDER: &str = include_str!("../../tests/fixtures/rarible-encode-order.json");
+    const ORDER: &str = include_str!("../../tests/fixtures/rarible-order.json");
+
+    /// The digest of the fixture order for the mainnet exchange
+    const ORDER_DIGEST: &str = "0x383ffde06d7c6a5939dc40bf8461441b55157a58268260a1b2662efafe102736";
+
+    const WETH: &str = "0xc02aaa39b223fe8d0a0e5c4f27ead9083c756cc2";
+
+    fn hash(s: &str) -> H256 {
+        H256::from_slice(&eth::decode_hex(s).unwrap())
+    }
+
+    #[test]
+    fn uses_the_exchange_constants() {
+        let class = |asset: AssetType| eth::encode_hex(asset.asset_class());
+        assert_eq!(class(AssetType::Eth), "0xaaaebeba");
+        assert_eq!(class(AssetType::erc20(WETH)), "0x8ae85d84");
+        assert_eq!(class(AssetType::erc721(WETH, "1")), "0x73ad2146");
+        assert_eq!(class(AssetType::erc1155(WETH, "1")), "0x973bb640");
+        assert_eq!(
+            eth::encode_hex(
+                OrderData::OrderRaribleV2DataV1 {
+                    payouts: vec![],
+                    origin_fees: vec![],
+                }
+                .data_type()
+            ),
+            "0x4c234266"
+        );
+
+        let type_hash = |ty: &str| H256(eth::keccak256(ty));
+        assert_eq!(
+            type_hash(ORDER_TYPE),
+            hash("0x477ed43b8020849b755512278536c3766a3b4ab547519949a75f483372493f8d")
+        );
+        assert_eq!(
+            type_hash(ASSET_TYPE),
+            hash("0xdb6f72e915676cfc289da13bc4ece054fd17b1df6d77ffc4a60510718c236b08")
+        );
+        assert_eq!(
+            type_hash(ASSET_TYPE_TYPE),
+            hash("0x452a0dc408cb0d27ffc3b3caff933a5208040a53a9dbecd8d89cad2c0d40e00c")
+        );
+        assert_eq!(
+            type_hash(PART_TYPE),
+            hash("0x397e04204c1e1a60ee8724b71f8244e10ab5f2e9009854d80f602bda21b59ebb")
+        );
+    }
+
+    #[test]
+    fn hashes_the_sign_message_like_the_order() {
+        let encoded: EncodedOrder = serde_json::from_str(ENCODED_ORDER).unwrap();
+        let order: Order = serde_json::from_str(ORDER).unwrap();
+        let domain = Eip712Domain::exchange_v2_mainnet();
+        assert_eq!(*encoded.sign_message.domain, domain);
+        assert_eq!(
+            encoded.sign_message.eip712_hash().unwrap(),
+            Some(hash(ORDER_DIGEST))
+        );
+        assert_eq!(order.eip712_hash(&domain).unwrap(), hash(ORDER_DIGEST));
+    }
+
+    #[test]
+    fn rejects_sign_messages_of_other_orders() {
+        let encoded: EncodedOrder = serde_json::from_str(ENCODED_ORDER).unwrap();
+        let order: Order = serde_json::from_str(ORDER).unwrap();
+        let form = OrderForm {
+            _type: order._type,
+            maker: order.maker,
+            taker: order.taker,
+            make: order.make,
+            take: order.take,
+            salt: order.salt,
+            start: order.start,
+            end: order.end,
+            data: order.data,
+            signature: None,
+        };
+        assert_eq!(encoded.verify(&form).unwrap(), hash(ORDER_DIGEST));
+        let other = OrderForm {
+            salt: "0x01".to_string(),
+            ..form
+        };
+        assert!(encoded.verify(&other).is_err());
+    }
+
+    /// The account of the web3.js documentation examples
+    #[cfg(feature = "signer")]
+    const KEY: &str = "0x4c0883a69102937d6231471b5dbb6204fe5129617082792ae468d01a3f362318";
+    const CREATOR: &str = "0x2c7536e3605d9c16a7a3d7b1898e529396a65c23";
+
+    fn lazy_nft(ty: &str, supply: &str) -> LazyNft {
+        let part = |value| Part {
+            account: CREATOR.to_string(),
+            value,
+        };
+        LazyNft {
+            contract: "0x6ede7f3c26975aad32a475e1021d8f6f39c89d82".to_string(),
+            token_id: format!("{}000000000000000000000001", CREATOR),
+            uri: "/ipfs/QmWLsBu6nS4ovaHbGAXprD1qEssJu4r5taQfB74sCG51tp".to_string(),
+            creators: vec![part(10_000)],
+            royalties: vec![part(1_000)],
+            signatures: Vec::new(),
+            _type: ty.to_string(),
+            supply: supply.to_string(),
+        }
+    }
+
+    /// Hashes the mint with the typed data encoder instead of the fixed
+    /// struct encoding
+    fn typed_mint_hash(nft: &LazyNft) -> H256 {
+        let erc1155 = nft._type == "ERC1155";
+        let mut members = vec![serde_json::json!({ "name": "tokenId", "type": "uint256" })];
+        if erc1155 {
+            members.push(serde_json::json!({ "name": "supply", "type": "uint256" }));
+        }
+        members.extend(vec![
+            serde_json::json!({ "name": "tokenURI", "type": "string" }),
+            serde_json::json!({ "name": "creators", "type": "Part[]" }),
+            serde_json::json!({ "name": "royalties", "type": "Part[]" }),
+        ]);
+        let name = if erc1155 { "Mint1155" } else { "Mint721" };
+        let types = serde_json::json!({
+            name: members,
+            "Part": [
+                { "name": "account", "type": "address" },
+                { "name": "value", "type": "uint96" }
+            ]
+        });
+        let data = serde_json::json!({
+            "tokenId": nft.token_id,
+            "supply": nft.supply,
+            "tokenURI": nft.uri,
+            "creators": nft.creators,
+            "royalties": nft.royalties,
+        });
+        let struct_hash = eth::hash_typed_struct(name, &types, &data).unwrap();
+        eth::eip712_digest(nft.domain(1).unwrap().separator().unwrap(), struct_hash)
+    }
+
+    #[test]
+    fn hashes_lazy_mints() {
+        let erc721 = lazy_nft("ERC721", "1");
+        assert_eq!(
+            erc721.eip712_hash(1).unwrap(),
+            hash("0x06fe3847d8e599867dcf306e81d6cb7b5a0c0380048cf629e8b1289dc852d807")
+        );
+        assert_eq!(erc721.eip712_hash(1).unwrap(), typed_mint_hash(&erc721));
+
+        let erc1155 = lazy_nft("ERC1155", "10");
+        assert_eq!(
+            erc1155.eip712_hash(1).unwrap(),
+            hash("0xd3226990453f77553fca940931b926287d016be9fdfb33ff610824a1bed60dbb")
+        );
+        assert_eq!(erc1155.eip712_hash(1).unwrap(), typed_mint_hash(&erc1155));
+
+        assert!(lazy_nft("ERC20", "1").eip712_hash(1).is_err());
+    }
+
+    #[cfg(feature = "signer")]
+    #[test]
+    fn verifies_the_signature_of_an_order() {
+        let domain = Eip712Domain::exchange_v2_mainnet();
+        let order: Order = serde_json::from_str(ORDER).unwrap();
+        order.verify_signature(&domain).unwrap();
+
+        let rinkeby = Eip712Domain::exchange_v2(4, EXCHANGE_V2_MAINNET);
+        assert!(order.verify_signature(&rinkeby).is_err());
+        let tampered = Order {
+            salt: "0x01".to_string(),
+            ..order.clone()
+        };
+        assert!(tampered.verify_signature(&domain).is_err());
+        let unsigned = Order {
+            signature: None,
+            ..order
+        };
+        assert!(unsigned.verify_signature(&domain).is_err());
+    }
+
+    #[cfg(feature = "signer")]
+    #[test]
+    fn signs_orders_of_the_maker_only() {
+        let domain = Eip712Domain::exchange_v2_mainnet();
+        let order: Order = serde_json::from_str(ORDER).unwrap();
+        let mut form = OrderForm {
+            _type: order._type,
+            maker: order.maker.clone(),
+            taker: order.taker.clone(),
+            make: order.make.clone(),
+            take: order.take.clone(),
+            salt: order.salt.clone(),
+            start: order.start,
+            end: order.end,
+            data: order.data.clone(),
+            signature: None,
+        };
+        let wallet = Wallet::from_private_key(KEY).unwrap();
+        form.sign(&wallet, &domain).unwrap();
+        // signatures are deterministic
+        assert_eq!(form.signature, order.signature);
+
+        let other = Wallet::from_private_key(eth::encode_hex(eth::keccak256("cow"))).unwrap();
+        assert!(form.sign(&other, &domain).is_err());
+    }
+
+    #[cfg(feature = "signer")]
+    #[test]
+    fn signs_and_verifies_lazy_mints() {
+        let wallet = Wallet::from_private_key(KEY).unwrap();
+        for (ty, supply) in [("ERC721", "1"), ("ERC1155", "10")] {
+            let mut nft = lazy_nft(ty, supply);
+            assert!(nft.verify_signatures(1).is_err());
+            nft.sign(&wallet, 1).unwrap();
+            nft.verify_signatures(1).unwrap();
+            assert!(nft.verify_signatures(4).is_err());
+
+            let other = Wallet::from_private_key(eth::encode_hex(eth::keccak256("cow"))).unwrap();
+            assert!(nft.sign(&other, 1).is_err());
+        }
+    }
+}
@@ -1,13 +1,21 @@
+pub mod eip712;
 pub mod models;
+pub mod order;
+#[cfg(feature = "stream")]
+pub mod stream;
+pub mod tx;
 
 use crate::rarible::models::*;
 use crate::ApiClient;
 
 use crate::error::RaribleApiError;
+use crate::eth::H256;
 use reqwest::IntoUrl;
 use serde::de::DeserializeOwned;
 use serde::Serialize;
 
+pub const API_BASE_MAINNET: &str = "https://api.rarible.com";
+
 pub fn urlencode<T: AsRef<str>>(s: T) -> String {
     ::url::form_urlencoded::byte_serialize(s.as_ref().as_bytes()).collect()
 }
@@ -815,7 +823,7 @@ impl ApiClient {
         Self::request_json_rarible(self.client.get(url)).await
     }
 
-    #[warn(clippy::too_many_arguments)]
+    #[allow(clippy::too_many_arguments)]
     pub async fn get_bids_by_item(
         &self,
         contract: &str,
@@ -1120,6 +1128,18 @@ impl ApiClient {
         .await
     }
 
+    /// Encodes the order like [`ApiClient::encode_order`] but also checks that
+    /// the message the server asks to sign matches the locally computed
+    /// EIP-712 digest, which is returned.
+    pub async fn encode_order_verified(
+        &self,
+        order_form: OrderForm,
+    ) -> anyhow::Result<(EncodedOrder, H256)> {
+        let encoded = self.encode_order(order_form.clone()).await?;
+        let digest = encoded.verify(&order_form)?;
+        Ok((encoded, digest))
+    }
+
     pub async fn encode_order_asset_type(
         &self,
         asset_type: AssetType,
@@ -185,8 +185,14 @@ pub struct Asset {
 }
 
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
-#[serde(tag = "assetclass")]
+#[serde(tag = "assetClass")]
 pub enum AssetType {
+    /// Any token of the collection, used by collection offers
+    #[serde(rename = "COLLECTION")]
+    Collection {
+        #[serde(rename = "contract")]
+        contract: String,
+    },
     #[serde(rename = "ERC1155")]
     Erc1155 {
         #[serde(rename = "contract")]
@@ -1619,6 +1625,7 @@ pub enum OrderBidStatus {
     #[serde(rename = "CANCELLED")]
     Cancelled,
 }
+#[allow(clippy::to_string_trait_impl)]
 impl ToString for OrderBidStatus {
     fn to_string(&self) -> String {
         match self {
@@ -1648,7 +1655,7 @@ pub struct OrderCancel {
 }
 
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
-#[serde(tag = "datatype")]
+#[serde(tag = "dataType")]
 pub enum OrderData {
     #[serde(rename = "LEGACY")]
     OrderDataLegacy {
@@ -1840,6 +1847,7 @@ pub enum OrderSide {
     #[serde(rename = "RIGHT")]
     Right,
 }
+#[allow(clippy::to_string_trait_impl)]
 impl ToString for OrderSide {
     fn to_string(&self) -> String {
         match self {
@@ -1878,6 +1886,7 @@ pub enum OrderType {
     #[serde(rename = "RARIBLE_V2")]
     V2,
 }
+#[allow(clippy::to_string_trait_impl)]
 impl ToString for OrderType {
     fn to_string(&self) -> String {
         match self {
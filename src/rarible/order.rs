@@ -0,0 +1,470 @@
+//! Creation of Rarible exchange v2 orders.
+//!
+//! An [`OrderFormBuilder`] fills in an [`OrderForm`] for a sell order, a bid
+//! on a token or an offer on any token of a collection: the make and take
+//! assets, a random salt, the validity period and the `RARIBLE_V2_DATA_V1`
+//! data with payouts and origin fees. Since the exchange accepts any order
+//! it can hash, mistakes like a bid paid in ETH or fees above 100% are
+//! rejected by [`OrderFormBuilder::build`] instead of producing an order
+//! that can never be filled.
+//!
+//! Creator royalties are not part of the order. The exchange looks them up
+//! in its royalties registry when the order is matched and pays them out of
+//! the seller's proceeds. An extra cut that the creator should get on top of
+//! that goes in as an origin fee with [`OrderFormBuilder::with_origin_fee`],
+//! and a share of the proceeds that a co-owner should get goes in as a payout
+//! with [`OrderFormBuilder::with_payout`].
+
+use std::convert::TryFrom;
+use std::time::Duration;
+
+use chrono::Utc;
+
+use crate::eth::{self, U256};
+use crate::market::models::WETH_MAINNET;
+use crate::rarible::models::{Asset, AssetType, OrderData, OrderForm, OrderType, Part};
+
+/// Payouts and origin fees are denominated in basis points
+const FULL_BPS: u64 = 10_000;
+
+impl AssetType {
+    pub fn erc721(contract: impl Into<String>, token_id: impl Into<String>) -> Self {
+        AssetType::Erc721 {
+            contract: contract.into(),
+            token_id: token_id.into(),
+        }
+    }
+
+    pub fn erc1155(contract: impl Into<String>, token_id: impl Into<String>) -> Self {
+        AssetType::Erc1155 {
+            contract: contract.into(),
+            token_id: token_id.into(),
+        }
+    }
+
+    pub fn erc20(contract: impl Into<String>) -> Self {
+        AssetType::Erc20 {
+            contract: contract.into(),
+        }
+    }
+
+    /// Any token of the collection
+    pub fn collection(contract: impl Into<String>) -> Self {
+        AssetType::Collection {
+            contract: contract.into(),
+        }
+    }
+
+    /// Whether this is a token or a collection, as opposed to a currency
+    pub fn is_nft(&self) -> bool {
+        matches!(
+            self,
+            AssetType::Erc721 { .. }
+                | AssetType::Erc1155 { .. }
+                | AssetType::Erc721Lazy { .. }
+                | AssetType::Erc1155Lazy { .. }
+                | AssetType::Collection { .. }
+        )
+    }
+
+    /// The contract of the asset, `None` for ETH
+    fn contract(&self) -> Option<&str> {
+        match self {
+            AssetType::Collection { contract }
+            | AssetType::Erc20 { contract }
+            | AssetType::Erc721 { contract, .. }
+            | AssetType::Erc1155 { contract, .. }
+            | AssetType::Erc721Lazy { contract, .. }
+            | AssetType::Erc1155Lazy { contract, .. } => Some(contract),
+            AssetType::Eth | AssetType::Flow => None,
+        }
+    }
+}
+
+impl OrderForm {
+    /// Sells the token, see [`OrderFormBuilder`]
+    pub fn sell(nft: AssetType) -> OrderFormBuilder {
+        OrderFormBuilder::new(Side::Sell, nft)
+    }
+
+    /// Bids on the token, see [`OrderFormBuilder`]
+    pub fn bid(nft: AssetType) -> OrderFormBuilder {
+        OrderFormBuilder::new(Side::Bid, nft)
+    }
+
+    /// Bids on any token of the collection, see [`OrderFormBuilder`]
+    pub fn collection_offer(contract: impl Into<String>) -> OrderFormBuilder {
+        OrderFormBuilder::new(Side::Bid, AssetType::collection(contract))
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Side {
+    Sell,
+    Bid,
+}
+
+/// Builds an [`OrderForm`] of a single nft.
+///
+/// The price covers all of `quantity` and is in the smallest unit of the
+/// currency. Orders start now and never expire unless set otherwise. Bids
+/// must be paid in an ERC20 token, since the exchange can only take ETH
+/// from the taker.
+#[derive(Clone, Debug)]
+pub struct OrderFormBuilder {
+    side: Side,
+    nft: AssetType,
+    quantity: U256,
+    currency: Option<AssetType>,
+    price: U256,
+    maker: Option<String>,
+    taker: Option<String>,
+    salt: Option<U256>,
+    start: Option<i64>,
+    end: Option<i64>,
+    expires_in: Option<Duration>,
+    payouts: Vec<(String, u64)>,
+    origin_fees: Vec<(String, u64)>,
+}
+
+impl OrderFormBuilder {
+    fn new(side: Side, nft: AssetType) -> Self {
+        Self {
+            side,
+            nft,
+            quantity: U256::one(),
+            currency: None,
+            price: U256::zero(),
+            maker: None,
+            taker: None,
+            salt: None,
+            start: None,
+            end: None,
+            expires_in: None,
+            payouts: Vec::new(),
+            origin_fees: Vec::new(),
+        }
+    }
+
+    pub fn maker<T: Into<String>>(mut self, value: T) -> Self {
+        self.maker = Some(value.into());
+        self
+    }
+
+    /// Restricts the order to a single counterparty
+    pub fn taker<T: Into<String>>(mut self, value: T) -> Self {
+        self.taker = Some(value.into());
+        self
+    }
+
+    /// Number of tokens, only ERC1155 tokens can be traded more than once
+    pub fn quantity<T: Into<U256>>(mut self, value: T) -> Self {
+        self.quantity = value.into();
+        self
+    }
+
+    /// The price in wei
+    pub fn for_eth<T: Into<U256>>(self, price: T) -> Self {
+        self.for_currency(AssetType::Eth, price)
+    }
+
+    /// The price in wei of WETH
+    pub fn for_weth<T: Into<U256>>(self, price: T) -> Self {
+        self.for_erc20(WETH_MAINNET, price)
+    }
+
+    /// The price in the smallest unit of the ERC20 token
+    pub fn for_erc20<T: Into<U256>>(self, contract: impl Into<String>, price: T) -> Self {
+        self.for_currency(AssetType::erc20(contract), price)
+    }
+
+    pub fn for_currency<T: Into<U256>>(mut self, currency: AssetType, price: T) -> Self {
+        self.currency = Some(currency);
+        self.price = price.into();
+        self
+    }
+
+    /// Pays `bps` of the proceeds to `account`. Without payouts the maker
+    /// receives everything, otherwise the payouts must add up to 100%.
+    /// Payouts split what is left after royalties and origin fees.
+    pub fn with_payout<T: Into<u64>>(mut self, account: impl Into<String>, bps: T) -> Self {
+        self.payouts.push((account.into(), bps.into()));
+        self
+    }
+
+    /// Charges a fee of `bps` of the price for `account`, e.g. a
+    /// marketplace's or referrer's fee. Royalties are charged by the exchange
+    /// itself and must not be added here.
+    pub fn with_origin_fee<T: Into<u64>>(mut self, account: impl Into<String>, bps: T) -> Self {
+        self.origin_fees.push((account.into(), bps.into()));
+        self
+    }
+
+    /// Defaults to a random salt
+    pub fn salt<T: Into<U256>>(mut self, value: T) -> Self {
+        self.salt = Some(value.into());
+        self
+    }
+
+    /// When the order becomes valid in unix seconds, defaults to now
+    pub fn start<T: Into<i64>>(mut self, value: T) -> Self {
+        self.start = Some(value.into());
+        self
+    }
+
+    /// When the order expires in unix seconds
+    pub fn end<T: Into<i64>>(mut self, value: T) -> Self {
+        self.end = Some(value.into());
+        self.expires_in = None;
+        self
+    }
+
+    /// Expires the order `duration` after its start
+    pub fn expires_in(mut self, duration: Duration) -> Self {
+        self.expires_in = Some(duration);
+        self.end = None;
+        self
+    }
+
+    /// Validates the parameters and creates the unsigned order
+    pub fn build(&self) -> anyhow::Result<OrderForm> {
+        let maker = self
+            .maker
+            .as_deref()
+            .ok_or_else(|| anyhow::anyhow!("order without maker"))?;
+        let maker = format!("{:?}", eth::parse_address(maker)?);
+        let taker = match &self.taker {
+            Some(taker) => Some(format!("{:?}", eth::parse_address(taker)?)),
+            None => None,
+        };
+
+        anyhow::ensure!(
+            self.nft.is_nft(),
+            "{} is not an nft",
+            self.nft.asset_class_name()
+        );
+        if let Some(contract) = self.nft.contract() {
+            eth::parse_address(contract)?;
+        }
+        anyhow::ensure!(!self.quantity.is_zero(), "quantity must not be zero");
+        if let AssetType::Erc721 { .. } | AssetType::Erc721Lazy { .. } = self.nft {
+            anyhow::ensure!(
+                self.quantity == U256::one(),
+                "ERC721 tokens can't be traded {} times",
+                self.quantity
+            );
+        }
+        if let AssetType::Collection { .. } = self.nft {
+            anyhow::ensure!(self.side == Side::Bid, "collections can only be bid on");
+        }
+
+        let currency = self
+            .currency
+            .clone()
+            .ok_or_else(|| anyhow::anyhow!("order without price"))?;
+        match &currency {
+            AssetType::Eth => anyhow::ensure!(
+                self.side == Side::Sell,
+                "bids must be paid in an ERC20 token"
+            ),
+            AssetType::Erc20 { contract } => {
+                eth::parse_address(contract)?;
+            }
+            other => anyhow::bail!("{} is not a currency", other.asset_class_name()),
+        }
+        anyhow::ensure!(!self.price.is_zero(), "price must not be zero");
+
+        let payouts = parts(&self.payouts)?;
+        if !payouts.is_empty() {
+            let total = self.payouts.iter().map(|(_, bps)| bps).sum::<u64>();
+            anyhow::ensure!(
+                total == FULL_BPS,
+                "payouts add up to {} basis points instead of {}",
+                total,
+                FULL_BPS
+            );
+        }
+        let origin_fees = parts(&self.origin_fees)?;
+        let total_fees = self.origin_fees.iter().map(|(_, bps)| bps).sum::<u64>();
+        anyhow::ensure!(
+            total_fees < FULL_BPS,
+            "origin fees of {} basis points exceed the price",
+            total_fees
+        );
+
+        let start = self.start.unwrap_or_else(|| Utc::now().timestamp());
+        let end = match self.expires_in {
+            Some(duration) => {
+                let end = i64::try_from(duration.as_secs())
+                    .ok()
+                    .and_then(|secs| start.checked_add(secs))
+                    .ok_or_else(|| {
+                        anyhow::anyhow!("order expires {:?} after {}", duration, start)
+                    })?;
+                Some(end)
+            }
+            None => self.end,
+        };
+        if let Some(end) = end {
+            anyhow::ensure!(
+                end > start,
+                "order ends at {}, before it starts at {}",
+                end,
+                start
+            );
+        }
+
+        let nft = Asset {
+            asset_type: Box::new(self.nft.clone()),
+            value: self.quantity.to_string(),
+        };
+        let payment = Asset {
+            asset_type: Box::new(currency),
+            value: self.price.to_string(),
+        };
+        let (make, take) = match self.side {
+            Side::Sell => (nft, payment),
+            Side::Bid => (payment, nft),
+        };
+        let mut salt = [0u8; 32];
+        self.salt
+            .unwrap_or_else(eth::random_salt)
+            .to_big_endian(&mut salt);
+        Ok(OrderForm {
+            _type: OrderType::V2,
+            maker,
+            taker,
+            make: Box::new(make),
+            take: Box::new(take),
+            salt: eth::encode_hex(salt),
+            start: Some(start),
+            end,
+            data: Box::new(OrderData::OrderRaribleV2DataV1 {
+                payouts,
+                origin_fees,
+            }),
+            signature: None,
+        })
+    }
+}
+
+/// Validates the parts, which must have a positive share
+fn parts(parts: &[(String, u64)]) -> anyhow::Result<Vec<Part>> {
+    parts
+        .iter()
+        .map(|(account, bps)| {
+            anyhow::ensure!(
+                *bps > 0 && *bps <= FULL_BPS,
+                "{} basis points of {} are out of range",
+                bps,
+                account
+            );
+            Ok(Part {
+                account: format!("{:?}", eth::parse_address(account)?),
+                value: *bps as i32,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rarible::models::Eip712Domain;
+
+    const TOKEN: &str = "0x60f80121c31a0d46b5279700f9df786054aa5ee5";
+    const MAKER: &str = "0x2c7536e3605d9c16a7a3d7b1898e529396a65c23";
+    const FEE_RECIPIENT: &str = "0x1cf0df2a5a20cd61d68d4489eebbf85b8d39e18a";
+    const CO_OWNER: &str = "0x0000000000000000000000000000000000000002";
+
+    /// The digest of `tests/fixtures/rarible-order.json` for the mainnet
+    /// exchange
+    const ORDER_DIGEST: &str = "0x383ffde06d7c6a5939dc40bf8461441b55157a58268260a1b2662efafe102736";
+
+    fn sell() -> OrderFormBuilder {
+        OrderForm::sell(AssetType::erc721(TOKEN, "1012"))
+            .maker(MAKER)
+            .for_eth(U256::exp10(18))
+            .start(1_629_236_424)
+    }
+
+    fn error(builder: OrderFormBuilder) -> String {
+        builder.build().unwrap_err().to_string()
+    }
+
+    #[test]
+    fn builds_the_fixture_order() {
+        let form = sell()
+            .salt(0x1234567u64)
+            .end(1_631_914_824)
+            .with_origin_fee(FEE_RECIPIENT, 250u64)
+            .build()
+            .unwrap();
+        assert_eq!(
+            form.salt,
+            "0x0000000000000000000000000000000000000000000000000000000001234567"
+        );
+        let digest = form
+            .eip712_hash(&Eip712Domain::exchange_v2_mainnet())
+            .unwrap();
+        assert_eq!(eth::encode_hex(digest), ORDER_DIGEST);
+    }
+
+    #[test]
+    fn builds_bids_with_the_payment_as_make() {
+        let form = OrderForm::collection_offer(TOKEN)
+            .maker(MAKER)
+            .for_weth(1000u64)
+            .start(1_629_236_424)
+            .expires_in(Duration::from_secs(60))
+            .with_payout(MAKER, 6000u64)
+            .with_payout(CO_OWNER, 4000u64)
+            .build()
+            .unwrap();
+        assert_eq!(*form.make.asset_type, AssetType::erc20(WETH_MAINNET));
+        assert_eq!(form.make.value, "1000");
+        assert_eq!(*form.take.asset_type, AssetType::collection(TOKEN));
+        assert_eq!(form.end, Some(1_629_236_484));
+    }
+
+    #[test]
+    fn rejects_unfillable_orders() {
+        let bid = OrderForm::bid(AssetType::erc721(TOKEN, "1012"))
+            .maker(MAKER)
+            .for_eth(1000u64);
+        assert_eq!(error(bid), "bids must be paid in an ERC20 token");
+        assert_eq!(
+            error(sell().quantity(2u64)),
+            "ERC721 tokens can't be traded 2 times"
+        );
+        assert_eq!(
+            error(
+                sell()
+                    .with_payout(MAKER, 5000u64)
+                    .with_payout(CO_OWNER, 4000u64)
+            ),
+            "payouts add up to 9000 basis points instead of 10000"
+        );
+        assert_eq!(
+            error(
+                sell()
+                    .with_origin_fee(FEE_RECIPIENT, 5000u64)
+                    .with_origin_fee(CO_OWNER, 5000u64)
+            ),
+            "origin fees of 10000 basis points exceed the price"
+        );
+        assert!(
+            error(sell().expires_in(Duration::from_secs(u64::MAX))).starts_with("order expires")
+        );
+        assert!(error(
+            sell()
+                .start(i64::MAX - 10)
+                .expires_in(Duration::from_secs(60))
+        )
+        .starts_with("order expires"));
+        let collection_sell = OrderForm::sell(AssetType::collection(TOKEN))
+            .maker(MAKER)
+            .for_eth(1000u64);
+        assert_eq!(error(collection_sell), "collections can only be bid on");
+    }
+}
@@ -0,0 +1,598 @@
+//! Real-time events of the Rarible protocol over a websocket.
+//!
+//! Rarible doesn't document a public websocket API, so there is no default
+//! endpoint: the wire format below is the one of a self-hosted relay that
+//! forwards the events of the protocol indexer, and the url of that relay
+//! has to be passed to [`EventSubscriber::new`]. The payloads are the models
+//! of the Rarible API.
+//!
+//! After connecting, the client subscribes to the topics it's interested in:
+//!
+//! ```json
+//! {"type":"SUBSCRIBE","topics":["ITEM","ORDER"],"collections":["0x..."],"owners":[],"lastEventId":"..."}
+//! ```
+//!
+//! and the server pushes one message per event, `event` is the model of the
+//! topic, e.g. [`NftItemEvent`] for `ITEM`:
+//!
+//! ```json
+//! {"topic":"ITEM","eventId":"...","event":{"type":"UPDATE","item":{...}}}
+//! ```
+//!
+//! If the connection drops, the stream reconnects with an exponential
+//! backoff and resubscribes with the id of the last event it received, so
+//! the server can replay the events that were missed in the meantime.
+//! The collection and owner filters are also applied locally, in case the
+//! server ignores them.
+
+use std::fmt;
+use std::time::Duration;
+
+use futures::stream::{self, BoxStream};
+use futures::{SinkExt, StreamExt};
+use log::{debug, warn};
+use reqwest::{IntoUrl, Url};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::net::TcpStream;
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::{MaybeTlsStream, WebSocketStream};
+
+use crate::market::models::rarible_nft;
+use crate::rarible::models::*;
+
+/// The kinds of events that can be subscribed to
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub enum Topic {
+    #[serde(rename = "ITEM")]
+    Item,
+    #[serde(rename = "OWNERSHIP")]
+    Ownership,
+    #[serde(rename = "ORDER_ITEM")]
+    OrderItem,
+    #[serde(rename = "ORDER")]
+    Order,
+    #[serde(rename = "ERC20_BALANCE")]
+    Erc20Balance,
+    #[serde(rename = "UNLOCKABLE")]
+    Unlockable,
+}
+
+impl fmt::Display for Topic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Topic::Item => f.write_str("ITEM"),
+            Topic::Ownership => f.write_str("OWNERSHIP"),
+            Topic::OrderItem => f.write_str("ORDER_ITEM"),
+            Topic::Order => f.write_str("ORDER"),
+            Topic::Erc20Balance => f.write_str("ERC20_BALANCE"),
+            Topic::Unlockable => f.write_str("UNLOCKABLE"),
+        }
+    }
+}
+
+/// An event pushed by the server
+#[derive(Clone, Debug, PartialEq)]
+pub enum RaribleEvent {
+    Item(NftItemEvent),
+    Ownership(NftOwnershipEvent),
+    OrderItem(NftOrderItemEvent),
+    Order(OrderEvent),
+    Erc20Balance(Erc20BalanceEvent),
+    Unlockable(UnlockableEvent),
+}
+
+impl RaribleEvent {
+    /// Deserializes the `event` of a message with `topic`
+    pub fn from_value(topic: Topic, event: Value) -> anyhow::Result<Self> {
+        let event = match topic {
+            Topic::Item => RaribleEvent::Item(serde_json::from_value(event)?),
+            Topic::Ownership => RaribleEvent::Ownership(serde_json::from_value(event)?),
+            Topic::OrderItem => RaribleEvent::OrderItem(serde_json::from_value(event)?),
+            Topic::Order => RaribleEvent::Order(serde_json::from_value(event)?),
+            Topic::Erc20Balance => RaribleEvent::Erc20Balance(serde_json::from_value(event)?),
+            Topic::Unlockable => RaribleEvent::Unlockable(serde_json::from_value(event)?),
+        };
+        Ok(event)
+    }
+
+    pub fn topic(&self) -> Topic {
+        match self {
+            RaribleEvent::Item(_) => Topic::Item,
+            RaribleEvent::Ownership(_) => Topic::Ownership,
+            RaribleEvent::OrderItem(_) => Topic::OrderItem,
+            RaribleEvent::Order(_) => Topic::Order,
+            RaribleEvent::Erc20Balance(_) => Topic::Erc20Balance,
+            RaribleEvent::Unlockable(_) => Topic::Unlockable,
+        }
+    }
+
+    /// The NFT contracts the event concerns, lowercase.
+    ///
+    /// Empty for balance events and orders that don't trade an NFT.
+    pub fn collections(&self) -> Vec<String> {
+        let collections = match self {
+            RaribleEvent::Item(NftItemEvent::NftItemDeleteEvent { item }) => {
+                vec![item.token.clone()]
+            }
+            RaribleEvent::Item(NftItemEvent::NftItemUpdateEvent { item }) => {
+                vec![item.contract.clone()]
+            }
+            RaribleEvent::Ownership(NftOwnershipEvent::NftOwnershipDeleteEvent { ownership }) => {
+                vec![ownership.token.clone()]
+            }
+            RaribleEvent::Ownership(NftOwnershipEvent::NftOwnershipUpdateEvent { ownership }) => {
+                vec![ownership.contract.clone()]
+            }
+            RaribleEvent::OrderItem(NftOrderItemEvent::NftOrderItemDeleteEvent { item }) => {
+                vec![item.token.clone()]
+            }
+            RaribleEvent::OrderItem(NftOrderItemEvent::NftOrderItemUpdateEvent { item }) => {
+                vec![item.contract.clone()]
+            }
+            RaribleEvent::Order(OrderEvent::OrderUpdateEvent { order }) => {
+                [&order.make, &order.take]
+                    .iter()
+                    .filter_map(|asset| rarible_nft(&asset.asset_type))
+                    .map(|nft| nft.contract)
+                    .collect()
+            }
+            RaribleEvent::Erc20Balance(_) => Vec::new(),
+            RaribleEvent::Unlockable(event) => event
+                .item_id
+                .split(':')
+                .next()
+                .map(|contract| vec![contract.to_string()])
+                .unwrap_or_default(),
+        };
+        collections
+            .into_iter()
+            .map(|contract| contract.to_lowercase())
+            .collect()
+    }
+
+    /// The accounts the event concerns, lowercase: the owners of items and
+    /// ownerships, the maker and taker of orders and the holder of balances
+    pub fn owners(&self) -> Vec<String> {
+        let owners = match self {
+            RaribleEvent::Item(NftItemEvent::NftItemUpdateEvent { item }) => item.owners.clone(),
+            RaribleEvent::Ownership(NftOwnershipEvent::NftOwnershipDeleteEvent { ownership }) => {
+                vec![ownership.owner.clone()]
+            }
+            RaribleEvent::Ownership(NftOwnershipEvent::NftOwnershipUpdateEvent { ownership }) => {
+                vec![ownership.owner.clone()]
+            }
+            RaribleEvent::OrderItem(NftOrderItemEvent::NftOrderItemUpdateEvent { item }) => {
+                item.owners.clone()
+            }
+            RaribleEvent::Order(OrderEvent::OrderUpdateEvent { order }) => {
+                std::iter::once(&order.maker)
+                    .chain(order.taker.as_ref())
+                    .cloned()
+                    .collect()
+            }
+            RaribleEvent::Erc20Balance(Erc20BalanceEvent::Erc20BalanceUpdateEvent { balance }) => {
+                vec![balance.owner.clone()]
+            }
+            _ => Vec::new(),
+        };
+        owners
+            .into_iter()
+            .map(|owner| owner.to_lowercase())
+            .collect()
+    }
+}
+
+/// A message pushed by the server
+#[derive(Clone, Debug, PartialEq)]
+pub struct EventMessage {
+    /// Id to resume the subscription after this event
+    pub event_id: Option<String>,
+    pub event: RaribleEvent,
+}
+
+#[derive(Deserialize)]
+struct RawMessage {
+    topic: Topic,
+    #[serde(rename = "eventId", default)]
+    event_id: Option<String>,
+    event: Value,
+}
+
+/// The events to subscribe to, all events if nothing is set.
+///
+/// An event matches if it's of one of the `topics` and concerns any of the
+/// `collections` and any of the `owners`. Events without collection, like
+/// balance events, never match a collection filter.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct Subscription {
+    pub topics: Vec<Topic>,
+    /// NFT contracts, lowercase
+    pub collections: Vec<String>,
+    /// Accounts, lowercase
+    pub owners: Vec<String>,
+}
+
+impl Subscription {
+    pub fn topic<T: Into<Topic>>(mut self, value: T) -> Self {
+        self.topics.push(value.into());
+        self
+    }
+
+    pub fn collection<T: AsRef<str>>(mut self, value: T) -> Self {
+        self.collections.push(value.as_ref().to_lowercase());
+        self
+    }
+
+    pub fn owner<T: AsRef<str>>(mut self, value: T) -> Self {
+        self.owners.push(value.as_ref().to_lowercase());
+        self
+    }
+
+    /// Whether the event passes the filters of the subscription
+    pub fn matches(&self, event: &RaribleEvent) -> bool {
+        if !self.topics.is_empty() && !self.topics.contains(&event.topic()) {
+            return false;
+        }
+        if !self.collections.is_empty()
+            && !event
+                .collections()
+                .iter()
+                .any(|contract| self.collections.contains(contract))
+        {
+            return false;
+        }
+        self.owners.is_empty()
+            || event
+                .owners()
+                .iter()
+                .any(|owner| self.owners.contains(owner))
+    }
+}
+
+#[derive(Serialize)]
+struct SubscribeRequest<'a> {
+    #[serde(rename = "type")]
+    _type: &'static str,
+    #[serde(flatten)]
+    subscription: &'a Subscription,
+    #[serde(rename = "lastEventId", skip_serializing_if = "Option::is_none")]
+    last_event_id: Option<&'a str>,
+}
+
+/// Subscribes to the events of a relay of Rarible events
+#[derive(Clone, Debug)]
+pub struct EventSubscriber {
+    url: Url,
+    subscription: Subscription,
+    /// Id of the event to resume after
+    last_event_id: Option<String>,
+    /// Delay before the first reconnect, doubled on every failed attempt
+    min_backoff: Duration,
+    max_backoff: Duration,
+    /// Give up after this many reconnects in a row without receiving a
+    /// message, `None` to retry forever
+    max_retries: Option<u32>,
+}
+
+impl EventSubscriber {
+    pub fn new(url: impl IntoUrl) -> anyhow::Result<Self> {
+        let url = url.into_url()?;
+        anyhow::ensure!(
+            url.scheme() == "ws" || url.scheme() == "wss",
+            "`{}` is not a websocket url",
+            url
+        );
+        Ok(Self {
+            url,
+            subscription: Subscription::default(),
+            last_event_id: None,
+            min_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(60),
+            max_retries: None,
+        })
+    }
+
+    pub fn url(&self) -> &Url {
+        &self.url
+    }
+
+    pub fn subscription<T: Into<Subscription>>(mut self, value: T) -> Self {
+        self.subscription = value.into();
+        self
+    }
+
+    /// Resumes after the event with this id instead of starting with the
+    /// latest events
+    pub fn resume_from<T: Into<String>>(mut self, value: T) -> Self {
+        self.last_event_id = Some(value.into());
+        self
+    }
+
+    pub fn backoff(mut self, min: Duration, max: Duration) -> Self {
+        self.min_backoff = min;
+        self.max_backoff = max.max(min);
+        self
+    }
+
+    pub fn max_retries<T: Into<u32>>(mut self, value: T) -> Self {
+        self.max_retries = Some(value.into());
+        self
+    }
+
+    /// Subscribes and yields the events, reconnecting when the connection
+    /// drops.
+    ///
+    /// Messages that can't be decoded are yielded as errors, the stream only
+    /// ends once `max_retries` reconnects failed.
+    pub fn subscribe(self) -> BoxStream<'static, anyhow::Result<RaribleEvent>> {
+        self.messages().map(|msg| msg.map(|msg| msg.event)).boxed()
+    }
+
+    /// Like [`EventSubscriber::subscribe`], but also yields the event ids to
+    /// persist for [`EventSubscriber::resume_from`]
+    pub fn messages(self) -> BoxStream<'static, anyhow::Result<EventMessage>> {
+        let conn = Connection {
+            subscriber: self,
+            socket: None,
+            retries: 0,
+            done: false,
+        };
+        stream::unfold(conn, |mut conn| async move {
+            let msg = conn.next().await?;
+            Some((msg, conn))
+        })
+        .boxed()
+    }
+}
+
+/// The state of a subscription
+struct Connection {
+    subscriber: EventSubscriber,
+    socket: Option<WebSocketStream<MaybeTlsStream<TcpStream>>>,
+    /// Reconnects since the last message was received
+    retries: u32,
+    done: bool,
+}
+
+impl Connection {
+    async fn connect(&mut self) -> anyhow::Result<WebSocketStream<MaybeTlsStream<TcpStream>>> {
+        let subscriber = &self.subscriber;
+        debug!("Connecting to {}", subscriber.url);
+        let (mut socket, _) = tokio_tungstenite::connect_async(subscriber.url.as_str()).await?;
+        let request = serde_json::to_string(&SubscribeRequest {
+            _type: "SUBSCRIBE",
+            subscription: &subscriber.subscription,
+            last_event_id: subscriber.last_event_id.as_deref(),
+        })?;
+        debug!("Subscribing: {}", request);
+        socket.send(Message::Text(request)).await?;
+        Ok(socket)
+    }
+
+    /// Waits before the next reconnect, fails if there are no retries left
+    async fn backoff(&mut self, err: anyhow::Error) -> anyhow::Result<()> {
+        let subscriber = &self.subscriber;
+        if let Some(max_retries) = subscriber.max_retries {
+            if self.retries >= max_retries {
+                return Err(err.context(format!("gave up after {} retries", self.retries)));
+            }
+        }
+        let delay = subscriber
+            .min_backoff
+            .checked_mul(1 << self.retries.min(16))
+            .unwrap_or(subscriber.max_backoff)
+            .min(subscriber.max_backoff);
+        warn!("{}, reconnecting in {:?}", err, delay);
+        self.retries += 1;
+        tokio::time::sleep(delay).await;
+        Ok(())
+    }
+
+    /// Decodes a text message, `None` if it doesn't match the subscription
+    fn decode(&mut self, text: &str) -> anyhow::Result<Option<EventMessage>> {
+        let msg: RawMessage = serde_json::from_str(text)?;
+        if msg.event_id.is_some() {
+            self.subscriber.last_event_id = msg.event_id.clone();
+        }
+        let event = RaribleEvent::from_value(msg.topic, msg.event)?;
+        if !self.subscriber.subscription.matches(&event) {
+            return Ok(None);
+        }
+        Ok(Some(EventMessage {
+            event_id: msg.event_id,
+            event,
+        }))
+    }
+
+    async fn next(&mut self) -> Option<anyhow::Result<EventMessage>> {
+        while !self.done {
+            let socket = match self.socket.as_mut() {
+                Some(socket) => socket,
+                None => {
+                    match self.connect().await {
+                        Ok(socket) => self.socket = Some(socket),
+                        Err(err) => {
+                            if let Err(err) = self.backoff(err).await {
+                                self.done = true;
+                                return Some(Err(err));
+                            }
+                        }
+                    }
+                    continue;
+                }
+            };
+            let err = match socket.next().await {
+                Some(Ok(Message::Text(text))) => {
+                    self.retries = 0;
+                    match self.decode(&text) {
+                        Ok(Some(msg)) => return Some(Ok(msg)),
+                        Ok(None) => continue,
+                        Err(err) => return Some(Err(err)),
+                    }
+                }
+                Some(Ok(Message::Close(frame))) => {
+                    anyhow::anyhow!("connection closed by server: {:?}", frame)
+                }
+                Some(Ok(_)) => continue,
+                Some(Err(err)) => err.into(),
+                None => anyhow::anyhow!("connection closed"),
+            };
+            self.socket = None;
+            if let Err(err) = self.backoff(err).await {
+                self.done = true;
+                return Some(Err(err));
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use tokio::net::TcpListener;
+    use tokio::sync::mpsc;
+
+    const COLLECTION: &str = "0x00000000000000000000000000000000000000aa";
+    const OTHER_COLLECTION: &str = "0x00000000000000000000000000000000000000bb";
+    const OWNER: &str = "0x0000000000000000000000000000000000000001";
+    const OTHER_OWNER: &str = "0x0000000000000000000000000000000000000002";
+
+    fn ownership_deleted(id: &str, contract: &str, owner: &str) -> String {
+        json!({
+            "topic": "OWNERSHIP",
+            "eventId": id,
+            "event": {
+                "type": "DELETE",
+                "ownership": {
+                    "id": format!("{}:1:{}", contract, owner),
+                    "token": contract,
+                    "tokenId": "1",
+                    "owner": owner
+                }
+            }
+        })
+        .to_string()
+    }
+
+    fn balance_updated(id: &str, owner: &str) -> String {
+        json!({
+            "topic": "ERC20_BALANCE",
+            "eventId": id,
+            "event": {
+                "type": "UPDATE",
+                "balance": { "contract": OTHER_COLLECTION, "owner": owner, "balance": "1" }
+            }
+        })
+        .to_string()
+    }
+
+    /// Serves one batch of messages per connection and then drops it,
+    /// forwarding the subscribe requests
+    async fn serve(batches: Vec<Vec<String>>) -> (Url, mpsc::UnboundedReceiver<Value>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = Url::parse(&format!("ws://{}/", listener.local_addr().unwrap())).unwrap();
+        let (tx, rx) = mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            for batch in batches {
+                let (stream, _) = listener.accept().await.unwrap();
+                let mut socket = tokio_tungstenite::accept_async(stream).await.unwrap();
+                if let Some(Ok(Message::Text(request))) = socket.next().await {
+                    tx.send(serde_json::from_str(&request).unwrap()).unwrap();
+                }
+                for msg in batch {
+                    socket.send(Message::Text(msg)).await.unwrap();
+                }
+            }
+        });
+        (url, rx)
+    }
+
+    fn subscriber(url: Url) -> EventSubscriber {
+        EventSubscriber::new(url)
+            .unwrap()
+            .backoff(Duration::from_millis(1), Duration::from_millis(10))
+    }
+
+    #[tokio::test]
+    async fn resubscribes_after_the_last_event() {
+        let (url, mut requests) = serve(vec![
+            vec![
+                ownership_deleted("1", COLLECTION, OWNER),
+                ownership_deleted("2", OTHER_COLLECTION, OWNER),
+            ],
+            vec![ownership_deleted("3", &COLLECTION.to_uppercase(), OWNER)],
+        ])
+        .await;
+        let subscription = Subscription::default()
+            .topic(Topic::Ownership)
+            .collection(COLLECTION);
+        let mut messages = subscriber(url)
+            .subscription(subscription)
+            .resume_from("0")
+            .messages();
+
+        let ids = vec![
+            messages.next().await.unwrap().unwrap().event_id,
+            messages.next().await.unwrap().unwrap().event_id,
+        ];
+        assert_eq!(ids, vec![Some("1".to_string()), Some("3".to_string())]);
+
+        let request = requests.recv().await.unwrap();
+        assert_eq!(
+            request,
+            json!({
+                "type": "SUBSCRIBE",
+                "topics": ["OWNERSHIP"],
+                "collections": [COLLECTION],
+                "owners": [],
+                "lastEventId": "0"
+            })
+        );
+        // the filtered event still moves the resume point
+        let request = requests.recv().await.unwrap();
+        assert_eq!(request["lastEventId"], "2");
+    }
+
+    #[tokio::test]
+    async fn filters_events_locally() {
+        let (url, _requests) = serve(vec![vec![
+            balance_updated("1", OWNER),
+            ownership_deleted("2", COLLECTION, OTHER_OWNER),
+            ownership_deleted("3", OTHER_COLLECTION, OWNER),
+            ownership_deleted("4", COLLECTION, OWNER),
+        ]])
+        .await;
+        let subscription = Subscription::default()
+            .collection(COLLECTION)
+            .owner(OWNER.to_uppercase());
+        let msg = subscriber(url)
+            .subscription(subscription)
+            .messages()
+            .next()
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(msg.event_id.as_deref(), Some("4"));
+        assert_eq!(msg.event.collections(), vec![COLLECTION.to_string()]);
+        assert_eq!(msg.event.owners(), vec![OWNER.to_string()]);
+    }
+
+    #[tokio::test]
+    async fn gives_up_after_max_retries() {
+        let (url, _requests) = serve(vec![vec![
+            ownership_deleted("1", COLLECTION, OWNER),
+            "{\"topic\":\"OWNERSHIP\"}".to_string(),
+        ]])
+        .await;
+        let mut events = subscriber(url).max_retries(2u32).subscribe();
+        assert!(events.next().await.unwrap().is_ok());
+        // undecodable messages are yielded without closing the stream
+        assert!(events.next().await.unwrap().is_err());
+        let err = events.next().await.unwrap().unwrap_err();
+        assert!(err.to_string().contains("gave up after 2 retries"));
+        assert!(events.next().await.is_none());
+    }
+}
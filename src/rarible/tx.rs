@@ -0,0 +1,517 @@
+//! Decoding of the `ExchangeV2` transactions prepared by the API.
+//!
+//! `PreparedOrderTx::data` is the ABI encoded call of the exchange, decoding
+//! it yields the orders as [`OrderForm`]s, so they can be displayed and
+//! compared with the orders the user expects to match or cancel.
+
+use ethabi::{ParamType, Token};
+
+use crate::eth::{self, Address, U256};
+use crate::rarible::eip712::DATA_TYPE_LEGACY;
+use crate::rarible::models::*;
+
+/// The ABI signature of `LibOrder.Order`
+const ORDER_TUPLE: &str =
+    "(address,((bytes4,bytes),uint256),address,((bytes4,bytes),uint256),uint256,uint256,uint256,bytes4,bytes)";
+
+/// A call of the `ExchangeV2` contract
+#[derive(Clone, Debug, PartialEq)]
+pub enum ExchangeCall {
+    /// `matchOrders(orderLeft, signatureLeft, orderRight, signatureRight)`,
+    /// the signatures are set on the orders
+    MatchOrders { left: OrderForm, right: OrderForm },
+    /// `cancel(order)`
+    Cancel(OrderForm),
+}
+
+impl ExchangeCall {
+    /// The orders of the call
+    pub fn orders(&self) -> Vec<&OrderForm> {
+        match self {
+            ExchangeCall::MatchOrders { left, right } => vec![left, right],
+            ExchangeCall::Cancel(order) => vec![order],
+        }
+    }
+}
+
+/// The selector of `matchOrders`
+pub fn match_orders_selector() -> [u8; 4] {
+    eth::id(format!(
+        "matchOrders({},bytes,{},bytes)",
+        ORDER_TUPLE, ORDER_TUPLE
+    ))
+}
+
+/// The selector of `cancel`
+pub fn cancel_selector() -> [u8; 4] {
+    eth::id(format!("cancel({})", ORDER_TUPLE))
+}
+
+fn asset_param() -> ParamType {
+    ParamType::Tuple(vec![
+        ParamType::Tuple(vec![ParamType::FixedBytes(4), ParamType::Bytes]),
+        ParamType::Uint(256),
+    ])
+}
+
+fn order_param() -> ParamType {
+    ParamType::Tuple(vec![
+        ParamType::Address,
+        asset_param(),
+        ParamType::Address,
+        asset_param(),
+        ParamType::Uint(256),
+        ParamType::Uint(256),
+        ParamType::Uint(256),
+        ParamType::FixedBytes(4),
+        ParamType::Bytes,
+    ])
+}
+
+fn part_param() -> ParamType {
+    ParamType::Tuple(vec![ParamType::Address, ParamType::Uint(96)])
+}
+
+fn parts_param() -> ParamType {
+    ParamType::Array(Box::new(part_param()))
+}
+
+/// Decodes the calldata of an `ExchangeV2` transaction
+pub fn decode_exchange_call(data: &[u8]) -> anyhow::Result<ExchangeCall> {
+    anyhow::ensure!(data.len() >= 4, "calldata without function selector");
+    let (selector, args) = data.split_at(4);
+    if selector == match_orders_selector() {
+        let mut tokens = ethabi::decode(
+            &[
+                order_param(),
+                ParamType::Bytes,
+                order_param(),
+                ParamType::Bytes,
+            ],
+            args,
+        )?
+        .into_iter();
+        let mut next_order = || -> anyhow::Result<OrderForm> {
+            match (tokens.next(), tokens.next()) {
+                (Some(order), Some(Token::Bytes(signature))) => order_form(order, Some(signature)),
+                _ => anyhow::bail!("invalid `matchOrders` arguments"),
+            }
+        };
+        let left = next_order()?;
+        let right = next_order()?;
+        Ok(ExchangeCall::MatchOrders { left, right })
+    } else if selector == cancel_selector() {
+        let order = ethabi::decode(&[order_param()], args)?
+            .into_iter()
+            .next()
+            .ok_or_else(|| anyhow::anyhow!("invalid `cancel` arguments"))?;
+        Ok(ExchangeCall::Cancel(order_form(order, None)?))
+    } else {
+        anyhow::bail!("unknown exchange function {}", eth::encode_hex(selector))
+    }
+}
+
+impl PreparedOrderTx {
+    /// Decodes the exchange call of the transaction
+    pub fn decode(&self) -> anyhow::Result<ExchangeCall> {
+        decode_exchange_call(&eth::decode_hex(&self.data)?)
+    }
+}
+
+fn tuple(token: Token) -> anyhow::Result<Vec<Token>> {
+    token
+        .into_tuple()
+        .ok_or_else(|| anyhow::anyhow!("expected tuple"))
+}
+
+fn address(token: Token) -> anyhow::Result<Address> {
+    token
+        .into_address()
+        .ok_or_else(|| anyhow::anyhow!("expected address"))
+}
+
+fn uint(token: Token) -> anyhow::Result<U256> {
+    token
+        .into_uint()
+        .ok_or_else(|| anyhow::anyhow!("expected uint"))
+}
+
+fn bytes(token: Token) -> anyhow::Result<Vec<u8>> {
+    match token {
+        Token::Bytes(bytes) | Token::FixedBytes(bytes) => Ok(bytes),
+        _ => anyhow::bail!("expected bytes"),
+    }
+}
+
+fn address_str(address: Address) -> String {
+    format!("{:?}", address)
+}
+
+/// Converts a timestamp, `0` is unset
+fn timestamp(value: U256) -> anyhow::Result<Option<i64>> {
+    if value.is_zero() {
+        return Ok(None);
+    }
+    anyhow::ensure!(value <= U256::from(i64::MAX), "invalid timestamp {}", value);
+    Ok(Some(value.as_u64() as i64))
+}
+
+fn order_form(token: Token, signature: Option<Vec<u8>>) -> anyhow::Result<OrderForm> {
+    let mut members = tuple(token)?.into_iter();
+    let mut next = || next_token(&mut members);
+    let maker = address(next()?)?;
+    let make = asset(next()?)?;
+    let taker = address(next()?)?;
+    let take = asset(next()?)?;
+    let salt = uint(next()?)?;
+    let start = timestamp(uint(next()?)?)?;
+    let end = timestamp(uint(next()?)?)?;
+    let data_type = bytes(next()?)?;
+    let data = order_data(&data_type, &bytes(next()?)?)?;
+    Ok(OrderForm {
+        _type: OrderType::V2,
+        maker: address_str(maker),
+        taker: if taker.is_zero() {
+            None
+        } else {
+            Some(address_str(taker))
+        },
+        make: Box::new(make),
+        take: Box::new(take),
+        salt: format!("{:#x}", salt),
+        start,
+        end,
+        data: Box::new(data),
+        signature: signature
+            .filter(|signature| !signature.is_empty())
+            .map(eth::encode_hex),
+    })
+}
+
+fn asset(token: Token) -> anyhow::Result<Asset> {
+    let mut members = tuple(token)?.into_iter();
+    let (asset_type, value) = match (members.next(), members.next()) {
+        (Some(asset_type), Some(value)) => (asset_type, uint(value)?),
+        _ => anyhow::bail!("invalid asset"),
+    };
+    let mut asset_type = tuple(asset_type)?.into_iter();
+    let (asset_class, data) = match (asset_type.next(), asset_type.next()) {
+        (Some(asset_class), Some(data)) => (bytes(asset_class)?, bytes(data)?),
+        _ => anyhow::bail!("invalid asset type"),
+    };
+    Ok(Asset {
+        asset_type: Box::new(decode_asset_type(&asset_class, &data)?),
+        value: value.to_string(),
+    })
+}
+
+/// Decodes the `data` of an asset type with class `asset_class`
+pub fn decode_asset_type(asset_class: &[u8], data: &[u8]) -> anyhow::Result<AssetType> {
+    let class = [
+        "ETH",
+        "ERC20",
+        "ERC721",
+        "ERC1155",
+        "ERC721_LAZY",
+        "ERC1155_LAZY",
+        "COLLECTION",
+    ]
+    .iter()
+    .find(|name| eth::id(name) == asset_class)
+    .ok_or_else(|| anyhow::anyhow!("unknown asset class {}", eth::encode_hex(asset_class)))?;
+    let asset_type = match *class {
+        "ETH" => AssetType::Eth,
+        "ERC20" | "COLLECTION" => {
+            let mut tokens = ethabi::decode(&[ParamType::Address], data)?.into_iter();
+            let contract = address_str(address(next_token(&mut tokens)?)?);
+            if *class == "ERC20" {
+                AssetType::Erc20 { contract }
+            } else {
+                AssetType::Collection { contract }
+            }
+        }
+        "ERC721" | "ERC1155" => {
+            let mut tokens =
+                ethabi::decode(&[ParamType::Address, ParamType::Uint(256)], data)?.into_iter();
+            let contract = address_str(address(next_token(&mut tokens)?)?);
+            let token_id = uint(next_token(&mut tokens)?)?.to_string();
+            if *class == "ERC721" {
+                AssetType::Erc721 { contract, token_id }
+            } else {
+                AssetType::Erc1155 { contract, token_id }
+            }
+        }
+        "ERC721_LAZY" => {
+            let mint = ParamType::Tuple(vec![
+                ParamType::Uint(256),
+                ParamType::String,
+                parts_param(),
+                parts_param(),
+                ParamType::Array(Box::new(ParamType::Bytes)),
+            ]);
+            let mut tokens = ethabi::decode(&[ParamType::Address, mint], data)?.into_iter();
+            let contract = address_str(address(next_token(&mut tokens)?)?);
+            let mut mint = tuple(next_token(&mut tokens)?)?.into_iter();
+            AssetType::Erc721Lazy {
+                contract,
+                token_id: uint(next_token(&mut mint)?)?.to_string(),
+                uri: string(next_token(&mut mint)?)?,
+                creators: parts(next_token(&mut mint)?)?,
+                royalties: parts(next_token(&mut mint)?)?,
+                signatures: signatures(next_token(&mut mint)?)?,
+            }
+        }
+        _ => {
+            let mint = ParamType::Tuple(vec![
+                ParamType::Uint(256),
+                ParamType::String,
+                ParamType::Uint(256),
+                parts_param(),
+                parts_param(),
+                ParamType::Array(Box::new(ParamType::Bytes)),
+            ]);
+            let mut tokens = ethabi::decode(&[ParamType::Address, mint], data)?.into_iter();
+            let contract = address_str(address(next_token(&mut tokens)?)?);
+            let mut mint = tuple(next_token(&mut tokens)?)?.into_iter();
+            AssetType::Erc1155Lazy {
+                contract,
+                token_id: uint(next_token(&mut mint)?)?.to_string(),
+                uri: string(next_token(&mut mint)?)?,
+                supply: uint(next_token(&mut mint)?)?.to_string(),
+                creators: parts(next_token(&mut mint)?)?,
+                royalties: parts(next_token(&mut mint)?)?,
+                signatures: signatures(next_token(&mut mint)?)?,
+            }
+        }
+    };
+    Ok(asset_type)
+}
+
+/// Decodes the `data` of an order with data type `data_type`
+pub fn order_data(data_type: &[u8], data: &[u8]) -> anyhow::Result<OrderData> {
+    if data_type == DATA_TYPE_LEGACY {
+        return Ok(OrderData::OrderDataLegacy { fee: 0 });
+    }
+    anyhow::ensure!(
+        data_type == eth::id("V1"),
+        "unknown order data type {}",
+        eth::encode_hex(data_type)
+    );
+    let mut tokens = ethabi::decode(
+        &[ParamType::Tuple(vec![parts_param(), parts_param()])],
+        data,
+    )?
+    .into_iter();
+    let mut members = tuple(next_token(&mut tokens)?)?.into_iter();
+    Ok(OrderData::OrderRaribleV2DataV1 {
+        payouts: parts(next_token(&mut members)?)?,
+        origin_fees: parts(next_token(&mut members)?)?,
+    })
+}
+
+fn next_token(tokens: &mut impl Iterator<Item = Token>) -> anyhow::Result<Token> {
+    tokens
+        .next()
+        .ok_or_else(|| anyhow::anyhow!("missing value"))
+}
+
+fn string(token: Token) -> anyhow::Result<String> {
+    token
+        .into_string()
+        .ok_or_else(|| anyhow::anyhow!("expected string"))
+}
+
+fn parts(token: Token) -> anyhow::Result<Vec<Part>> {
+    let parts = token
+        .into_array()
+        .ok_or_else(|| anyhow::anyhow!("expected array of parts"))?;
+    parts
+        .into_iter()
+        .map(|part| {
+            let mut members = tuple(part)?.into_iter();
+            let account = address(next_token(&mut members)?)?;
+            let value = uint(next_token(&mut members)?)?;
+            anyhow::ensure!(
+                value <= U256::from(i32::MAX),
+                "part value {} out of range",
+                value
+            );
+            Ok(Part {
+                account: address_str(account),
+                value: value.as_u32() as i32,
+            })
+        })
+        .collect()
+}
+
+fn signatures(token: Token) -> anyhow::Result<Vec<String>> {
+    let signatures = token
+        .into_array()
+        .ok_or_else(|| anyhow::anyhow!("expected array of signatures"))?;
+    signatures
+        .into_iter()
+        .map(|signature| bytes(signature).map(eth::encode_hex))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TOKEN: &str = "0x60f80121c31a0d46b5279700f9df786054aa5ee5";
+    const WETH: &str = "0xc02aaa39b223fe8d0a0e5c4f27ead9083c756cc2";
+    const MAKER: &str = "0x2c7536e3605d9c16a7a3d7b1898e529396a65c23";
+    const TAKER: &str = "0x0000000000000000000000000000000000000002";
+
+    fn part(account: &str, value: i32) -> Part {
+        Part {
+            account: account.to_string(),
+            value,
+        }
+    }
+
+    fn asset(asset_type: AssetType, value: &str) -> Box<Asset> {
+        Box::new(Asset {
+            asset_type: Box::new(asset_type),
+            value: value.to_string(),
+        })
+    }
+
+    /// A sell order of a lazy minted token for WETH, in the form it decodes to
+    fn sell_order() -> OrderForm {
+        let token = AssetType::Erc721Lazy {
+            contract: TOKEN.to_string(),
+            token_id: "1012".to_string(),
+            uri: "/ipfs/QmHash".to_string(),
+            creators: vec![part(MAKER, 10000)],
+            royalties: vec![part(MAKER, 500)],
+            signatures: vec![format!("0x{}1b", "11".repeat(64))],
+        };
+        OrderForm {
+            _type: OrderType::V2,
+            maker: MAKER.to_string(),
+            taker: None,
+            make: asset(token, "1"),
+            take: asset(
+                AssetType::Erc20 {
+                    contract: WETH.to_string(),
+                },
+                "1000000000000000000",
+            ),
+            salt: "0x1234567".to_string(),
+            start: None,
+            end: Some(1_631_914_824),
+            data: Box::new(OrderData::OrderRaribleV2DataV1 {
+                payouts: vec![part(MAKER, 10000)],
+                origin_fees: vec![part(TAKER, 250)],
+            }),
+            signature: Some(format!("0x{}1c", "22".repeat(64))),
+        }
+    }
+
+    fn asset_token(asset: &Asset) -> Token {
+        Token::Tuple(vec![
+            Token::Tuple(vec![
+                Token::FixedBytes(asset.asset_type.asset_class().to_vec()),
+                Token::Bytes(asset.asset_type.encode_data().unwrap()),
+            ]),
+            Token::Uint(eth::parse_u256(&asset.value).unwrap()),
+        ])
+    }
+
+    /// `LibOrder.Order` of the form
+    fn order_token(order: &OrderForm) -> Token {
+        let address = |s: &str| Token::Address(eth::parse_address(s).unwrap());
+        let time = |t: Option<i64>| Token::Uint(t.unwrap_or_default().into());
+        Token::Tuple(vec![
+            address(&order.maker),
+            asset_token(&order.make),
+            address(
+                order
+                    .taker
+                    .as_deref()
+                    .unwrap_or(&address_str(Address::zero())),
+            ),
+            asset_token(&order.take),
+            Token::Uint(eth::parse_u256(&order.salt).unwrap()),
+            time(order.start),
+            time(order.end),
+            Token::FixedBytes(order.data.data_type().to_vec()),
+            Token::Bytes(order.data.encode().unwrap()),
+        ])
+    }
+
+    fn signature_token(order: &OrderForm) -> Token {
+        Token::Bytes(
+            order
+                .signature
+                .as_deref()
+                .map(|signature| eth::decode_hex(signature).unwrap())
+                .unwrap_or_default(),
+        )
+    }
+
+    #[test]
+    fn decodes_match_orders() {
+        let left = sell_order();
+        let right = OrderForm {
+            maker: TAKER.to_string(),
+            taker: Some(MAKER.to_string()),
+            make: left.take.clone(),
+            take: left.make.clone(),
+            salt: "0x0".to_string(),
+            end: None,
+            data: Box::new(OrderData::OrderDataLegacy { fee: 0 }),
+            signature: None,
+            ..left.clone()
+        };
+        let mut data = match_orders_selector().to_vec();
+        data.extend(ethabi::encode(&[
+            order_token(&left),
+            signature_token(&left),
+            order_token(&right),
+            signature_token(&right),
+        ]));
+        let call = decode_exchange_call(&data).unwrap();
+        assert_eq!(call, ExchangeCall::MatchOrders { left, right });
+    }
+
+    #[test]
+    fn decodes_cancel() {
+        let order = OrderForm {
+            make: asset(
+                AssetType::Erc1155 {
+                    contract: TOKEN.to_string(),
+                    token_id: "7".to_string(),
+                },
+                "3",
+            ),
+            take: asset(AssetType::Eth, "100"),
+            signature: None,
+            ..sell_order()
+        };
+        let mut data = cancel_selector().to_vec();
+        data.extend(ethabi::encode(&[order_token(&order)]));
+        let tx = PreparedOrderTx {
+            to: TOKEN.to_string(),
+            data: eth::encode_hex(&data),
+        };
+        assert_eq!(tx.decode().unwrap(), ExchangeCall::Cancel(order));
+    }
+
+    #[test]
+    fn rejects_other_calls() {
+        let mut data = eth::id("transfer(address,uint256)").to_vec();
+        data.extend(ethabi::encode(&[order_token(&sell_order())]));
+        let err = decode_exchange_call(&data).unwrap_err();
+        assert_eq!(err.to_string(), "unknown exchange function 0xa9059cbb");
+        assert!(decode_exchange_call(&cancel_selector()[..3]).is_err());
+
+        assert!(decode_asset_type(&eth::id("FLOW"), &[]).is_err());
+        assert!(order_data(&eth::id("V2"), &[]).is_err());
+        assert_eq!(
+            order_data(&DATA_TYPE_LEGACY, &[]).unwrap(),
+            OrderData::OrderDataLegacy { fee: 0 }
+        );
+    }
+}
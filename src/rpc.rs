@@ -0,0 +1,582 @@
+//! Minimal Ethereum JSON-RPC client to check the marketplace data against
+//! the chain.
+
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use ethabi::{ParamType, Token};
+use log::debug;
+use reqwest::{IntoUrl, Url};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::eth::{self, Address, U256};
+use crate::market::models::NftId;
+use crate::opensea::models::OpenSeaAsset;
+#[cfg(feature = "rarible")]
+use crate::rarible::models::NftItem;
+
+/// `supportsInterface` id of ERC721
+pub const ERC721_INTERFACE_ID: [u8; 4] = [0x80, 0xac, 0x58, 0xcd];
+
+/// `supportsInterface` id of ERC1155
+pub const ERC1155_INTERFACE_ID: [u8; 4] = [0xd9, 0xb6, 0x7a, 0x26];
+
+/// An error object returned by the node
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct JsonRpcError {
+    pub code: i64,
+    pub message: String,
+    #[serde(default)]
+    pub data: Option<Value>,
+}
+
+impl std::error::Error for JsonRpcError {}
+
+impl fmt::Display for JsonRpcError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "code: `{}`, ", self.code)?;
+        write!(f, "message: `{}`", self.message)
+    }
+}
+
+#[derive(Deserialize)]
+struct Response<T> {
+    result: Option<T>,
+    error: Option<JsonRpcError>,
+}
+
+/// The token standards an NFT contract can implement
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub enum TokenStandard {
+    Erc721,
+    Erc1155,
+}
+
+impl fmt::Display for TokenStandard {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TokenStandard::Erc721 => f.write_str("ERC721"),
+            TokenStandard::Erc1155 => f.write_str("ERC1155"),
+        }
+    }
+}
+
+/// Result of comparing the owners reported by a marketplace with the chain
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct OwnershipCheck {
+    pub nft: NftId,
+    pub standard: TokenStandard,
+    /// The current owner of an ERC721 token
+    pub owner: Option<Address>,
+    /// The on-chain balances of the owners reported by the marketplace
+    pub balances: Vec<(Address, U256)>,
+}
+
+impl OwnershipCheck {
+    /// The reported owners that don't hold the token anymore
+    pub fn stale_owners(&self) -> Vec<Address> {
+        self.balances
+            .iter()
+            .filter(|(_, balance)| balance.is_zero())
+            .map(|(owner, _)| *owner)
+            .collect()
+    }
+
+    /// Whether the reported owners match the chain: all of them hold the
+    /// token and for ERC721 the actual owner was reported.
+    pub fn is_consistent(&self) -> bool {
+        if !self.stale_owners().is_empty() {
+            return false;
+        }
+        match self.owner {
+            Some(owner) => self.balances.iter().any(|(reported, _)| *reported == owner),
+            None => true,
+        }
+    }
+}
+
+/// Sends JSON-RPC requests to an Ethereum node over http
+#[derive(Clone)]
+pub struct RpcClient {
+    /// The client that executes the http requests
+    client: Arc<reqwest::Client>,
+    /// Url of the node
+    url: Url,
+    /// Id of the next request
+    id: Arc<AtomicU64>,
+}
+
+impl RpcClient {
+    pub fn new(client: Arc<reqwest::Client>, url: impl IntoUrl) -> anyhow::Result<Self> {
+        Ok(Self {
+            client,
+            url: url.into_url()?,
+            id: Arc::new(AtomicU64::new(1)),
+        })
+    }
+
+    /// Creates a client for the node at `url` with a default http client
+    pub fn connect(url: impl IntoUrl) -> anyhow::Result<Self> {
+        Self::new(Arc::new(reqwest::Client::new()), url)
+    }
+
+    pub fn url(&self) -> &Url {
+        &self.url
+    }
+
+    /// Sends the request and returns its `result`
+    pub async fn request<T: DeserializeOwned>(
+        &self,
+        method: &str,
+        params: Value,
+    ) -> anyhow::Result<T> {
+        let id = self.id.fetch_add(1, Ordering::Relaxed);
+        let body = json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "method": method,
+            "params": params,
+        });
+        debug!("JSON-RPC request: {}", body);
+        let resp: Response<T> = self
+            .client
+            .post(self.url.clone())
+            .json(&body)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        if let Some(err) = resp.error {
+            return Err(err.into());
+        }
+        resp.result
+            .ok_or_else(|| anyhow::anyhow!("missing result of `{}`", method))
+    }
+
+    /// Executes a read only call of `contract` at the latest block
+    pub async fn call(&self, contract: Address, data: &[u8]) -> anyhow::Result<Vec<u8>> {
+        let result: String = self
+            .request(
+                "eth_call",
+                json!([{ "to": contract, "data": eth::encode_hex(data) }, "latest"]),
+            )
+            .await?;
+        eth::decode_hex(result)
+    }
+
+    async fn call_function(
+        &self,
+        contract: Address,
+        signature: &str,
+        args: &[Token],
+        output: &[ParamType],
+    ) -> anyhow::Result<Vec<Token>> {
+        let mut data = eth::id(signature).to_vec();
+        data.extend(ethabi::encode(args));
+        let result = self.call(contract, &data).await?;
+        anyhow::ensure!(
+            !result.is_empty(),
+            "`{}` of {:?} returned no data",
+            signature,
+            contract
+        );
+        Ok(ethabi::decode(output, &result)?)
+    }
+
+    /// The owner of an ERC721 token
+    pub async fn owner_of(&self, contract: Address, token_id: U256) -> anyhow::Result<Address> {
+        let tokens = self
+            .call_function(
+                contract,
+                "ownerOf(uint256)",
+                &[Token::Uint(token_id)],
+                &[ParamType::Address],
+            )
+            .await?;
+        tokens
+            .into_iter()
+            .next()
+            .and_then(Token::into_address)
+            .ok_or_else(|| anyhow::anyhow!("invalid `ownerOf` result"))
+    }
+
+    /// The number of ERC721 tokens of `contract` held by `owner`
+    pub async fn balance_of(&self, contract: Address, owner: Address) -> anyhow::Result<U256> {
+        let tokens = self
+            .call_function(
+                contract,
+                "balanceOf(address)",
+                &[Token::Address(owner)],
+                &[ParamType::Uint(256)],
+            )
+            .await?;
+        first_uint(tokens)
+    }
+
+    /// The number of ERC1155 tokens `token_id` held by `owner`
+    pub async fn balance_of_erc1155(
+        &self,
+        contract: Address,
+        owner: Address,
+        token_id: U256,
+    ) -> anyhow::Result<U256> {
+        let tokens = self
+            .call_function(
+                contract,
+                "balanceOf(address,uint256)",
+                &[Token::Address(owner), Token::Uint(token_id)],
+                &[ParamType::Uint(256)],
+            )
+            .await?;
+        first_uint(tokens)
+    }
+
+    /// The metadata uri of an ERC721 token
+    pub async fn token_uri(&self, contract: Address, token_id: U256) -> anyhow::Result<String> {
+        let tokens = self
+            .call_function(
+                contract,
+                "tokenURI(uint256)",
+                &[Token::Uint(token_id)],
+                &[ParamType::String],
+            )
+            .await?;
+        first_string(tokens)
+    }
+
+    /// The metadata uri of an ERC1155 token, `{id}` is not substituted
+    pub async fn uri(&self, contract: Address, token_id: U256) -> anyhow::Result<String> {
+        let tokens = self
+            .call_function(
+                contract,
+                "uri(uint256)",
+                &[Token::Uint(token_id)],
+                &[ParamType::String],
+            )
+            .await?;
+        first_string(tokens)
+    }
+
+    /// Whether the contract implements the ERC165 `interface_id`.
+    ///
+    /// Contracts without ERC165 support don't implement it.
+    pub async fn supports_interface(
+        &self,
+        contract: Address,
+        interface_id: [u8; 4],
+    ) -> anyhow::Result<bool> {
+        let mut data = eth::id("supportsInterface(bytes4)").to_vec();
+        data.extend(ethabi::encode(&[Token::FixedBytes(interface_id.to_vec())]));
+        let result = match self.call(contract, &data).await {
+            Ok(result) => result,
+            Err(err) if err.is::<JsonRpcError>() => return Ok(false),
+            Err(err) => return Err(err),
+        };
+        Ok(ethabi::decode(&[ParamType::Bool], &result)
+            .ok()
+            .and_then(|tokens| tokens.into_iter().next())
+            .and_then(Token::into_bool)
+            .unwrap_or_default())
+    }
+
+    /// Detects the token standard the contract implements
+    pub async fn token_standard(&self, contract: Address) -> anyhow::Result<Option<TokenStandard>> {
+        if self
+            .supports_interface(contract, ERC721_INTERFACE_ID)
+            .await?
+        {
+            Ok(Some(TokenStandard::Erc721))
+        } else if self
+            .supports_interface(contract, ERC1155_INTERFACE_ID)
+            .await?
+        {
+            Ok(Some(TokenStandard::Erc1155))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Compares the `owners` a marketplace reports for `nft` with the chain
+    pub async fn verify_ownership(
+        &self,
+        nft: &NftId,
+        owners: &[String],
+    ) -> anyhow::Result<OwnershipCheck> {
+        let contract = eth::parse_address(&nft.contract)?;
+        let token_id = eth::parse_u256(&nft.token_id)?;
+        let owners = owners
+            .iter()
+            .map(eth::parse_address)
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        let standard = self
+            .token_standard(contract)
+            .await?
+            .ok_or_else(|| anyhow::anyhow!("{} is neither ERC721 nor ERC1155", nft.contract))?;
+
+        let mut check = OwnershipCheck {
+            nft: nft.clone(),
+            standard,
+            owner: None,
+            balances: Vec::with_capacity(owners.len()),
+        };
+        match standard {
+            TokenStandard::Erc721 => {
+                let owner = self.owner_of(contract, token_id).await?;
+                check.owner = Some(owner);
+                for reported in owners {
+                    let balance = if reported == owner {
+                        U256::one()
+                    } else {
+                        U256::zero()
+                    };
+                    check.balances.push((reported, balance));
+                }
+            }
+            TokenStandard::Erc1155 => {
+                for reported in owners {
+                    let balance = self
+                        .balance_of_erc1155(contract, reported, token_id)
+                        .await?;
+                    check.balances.push((reported, balance));
+                }
+            }
+        }
+        Ok(check)
+    }
+
+    /// Checks the owner of an asset returned by the OpenSea API.
+    ///
+    /// OpenSea reports the null address as owner of tokens with multiple
+    /// owners, which is skipped.
+    pub async fn verify_asset_ownership(
+        &self,
+        asset: &OpenSeaAsset,
+    ) -> anyhow::Result<OwnershipCheck> {
+        let token_id = asset
+            .token_id
+            .as_deref()
+            .ok_or_else(|| anyhow::anyhow!("asset without token id"))?;
+        let nft = NftId::new(&asset.asset_contract.address, token_id);
+        let mut owners = Vec::new();
+        if eth::parse_address(&asset.owner.address)? != Address::zero() {
+            owners.push(asset.owner.address.clone());
+        }
+        self.verify_ownership(&nft, &owners).await
+    }
+
+    /// Checks the owners of an item returned by the Rarible API
+    #[cfg(feature = "rarible")]
+    pub async fn verify_item_ownership(&self, item: &NftItem) -> anyhow::Result<OwnershipCheck> {
+        let nft = NftId::new(&item.contract, item.token_id.clone());
+        self.verify_ownership(&nft, &item.owners).await
+    }
+}
+
+fn first_uint(tokens: Vec<Token>) -> anyhow::Result<U256> {
+    tokens
+        .into_iter()
+        .next()
+        .and_then(Token::into_uint)
+        .ok_or_else(|| anyhow::anyhow!("expected uint result"))
+}
+
+fn first_string(tokens: Vec<Token>) -> anyhow::Result<String> {
+    tokens
+        .into_iter()
+        .next()
+        .and_then(Token::into_string)
+        .ok_or_else(|| anyhow::anyhow!("expected string result"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{MockServer, Request};
+
+    const ERC721: &str = "0x00000000000000000000000000000000000000aa";
+    const ERC1155: &str = "0x00000000000000000000000000000000000000bb";
+    /// A contract without ERC165 support, `supportsInterface` reverts
+    const LEGACY: &str = "0x00000000000000000000000000000000000000cc";
+    /// An account without code, calls return no data
+    const EOA: &str = "0x00000000000000000000000000000000000000dd";
+    const OWNER: &str = "0x0000000000000000000000000000000000000001";
+    const OTHER: &str = "0x0000000000000000000000000000000000000002";
+
+    fn address(s: &str) -> Address {
+        eth::parse_address(s).unwrap()
+    }
+
+    fn word(token: Token) -> String {
+        eth::encode_hex(ethabi::encode(&[token]))
+    }
+
+    /// Answers `eth_call`s like a node with the contracts above
+    fn node(request: &Request) -> (u16, String) {
+        let body: Value = serde_json::from_str(&request.body).unwrap();
+        assert_eq!(body["method"], "eth_call");
+        let call = &body["params"][0];
+        let to = call["to"].as_str().unwrap().to_string();
+        let data = eth::decode_hex(call["data"].as_str().unwrap()).unwrap();
+        let (selector, args) = data.split_at(4);
+        let is = |signature: &str| selector == eth::id(signature);
+        let arg = |idx: usize| U256::from_big_endian(&args[idx * 32..(idx + 1) * 32]);
+        let interface = || args[..4].to_vec();
+
+        let result = match to.as_str() {
+            ERC721 if is("supportsInterface(bytes4)") => {
+                Ok(word(Token::Bool(interface() == ERC721_INTERFACE_ID)))
+            }
+            ERC721 if is("ownerOf(uint256)") && arg(0) == U256::one() => {
+                Ok(word(Token::Address(address(OWNER))))
+            }
+            ERC721 if is("ownerOf(uint256)") => Err("execution reverted: nonexistent token"),
+            ERC721 if is("balanceOf(address)") => Ok(word(Token::Uint(3.into()))),
+            ERC1155 if is("supportsInterface(bytes4)") => {
+                Ok(word(Token::Bool(interface() == ERC1155_INTERFACE_ID)))
+            }
+            ERC1155 if is("balanceOf(address,uint256)") => {
+                let owner = Address::from_slice(&args[12..32]);
+                let balance = if owner == address(OWNER) { 5 } else { 0 };
+                Ok(word(Token::Uint(balance.into())))
+            }
+            // a truncated word
+            ERC1155 if is("uri(uint256)") => Ok("0x0000000000000020".to_string()),
+            LEGACY => Err("execution reverted"),
+            _ => Ok("0x".to_string()),
+        };
+        let response = match result {
+            Ok(result) => json!({ "jsonrpc": "2.0", "id": body["id"], "result": result }),
+            Err(message) => json!({
+                "jsonrpc": "2.0",
+                "id": body["id"],
+                "error": { "code": 3, "message": message, "data": "0x" }
+            }),
+        };
+        (200, response.to_string())
+    }
+
+    fn client(server: &MockServer) -> RpcClient {
+        RpcClient::connect(server.url().clone()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn decodes_call_results() {
+        let server = MockServer::start(node);
+        let rpc = client(&server);
+        assert_eq!(
+            rpc.owner_of(address(ERC721), U256::one()).await.unwrap(),
+            address(OWNER)
+        );
+        assert_eq!(
+            rpc.balance_of(address(ERC721), address(OWNER))
+                .await
+                .unwrap(),
+            U256::from(3)
+        );
+        assert_eq!(
+            rpc.balance_of_erc1155(address(ERC1155), address(OWNER), U256::one())
+                .await
+                .unwrap(),
+            U256::from(5)
+        );
+
+        let ids = server
+            .requests()
+            .iter()
+            .map(|request| serde_json::from_str::<Value>(&request.body).unwrap()["id"].clone())
+            .collect::<Vec<_>>();
+        assert_eq!(ids, vec![json!(1), json!(2), json!(3)]);
+    }
+
+    #[tokio::test]
+    async fn reports_reverts_and_short_returns() {
+        let server = MockServer::start(node);
+        let rpc = client(&server);
+        let err = rpc
+            .owner_of(address(ERC721), U256::from(2))
+            .await
+            .unwrap_err();
+        let err = err.downcast_ref::<JsonRpcError>().unwrap();
+        assert_eq!(err.code, 3);
+        assert_eq!(err.message, "execution reverted: nonexistent token");
+
+        let err = rpc.owner_of(address(EOA), U256::one()).await.unwrap_err();
+        assert!(err.to_string().contains("returned no data"));
+        assert!(rpc.uri(address(ERC1155), U256::one()).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn detects_supported_interfaces() {
+        let server = MockServer::start(node);
+        let rpc = client(&server);
+        let supports = |contract, id| {
+            let rpc = rpc.clone();
+            async move { rpc.supports_interface(address(contract), id).await.unwrap() }
+        };
+        assert!(supports(ERC721, ERC721_INTERFACE_ID).await);
+        assert!(!supports(ERC721, ERC1155_INTERFACE_ID).await);
+        assert!(supports(ERC1155, ERC1155_INTERFACE_ID).await);
+        // reverts and empty results count as unsupported
+        assert!(!supports(LEGACY, ERC721_INTERFACE_ID).await);
+        assert!(!supports(EOA, ERC721_INTERFACE_ID).await);
+
+        assert_eq!(
+            rpc.token_standard(address(ERC1155)).await.unwrap(),
+            Some(TokenStandard::Erc1155)
+        );
+        assert_eq!(rpc.token_standard(address(LEGACY)).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn verifies_erc721_ownership() {
+        let server = MockServer::start(node);
+        let rpc = client(&server);
+        let nft = NftId::new(ERC721, "1".to_string());
+
+        let check = rpc
+            .verify_ownership(&nft, &[OWNER.to_string()])
+            .await
+            .unwrap();
+        assert_eq!(check.standard, TokenStandard::Erc721);
+        assert_eq!(check.owner, Some(address(OWNER)));
+        assert!(check.is_consistent());
+
+        let check = rpc
+            .verify_ownership(&nft, &[OTHER.to_string()])
+            .await
+            .unwrap();
+        assert_eq!(check.stale_owners(), vec![address(OTHER)]);
+        assert!(!check.is_consistent());
+
+        let burned = NftId::new(ERC721, "2".to_string());
+        assert!(rpc.verify_ownership(&burned, &[]).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn verifies_erc1155_balances() {
+        let server = MockServer::start(node);
+        let rpc = client(&server);
+        let nft = NftId::new(ERC1155, "1".to_string());
+        let check = rpc
+            .verify_ownership(&nft, &[OWNER.to_string(), OTHER.to_string()])
+            .await
+            .unwrap();
+        assert_eq!(check.standard, TokenStandard::Erc1155);
+        assert_eq!(check.owner, None);
+        assert_eq!(
+            check.balances,
+            vec![
+                (address(OWNER), U256::from(5)),
+                (address(OTHER), U256::zero())
+            ]
+        );
+        assert_eq!(check.stale_owners(), vec![address(OTHER)]);
+
+        let unknown = NftId::new(LEGACY, "1".to_string());
+        assert!(rpc.verify_ownership(&unknown, &[]).await.is_err());
+    }
+}
@@ -0,0 +1,239 @@
+//! Signing of order digests with a local secp256k1 key.
+
+use std::fmt;
+use std::str::FromStr;
+
+use k256::ecdsa::{RecoveryId, Signature as EcdsaSignature, SigningKey, VerifyingKey};
+
+use crate::eth::{self, Address, H256, U256};
+
+/// A recoverable secp256k1 signature in the `r ‖ s ‖ v` layout the
+/// marketplaces expect, with `v` being `27` or `28`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Signature {
+    pub r: U256,
+    pub s: U256,
+    pub v: u8,
+}
+
+impl Signature {
+    /// Parses a 65 byte `r ‖ s ‖ v` signature, `v` may be `0`/`1` or `27`/`28`
+    pub fn from_bytes(bytes: &[u8]) -> anyhow::Result<Self> {
+        anyhow::ensure!(
+            bytes.len() == 65,
+            "signature must be 65 bytes, got {}",
+            bytes.len()
+        );
+        Ok(Self {
+            r: U256::from_big_endian(&bytes[..32]),
+            s: U256::from_big_endian(&bytes[32..64]),
+            v: normalize_v(bytes[64])?,
+        })
+    }
+
+    pub fn to_bytes(&self) -> [u8; 65] {
+        let mut bytes = [0u8; 65];
+        self.r.to_big_endian(&mut bytes[..32]);
+        self.s.to_big_endian(&mut bytes[32..64]);
+        bytes[64] = self.v;
+        bytes
+    }
+
+    /// Recovers the address that signed the `digest`
+    pub fn recover(&self, digest: H256) -> anyhow::Result<Address> {
+        let mut rs = [0u8; 64];
+        self.r.to_big_endian(&mut rs[..32]);
+        self.s.to_big_endian(&mut rs[32..]);
+        let mut signature = EcdsaSignature::from_slice(&rs)?;
+        let mut recovery_id = RecoveryId::from_byte(normalize_v(self.v)? - 27)
+            .ok_or_else(|| anyhow::anyhow!("invalid recovery id {}", self.v))?;
+        // `ecrecover` accepts signatures with a high `s`, k256 only the
+        // normalized form which recovers to the same key with flipped parity
+        if let Some(normalized) = signature.normalize_s() {
+            signature = normalized;
+            recovery_id = RecoveryId::new(!recovery_id.is_y_odd(), recovery_id.is_x_reduced());
+        }
+        let key = VerifyingKey::recover_from_prehash(digest.as_bytes(), &signature, recovery_id)
+            .map_err(|_| anyhow::anyhow!("failed to recover signer of {:?}", digest))?;
+        Ok(public_key_address(&key))
+    }
+
+    /// Whether `address` signed the `digest`
+    pub fn verify(&self, digest: H256, address: Address) -> bool {
+        self.recover(digest).ok() == Some(address)
+    }
+}
+
+impl FromStr for Signature {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::from_bytes(&eth::decode_hex(s)?)
+    }
+}
+
+impl fmt::Display for Signature {
+    /// Formats the signature as `0x` prefixed hex string
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&eth::encode_hex(self.to_bytes()))
+    }
+}
+
+fn normalize_v(v: u8) -> anyhow::Result<u8> {
+    match v {
+        0 | 1 => Ok(v + 27),
+        27 | 28 => Ok(v),
+        v => anyhow::bail!("invalid signature v value {}", v),
+    }
+}
+
+/// The address of a public key: the last 20 bytes of the keccak256 hash of
+/// the uncompressed key
+fn public_key_address(key: &VerifyingKey) -> Address {
+    let point = key.to_encoded_point(false);
+    let hash = eth::keccak256(&point.as_bytes()[1..]);
+    Address::from_slice(&hash[12..])
+}
+
+/// A private key that signs locally
+#[derive(Clone)]
+pub struct Wallet {
+    key: SigningKey,
+    address: Address,
+}
+
+impl Wallet {
+    /// Creates a wallet from a hex encoded 32 byte private key
+    pub fn from_private_key(key: impl AsRef<str>) -> anyhow::Result<Self> {
+        let key = SigningKey::from_slice(&eth::decode_hex(key)?)
+            .map_err(|_| anyhow::anyhow!("invalid private key"))?;
+        let address = public_key_address(key.verifying_key());
+        Ok(Self { key, address })
+    }
+
+    pub fn address(&self) -> Address {
+        self.address
+    }
+
+    /// Signs the 32 byte `digest` as is, e.g. an EIP-712 digest
+    pub fn sign_hash(&self, digest: H256) -> anyhow::Result<Signature> {
+        let (signature, recovery_id) = self
+            .key
+            .sign_prehash_recoverable(digest.as_bytes())
+            .map_err(|_| anyhow::anyhow!("failed to sign {:?}", digest))?;
+        let (r, s) = signature.split_bytes();
+        Ok(Signature {
+            r: U256::from_big_endian(&r),
+            s: U256::from_big_endian(&s),
+            v: 27 + recovery_id.to_byte(),
+        })
+    }
+
+    /// Signs the `message` the way `eth_sign` does
+    pub fn sign_message(&self, message: impl AsRef<[u8]>) -> anyhow::Result<Signature> {
+        self.sign_hash(eth::hash_message(message))
+    }
+}
+
+impl FromStr for Wallet {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::from_private_key(s)
+    }
+}
+
+impl fmt::Debug for Wallet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Wallet")
+            .field("address", &self.address)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The account of the web3.js documentation examples
+    const KEY: &str = "0x4c0883a69102937d6231471b5dbb6204fe5129617082792ae468d01a3f362318";
+    const ADDRESS: &str = "0x2c7536e3605d9c16a7a3d7b1898e529396a65c23";
+
+    fn hash(s: &str) -> H256 {
+        H256::from_slice(&eth::decode_hex(s).unwrap())
+    }
+
+    #[test]
+    fn derives_the_address() {
+        let wallet = Wallet::from_private_key(KEY).unwrap();
+        assert_eq!(wallet.address(), eth::parse_address(ADDRESS).unwrap());
+        assert!(Wallet::from_private_key("0x00").is_err());
+    }
+
+    #[test]
+    fn signs_messages_like_eth_sign() {
+        let wallet = Wallet::from_private_key(KEY).unwrap();
+        let signature = wallet.sign_message("Some data").unwrap();
+        assert_eq!(
+            signature.to_string(),
+            "0xb91467e570a6466aa9e9876cbcd013baba02900b8979d43fe208a4a4f339f5fd6007e74cd82e037b800186422fc2da167c747ef045e5d18a5f5d4300f8e1a0291c"
+        );
+        let digest = eth::hash_message("Some data");
+        assert_eq!(signature.recover(digest).unwrap(), wallet.address());
+        assert!(!signature.verify(eth::hash_message("Other data"), wallet.address()));
+    }
+
+    #[test]
+    fn signs_the_eip712_example() {
+        // the `Mail` example of the EIP-712 specification, signed by `Cow`
+        let wallet = Wallet::from_private_key(eth::encode_hex(eth::keccak256("cow"))).unwrap();
+        let digest = hash("0xbe609aee343fb3c4b28e1df9e632fca64fcfaede20f02e86244efddf30957bd2");
+        let signature = wallet.sign_hash(digest).unwrap();
+        assert_eq!(signature.v, 28);
+        assert_eq!(
+            signature.r,
+            U256::from_str("4355c47d63924e8a72e509b65029052eb6c299d53a04e167c5775fd466751c9d")
+                .unwrap()
+        );
+        assert_eq!(
+            signature.s,
+            U256::from_str("07299936d304c153f6443dfa05f40ff007d72911b6f72307f996231605b91562")
+                .unwrap()
+        );
+        assert_eq!(
+            signature.recover(digest).unwrap(),
+            eth::parse_address("0xCD2a3d9F938E13CD947Ec05AbC7FE734Df8DD826").unwrap()
+        );
+    }
+
+    #[test]
+    fn parses_signatures() {
+        let wallet = Wallet::from_private_key(KEY).unwrap();
+        let digest = eth::hash_message("Some data");
+        let signature = wallet.sign_hash(digest).unwrap();
+        let mut bytes = signature.to_bytes();
+        assert_eq!(Signature::from_bytes(&bytes).unwrap(), signature);
+        // `v` as recovery id
+        bytes[64] -= 27;
+        assert_eq!(Signature::from_bytes(&bytes).unwrap(), signature);
+        bytes[64] = 2;
+        assert!(Signature::from_bytes(&bytes).is_err());
+        assert!(Signature::from_bytes(&bytes[..64]).is_err());
+    }
+
+    #[test]
+    fn recovers_signatures_with_high_s() {
+        let wallet = Wallet::from_private_key(KEY).unwrap();
+        let digest = eth::hash_message("Some data");
+        let signature = wallet.sign_hash(digest).unwrap();
+        // `ecrecover` also accepts `(r, n - s)` with the other parity
+        let n = U256::from_str("fffffffffffffffffffffffffffffffebaaedce6af48a03bbfd25e8cd0364141")
+            .unwrap();
+        let high = Signature {
+            r: signature.r,
+            s: n - signature.s,
+            v: if signature.v == 27 { 28 } else { 27 },
+        };
+        assert_eq!(high.recover(digest).unwrap(), wallet.address());
+    }
+}
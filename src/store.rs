@@ -0,0 +1,1191 @@
+//! Local SQLite index of assets, orders and activities.
+//!
+//! Every record is stored as json together with the columns it's looked up
+//! by, so the models can be read back as they were fetched while queries
+//! can still filter and join on the indexed columns. Records are upserted,
+//! storing a record again replaces it.
+//!
+//! [`Store::sync_collection`] fetches everything of a collection and keeps
+//! the pagination state in the `sync_state` table, so an interrupted sync
+//! resumes where it stopped and later syncs only fetch what changed. Later
+//! syncs also fetch the orders of the tokens with live OpenSea orders again,
+//! so cancellations and fills reach the store.
+
+use std::collections::{BTreeSet, HashSet};
+use std::path::Path;
+
+use chrono::{DateTime, Utc};
+use log::debug;
+use rusqlite::types::ToSql;
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::market::models::rarible_nft;
+use crate::opensea::models::{self as opensea, ExchangeMetadata, OpenSeaAsset, WyvernAsset};
+use crate::opensea::query::{OpenSeaAssetsQuery, OrderQuery};
+use crate::rarible::models::{self as rarible, NftActivity, NftItem, OrderActivity};
+use crate::ApiClient;
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS opensea_assets (
+    contract TEXT NOT NULL,
+    token_id TEXT NOT NULL,
+    owner TEXT NOT NULL,
+    name TEXT,
+    data TEXT NOT NULL,
+    PRIMARY KEY (contract, token_id)
+);
+CREATE TABLE IF NOT EXISTS opensea_orders (
+    order_hash TEXT PRIMARY KEY,
+    contract TEXT,
+    token_id TEXT,
+    side INTEGER NOT NULL,
+    maker TEXT NOT NULL,
+    current_price TEXT NOT NULL,
+    payment_token TEXT NOT NULL,
+    listing_time INTEGER NOT NULL,
+    expiration_time INTEGER NOT NULL,
+    cancelled INTEGER NOT NULL,
+    finalized INTEGER NOT NULL,
+    data TEXT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS opensea_orders_nft ON opensea_orders (contract, token_id);
+CREATE TABLE IF NOT EXISTS rarible_items (
+    id TEXT PRIMARY KEY,
+    contract TEXT NOT NULL,
+    token_id TEXT NOT NULL,
+    date INTEGER,
+    deleted INTEGER NOT NULL,
+    data TEXT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS rarible_items_contract ON rarible_items (contract);
+CREATE TABLE IF NOT EXISTS rarible_orders (
+    hash TEXT PRIMARY KEY,
+    contract TEXT,
+    token_id TEXT,
+    maker TEXT NOT NULL,
+    make_stock TEXT NOT NULL,
+    cancelled INTEGER NOT NULL,
+    last_update_at INTEGER NOT NULL,
+    data TEXT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS rarible_orders_nft ON rarible_orders (contract, token_id);
+CREATE TABLE IF NOT EXISTS rarible_nft_activities (
+    transaction_hash TEXT NOT NULL,
+    log_index INTEGER NOT NULL,
+    type TEXT NOT NULL,
+    contract TEXT NOT NULL,
+    token_id TEXT NOT NULL,
+    owner TEXT NOT NULL,
+    block_number INTEGER NOT NULL,
+    data TEXT NOT NULL,
+    PRIMARY KEY (transaction_hash, log_index)
+);
+CREATE INDEX IF NOT EXISTS rarible_nft_activities_nft ON rarible_nft_activities (contract, token_id);
+CREATE TABLE IF NOT EXISTS rarible_order_activities (
+    id TEXT PRIMARY KEY,
+    type TEXT NOT NULL,
+    hash TEXT NOT NULL,
+    contract TEXT,
+    token_id TEXT,
+    price TEXT NOT NULL,
+    date INTEGER NOT NULL,
+    block_number INTEGER NOT NULL,
+    data TEXT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS rarible_order_activities_nft ON rarible_order_activities (contract, token_id);
+CREATE TABLE IF NOT EXISTS sync_state (
+    source TEXT NOT NULL,
+    contract TEXT NOT NULL,
+    continuation TEXT,
+    last_updated INTEGER,
+    high_water INTEGER,
+    PRIMARY KEY (source, contract)
+);
+";
+
+/// Page size of the Rarible requests
+const RARIBLE_PAGE_SIZE: i32 = 100;
+
+/// Page size of the OpenSea requests, the maximum the API allows
+const OPENSEA_PAGE_SIZE: u32 = 50;
+
+/// The data sources [`Store::sync_collection`] keeps a [`SyncState`] for
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum SyncSource {
+    OpenSeaAssets,
+    OpenSeaOrders,
+    RaribleItems,
+    RaribleOrders,
+    RaribleNftActivities,
+    RaribleOrderActivities,
+}
+
+impl SyncSource {
+    fn as_str(&self) -> &'static str {
+        match self {
+            SyncSource::OpenSeaAssets => "opensea_assets",
+            SyncSource::OpenSeaOrders => "opensea_orders",
+            SyncSource::RaribleItems => "rarible_items",
+            SyncSource::RaribleOrders => "rarible_orders",
+            SyncSource::RaribleNftActivities => "rarible_nft_activities",
+            SyncSource::RaribleOrderActivities => "rarible_order_activities",
+        }
+    }
+}
+
+/// How far a source of a collection is synced
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SyncState {
+    /// Continuation token or offset of the next page of an unfinished sync
+    pub continuation: Option<String>,
+    /// Newest update of the last complete sync: a unix timestamp in
+    /// milliseconds, seconds for OpenSea orders or a block number for nft
+    /// activities
+    pub last_updated: Option<i64>,
+    /// Newest update seen by the unfinished sync
+    pub high_water: Option<i64>,
+}
+
+impl SyncState {
+    fn page(&mut self, continuation: Option<String>, updated: impl IntoIterator<Item = i64>) {
+        self.continuation = continuation;
+        self.high_water = updated.into_iter().chain(self.high_water).max();
+    }
+
+    fn finish(&mut self) {
+        self.continuation = None;
+        self.last_updated = self.high_water.take().max(self.last_updated);
+    }
+}
+
+/// Number of records stored by [`Store::sync_collection`]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SyncReport {
+    pub opensea_assets: usize,
+    pub opensea_orders: usize,
+    pub rarible_items: usize,
+    pub rarible_orders: usize,
+    pub rarible_nft_activities: usize,
+    pub rarible_order_activities: usize,
+}
+
+/// Milliseconds of a Rarible date
+fn millis(date: &str) -> anyhow::Result<i64> {
+    Ok(DateTime::parse_from_rfc3339(date)?.timestamp_millis())
+}
+
+fn json<T: Serialize>(value: &T) -> anyhow::Result<String> {
+    Ok(serde_json::to_string(value)?)
+}
+
+/// The contract and token of an OpenSea order, without token for bundles
+fn opensea_order_nft(order: &opensea::Order) -> (Option<String>, Option<String>) {
+    let (asset, single) = match &order.metadata {
+        ExchangeMetadata::Asset(meta) => (Some(&meta.asset), true),
+        ExchangeMetadata::Bundle(meta) => (meta.bundle.assets.first(), false),
+    };
+    match asset {
+        Some(WyvernAsset::NFT(nft)) => (
+            Some(nft.address.to_lowercase()),
+            Some(nft.id.clone()).filter(|_| single),
+        ),
+        Some(WyvernAsset::FT(ft)) => (
+            Some(ft.address.to_lowercase()),
+            ft.id.clone().filter(|_| single),
+        ),
+        None => (None, None),
+    }
+}
+
+/// The nft traded by a Rarible order or activity
+fn rarible_order_nft(
+    make: &rarible::AssetType,
+    take: &rarible::AssetType,
+) -> (Option<String>, Option<String>) {
+    rarible_nft(make)
+        .or_else(|| rarible_nft(take))
+        .map(|nft| (Some(nft.contract), Some(nft.token_id)))
+        .unwrap_or_default()
+}
+
+/// Assets, orders and activities stored in an SQLite database
+pub struct Store {
+    conn: Connection,
+}
+
+impl Store {
+    /// Opens the database at `path`, creating it if it doesn't exist
+    pub fn open(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        Self::new(Connection::open(path)?)
+    }
+
+    pub fn open_in_memory() -> anyhow::Result<Self> {
+        Self::new(Connection::open_in_memory()?)
+    }
+
+    /// Creates the schema if it doesn't exist
+    pub fn new(conn: Connection) -> anyhow::Result<Self> {
+        conn.execute_batch(SCHEMA)?;
+        Ok(Self { conn })
+    }
+
+    /// The connection, to run queries on the tables
+    pub fn connection(&self) -> &Connection {
+        &self.conn
+    }
+
+    fn upsert(
+        &self,
+        table: &str,
+        key: &[&str],
+        columns: &[(&str, &dyn ToSql)],
+    ) -> anyhow::Result<()> {
+        let names = columns.iter().map(|(name, _)| *name).collect::<Vec<_>>();
+        let placeholders = (1..=names.len())
+            .map(|idx| format!("?{}", idx))
+            .collect::<Vec<_>>();
+        let updates = names
+            .iter()
+            .filter(|name| !key.contains(name))
+            .map(|name| format!("{0} = excluded.{0}", name))
+            .collect::<Vec<_>>();
+        let sql = format!(
+            "INSERT INTO {} ({}) VALUES ({}) ON CONFLICT ({}) DO UPDATE SET {}",
+            table,
+            names.join(", "),
+            placeholders.join(", "),
+            key.join(", "),
+            updates.join(", ")
+        );
+        let values = columns.iter().map(|(_, value)| *value).collect::<Vec<_>>();
+        self.conn.prepare_cached(&sql)?.execute(&*values)?;
+        Ok(())
+    }
+
+    fn select<T: DeserializeOwned>(
+        &self,
+        sql: &str,
+        params: &[&dyn ToSql],
+    ) -> anyhow::Result<Vec<T>> {
+        let mut stmt = self.conn.prepare_cached(sql)?;
+        let rows = stmt.query_map(params, |row| row.get::<_, String>(0))?;
+        let mut records = Vec::new();
+        for data in rows {
+            records.push(serde_json::from_str(&data?)?);
+        }
+        Ok(records)
+    }
+
+    /// Runs `f` in a transaction
+    fn transaction<T>(&mut self, f: impl FnOnce(&Self) -> anyhow::Result<T>) -> anyhow::Result<T> {
+        self.conn.execute_batch("BEGIN")?;
+        match f(self) {
+            Ok(value) => {
+                self.conn.execute_batch("COMMIT")?;
+                Ok(value)
+            }
+            Err(err) => {
+                self.conn.execute_batch("ROLLBACK")?;
+                Err(err)
+            }
+        }
+    }
+
+    pub fn upsert_opensea_asset(&self, asset: &OpenSeaAsset) -> anyhow::Result<()> {
+        let token_id = asset
+            .token_id
+            .as_deref()
+            .ok_or_else(|| anyhow::anyhow!("asset without token id"))?;
+        self.upsert(
+            "opensea_assets",
+            &["contract", "token_id"],
+            &[
+                ("contract", &asset.asset_contract.address.to_lowercase()),
+                ("token_id", &token_id),
+                ("owner", &asset.owner.address.to_lowercase()),
+                ("name", &asset.name),
+                ("data", &json(asset)?),
+            ],
+        )
+    }
+
+    pub fn upsert_opensea_order(&self, order: &opensea::Order) -> anyhow::Result<()> {
+        let (contract, token_id) = opensea_order_nft(order);
+        self.upsert(
+            "opensea_orders",
+            &["order_hash"],
+            &[
+                ("order_hash", &order.order_hash),
+                ("contract", &contract),
+                ("token_id", &token_id),
+                ("side", &order.side),
+                ("maker", &order.maker.address.to_lowercase()),
+                ("current_price", &order.current_price),
+                ("payment_token", &order.payment_token.to_lowercase()),
+                ("listing_time", &order.listing_time),
+                ("expiration_time", &order.expiration_time),
+                ("cancelled", &order.cancelled),
+                ("finalized", &order.finalized),
+                ("data", &json(order)?),
+            ],
+        )
+    }
+
+    pub fn upsert_rarible_item(&self, item: &NftItem) -> anyhow::Result<()> {
+        let date = item.date.as_deref().map(millis).transpose()?;
+        self.upsert(
+            "rarible_items",
+            &["id"],
+            &[
+                ("id", &item.id.to_lowercase()),
+                ("contract", &item.contract.to_lowercase()),
+                ("token_id", &item.token_id),
+                ("date", &date),
+                ("deleted", &item.deleted.unwrap_or_default()),
+                ("data", &json(item)?),
+            ],
+        )
+    }
+
+    pub fn upsert_rarible_order(&self, order: &rarible::Order) -> anyhow::Result<()> {
+        let (contract, token_id) =
+            rarible_order_nft(&order.make.asset_type, &order.take.asset_type);
+        self.upsert(
+            "rarible_orders",
+            &["hash"],
+            &[
+                ("hash", &order.hash),
+                ("contract", &contract),
+                ("token_id", &token_id),
+                ("maker", &order.maker.to_lowercase()),
+                ("make_stock", &order.make_stock),
+                ("cancelled", &order.cancelled),
+                ("last_update_at", &millis(&order.last_update_at)?),
+                ("data", &json(order)?),
+            ],
+        )
+    }
+
+    pub fn upsert_nft_activity(&self, activity: &NftActivity) -> anyhow::Result<()> {
+        self.upsert(
+            "rarible_nft_activities",
+            &["transaction_hash", "log_index"],
+            &[
+                ("transaction_hash", &activity.transaction_hash),
+                ("log_index", &activity.log_index),
+                ("type", &activity._type),
+                ("contract", &activity.contract.to_lowercase()),
+                ("token_id", &activity.token_id),
+                ("owner", &activity.owner.to_lowercase()),
+                ("block_number", &activity.block_number),
+                ("data", &json(activity)?),
+            ],
+        )
+    }
+
+    pub fn upsert_order_activity(&self, activity: &OrderActivity) -> anyhow::Result<()> {
+        let (contract, token_id) = rarible_order_nft(&activity.make, &activity.take);
+        self.upsert(
+            "rarible_order_activities",
+            &["id"],
+            &[
+                ("id", &activity.id),
+                ("type", &activity._type),
+                ("hash", &activity.hash),
+                ("contract", &contract),
+                ("token_id", &token_id),
+                ("price", &activity.price),
+                ("date", &millis(&activity.date)?),
+                ("block_number", &activity.block_number),
+                ("data", &json(activity)?),
+            ],
+        )
+    }
+
+    pub fn opensea_asset(
+        &self,
+        contract: &str,
+        token_id: &str,
+    ) -> anyhow::Result<Option<OpenSeaAsset>> {
+        Ok(self
+            .select(
+                "SELECT data FROM opensea_assets WHERE contract = ?1 AND token_id = ?2",
+                params![contract.to_lowercase(), token_id],
+            )?
+            .pop())
+    }
+
+    pub fn opensea_assets(&self, contract: &str) -> anyhow::Result<Vec<OpenSeaAsset>> {
+        self.select(
+            "SELECT data FROM opensea_assets WHERE contract = ?1",
+            params![contract.to_lowercase()],
+        )
+    }
+
+    /// The OpenSea orders of the collection, newest listings first
+    pub fn opensea_orders(&self, contract: &str) -> anyhow::Result<Vec<opensea::Order>> {
+        self.select(
+            "SELECT data FROM opensea_orders WHERE contract = ?1 ORDER BY listing_time DESC",
+            params![contract.to_lowercase()],
+        )
+    }
+
+    pub fn rarible_item(&self, item_id: &str) -> anyhow::Result<Option<NftItem>> {
+        Ok(self
+            .select(
+                "SELECT data FROM rarible_items WHERE id = ?1",
+                params![item_id.to_lowercase()],
+            )?
+            .pop())
+    }
+
+    /// The items of the collection, without deleted ones
+    pub fn rarible_items(&self, contract: &str) -> anyhow::Result<Vec<NftItem>> {
+        self.select(
+            "SELECT data FROM rarible_items WHERE contract = ?1 AND NOT deleted",
+            params![contract.to_lowercase()],
+        )
+    }
+
+    /// The Rarible orders of the collection, most recently updated first
+    pub fn rarible_orders(&self, contract: &str) -> anyhow::Result<Vec<rarible::Order>> {
+        self.select(
+            "SELECT data FROM rarible_orders WHERE contract = ?1 ORDER BY last_update_at DESC",
+            params![contract.to_lowercase()],
+        )
+    }
+
+    /// The nft activities of the collection, newest first
+    pub fn nft_activities(&self, contract: &str) -> anyhow::Result<Vec<NftActivity>> {
+        self.select(
+            "SELECT data FROM rarible_nft_activities WHERE contract = ?1 \
+             ORDER BY block_number DESC, log_index DESC",
+            params![contract.to_lowercase()],
+        )
+    }
+
+    /// The order activities of the collection, newest first
+    pub fn order_activities(&self, contract: &str) -> anyhow::Result<Vec<OrderActivity>> {
+        self.select(
+            "SELECT data FROM rarible_order_activities WHERE contract = ?1 ORDER BY date DESC",
+            params![contract.to_lowercase()],
+        )
+    }
+
+    pub fn sync_state(&self, source: SyncSource, contract: &str) -> anyhow::Result<SyncState> {
+        let state = self
+            .conn
+            .prepare_cached(
+                "SELECT continuation, last_updated, high_water FROM sync_state \
+                 WHERE source = ?1 AND contract = ?2",
+            )?
+            .query_row(params![source.as_str(), contract.to_lowercase()], |row| {
+                Ok(SyncState {
+                    continuation: row.get(0)?,
+                    last_updated: row.get(1)?,
+                    high_water: row.get(2)?,
+                })
+            })
+            .optional()?;
+        Ok(state.unwrap_or_default())
+    }
+
+    pub fn set_sync_state(
+        &self,
+        source: SyncSource,
+        contract: &str,
+        state: &SyncState,
+    ) -> anyhow::Result<()> {
+        self.upsert(
+            "sync_state",
+            &["source", "contract"],
+            &[
+                ("source", &source.as_str()),
+                ("contract", &contract.to_lowercase()),
+                ("continuation", &state.continuation),
+                ("last_updated", &state.last_updated),
+                ("high_water", &state.high_water),
+            ],
+        )
+    }
+
+    /// Stores a page of records and the sync state after it in one
+    /// transaction
+    fn store_page<T>(
+        &mut self,
+        source: SyncSource,
+        contract: &str,
+        state: &SyncState,
+        records: &[T],
+        upsert: impl Fn(&Self, &T) -> anyhow::Result<()>,
+    ) -> anyhow::Result<()> {
+        self.transaction(|store| {
+            for record in records {
+                upsert(store, record)?;
+            }
+            store.set_sync_state(source, contract, state)
+        })
+    }
+
+    /// Syncs the assets, orders and activities of the collection from
+    /// OpenSea and Rarible.
+    ///
+    /// Every page is stored together with the position of the next one, if
+    /// the sync fails it resumes from there the next time. Complete syncs
+    /// only fetch what changed since the previous one.
+    pub async fn sync_collection(
+        &mut self,
+        client: &ApiClient,
+        contract: &str,
+    ) -> anyhow::Result<SyncReport> {
+        let contract = contract.to_lowercase();
+        let report = SyncReport {
+            opensea_assets: self.sync_opensea_assets(client, &contract).await?,
+            opensea_orders: self.sync_opensea_orders(client, &contract).await?,
+            rarible_items: self.sync_rarible_items(client, &contract).await?,
+            rarible_orders: self.sync_rarible_orders(client, &contract).await?,
+            rarible_nft_activities: self.sync_nft_activities(client, &contract).await?,
+            rarible_order_activities: self.sync_order_activities(client, &contract).await?,
+        };
+        debug!("Synced {}: {:?}", contract, report);
+        Ok(report)
+    }
+
+    /// Fetches all assets, OpenSea can't filter them by update time
+    pub async fn sync_opensea_assets(
+        &mut self,
+        client: &ApiClient,
+        contract: &str,
+    ) -> anyhow::Result<usize> {
+        let source = SyncSource::OpenSeaAssets;
+        let mut state = self.sync_state(source, contract)?;
+        let mut offset = state
+            .continuation
+            .as_deref()
+            .map(str::parse)
+            .transpose()?
+            .unwrap_or(0u32);
+        let mut count = 0;
+        loop {
+            let query = OpenSeaAssetsQuery::default()
+                .asset_contract_address(contract)
+                .limit(OPENSEA_PAGE_SIZE)
+                .offset(offset);
+            let assets = client.get_assets(&query).await?.assets;
+            let done = (assets.len() as u32) < OPENSEA_PAGE_SIZE;
+            offset += assets.len() as u32;
+            state.page(Some(offset.to_string()), None);
+            if done {
+                state.finish();
+            }
+            self.store_page(
+                source,
+                contract,
+                &state,
+                &assets,
+                Self::upsert_opensea_asset,
+            )?;
+            count += assets.len();
+            if done {
+                return Ok(count);
+            }
+        }
+    }
+
+    /// Fetches the orders listed since the last sync, and then refreshes the
+    /// stored orders that are still live if there was a previous sync
+    pub async fn sync_opensea_orders(
+        &mut self,
+        client: &ApiClient,
+        contract: &str,
+    ) -> anyhow::Result<usize> {
+        let source = SyncSource::OpenSeaOrders;
+        let mut state = self.sync_state(source, contract)?;
+        let incremental = state.last_updated.is_some();
+        let mut offset = state
+            .continuation
+            .as_deref()
+            .map(str::parse)
+            .transpose()?
+            .unwrap_or(0u32);
+        let mut count = 0;
+        loop {
+            let mut query = OrderQuery::default()
+                .asset_contract_address(contract)
+                .limit(OPENSEA_PAGE_SIZE)
+                .offset(offset);
+            if let Some(last_updated) = state.last_updated {
+                // includes the orders listed in the same second
+                query = query.listed_after((last_updated - 1).to_string());
+            }
+            let orders = client.get_orders(&query).await?.orders;
+            let done = (orders.len() as u32) < OPENSEA_PAGE_SIZE;
+            offset += orders.len() as u32;
+            state.page(
+                Some(offset.to_string()),
+                orders.iter().map(|order| order.listing_time),
+            );
+            if done {
+                state.finish();
+            }
+            self.store_page(
+                source,
+                contract,
+                &state,
+                &orders,
+                Self::upsert_opensea_order,
+            )?;
+            count += orders.len();
+            if done {
+                break;
+            }
+        }
+        if incremental {
+            count += self.refresh_opensea_orders(client, contract).await?;
+        }
+        Ok(count)
+    }
+
+    /// Fetches all orders of the tokens with stored orders that are neither
+    /// cancelled, finalized nor expired, including invalid ones.
+    ///
+    /// Live orders OpenSea doesn't return anymore are stored as cancelled.
+    pub async fn refresh_opensea_orders(
+        &mut self,
+        client: &ApiClient,
+        contract: &str,
+    ) -> anyhow::Result<usize> {
+        let live: Vec<opensea::Order> = self.select(
+            "SELECT data FROM opensea_orders WHERE contract = ?1 AND token_id IS NOT NULL \
+             AND NOT cancelled AND NOT finalized \
+             AND (expiration_time = 0 OR expiration_time > ?2)",
+            params![contract.to_lowercase(), Utc::now().timestamp()],
+        )?;
+        let tokens = live
+            .iter()
+            .filter_map(|order| opensea_order_nft(order).1)
+            .collect::<BTreeSet<_>>();
+        let mut count = 0;
+        for token_id in tokens {
+            let mut orders = Vec::new();
+            let mut offset = 0;
+            loop {
+                let query = OrderQuery::default()
+                    .asset_contract_address(contract)
+                    .token_id(token_id.as_str())
+                    .include_invalid(true)
+                    .limit(OPENSEA_PAGE_SIZE)
+                    .offset(offset);
+                let page = client.get_orders(&query).await?.orders;
+                let done = (page.len() as u32) < OPENSEA_PAGE_SIZE;
+                offset += page.len() as u32;
+                orders.extend(page);
+                if done {
+                    break;
+                }
+            }
+            let returned = orders
+                .iter()
+                .map(|order| order.order_hash.clone())
+                .collect::<HashSet<_>>();
+            let gone = live
+                .iter()
+                .filter(|order| opensea_order_nft(order).1.as_ref() == Some(&token_id))
+                .filter(|order| !returned.contains(&order.order_hash))
+                .map(|order| opensea::Order {
+                    cancelled: true,
+                    ..order.clone()
+                })
+                .collect::<Vec<_>>();
+            orders.extend(gone);
+            self.transaction(|store| {
+                for order in &orders {
+                    store.upsert_opensea_order(order)?;
+                }
+                Ok(())
+            })?;
+            count += orders.len();
+        }
+        Ok(count)
+    }
+
+    /// Fetches the items of the collection with their meta.
+    ///
+    /// The first sync pages through the collection, which doesn't include
+    /// deleted items. Later syncs page through all items updated since the
+    /// last sync, deleted ones included, and keep those of the collection,
+    /// so burned items are marked as deleted.
+    pub async fn sync_rarible_items(
+        &mut self,
+        client: &ApiClient,
+        contract: &str,
+    ) -> anyhow::Result<usize> {
+        let source = SyncSource::RaribleItems;
+        let mut state = self.sync_state(source, contract)?;
+        let since = state.last_updated;
+        let include_meta = Some(true);
+        let mut count = 0;
+        loop {
+            let continuation = state.continuation.as_deref();
+            let page = match since {
+                None => {
+                    client
+                        .get_nft_items_by_collection(
+                            contract,
+                            continuation,
+                            Some(RARIBLE_PAGE_SIZE),
+                            include_meta,
+                        )
+                        .await?
+                }
+                Some(since) => {
+                    let show_deleted = Some(true);
+                    client
+                        .get_nft_all_items(
+                            continuation,
+                            Some(RARIBLE_PAGE_SIZE),
+                            show_deleted,
+                            Some(since),
+                            None,
+                            include_meta,
+                        )
+                        .await?
+                }
+            };
+            let dates = page
+                .items
+                .iter()
+                .filter_map(|item| item.date.as_deref())
+                .map(millis)
+                .collect::<anyhow::Result<Vec<_>>>()?;
+            let items = page
+                .items
+                .into_iter()
+                .filter(|item| item.contract.eq_ignore_ascii_case(contract))
+                .collect::<Vec<_>>();
+            let done = page.continuation.is_none();
+            state.page(page.continuation, dates);
+            if done {
+                state.finish();
+            }
+            self.store_page(source, contract, &state, &items, Self::upsert_rarible_item)?;
+            count += items.len();
+            if done {
+                return Ok(count);
+            }
+        }
+    }
+
+    /// Fetches the sell orders, newest first, until the orders were updated
+    /// before the last sync
+    pub async fn sync_rarible_orders(
+        &mut self,
+        client: &ApiClient,
+        contract: &str,
+    ) -> anyhow::Result<usize> {
+        let source = SyncSource::RaribleOrders;
+        let mut state = self.sync_state(source, contract)?;
+        let mut count = 0;
+        loop {
+            let page = client
+                .get_sell_orders_by_collection(
+                    contract,
+                    None,
+                    state.continuation.as_deref(),
+                    Some(RARIBLE_PAGE_SIZE),
+                )
+                .await?;
+            let dates = page
+                .orders
+                .iter()
+                .map(|order| millis(&order.last_update_at))
+                .collect::<anyhow::Result<Vec<_>>>()?;
+            let done = page.continuation.is_none()
+                || matches!((dates.iter().min(), state.last_updated), (Some(oldest), Some(last)) if *oldest < last);
+            state.page(page.continuation, dates);
+            if done {
+                state.finish();
+            }
+            self.store_page(
+                source,
+                contract,
+                &state,
+                &page.orders,
+                Self::upsert_rarible_order,
+            )?;
+            count += page.orders.len();
+            if done {
+                return Ok(count);
+            }
+        }
+    }
+
+    /// Fetches the nft activities, newest first, until the block of the last
+    /// sync
+    pub async fn sync_nft_activities(
+        &mut self,
+        client: &ApiClient,
+        contract: &str,
+    ) -> anyhow::Result<usize> {
+        let source = SyncSource::RaribleNftActivities;
+        let mut state = self.sync_state(source, contract)?;
+        let filter = rarible::NftActivityFilter {
+            _type: "by_collection".to_string(),
+            types: vec![
+                rarible::ActivityTypes::Transfer,
+                rarible::ActivityTypes::Mint,
+                rarible::ActivityTypes::Burn,
+            ],
+            users: Vec::new(),
+            contract: contract.to_string(),
+            token_id: String::new(),
+        };
+        let mut count = 0;
+        loop {
+            let page = client
+                .get_nft_activities(
+                    filter.clone(),
+                    state.continuation.as_deref(),
+                    Some(RARIBLE_PAGE_SIZE),
+                )
+                .await?;
+            let blocks = page
+                .items
+                .iter()
+                .map(|activity| activity.block_number)
+                .collect::<Vec<_>>();
+            let done = page.continuation.is_none()
+                || matches!((blocks.iter().min(), state.last_updated), (Some(oldest), Some(last)) if *oldest < last);
+            state.page(page.continuation, blocks);
+            if done {
+                state.finish();
+            }
+            self.store_page(
+                source,
+                contract,
+                &state,
+                &page.items,
+                Self::upsert_nft_activity,
+            )?;
+            count += page.items.len();
+            if done {
+                return Ok(count);
+            }
+        }
+    }
+
+    /// Fetches the order activities, newest first, until the activities of
+    /// the last sync
+    pub async fn sync_order_activities(
+        &mut self,
+        client: &ApiClient,
+        contract: &str,
+    ) -> anyhow::Result<usize> {
+        let source = SyncSource::RaribleOrderActivities;
+        let mut state = self.sync_state(source, contract)?;
+        let filter = rarible::OrderActivityFilter {
+            _type: "by_collection".to_string(),
+            types: vec![
+                rarible::OrderActivityTypes::List,
+                rarible::OrderActivityTypes::Bid,
+                rarible::OrderActivityTypes::_Match,
+            ],
+            users: Vec::new(),
+            contract: contract.to_string(),
+            token_id: String::new(),
+        };
+        let mut count = 0;
+        loop {
+            let page = client
+                .get_order_activities(
+                    filter.clone(),
+                    state.continuation.as_deref(),
+                    Some(RARIBLE_PAGE_SIZE),
+                )
+                .await?;
+            let dates = page
+                .items
+                .iter()
+                .map(|activity| millis(&activity.date))
+                .collect::<anyhow::Result<Vec<_>>>()?;
+            let done = page.continuation.is_none()
+                || matches!((dates.iter().min(), state.last_updated), (Some(oldest), Some(last)) if *oldest < last);
+            state.page(page.continuation, dates);
+            if done {
+                state.finish();
+            }
+            self.store_page(
+                source,
+                contract,
+                &state,
+                &page.items,
+                Self::upsert_order_activity,
+            )?;
+            count += page.items.len();
+            if done {
+                return Ok(count);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::MockServer;
+    use serde_json::json;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    const ORDERBOOK: &str = include_str!("../examples/responses/opensea-orderbook.json");
+    const RARIBLE_ORDER: &str = include_str!("../tests/fixtures/rarible-order.json");
+
+    const MEEBITS: &str = "0x7bd29408f11d2bfc23c34f18275bbf23bb716bc7";
+    const RARIBLE: &str = "0x60f80121c31a0d46b5279700f9df786054aa5ee5";
+
+    /// The fixture order, without expiration
+    fn opensea_order() -> opensea::Order {
+        let book: opensea::OrderBook = serde_json::from_str(ORDERBOOK).unwrap();
+        opensea::Order {
+            expiration_time: 0,
+            ..book.orders[0].clone()
+        }
+    }
+
+    fn rarible_order(hash: &str, updated: &str) -> rarible::Order {
+        rarible::Order {
+            hash: hash.to_string(),
+            last_update_at: updated.to_string(),
+            ..serde_json::from_str(RARIBLE_ORDER).unwrap()
+        }
+    }
+
+    fn item(token_id: &str, deleted: bool) -> NftItem {
+        serde_json::from_value(json!({
+            "id": format!("{}:{}", RARIBLE.to_uppercase(), token_id),
+            "contract": RARIBLE,
+            "tokenId": token_id,
+            "creators": [],
+            "supply": "1",
+            "lazySupply": "0",
+            "owners": [],
+            "royalties": [],
+            "date": "2021-08-17T21:40:24Z",
+            "deleted": deleted
+        }))
+        .unwrap()
+    }
+
+    fn count(store: &Store, table: &str) -> i64 {
+        store
+            .connection()
+            .query_row(&format!("SELECT COUNT(*) FROM {}", table), [], |row| {
+                row.get(0)
+            })
+            .unwrap()
+    }
+
+    fn client(server: &MockServer) -> ApiClient {
+        ApiClient::builder().build(server.url().clone()).unwrap()
+    }
+
+    #[test]
+    fn upserts_records_once() {
+        let store = Store::open_in_memory().unwrap();
+        let mut order = opensea_order();
+        store.upsert_opensea_order(&order).unwrap();
+        order.current_price = "1".to_string();
+        store.upsert_opensea_order(&order).unwrap();
+        assert_eq!(count(&store, "opensea_orders"), 1);
+        assert_eq!(store.opensea_orders(MEEBITS).unwrap(), vec![order]);
+
+        store.upsert_rarible_item(&item("1", false)).unwrap();
+        store.upsert_rarible_item(&item("1", true)).unwrap();
+        assert_eq!(count(&store, "rarible_items"), 1);
+        assert!(store.rarible_items(RARIBLE).unwrap().is_empty());
+        let id = format!("{}:1", RARIBLE);
+        assert_eq!(store.rarible_item(&id).unwrap(), Some(item("1", true)));
+    }
+
+    #[test]
+    fn saves_the_sync_state() {
+        let store = Store::open_in_memory().unwrap();
+        let source = SyncSource::RaribleOrders;
+        assert_eq!(
+            store.sync_state(source, RARIBLE).unwrap(),
+            SyncState::default()
+        );
+        let state = SyncState {
+            continuation: Some("next".to_string()),
+            last_updated: Some(1),
+            high_water: Some(2),
+        };
+        store
+            .set_sync_state(source, &RARIBLE.to_uppercase(), &state)
+            .unwrap();
+        assert_eq!(store.sync_state(source, RARIBLE).unwrap(), state);
+        let other = SyncSource::RaribleItems;
+        assert_eq!(
+            store.sync_state(other, RARIBLE).unwrap(),
+            SyncState::default()
+        );
+    }
+
+    #[tokio::test]
+    async fn resumes_an_interrupted_sync() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&calls);
+        let server = MockServer::start(move |_| match counter.fetch_add(1, Ordering::SeqCst) {
+            0 => {
+                let orders = [rarible_order("0x01", "2021-08-17T21:40:24Z")];
+                (
+                    200,
+                    json!({ "orders": orders, "continuation": "next" }).to_string(),
+                )
+            }
+            1 => (
+                500,
+                json!({ "code": "ERROR", "message": "down" }).to_string(),
+            ),
+            _ => {
+                let orders = [rarible_order("0x02", "2021-08-18T21:40:24Z")];
+                (200, json!({ "orders": orders }).to_string())
+            }
+        });
+        let client = client(&server);
+        let mut store = Store::open_in_memory().unwrap();
+        let source = SyncSource::RaribleOrders;
+
+        assert!(store.sync_rarible_orders(&client, RARIBLE).await.is_err());
+        let state = store.sync_state(source, RARIBLE).unwrap();
+        assert_eq!(state.continuation.as_deref(), Some("next"));
+        assert_eq!(state.last_updated, None);
+        assert_eq!(count(&store, "rarible_orders"), 1);
+
+        assert_eq!(
+            store.sync_rarible_orders(&client, RARIBLE).await.unwrap(),
+            1
+        );
+        let state = store.sync_state(source, RARIBLE).unwrap();
+        assert_eq!(state.continuation, None);
+        assert_eq!(
+            state.last_updated,
+            Some(millis("2021-08-18T21:40:24Z").unwrap())
+        );
+        assert_eq!(state.high_water, None);
+        assert_eq!(count(&store, "rarible_orders"), 2);
+
+        let paths = server
+            .requests()
+            .into_iter()
+            .map(|request| request.path)
+            .collect::<Vec<_>>();
+        assert!(!paths[0].contains("continuation"));
+        assert!(paths[1].contains("continuation=next"));
+        assert!(paths[2].contains("continuation=next"));
+    }
+
+    /// Serves the fixture order to the first sync and `refreshed` to the
+    /// requests by token after it
+    fn orderbook(refreshed: Vec<opensea::Order>) -> MockServer {
+        MockServer::start(move |request| {
+            let orders = if request.path.contains("token_id=7325") {
+                refreshed.clone()
+            } else if request.path.contains("listed_after") {
+                Vec::new()
+            } else {
+                vec![opensea_order()]
+            };
+            let count = orders.len();
+            (200, json!({ "orders": orders, "count": count }).to_string())
+        })
+    }
+
+    fn cancelled(store: &Store) -> bool {
+        store
+            .connection()
+            .query_row("SELECT cancelled FROM opensea_orders", [], |row| row.get(0))
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn refreshes_cancelled_orders() {
+        let order = opensea::Order {
+            cancelled: true,
+            ..opensea_order()
+        };
+        let server = orderbook(vec![order.clone()]);
+        let client = client(&server);
+        let mut store = Store::open_in_memory().unwrap();
+
+        assert_eq!(
+            store.sync_opensea_orders(&client, MEEBITS).await.unwrap(),
+            1
+        );
+        assert!(!cancelled(&store));
+        assert_eq!(
+            store.sync_opensea_orders(&client, MEEBITS).await.unwrap(),
+            1
+        );
+        assert!(cancelled(&store));
+        assert_eq!(store.opensea_orders(MEEBITS).unwrap(), vec![order]);
+
+        let paths = server
+            .requests()
+            .into_iter()
+            .map(|request| request.path)
+            .collect::<Vec<_>>();
+        assert_eq!(paths.len(), 3);
+        assert!(paths[1].contains("listed_after=1629236423"));
+        assert!(paths[2].contains("include_invalid=true"));
+
+        // cancelled orders aren't refreshed again
+        store.sync_opensea_orders(&client, MEEBITS).await.unwrap();
+        assert_eq!(server.requests().len(), 4);
+    }
+
+    #[tokio::test]
+    async fn cancels_orders_that_disappeared() {
+        let server = orderbook(Vec::new());
+        let client = client(&server);
+        let mut store = Store::open_in_memory().unwrap();
+        store.sync_opensea_orders(&client, MEEBITS).await.unwrap();
+        store.sync_opensea_orders(&client, MEEBITS).await.unwrap();
+        assert!(cancelled(&store));
+    }
+
+    #[tokio::test]
+    async fn syncs_deleted_items_after_the_first_sync() {
+        let server = MockServer::start(|request| {
+            let items = if request.path.contains("byCollection") {
+                vec![item("1", false), item("2", false)]
+            } else {
+                let mut other = item("3", false);
+                other.contract = MEEBITS.to_string();
+                vec![item("2", true), other]
+            };
+            (
+                200,
+                json!({ "total": items.len(), "items": items }).to_string(),
+            )
+        });
+        let client = client(&server);
+        let mut store = Store::open_in_memory().unwrap();
+
+        assert_eq!(store.sync_rarible_items(&client, RARIBLE).await.unwrap(), 2);
+        assert_eq!(store.sync_rarible_items(&client, RARIBLE).await.unwrap(), 1);
+        assert_eq!(
+            store.rarible_items(RARIBLE).unwrap(),
+            vec![item("1", false)]
+        );
+        assert_eq!(count(&store, "rarible_items"), 2);
+
+        let since = millis("2021-08-17T21:40:24Z").unwrap();
+        let path = &server.requests()[1].path;
+        assert!(path.starts_with("/protocol/v0.1/ethereum/nft/items/all?"));
+        assert!(path.contains("showDeleted=true"));
+        assert!(path.contains(&format!("lastUpdatedFrom={}", since)));
+    }
+}